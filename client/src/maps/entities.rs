@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 
+use macroquad::rand;
 use shared::{
+    effects::StatusEffect,
     gems::{self, Gem},
     items::{self, Item},
     maps::{
-        entities::{Direction, Entity},
-        Map, TileCoords
+        entities::{Appearance, Direction, Entity, FacialExpression},
+        Map, PlacedTrap, TileCoords
     },
     messages, Id
 };
@@ -44,16 +46,21 @@ impl MyEntity {
 
     pub fn update(&mut self, delta: f32) {
         self.movement_time_countdown -= delta;
+
+        // Status effects are also expired locally so that movement prediction remains accurate while waiting for the
+        // server to inform this client of their expiry:
+        self.contained.status_effects.update(delta);
     }
 
     /// Will attempt to move the player entity in the specified direction but will fail if moving now would exceed the
-    /// movement speed limit, or if the destination tile is occupied/blocking, or if unable to contact the server.
+    /// movement speed limit, if the player entity is stunned, or if the destination tile is occupied/blocking, or if
+    /// unable to contact the server.
     pub fn move_towards_checked(
         &mut self, direction: Direction, map: &mut ClientMap, connection: &mut networking::Connection,
         renderer: &mut MapRenderer
     ) -> networking::Result<()> {
         // Check if required amount of time has paced since last movement (i.e. don't exceed maximum movement speed):
-        if self.movement_time_countdown <= 0.0 {
+        if self.movement_time_countdown <= 0.0 && self.contained.can_move() {
             // Check if the position the player wants to move to is free (i.e. not a blocking tile and no other
             // entities persent at that position):
             let new_pos = direction.apply(self.contained.pos);
@@ -103,6 +110,18 @@ impl MyEntity {
         self.contained.gem_collection.increase_quantity(gem_type, quantity_increase);
     }
 
+    /// This method is called from the main game state whenever a [`shared::messages::FromServer::YouLostGems`] message
+    /// is received.
+    pub fn lost_gems(&mut self, gem_type: Gem, quantity_decrease: u32) {
+        self.contained.gem_collection.decrease_quantity(gem_type, quantity_decrease);
+    }
+
+    /// This method is called from the main game state whenever a
+    /// [`shared::messages::FromServer::YourInventoryChanged`] message is received.
+    pub fn inventory_changed(&mut self, inventory: items::Inventory) {
+        self.contained.item_inventory = inventory;
+    }
+
     /// This method is called from the main game state whenever a [`shared::messages::FromSever::YourEntityMoved`]
     /// message is received. It is the role of this method to ensure that previous predictions regarding player
     /// entity position after movement were correct.
@@ -135,6 +154,28 @@ impl MyEntity {
         self.unverified_movements.remove(&request_number);
     }
 
+    /// This method is called from the main game state whenever a [`shared::messages::FromServer::YouDied`] message is
+    /// received. Any movement predictions are discarded as the player entity has been moved to the respawn position.
+    pub fn died(&mut self, respawn_position: TileCoords, renderer: &mut MapRenderer) {
+        log::info!("Player entity died and respawned at {}", respawn_position);
+
+        self.unverified_movements.clear();
+        self.contained.pos = respawn_position;
+
+        renderer.my_entity_respawned(respawn_position);
+    }
+
+    /// This method is called from the main game state whenever a [`shared::messages::FromServer::YouTeleported`]
+    /// message is received.
+    pub fn teleported(&mut self, position: TileCoords, renderer: &mut MapRenderer) {
+        log::info!("Player entity was teleported to {}", position);
+
+        self.unverified_movements.clear();
+        self.contained.pos = position;
+
+        renderer.my_entity_respawned(position);
+    }
+
     /// Attempt to purchase a 'bool item' (an item that a player can either 0 or 1 of). Will send a message to the
     /// server informing it of the purchase provided that the player has the required gems and does not already own
     /// the item.
@@ -194,6 +235,90 @@ impl MyEntity {
         Ok(())
     }
 
+    /// Place a trap of the given type at the player's current position provided that the player has one and that there
+    /// is not already a trap or gem on the ground at that position. The type of gem that the trap will appear as to
+    /// other players is chosen at random (see [`random_trap_disguise`]).
+    pub fn place_trap(
+        &mut self, trap: items::Trap, map: &mut ClientMap, connection: &mut networking::Connection
+    ) -> networking::Result<()> {
+        let pos = self.contained.pos;
+
+        if self.contained.item_inventory.has_how_many(trap.as_item()) >= 1 && map.ground_gem_at(pos).is_none() {
+            let disguise = random_trap_disguise();
+
+            // Place the trap on the map locally:
+            if map.set_trap_at(pos, PlacedTrap { trap, placed_by: self.id, disguise }) {
+                // Inform the server:
+                connection.send(&messages::ToServer::PlaceTrap { trap, disguise })?;
+
+                // Remove placed trap from inventory:
+                self.contained.item_inventory.take_quantity(trap.as_item(), 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Consume one of the player's energy drinks (provided they have any). The speed boost effect is not applied until
+    /// the server confirms it with a [`shared::messages::FromServer::StatusEffectStarted`] message.
+    pub fn consume_energy_drink(&mut self, connection: &mut networking::Connection) -> networking::Result<()> {
+        if self.contained.item_inventory.has_how_many(items::QuantitativeItem::EnergyDrink) >= 1 {
+            // Inform the server:
+            connection.send(&messages::ToServer::ConsumeEnergyDrink)?;
+
+            // Remove consumed energy drink from inventory:
+            self.contained.item_inventory.take_quantity(items::QuantitativeItem::EnergyDrink, 1);
+        }
+
+        Ok(())
+    }
+
+    /// This method is called from the main game state whenever a
+    /// [`shared::messages::FromServer::StatusEffectStarted`] message regarding the player entity is received.
+    pub fn status_effect_started(&mut self, effect: StatusEffect) {
+        self.contained.status_effects.apply(effect);
+    }
+
+    /// This method is called from the main game state whenever a [`shared::messages::FromServer::StatusEffectEnded`]
+    /// message regarding the player entity is received.
+    pub fn status_effect_ended(&mut self, effect: StatusEffect) {
+        self.contained.status_effects.remove(effect);
+    }
+
+    /// Ask the server to change the player entity's facial expression - either held until it times out or briefly
+    /// shown as an emote. The expression is only changed locally once the server responds with a
+    /// [`shared::messages::FromServer::FacialExpressionChanged`] message.
+    pub fn express(
+        &self, expression: FacialExpression, emote: bool, connection: &mut networking::Connection
+    ) -> networking::Result<()> {
+        if emote {
+            connection.send(&messages::ToServer::PlayEmote(expression))
+        }
+        else {
+            connection.send(&messages::ToServer::SetFacialExpression(expression))
+        }
+    }
+
+    /// This method is called from the main game state whenever a
+    /// [`shared::messages::FromServer::FacialExpressionChanged`] message regarding the player entity is received.
+    pub fn facial_expression_changed(&mut self, expression: FacialExpression) {
+        self.contained.facial_expression = expression;
+    }
+
+    /// Request that the server change the player entity's appearance. The change is only made locally once confirmed by
+    /// the server (see [`Self::appearance_changed`]).
+    pub fn change_appearance(
+        &self, appearance: Appearance, connection: &mut networking::Connection
+    ) -> networking::Result<()> {
+        connection.send(&messages::ToServer::ChangeAppearance(appearance))
+    }
+
+    /// This method is called from the main game state whenever a [`shared::messages::FromServer::AppearanceChanged`]
+    /// message regarding the player entity is received.
+    pub fn appearance_changed(&mut self, appearance: Appearance) {
+        self.contained.set_appearance(appearance);
+    }
+
     /// Detonate all the bombs placed by the player *within currently loaded chunks.*
     pub fn detonate_bombs(
         &mut self, map: &mut ClientMap, renderer: &mut MapRenderer, connection: &mut networking::Connection
@@ -222,6 +347,10 @@ impl MyEntity {
         self.contained.bombs_placed_count
     }
 
+    pub fn get_id(&self) -> Id {
+        self.id
+    }
+
     pub fn get_contained_entity(&self) -> &Entity {
         &self.contained
     }
@@ -238,3 +367,19 @@ impl MyEntity {
         &self.contained.item_inventory
     }
 }
+
+/// Choose the type of gem that a trap is to be disguised as using the same weights as those used by the server when
+/// placing gems on the ground (see [`gems::GROUND_GEM_WEIGHTS`]).
+fn random_trap_disguise() -> Gem {
+    let total_weight: u32 = gems::GROUND_GEM_WEIGHTS.iter().map(|(_, weight)| weight).sum();
+    let mut roll = rand::gen_range(0, total_weight);
+
+    for (gem, weight) in gems::GROUND_GEM_WEIGHTS {
+        if roll < *weight {
+            return *gem;
+        }
+        roll -= weight;
+    }
+
+    unreachable!()
+}
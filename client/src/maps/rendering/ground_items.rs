@@ -0,0 +1,30 @@
+use macroquad::prelude as quad;
+use shared::{gems::Gem, items::Trap};
+
+/// Gems on the ground are drawn as diamond shapes this fraction of the size of a tile.
+const GROUND_GEM_SIZE_MULTIPLIER: f32 = 0.3;
+
+/// Traps are drawn as circle outlines this fraction of the size of a tile.
+const TRAP_SIZE_MULTIPLIER: f32 = 0.35;
+
+pub fn draw_ground_gem(draw_pos: quad::Vec2, tile_draw_size: f32, gem: Gem) {
+    let colour = match gem {
+        Gem::Emerald => quad::GREEN,
+        Gem::Ruby => quad::RED,
+        Gem::Diamond => quad::SKYBLUE
+    };
+
+    let centre = draw_pos + quad::vec2(tile_draw_size / 2.0, tile_draw_size / 2.0);
+    quad::draw_poly(centre.x, centre.y, 4, tile_draw_size * GROUND_GEM_SIZE_MULTIPLIER, 0.0, colour);
+}
+
+/// Draws a trap placed by this client's player (traps placed by other players appear as gems on the ground).
+pub fn draw_trap(draw_pos: quad::Vec2, tile_draw_size: f32, trap: Trap) {
+    let colour = match trap {
+        Trap::Speed => quad::ORANGE,
+        Trap::Theft => quad::PURPLE
+    };
+
+    let centre = draw_pos + quad::vec2(tile_draw_size / 2.0, tile_draw_size / 2.0);
+    quad::draw_circle_lines(centre.x, centre.y, tile_draw_size * TRAP_SIZE_MULTIPLIER, tile_draw_size * 0.1, colour);
+}
@@ -1,6 +1,7 @@
 mod animations;
 mod bombs;
 mod entities;
+mod ground_items;
 mod tiles;
 
 use std::collections::HashMap;
@@ -47,14 +48,7 @@ impl MapRenderer {
     pub fn draw(&mut self, map: &ClientMap, my_entity_contained: &Entity, assets: &AssetManager, delta: f32) {
         // Adjust camera zoom so that textures don't become distorted when the screen is resized:
 
-        self.camera.zoom = {
-            if quad::screen_width() > quad::screen_height() {
-                quad::vec2(1.0, quad::screen_width() / quad::screen_height())
-            }
-            else {
-                quad::vec2(quad::screen_height() / quad::screen_width(), 1.0)
-            }
-        };
+        self.camera.zoom = undistorted_camera_zoom();
 
         // Update this client's entity and centre camera around it:
 
@@ -118,9 +112,20 @@ impl MapRenderer {
             }
         }
 
-        // Draw undetonated bombs:
+        // Draw gems on the ground, traps, and undetonated bombs:
 
         for chunk in on_screen_chunk_coords.into_iter().filter_map(|coords| map.loaded_chunk_at(coords)) {
+            for (gem_coords, gem) in chunk.get_ground_gems() {
+                let draw_pos = tile_coords_to_vec2(*gem_coords, TILE_DRAW_SIZE);
+                ground_items::draw_ground_gem(draw_pos, TILE_DRAW_SIZE, *gem);
+            }
+
+            // The only traps known to this client are those placed by its own player:
+            for (trap_coords, placed_trap) in chunk.get_traps() {
+                let draw_pos = tile_coords_to_vec2(*trap_coords, TILE_DRAW_SIZE);
+                ground_items::draw_trap(draw_pos, TILE_DRAW_SIZE, placed_trap.trap);
+            }
+
             // Iterate all bomb positions within the chunk irrespective of who placed them:
             for bomb_coords in chunk.get_undetonated_bomb_positions() {
                 let draw_pos = tile_coords_to_vec2(*bomb_coords, TILE_DRAW_SIZE);
@@ -211,6 +216,12 @@ impl MapRenderer {
         self.my_entity_renderer.do_movement(correct_coords, ENTITY_POSITION_CORRECTED_MOVEMENT_TIME, 1, TILE_DRAW_SIZE);
     }
 
+    /// Instantly place this client's entity at the specified position. This method is to be called by the
+    /// [`crate::maps::entities::MyEntity::died`] method.
+    pub fn my_entity_respawned(&mut self, respawn_coords: TileCoords) {
+        self.my_entity_renderer = entities::Renderer::new(respawn_coords);
+    }
+
     /// Begin the animated movement of the specified remote entity to the given position. This method is to be called by
     /// the [`ClientMap::set_remote_entity_position`] method.
     pub fn remote_entity_moved(
@@ -243,6 +254,28 @@ impl MapRenderer {
     }
 }
 
+/// Draw the given entity at the centre of the screen at the specified size (in camera space). Used to preview changes
+/// to the appearance of this client's player entity.
+pub fn draw_entity_preview(entity: &Entity, assets: &AssetManager, draw_size: f32) {
+    quad::set_camera(quad::Camera2D { zoom: undistorted_camera_zoom(), ..Default::default() });
+
+    let mut renderer = entities::Renderer::default();
+    renderer.current_pos = quad::vec2(-draw_size / 2.0, -draw_size / 2.0);
+
+    renderer.draw_lower(entity, assets.texture(TextureKey::Entities), draw_size);
+    renderer.draw_upper(entity, assets.texture(TextureKey::Entities), draw_size);
+}
+
+/// Camera zoom that ensures textures don't become distorted when the screen is resized.
+fn undistorted_camera_zoom() -> quad::Vec2 {
+    if quad::screen_width() > quad::screen_height() {
+        quad::vec2(1.0, quad::screen_width() / quad::screen_height())
+    }
+    else {
+        quad::vec2(quad::screen_height() / quad::screen_width(), 1.0)
+    }
+}
+
 fn tile_coords_to_vec2(coords: TileCoords, tile_draw_size: f32) -> quad::Vec2 {
     quad::vec2(coords.x as f32 * tile_draw_size, coords.y as f32 * tile_draw_size)
 }
@@ -13,3 +13,11 @@ pub fn set(file_path: &str, value: &str) -> io::Result<()> {
     let mut file = fs::File::create(file_path)?;
     file.write_all(value.as_bytes())
 }
+
+/// Remove the file at the given path (should it exist).
+pub fn remove(file_path: &str) -> io::Result<()> {
+    match fs::remove_file(file_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(())
+    }
+}
@@ -4,26 +4,76 @@ mod browser;
 mod desktop;
 
 #[cfg(target_arch = "wasm32")]
-const LOCAL_STORAGE_KEY: &str = "clientid";
+const SESSION_TOKEN_LOCAL_STORAGE_KEY: &str = "sessiontoken";
 #[cfg(not(target_arch = "wasm32"))]
-const FILE_PATH: &str = "clientid.txt";
+const SESSION_TOKEN_FILE_PATH: &str = "sessiontoken.txt";
 
-use shared::Id;
+/// Where client IDs were stored before session tokens were introduced.
+#[cfg(target_arch = "wasm32")]
+const LEGACY_CLIENT_ID_LOCAL_STORAGE_KEY: &str = "clientid";
+#[cfg(not(target_arch = "wasm32"))]
+const LEGACY_CLIENT_ID_FILE_PATH: &str = "clientid.txt";
+
+use shared::{messages::Credentials, Id};
+
+/// Store the session token given by the server, replacing any previous token. Any legacy client ID is kept so that it
+/// can still be presented should the token expire before the player next connects (provided the server still accepts
+/// legacy client IDs by then).
+pub fn store_session_token(token: &str) {
+    #[cfg(target_arch = "wasm32")]
+    browser::set(SESSION_TOKEN_LOCAL_STORAGE_KEY, token);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    desktop::set(SESSION_TOKEN_FILE_PATH, token).unwrap(); // TODO: Don't just unwrap!
+}
+
+/// Retrieve the credentials to be presented to the server: the stored session token or, should there not be one, a
+/// client ID stored by a previous version of the game.
+pub fn retrieve_credentials() -> Option<Credentials> {
+    retrieve_session_token()
+        .map(Credentials::SessionToken)
+        .or_else(|| retrieve_legacy_client_id().map(Credentials::LegacyClientId))
+}
 
-pub fn store_client_id(id: Id) {
-    let encoded = id.encode();
+/// Discard the stored session token (e.g. as the server refused it) so that any legacy client ID is presented instead
+/// next time.
+pub fn clear_session_token() {
+    #[cfg(target_arch = "wasm32")]
+    browser::set(SESSION_TOKEN_LOCAL_STORAGE_KEY, "");
+
+    #[cfg(not(target_arch = "wasm32"))]
+    desktop::remove(SESSION_TOKEN_FILE_PATH).unwrap();
+}
+
+/// Discard the stored legacy client ID (i.e. once the server no longer accepts it).
+pub fn clear_legacy_client_id() {
+    #[cfg(target_arch = "wasm32")]
+    browser::set(LEGACY_CLIENT_ID_LOCAL_STORAGE_KEY, "");
 
+    #[cfg(not(target_arch = "wasm32"))]
+    desktop::remove(LEGACY_CLIENT_ID_FILE_PATH).unwrap();
+}
+
+fn retrieve_session_token() -> Option<String> {
     #[cfg(target_arch = "wasm32")]
-    browser::set(LOCAL_STORAGE_KEY, &encoded);
+    let token = browser::get(SESSION_TOKEN_LOCAL_STORAGE_KEY)?;
 
     #[cfg(not(target_arch = "wasm32"))]
-    desktop::set(FILE_PATH, &encoded).unwrap(); // TODO: Don't just unwrap!
+    let token = desktop::get(SESSION_TOKEN_FILE_PATH).ok()?;
+
+    // Cleared values are stored as empty strings in browser local storage:
+    if token.is_empty() {
+        None
+    }
+    else {
+        Some(token)
+    }
 }
 
-pub fn retrieve_client_id() -> Option<Id> {
+fn retrieve_legacy_client_id() -> Option<Id> {
     #[cfg(target_arch = "wasm32")]
-    return Id::decode(&browser::get(LOCAL_STORAGE_KEY)?);
+    return Id::decode(&browser::get(LEGACY_CLIENT_ID_LOCAL_STORAGE_KEY)?);
 
     #[cfg(not(target_arch = "wasm32"))]
-    Id::decode(&desktop::get(FILE_PATH).ok()?)
+    Id::decode(&desktop::get(LEGACY_CLIENT_ID_FILE_PATH).ok()?)
 }
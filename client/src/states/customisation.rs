@@ -0,0 +1,118 @@
+use macroquad::prelude as quad;
+use shared::maps::entities::{self, Appearance};
+
+use crate::{
+    maps::{entities::MyEntity, rendering},
+    networking, AssetManager
+};
+
+/// The width and height (in camera space) at which the preview of the player entity is drawn.
+const PREVIEW_DRAW_SIZE: f32 = 0.5;
+
+/// Labels of the aspects of the player entity's appearance that can be changed (in the order they are listed).
+const OPTION_LABELS: [&str; 3] = ["Hair style", "Hair colour", "Skin colour"];
+
+/// Allows the player to customise the appearance of their entity while previewing their changes. As the connection
+/// with the server must continue to be serviced while customising, this state is entered and exited from within
+/// [`super::game::GameState`] rather than replacing it.
+///
+/// The up and down arrow keys select an option, the left and right arrow keys cycle through its values, Enter saves
+/// the changes, and Escape discards them.
+pub struct CustomisationState {
+    /// The appearance currently being previewed.
+    appearance: Appearance,
+    /// Index of the currently selected option in [`OPTION_LABELS`].
+    selected_option: usize
+}
+
+impl CustomisationState {
+    pub fn new(my_entity: &MyEntity) -> Self {
+        CustomisationState { appearance: my_entity.get_contained_entity().appearance(), selected_option: 0 }
+    }
+
+    /// Handle keyboard input and draw the preview and options. Returns whether or not customisation has finished (in
+    /// which case this state should be exited).
+    pub fn update_and_draw(
+        &mut self, my_entity: &MyEntity, connection: &mut networking::Connection, assets: &AssetManager
+    ) -> networking::Result<bool> {
+        if quad::is_key_pressed(quad::KeyCode::Up) {
+            self.selected_option = (self.selected_option + OPTION_LABELS.len() - 1) % OPTION_LABELS.len();
+        }
+        if quad::is_key_pressed(quad::KeyCode::Down) {
+            self.selected_option = (self.selected_option + 1) % OPTION_LABELS.len();
+        }
+
+        for (key, forwards) in &[(quad::KeyCode::Left, false), (quad::KeyCode::Right, true)] {
+            if quad::is_key_pressed(*key) {
+                self.cycle_selected_option(*forwards);
+            }
+        }
+
+        if quad::is_key_pressed(quad::KeyCode::Escape) {
+            return Ok(true);
+        }
+
+        if quad::is_key_pressed(quad::KeyCode::Enter) {
+            if self.appearance != my_entity.get_contained_entity().appearance() {
+                my_entity.change_appearance(self.appearance, connection)?;
+            }
+            return Ok(true);
+        }
+
+        self.draw(my_entity, assets);
+
+        Ok(false)
+    }
+
+    fn cycle_selected_option(&mut self, forwards: bool) {
+        match self.selected_option {
+            0 => self.appearance.hair_style = entities::cycle_variant(self.appearance.hair_style, forwards),
+            1 => self.appearance.hair_colour = entities::cycle_variant(self.appearance.hair_colour, forwards),
+            _ => self.appearance.skin_colour = entities::cycle_variant(self.appearance.skin_colour, forwards)
+        }
+    }
+
+    fn draw(&self, my_entity: &MyEntity, assets: &AssetManager) {
+        quad::set_default_camera();
+        quad::draw_rectangle(
+            0.0,
+            0.0,
+            quad::screen_width(),
+            quad::screen_height(),
+            quad::Color::new(0.0, 0.0, 0.0, 0.6)
+        );
+
+        let mut preview_entity = my_entity.get_contained_entity().clone();
+        preview_entity.set_appearance(self.appearance);
+        rendering::draw_entity_preview(&preview_entity, assets, PREVIEW_DRAW_SIZE);
+
+        quad::set_default_camera();
+
+        let font_size = 32.0;
+        let x = quad::screen_width() * 0.05;
+        let mut y = quad::screen_height() * 0.3;
+
+        quad::draw_text("Customise your character", x, y, font_size * 1.25, quad::WHITE);
+        y += font_size * 2.0;
+
+        let values = [
+            self.appearance.hair_style.to_string(),
+            format!("{:?}", self.appearance.hair_colour),
+            format!("{:?}", self.appearance.skin_colour)
+        ];
+
+        for (i, (label, value)) in OPTION_LABELS.iter().zip(values.iter()).enumerate() {
+            let (text, colour) = if i == self.selected_option {
+                (format!("{}: < {} >", label, value), quad::YELLOW)
+            }
+            else {
+                (format!("{}: {}", label, value), quad::LIGHTGRAY)
+            };
+
+            quad::draw_text(&text, x, y, font_size, colour);
+            y += font_size * 1.5;
+        }
+
+        quad::draw_text("Enter to save, Escape to cancel", x, y + font_size, font_size * 0.75, quad::GRAY);
+    }
+}
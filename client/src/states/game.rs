@@ -1,10 +1,13 @@
 use macroquad::prelude as quad;
 use shared::{
-    maps::{entities::Direction, Map},
+    maps::{
+        entities::{Direction, FacialExpression},
+        Map, Tile
+    },
     messages
 };
 
-use super::State;
+use super::{customisation::CustomisationState, pregame::DisconnectedState, State};
 use crate::{
     maps::{self, entities::MyEntity, MapRenderer},
     networking::{self, ConnectionTrait},
@@ -12,6 +15,14 @@ use crate::{
     AssetManager, TextureKey
 };
 
+/// Keys used to change the player entity's facial expression.
+const EXPRESSION_KEY_BINDINGS: [(quad::KeyCode, FacialExpression); 4] = [
+    (quad::KeyCode::Key1, FacialExpression::Neutral),
+    (quad::KeyCode::Key2, FacialExpression::Angry),
+    (quad::KeyCode::Key3, FacialExpression::Shocked),
+    (quad::KeyCode::Key4, FacialExpression::Skeptical)
+];
+
 pub struct GameState {
     /// Connection with the remote server.
     connection: networking::Connection,
@@ -22,7 +33,15 @@ pub struct GameState {
     /// The rendering system used to draw the game map to the screen.
     map_renderer: MapRenderer,
     /// User interface.
-    ui: Ui
+    ui: Ui,
+    /// The reason given by the server for shutting down and the number of seconds remaining until the connection is
+    /// closed. Is `None` unless the server has indicated that it is shutting down.
+    shutdown_notice: Option<(String, f32)>,
+    /// The reason given by the server for ending this session (should it have done so) which is displayed once the
+    /// connection is closed.
+    session_end_reason_option: Option<messages::SessionEndReason>,
+    /// Is `Some` while the player is customising the appearance of their entity (entered by pressing C).
+    customisation_option: Option<CustomisationState>
 }
 
 impl GameState {
@@ -33,7 +52,10 @@ impl GameState {
             my_entity,
             map: maps::ClientMap::new(),
             map_renderer: MapRenderer::new(my_entity_pos),
-            ui: Ui::new(0.12)
+            ui: Ui::new(0.12),
+            shutdown_notice: None,
+            session_end_reason_option: None,
+            customisation_option: None
         }
     }
 
@@ -53,7 +75,12 @@ impl GameState {
             }
 
             messages::FromServer::ChangeTile(coords, tile) => {
-                if self.map.is_tile_loaded(coords) {
+                if let Some(old_tile) = self.map.loaded_tile_at(coords) {
+                    // Rocks destroyed by bomb blasts are animated in the same way as rocks smashed by entities:
+                    if old_tile.is_smashable() && tile == Tile::RockSmashed {
+                        self.map_renderer.rock_tile_smashed(coords);
+                    }
+
                     self.map.set_loaded_tile_at(coords, tile);
                 }
                 else {
@@ -87,15 +114,122 @@ impl GameState {
                 self.map.set_bomb_at(position, placed_by_entity_id);
             }
 
-            messages::FromServer::BombsDetonated { placed_by_entity_id, in_and_around_chunk_coords } => {
-                let positions =
-                    self.map.take_bombs_placed_by_in_and_around_chunk(placed_by_entity_id, in_and_around_chunk_coords);
+            messages::FromServer::BombsDetonated { placed_by_entity_id, positions } => {
+                let positions = self.map.take_bombs_placed_by_at(placed_by_entity_id, &positions);
                 self.map_renderer.bombs_detonated(positions);
             }
 
             messages::FromServer::YouCollectedGems { gem_type, quantity_increase } => {
                 self.my_entity.obtained_gems(gem_type, quantity_increase);
             }
+
+            messages::FromServer::YouLostGems { gem_type, quantity_decrease } => {
+                self.my_entity.lost_gems(gem_type, quantity_decrease);
+            }
+
+            messages::FromServer::GroundGemPlaced { position, gem } => {
+                self.map.set_ground_gem_at(position, gem);
+            }
+
+            messages::FromServer::GroundGemCollected { position, collected_by_entity_id } => {
+                log::debug!("Gem on the ground at {} collected by entity {}", position, collected_by_entity_id);
+
+                self.map.take_ground_gem_at(position);
+            }
+
+            messages::FromServer::TrapTriggered { position, trap, triggered_by_entity_id } => {
+                log::debug!("Trap {:?} at {} triggered by entity {}", trap, position, triggered_by_entity_id);
+
+                // The trap will either be known to this client (if placed by this client's player) or will appear as
+                // a gem on the ground:
+                self.map.take_trap_at(position);
+                self.map.take_ground_gem_at(position);
+            }
+
+            messages::FromServer::StatusEffectStarted { entity_id, effect } => {
+                if entity_id == self.my_entity.get_id() {
+                    self.my_entity.status_effect_started(effect);
+                }
+                else if let Some(entity) = self.map.entity_by_id_mut(entity_id) {
+                    entity.status_effects.apply(effect);
+                }
+            }
+
+            messages::FromServer::StatusEffectEnded { entity_id, effect } => {
+                if entity_id == self.my_entity.get_id() {
+                    self.my_entity.status_effect_ended(effect);
+                }
+                else if let Some(entity) = self.map.entity_by_id_mut(entity_id) {
+                    entity.status_effects.remove(effect);
+                }
+            }
+
+            messages::FromServer::YouDied { respawn_position } => {
+                self.my_entity.died(respawn_position, &mut self.map_renderer);
+            }
+
+            messages::FromServer::EntityDied { entity_id, position } => {
+                log::debug!("Entity {} died at {}", entity_id, position);
+
+                self.map_renderer.remove_remote_entity(entity_id);
+                self.map.remove_entity(entity_id);
+            }
+
+            messages::FromServer::ServerShuttingDown { reason, seconds } => {
+                log::warn!("Server shutting down in {} seconds due to: {}", seconds, reason);
+
+                self.shutdown_notice = Some((reason, seconds as f32));
+            }
+
+            messages::FromServer::SessionEnded(reason) => {
+                log::warn!("Session ended by the server: {:?}", reason);
+
+                self.session_end_reason_option = Some(reason);
+            }
+
+            messages::FromServer::ChatMessage { channel, sender_entity_id, text } => {
+                self.ui.chat_box.message_received(channel, sender_entity_id, text, self.my_entity.get_id());
+            }
+
+            messages::FromServer::ChatMessageRejected(rejection) => {
+                log::warn!("Chat message rejected by server: {:?}", rejection);
+                self.ui.chat_box.message_rejected(rejection);
+            }
+
+            messages::FromServer::FacialExpressionChanged { entity_id, expression } => {
+                if entity_id == self.my_entity.get_id() {
+                    self.my_entity.facial_expression_changed(expression);
+                }
+                else if let Some(entity) = self.map.entity_by_id_mut(entity_id) {
+                    entity.facial_expression = expression;
+                }
+            }
+
+            messages::FromServer::AppearanceChanged { entity_id, appearance } => {
+                if entity_id == self.my_entity.get_id() {
+                    self.my_entity.appearance_changed(appearance);
+                }
+                else if let Some(entity) = self.map.entity_by_id_mut(entity_id) {
+                    entity.set_appearance(appearance);
+                }
+            }
+
+            messages::FromServer::Leaderboard { top_scores, your_rank, your_score } => {
+                self.ui.leaderboard_received(ui::Leaderboard { top_scores, my_rank: your_rank, my_score: your_score });
+            }
+
+            messages::FromServer::YouTeleported { position } => {
+                self.my_entity.teleported(position, &mut self.map_renderer);
+            }
+
+            messages::FromServer::YourInventoryChanged(inventory) => {
+                self.my_entity.inventory_changed(inventory);
+            }
+
+            messages::FromServer::RateLimitWarning => {
+                log::warn!("Server is discarding messages as they are being sent too quickly");
+                self.ui.chat_box.rate_limit_warning_received();
+            }
         }
     }
 }
@@ -108,9 +242,27 @@ impl State for GameState {
     fn update_and_draw(&mut self, assets: &AssetManager, delta: f32) -> Option<Box<dyn State>> {
         self.map_renderer.draw(&self.map, self.my_entity.get_contained_entity(), assets, delta);
 
-        self.ui
-            .update_and_draw(&mut self.my_entity, &mut self.map, &mut self.map_renderer, &mut self.connection, assets)
-            .unwrap(); // TODO: Don't unwrap.
+        // The customisation screen is drawn in place of the user interface (and takes all keyboard input) while open:
+        if let Some(customisation) = &mut self.customisation_option {
+            // TODO: Don't unwrap.
+            if customisation.update_and_draw(&self.my_entity, &mut self.connection, assets).unwrap() {
+                self.customisation_option = None;
+            }
+        }
+        else {
+            self.ui
+                .update_and_draw(
+                    &mut self.my_entity,
+                    &mut self.map,
+                    &mut self.map_renderer,
+                    &mut self.connection,
+                    assets,
+                    delta
+                )
+                .unwrap(); // TODO: Don't unwrap.
+        }
+
+        let accepting_input = !self.ui.chat_box.is_typing() && self.customisation_option.is_none();
 
         #[cfg(debug_assertions)]
         ui::draw_debug_text(
@@ -121,12 +273,23 @@ impl State for GameState {
             self.map.get_loaded_chunk_coords()
         );
 
+        if let Some((reason, remaining_time)) = &mut self.shutdown_notice {
+            *remaining_time = (*remaining_time - delta).max(0.0);
+
+            let text = format!("Server shutting down in {:.0} seconds: {}", remaining_time.ceil(), reason);
+            quad::draw_text(&text, 0.0, 32.0, 32.0, quad::RED);
+        }
+
         // Player entity updates/input handling:
 
         self.my_entity.update(delta);
 
         let direction_option = {
-            if quad::is_key_down(quad::KeyCode::W) {
+            if !accepting_input {
+                // Keys pressed while typing a chat message or customising should not move the player entity:
+                None
+            }
+            else if quad::is_key_down(quad::KeyCode::W) {
                 Some(Direction::Up)
             }
             else if quad::is_key_down(quad::KeyCode::A) {
@@ -150,6 +313,29 @@ impl State for GameState {
                 .unwrap();
         }
 
+        // Number keys change the player entity's facial expression (or play an emote while Shift is held):
+
+        if accepting_input {
+            let emote = quad::is_key_down(quad::KeyCode::LeftShift) || quad::is_key_down(quad::KeyCode::RightShift);
+
+            for (key, expression) in &EXPRESSION_KEY_BINDINGS {
+                if quad::is_key_pressed(*key) {
+                    // TODO: Don't just unwrap.
+                    self.my_entity.express(*expression, emote, &mut self.connection).unwrap();
+                }
+            }
+
+            // The C key opens the customisation screen:
+            if quad::is_key_pressed(quad::KeyCode::C) {
+                self.customisation_option = Some(CustomisationState::new(&self.my_entity));
+            }
+
+            // The L key shows/hides the leaderboard:
+            if quad::is_key_pressed(quad::KeyCode::L) {
+                self.ui.toggle_leaderboard(&mut self.connection).unwrap(); // TODO: Don't unwrap.
+            }
+        }
+
         // Networking:
 
         match self.connection.receive::<messages::FromServer>() {
@@ -174,7 +360,14 @@ impl State for GameState {
                         log::error!("Connection closed by the server");
                     }
                 }
-                panic!() // TODO
+
+                let text = match (self.session_end_reason_option.take(), self.shutdown_notice.take()) {
+                    (Some(reason), _) => reason.to_string(),
+                    (None, Some((reason, _))) => format!("The server has shut down: {}", reason),
+                    (None, None) => "Lost connection to the server :(".to_string()
+                };
+
+                return Some(Box::new(DisconnectedState::new(text)));
             }
         }
 
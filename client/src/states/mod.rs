@@ -1,6 +1,7 @@
 //! Module containing all code relating to game 'states' (e.g. the main menu state, the settings state, the gameplay
 //! state, etc.)
 
+pub mod customisation;
 pub mod game;
 pub mod pregame;
 
@@ -58,12 +58,17 @@ impl State for ConnectingState {
 
 struct ConnectedState {
     connection: Option<networking::Connection>,
-    text: &'static str
+    text: &'static str,
+    /// Whether a legacy client ID (rather than a session token) was presented in the 'hello' message.
+    presented_legacy_client_id: bool
 }
 
 impl ConnectedState {
     fn new(mut connection: networking::Connection) -> Self {
-        let hello_msg = messages::ToServer::Hello { client_id_option: sessions::retrieve_client_id() };
+        let credentials_option = sessions::retrieve_credentials();
+        let presented_legacy_client_id = matches!(credentials_option, Some(messages::Credentials::LegacyClientId(_)));
+
+        let hello_msg = messages::ToServer::Hello { protocol_version: messages::PROTOCOL_VERSION, credentials_option };
 
         let text = match connection.send(&hello_msg) {
             Ok(_) => {
@@ -77,7 +82,7 @@ impl ConnectedState {
             }
         };
 
-        ConnectedState { connection: Some(connection), text }
+        ConnectedState { connection: Some(connection), text, presented_legacy_client_id }
     }
 }
 
@@ -90,38 +95,66 @@ impl State for ConnectedState {
                         messages::FromServer::Welcome {
                             version,
                             your_client_id,
+                            your_session_token,
                             your_entity_with_id: (entity_id, entity)
                         } => {
-                            log::debug!("Server version: {}", version);
+                            // The server only welcomes clients speaking a protocol version it supports so the
+                            // versions of the game are not required to match:
+                            log::debug!("Server version: {} (client version: {})", version, shared::VERSION);
 
-                            // Check version aligns with that of the version:
+                            // Save the session token (browser local storage) so that the player can continue playing as
+                            // the same character next time:
 
-                            if version == shared::VERSION {
-                                // Save the client ID (browser local storage):
+                            log::debug!("Given client ID: {}", your_client_id);
 
-                                log::debug!("Given client ID: {}", your_client_id);
+                            sessions::store_session_token(&your_session_token);
 
-                                sessions::store_client_id(your_client_id);
+                            // Enter the main game state:
 
-                                // Enter the main game state:
+                            log::debug!("Given player entity: {} - {}", entity, entity_id);
 
-                                log::debug!("Given player entity: {} - {}", entity, entity_id);
+                            let my_entity = MyEntity::new(entity, entity_id);
+                            let taken_connection = self.connection.take().unwrap();
+                            let game_state = super::game::GameState::new(taken_connection, my_entity);
 
-                                let my_entity = MyEntity::new(entity, entity_id);
-                                let taken_connection = self.connection.take().unwrap();
-                                let game_state = super::game::GameState::new(taken_connection, my_entity);
+                            return Some(Box::new(game_state));
+                        }
 
-                                return Some(Box::new(game_state));
+                        messages::FromServer::ProtocolRejected {
+                            reason,
+                            min_supported_version,
+                            max_supported_version
+                        } => {
+                            log::error!(
+                                "Server refused protocol version {} of this client (supported versions: {} to {})",
+                                messages::PROTOCOL_VERSION,
+                                min_supported_version,
+                                max_supported_version
+                            );
+
+                            match reason {
+                                messages::ProtocolRejection::ClientTooOld => self.text = WRONG_VERSION_TEXT,
+                                messages::ProtocolRejection::ClientTooNew => {
+                                    return Some(Box::new(DisconnectedState::new(reason.to_string())));
+                                }
                             }
-                            else {
-                                log::error!(
-                                    "Version of server ({}) differs from that of this client ({})",
-                                    version,
-                                    shared::VERSION
-                                );
-
-                                self.text = WRONG_VERSION_TEXT;
+                        }
+
+                        messages::FromServer::SessionEnded(reason) => {
+                            log::warn!("Server refused connection: {:?}", reason);
+
+                            // Only the refused credentials are discarded so that a legacy client ID is still presented
+                            // next time should it be a session token that was refused (e.g. as it expired):
+                            if reason == messages::SessionEndReason::InvalidCredentials {
+                                if self.presented_legacy_client_id {
+                                    sessions::clear_legacy_client_id();
+                                }
+                                else {
+                                    sessions::clear_session_token();
+                                }
                             }
+
+                            return Some(Box::new(DisconnectedState::new(reason.to_string())));
                         }
 
                         other_msg => {
@@ -148,3 +181,26 @@ impl State for ConnectedState {
         "Connected To Server"
     }
 }
+
+/// Displayed once the connection with the server has been closed or lost.
+pub struct DisconnectedState {
+    text: String
+}
+
+impl DisconnectedState {
+    pub fn new(text: String) -> Self {
+        DisconnectedState { text }
+    }
+}
+
+impl State for DisconnectedState {
+    fn update_and_draw(&mut self, _assets: &AssetManager, _delta: f32) -> Option<Box<dyn State>> {
+        quad::draw_text(&self.text, 0.0, 0.0, 32.0, quad::WHITE);
+
+        None
+    }
+
+    fn title(&self) -> &'static str {
+        "Disconnected From Server"
+    }
+}
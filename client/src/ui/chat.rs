@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+
+use macroquad::prelude as quad;
+use shared::{chat, messages, Id};
+
+use crate::networking::{self, ConnectionTrait};
+
+/// The maximum number of messages kept in the chat log.
+const MAX_LOG_ENTRIES: usize = 8;
+
+/// The number of seconds that a message remains visible in the chat log (while the player is not typing).
+const LOG_ENTRY_VISIBLE_TIME: f32 = 20.0;
+
+/// The number of seconds over which a message in the chat log fades out before disappearing.
+const LOG_ENTRY_FADE_TIME: f32 = 2.0;
+
+/// Chat message log along with a text box for sending chat messages. Pressing Enter begins typing a message (and
+/// sends it once pressed again), Tab switches between the proximity and global channels, and Escape cancels.
+pub struct ChatBox {
+    font_size: f32,
+    /// Received messages and notices (oldest first).
+    log: VecDeque<LogEntry>,
+    /// The text typed so far. Is `None` while the player is not typing a message.
+    input_option: Option<String>,
+    /// The channel that typed messages are sent on.
+    channel: chat::Channel
+}
+
+struct LogEntry {
+    text: String,
+    colour: quad::Color,
+    /// The number of seconds since this entry was added to the log.
+    age: f32
+}
+
+impl ChatBox {
+    pub fn new(font_size: f32) -> Self {
+        ChatBox { font_size, log: VecDeque::new(), input_option: None, channel: chat::Channel::Proximity }
+    }
+
+    /// Whether or not the player is currently typing a message (in which case key presses should not be handled
+    /// elsewhere, e.g. for movement).
+    pub fn is_typing(&self) -> bool {
+        self.input_option.is_some()
+    }
+
+    /// Add a chat message received from the server to the log.
+    pub fn message_received(&mut self, channel: chat::Channel, sender_entity_id: Id, text: String, my_entity_id: Id) {
+        let sender = if sender_entity_id == my_entity_id {
+            "You".to_string()
+        }
+        else {
+            // Players do not yet have names so are referred to by the start of their entity IDs:
+            format!("Player {}", &sender_entity_id.encode()[..6])
+        };
+
+        self.add_log_entry(format!("[{}] {}: {}", channel_label(channel), sender, text), channel_colour(channel));
+    }
+
+    /// Inform the player that their most recent message was not delivered.
+    pub fn message_rejected(&mut self, rejection: chat::Rejection) {
+        let text = match rejection {
+            chat::Rejection::Empty => "Message not sent as it was empty".to_string(),
+            chat::Rejection::TooLong => {
+                format!("Message not sent as it was longer than {} characters", chat::MAX_MESSAGE_LENGTH)
+            }
+            chat::Rejection::RateLimited => "Message not sent - please wait before sending another".to_string()
+        };
+
+        self.add_log_entry(text, quad::RED);
+    }
+
+    /// Inform the player that the server discarded some of the messages sent to it as they were sent too quickly.
+    pub fn rate_limit_warning_received(&mut self) {
+        self.add_log_entry(
+            "You are doing that too quickly - slow down or you will be disconnected".to_string(),
+            quad::RED
+        );
+    }
+
+    /// Handle keyboard input (sending a message to the server should one be entered) and age the log entries.
+    pub fn update(&mut self, delta: f32, connection: &mut networking::Connection) -> networking::Result<()> {
+        for entry in &mut self.log {
+            entry.age += delta;
+        }
+
+        if let Some(input) = &mut self.input_option {
+            while let Some(c) = quad::get_char_pressed() {
+                if !c.is_control() && input.chars().count() < chat::MAX_MESSAGE_LENGTH {
+                    input.push(c);
+                }
+            }
+
+            if quad::is_key_pressed(quad::KeyCode::Backspace) {
+                input.pop();
+            }
+
+            if quad::is_key_pressed(quad::KeyCode::Tab) {
+                self.channel = match self.channel {
+                    chat::Channel::Proximity => chat::Channel::Global,
+                    chat::Channel::Global => chat::Channel::Proximity
+                };
+            }
+
+            if quad::is_key_pressed(quad::KeyCode::Escape) {
+                self.input_option = None;
+            }
+            else if quad::is_key_pressed(quad::KeyCode::Enter) {
+                let text = chat::sanitise(input);
+                self.input_option = None;
+
+                if chat::validate(&text).is_ok() {
+                    connection.send(&messages::ToServer::SendChatMessage { channel: self.channel, text })?;
+                }
+            }
+        }
+        else if quad::is_key_pressed(quad::KeyCode::Enter) {
+            // Discard characters typed before the text box was opened:
+            while quad::get_char_pressed().is_some() {}
+
+            self.input_option = Some(String::new());
+        }
+
+        Ok(())
+    }
+
+    pub fn draw(&self) {
+        let x = quad::screen_width() * 0.55;
+        let line_height = self.font_size * 1.2;
+        let mut y = quad::screen_height() * 0.05;
+
+        for entry in &self.log {
+            // Entries fade out once old unless the player is typing:
+            let alpha = if self.is_typing() {
+                1.0
+            }
+            else {
+                ((LOG_ENTRY_VISIBLE_TIME - entry.age) / LOG_ENTRY_FADE_TIME).min(1.0)
+            };
+
+            if alpha > 0.0 {
+                let colour = quad::Color::new(entry.colour.r, entry.colour.g, entry.colour.b, alpha);
+                quad::draw_text(&entry.text, x, y, self.font_size, colour);
+            }
+
+            y += line_height;
+        }
+
+        if let Some(input) = &self.input_option {
+            let width = quad::screen_width() * 0.43;
+            quad::draw_rectangle(x, y - self.font_size, width, line_height, quad::Color::new(0.0, 0.0, 0.0, 0.5));
+
+            let text = format!("[{}] {}_", channel_label(self.channel), input);
+            quad::draw_text(&text, x, y, self.font_size, channel_colour(self.channel));
+        }
+    }
+
+    fn add_log_entry(&mut self, text: String, colour: quad::Color) {
+        if self.log.len() == MAX_LOG_ENTRIES {
+            self.log.pop_front();
+        }
+
+        self.log.push_back(LogEntry { text, colour, age: 0.0 });
+    }
+}
+
+fn channel_label(channel: chat::Channel) -> &'static str {
+    match channel {
+        chat::Channel::Proximity => "Nearby",
+        chat::Channel::Global => "Global"
+    }
+}
+
+fn channel_colour(channel: chat::Channel) -> quad::Color {
+    match channel {
+        chat::Channel::Proximity => quad::WHITE,
+        chat::Channel::Global => quad::YELLOW
+    }
+}
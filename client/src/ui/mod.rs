@@ -1,15 +1,19 @@
+mod chat;
 mod widgets;
 
+pub use chat::ChatBox;
 use macroquad::prelude as quad;
 use shared::{
     items,
-    maps::{entities::Entity, ChunkCoords}
+    maps::{entities::Entity, ChunkCoords},
+    messages, Id
 };
 use widgets::Button;
 
 use crate::{
     maps::{entities::MyEntity, rendering::MapRenderer, ClientMap},
-    networking, AssetManager
+    networking::{self, ConnectionTrait},
+    AssetManager
 };
 
 pub struct Ui {
@@ -18,9 +22,26 @@ pub struct Ui {
     show_purchase_buttons_button: widgets::SimpleButton,
     place_bomb_button: widgets::QuantityButton,
     detonate_bombs_button: widgets::QuantityButton,
+    consume_energy_drink_button: widgets::QuantityButton,
+    place_speed_trap_button: widgets::QuantityButton,
+    place_theft_trap_button: widgets::QuantityButton,
     showing_purchase_buttons: bool,
     bool_item_purchase_buttons: Vec<widgets::PurchaseButton<items::BoolItem>>,
-    quantitative_item_purchase_buttons: Vec<widgets::PurchaseButton<items::QuantitativeItem>>
+    quantitative_item_purchase_buttons: Vec<widgets::PurchaseButton<items::QuantitativeItem>>,
+    pub chat_box: ChatBox,
+    /// Whether or not the leaderboard is being shown (in which case the server sends updates to it).
+    showing_leaderboard: bool,
+    /// The most recent leaderboard received from the server. Is `None` while the leaderboard is hidden or until it is
+    /// first received.
+    leaderboard_option: Option<Leaderboard>
+}
+
+/// The players with the highest scores along with the rank and score of this client's player, as provided by the
+/// server in a [`shared::messages::FromServer::Leaderboard`] message.
+pub struct Leaderboard {
+    pub top_scores: Vec<(Id, u64)>,
+    pub my_rank: u32,
+    pub my_score: u64
 }
 
 impl Ui {
@@ -31,6 +52,9 @@ impl Ui {
             show_purchase_buttons_button: widgets::SimpleButton::new(-0.425, 0.4, 2, 4),
             place_bomb_button: widgets::QuantityButton::new(0.425, 0.4, 2, 6),
             detonate_bombs_button: widgets::QuantityButton::new(0.325, 0.4, 4, 6),
+            consume_energy_drink_button: widgets::QuantityButton::new(0.225, 0.4, 6, 4),
+            place_speed_trap_button: widgets::QuantityButton::new(0.425, 0.3, 6, 6),
+            place_theft_trap_button: widgets::QuantityButton::new(0.325, 0.3, 0, 8),
             showing_purchase_buttons: false,
             bool_item_purchase_buttons: vec![widgets::PurchaseButton::new(
                 -0.32,
@@ -39,19 +63,40 @@ impl Ui {
                 0,
                 items::BoolItem::RunningShoes
             )],
-            quantitative_item_purchase_buttons: vec![widgets::PurchaseButton::new(
-                -0.24,
-                0.4,
-                6,
-                2,
-                items::QuantitativeItem::Bomb
-            )]
+            quantitative_item_purchase_buttons: vec![
+                widgets::PurchaseButton::new(-0.24, 0.4, 6, 2, items::QuantitativeItem::Bomb),
+                widgets::PurchaseButton::new(-0.16, 0.4, 6, 4, items::QuantitativeItem::EnergyDrink),
+                widgets::PurchaseButton::new(-0.08, 0.4, 6, 6, items::QuantitativeItem::SpeedTrap),
+                widgets::PurchaseButton::new(0.0, 0.4, 0, 8, items::QuantitativeItem::TheftTrap),
+            ],
+            chat_box: ChatBox::new(24.0),
+            showing_leaderboard: false,
+            leaderboard_option: None
+        }
+    }
+
+    /// Show or hide the leaderboard, informing the server so that updates to it are only sent while it is shown.
+    pub fn toggle_leaderboard(&mut self, connection: &mut networking::Connection) -> networking::Result<()> {
+        self.showing_leaderboard = !self.showing_leaderboard;
+
+        if !self.showing_leaderboard {
+            self.leaderboard_option = None;
+        }
+
+        connection.send(&messages::ToServer::WatchLeaderboard(self.showing_leaderboard))
+    }
+
+    /// Replace the displayed leaderboard with the given one received from the server.
+    pub fn leaderboard_received(&mut self, leaderboard: Leaderboard) {
+        // Ignore any update sent before the server was informed that the leaderboard was hidden:
+        if self.showing_leaderboard {
+            self.leaderboard_option = Some(leaderboard);
         }
     }
 
     pub fn update_and_draw(
         &mut self, player: &mut MyEntity, map: &mut ClientMap, map_renderer: &mut MapRenderer,
-        connection: &mut networking::Connection, assets: &AssetManager
+        connection: &mut networking::Connection, assets: &AssetManager, delta: f32
     ) -> networking::Result<()> {
         // Set bomb button quantity meter based on how many bombs the player has in their inventory:
         self.place_bomb_button.quantity = player.get_inventory().has_how_many(items::QuantitativeItem::Bomb);
@@ -59,6 +104,14 @@ impl Ui {
         // Set detonate bomb button quantity meter based on how many bombs the player has placed in the world:
         self.detonate_bombs_button.quantity = player.how_many_bombs_placed() as u32;
 
+        // Set energy drink button quantity meter based on how many energy drinks the player has:
+        self.consume_energy_drink_button.quantity =
+            player.get_inventory().has_how_many(items::QuantitativeItem::EnergyDrink);
+
+        // Set trap button quantity meters based on how many of each trap the player has:
+        self.place_speed_trap_button.quantity = player.get_inventory().has_how_many(items::QuantitativeItem::SpeedTrap);
+        self.place_theft_trap_button.quantity = player.get_inventory().has_how_many(items::QuantitativeItem::TheftTrap);
+
         if self.show_purchase_buttons_button.update(self.large_button_size) {
             // Toggle visibility of item purchase buttons:
             self.showing_purchase_buttons = !self.showing_purchase_buttons;
@@ -74,6 +127,18 @@ impl Ui {
             player.detonate_bombs(map, map_renderer, connection)?;
         }
 
+        if self.consume_energy_drink_button.update(self.large_button_size) {
+            player.consume_energy_drink(connection)?;
+        }
+
+        if self.place_speed_trap_button.update(self.large_button_size) {
+            player.place_trap(items::Trap::Speed, map, connection)?;
+        }
+
+        if self.place_theft_trap_button.update(self.large_button_size) {
+            player.place_trap(items::Trap::Theft, map, connection)?;
+        }
+
         if self.showing_purchase_buttons {
             for btn in &mut self.bool_item_purchase_buttons {
                 if btn.update(self.small_button_size) {
@@ -88,12 +153,24 @@ impl Ui {
             }
         }
 
+        self.chat_box.update(delta, connection)?;
+
         quad::set_default_camera();
 
         widgets::menus::draw_gem_collection_menu(-0.425, -0.38, 0.1, player.get_gem_collection(), assets);
 
-        let large_buttons: &[&dyn Button] =
-            &[&self.show_purchase_buttons_button, &self.place_bomb_button, &self.detonate_bombs_button];
+        if let Some(leaderboard) = &self.leaderboard_option {
+            widgets::menus::draw_leaderboard_menu(-0.34, 0.0, 0.25, leaderboard, player.get_id());
+        }
+
+        let large_buttons: &[&dyn Button] = &[
+            &self.show_purchase_buttons_button,
+            &self.place_bomb_button,
+            &self.detonate_bombs_button,
+            &self.consume_energy_drink_button,
+            &self.place_speed_trap_button,
+            &self.place_theft_trap_button
+        ];
 
         for large_btn in large_buttons {
             large_btn.draw(assets, self.large_button_size);
@@ -108,6 +185,8 @@ impl Ui {
             }
         }
 
+        self.chat_box.draw();
+
         Ok(())
     }
 }
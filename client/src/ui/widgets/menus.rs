@@ -1,7 +1,10 @@
 use macroquad::prelude as quad;
-use shared::gems::{self, Gem};
+use shared::{
+    gems::{self, Gem},
+    Id
+};
 
-use crate::{AssetManager, TextureKey};
+use crate::{ui::Leaderboard, AssetManager, TextureKey};
 
 const GEM_COLLECTION_TEXTURE_SOURCE: quad::Rect =
     crate::make_texture_source_rect(super::UI_TEXTURE_TILE_SIZE, (0, 3), (2, 3));
@@ -35,4 +38,38 @@ pub fn draw_gem_collection_menu(x: f32, y: f32, width: f32, gem_collection: &gem
     }
 }
 
-// pub fn draw_leaderboard_menu
+/// Draws the players with the highest scores along with this client's player's rank and score. The given width is a
+/// proportion of the screen width while the height depends on the number of players listed.
+pub fn draw_leaderboard_menu(x: f32, y: f32, width: f32, leaderboard: &Leaderboard, my_entity_id: Id) {
+    let draw_width = quad::screen_width() * width;
+    let font_size = draw_width * 0.08;
+    let line_height = font_size * 1.3;
+    let draw_height = line_height * (leaderboard.top_scores.len() as f32 + 3.5);
+
+    let (draw_x, draw_y) = super::calculate_draw_position(x, y, draw_width, draw_height);
+
+    quad::draw_rectangle(draw_x, draw_y, draw_width, draw_height, quad::Color::new(0.0, 0.0, 0.0, 0.6));
+
+    let text_x = draw_x + (font_size * 0.5);
+    let mut text_y = draw_y + line_height;
+
+    quad::draw_text("Leaderboard", text_x, text_y, font_size * 1.2, quad::GOLD);
+    text_y += line_height * 1.5;
+
+    for (i, (entity_id, score)) in leaderboard.top_scores.iter().enumerate() {
+        let (name, colour) = if *entity_id == my_entity_id {
+            ("You".to_string(), quad::YELLOW)
+        }
+        else {
+            // Players do not yet have names so are referred to by the start of their entity IDs:
+            (format!("Player {}", &entity_id.encode()[..6]), quad::WHITE)
+        };
+
+        quad::draw_text(&format!("{:2}. {}", i + 1, name), text_x, text_y, font_size, colour);
+        quad::draw_text(&score.to_string(), draw_x + (draw_width * 0.72), text_y, font_size, colour);
+        text_y += line_height;
+    }
+
+    let text = format!("Your rank: #{} ({} points)", leaderboard.my_rank, leaderboard.my_score);
+    quad::draw_text(&text, text_x, text_y + (line_height * 0.5), font_size, quad::YELLOW);
+}
@@ -0,0 +1,3 @@
+INSERT INTO banned_clients (client_id, banned_at)
+VALUES ($1, $2)
+ON CONFLICT (client_id) DO NOTHING
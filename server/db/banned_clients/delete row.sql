@@ -0,0 +1 @@
+DELETE FROM banned_clients WHERE client_id = $1
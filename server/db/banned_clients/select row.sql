@@ -0,0 +1 @@
+SELECT banned_at FROM banned_clients WHERE client_id = $1
@@ -0,0 +1,2 @@
+DELETE FROM client_entities
+WHERE entity_id = $1 AND last_seen < $2
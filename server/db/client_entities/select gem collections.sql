@@ -0,0 +1 @@
+SELECT entity_id, gem_collection FROM client_entities
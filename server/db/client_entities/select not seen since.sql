@@ -0,0 +1 @@
+SELECT entity_id, last_seen FROM client_entities WHERE last_seen < $1
@@ -0,0 +1,3 @@
+UPDATE client_entities
+SET last_seen = $1
+WHERE entity_id = $2
@@ -0,0 +1 @@
+SELECT chunk_x, chunk_y FROM map_chunks
@@ -10,4 +10,17 @@ CREATE TABLE IF NOT EXISTS client_entities (
     gem_collection BYTEA,
     item_inventory BYTEA,
     bombs_placed_count INTEGER NOT NULL
-)
+);
+
+CREATE TABLE IF NOT EXISTS map (
+    single_row BOOLEAN PRIMARY KEY DEFAULT TRUE,
+    seed INTEGER NOT NULL,
+    CONSTRAINT single_row_constraint CHECK (single_row)
+);
+
+CREATE TABLE IF NOT EXISTS map_chunks (
+    chunk_x INTEGER NOT NULL,
+    chunk_y INTEGER NOT NULL,
+    data BYTEA,
+    PRIMARY KEY (chunk_x, chunk_y)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE client_entities
+ADD COLUMN IF NOT EXISTS last_seen BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM NOW())::BIGINT
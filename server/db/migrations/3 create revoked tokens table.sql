@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    client_id TEXT PRIMARY KEY,
+    revoked_at BIGINT NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS banned_clients (
+    client_id TEXT PRIMARY KEY,
+    banned_at BIGINT NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE map_chunks
+ADD COLUMN IF NOT EXISTS encoding SMALLINT NOT NULL DEFAULT 0
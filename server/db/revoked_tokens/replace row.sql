@@ -0,0 +1,4 @@
+INSERT INTO revoked_tokens (client_id, revoked_at)
+VALUES ($1, $2)
+ON CONFLICT (client_id) DO UPDATE
+    SET revoked_at = $2
@@ -0,0 +1 @@
+SELECT revoked_at FROM revoked_tokens WHERE client_id = $1
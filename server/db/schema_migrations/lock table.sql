@@ -0,0 +1 @@
+LOCK TABLE schema_migrations IN EXCLUSIVE MODE
@@ -0,0 +1,115 @@
+//! Record of every command performed by the server's operators. Each entry is appended to the audit log file as a
+//! single line of JSON (and also written to the server's log).
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path
+};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use super::Command;
+use crate::storage;
+
+/// Appends entries to the audit log file.
+pub struct AuditLog {
+    file: Mutex<File>
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    /// The time (as a Unix timestamp in seconds) at which the command was performed.
+    timestamp: i64,
+    /// Where the command came from (e.g. 'console').
+    source: &'a str,
+    command: String,
+    outcome: Outcome,
+    error: Option<String>
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Succeeded,
+    /// The command was passed on to be performed by another task (see [`Command::is_request`]) so whether it then
+    /// succeeded is not known.
+    Requested,
+    Failed
+}
+
+impl AuditLog {
+    /// Open the audit log file at the given path (created should it not yet exist) so that entries are appended to
+    /// it.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog { file: Mutex::new(file) })
+    }
+
+    /// Record that the given command from the given source was performed (or requested) with the given result.
+    pub fn record<T>(&self, source: &str, command: &Command, result: &super::Result<T>) {
+        let outcome = match result {
+            Ok(_) if command.is_request() => Outcome::Requested,
+            Ok(_) => Outcome::Succeeded,
+            Err(_) => Outcome::Failed
+        };
+
+        let entry = Entry {
+            timestamp: storage::current_timestamp(),
+            source,
+            command: command.to_string(),
+            outcome,
+            error: result.as_ref().err().map(ToString::to_string)
+        };
+
+        match (&entry.outcome, &entry.error) {
+            (_, Some(e)) => log::info!("Admin command '{}' from {} failed - {}", entry.command, source, e),
+            (Outcome::Requested, None) => log::info!("Admin command '{}' from {} requested", entry.command, source),
+            _ => log::info!("Admin command '{}' from {} succeeded", entry.command, source)
+        }
+
+        let mut line = serde_json::to_string(&entry).unwrap();
+        line.push('\n');
+
+        if let Err(e) = self.file.lock().write_all(line.as_bytes()) {
+            log::error!("Failed to write to admin audit log - {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_appended() {
+        let path = std::env::temp_dir().join(format!("gemgame-test-audit-{:016x}", rand::random::<u64>()));
+
+        let audit_log = AuditLog::open(&path).unwrap();
+        audit_log.record::<()>("console", &Command::Save, &Ok(()));
+        audit_log.record::<()>("http", &Command::Players, &Err(super::super::Error::SaveFailed(2)));
+        let action = super::super::PlayerAction::Teleport(shared::maps::TileCoords { x: 1, y: 2 });
+        audit_log.record::<()>("http", &Command::Player { entity_id: crate::id::generate_random(), action }, &Ok(()));
+        drop(audit_log);
+
+        // Reopening should append to rather than replace the existing entries:
+        AuditLog::open(&path).unwrap().record::<()>("console", &Command::Chunks, &Ok(()));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let entries: Vec<serde_json::Value> =
+            contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0]["command"], "save");
+        assert_eq!(entries[0]["outcome"], "succeeded");
+        assert_eq!(entries[1]["source"], "http");
+        assert_eq!(entries[1]["outcome"], "failed");
+        assert!(entries[1]["error"].is_string());
+        assert_eq!(entries[2]["outcome"], "requested");
+        assert!(entries[2]["error"].is_null());
+        assert_eq!(entries[3]["command"], "chunks");
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
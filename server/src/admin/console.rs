@@ -0,0 +1,38 @@
+//! Interactive console through which the server's operators enter commands on the server's standard input.
+
+use std::sync::Arc;
+
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+
+use super::{Admin, Command};
+
+/// Read commands from standard input (one per line) and perform them, printing the output of each, until standard
+/// input is closed.
+pub async fn run(admin: Arc<Admin>) {
+    let mut lines = BufReader::new(io::stdin()).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                log::error!("Failed to read from admin console - {}", e);
+                break;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match line.parse::<Command>() {
+            Ok(command) => match admin.execute(command, "console").await {
+                Ok(output) => println!("{}", output),
+                Err(e) => println!("Error: {}", e)
+            },
+            Err(e) => println!("{} (enter 'help' for a list of commands)", e)
+        }
+    }
+
+    log::info!("Admin console closed");
+}
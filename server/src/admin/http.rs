@@ -0,0 +1,144 @@
+//! HTTP API through which the server's operators (or their tools) perform commands. The API is only served on the
+//! loopback interface so is only reachable from the machine running the server. Responses are plain text (the same
+//! output as shown in the console).
+//!
+//! - `GET /players` lists the connected players.
+//! - `GET /chunks` lists the loaded chunks.
+//! - `POST /commands` performs the command given as the request body (e.g. `kick <client ID>`).
+
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    body::HttpBody,
+    header::HeaderValue,
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode
+};
+
+use super::{Admin, Command, Error};
+
+/// The maximum size (in bytes) of a request body.
+const MAX_BODY_SIZE: usize = 1024;
+
+/// Serve the API on the given port of the loopback interface until the server stops.
+pub async fn serve(admin: Arc<Admin>, port: u16) {
+    let address = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let make_service = make_service_fn(move |connection: &AddrStream| {
+        let admin = Arc::clone(&admin);
+        let remote_address = connection.remote_addr();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let admin = Arc::clone(&admin);
+                async move { Ok::<_, Infallible>(handle_request(&admin, request, remote_address, port).await) }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            log::error!("Failed to bind admin HTTP API to address {} - {}", address, e);
+            return;
+        }
+    };
+
+    log::info!("Serving admin HTTP API at: http://{}", address);
+
+    if let Err(e) = server.await {
+        log::error!("Admin HTTP API stopped - {}", e);
+    }
+}
+
+async fn handle_request(
+    admin: &Admin, request: Request<Body>, remote_address: SocketAddr, port: u16
+) -> Response<Body> {
+    // Requests made by web pages carry an 'Origin' header and are refused so that a page visited in a browser on the
+    // server's machine cannot perform commands. The 'Host' header must also name the loopback interface so that a page
+    // whose domain has been made to resolve to the loopback address (DNS rebinding) cannot make same-origin requests:
+    if !remote_address.ip().is_loopback()
+        || request.headers().contains_key(hyper::header::ORIGIN)
+        || !is_loopback_host(request.headers().get(hyper::header::HOST), port)
+    {
+        return respond(StatusCode::FORBIDDEN, "Forbidden".to_string());
+    }
+
+    let command = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/players") => Command::Players,
+        (&Method::GET, "/chunks") => Command::Chunks,
+        (&Method::POST, "/commands") => {
+            let body = match read_body(request.into_body()).await {
+                Some(body) => body,
+                None => return respond(StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large".to_string())
+            };
+
+            match body.parse() {
+                Ok(command) => command,
+                Err(e) => return respond(StatusCode::BAD_REQUEST, e)
+            }
+        }
+        _ => return respond(StatusCode::NOT_FOUND, "Not found".to_string())
+    };
+
+    let source = format!("http ({})", remote_address);
+
+    match admin.execute(command, &source).await {
+        Ok(output) => respond(StatusCode::OK, output),
+        Err(e) => {
+            let status = match e {
+                Error::PlayerNotOnline(_) | Error::ClientNotConnected(_) => StatusCode::NOT_FOUND,
+                Error::SaveFailed(_) | Error::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR
+            };
+            respond(status, e.to_string())
+        }
+    }
+}
+
+/// Whether the given 'Host' header value is `127.0.0.1` or `localhost` with the given port.
+fn is_loopback_host(host_option: Option<&HeaderValue>, port: u16) -> bool {
+    match host_option.and_then(|host| host.to_str().ok()) {
+        Some(host) => host == format!("127.0.0.1:{}", port) || host == format!("localhost:{}", port),
+        None => false
+    }
+}
+
+/// Read the given request body as text, returning `None` should it exceed [`MAX_BODY_SIZE`]. Invalid UTF-8 is replaced
+/// (and so results in a command that fails to parse).
+async fn read_body(mut body: Body) -> Option<String> {
+    let mut bytes = Vec::new();
+
+    while let Some(chunk_result) = body.data().await {
+        let chunk = chunk_result.ok()?;
+
+        if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+            return None;
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn respond(status: StatusCode, text: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(text + "\n"));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_hosts() {
+        assert!(is_loopback_host(Some(&HeaderValue::from_static("127.0.0.1:8081")), 8081));
+        assert!(is_loopback_host(Some(&HeaderValue::from_static("localhost:8081")), 8081));
+
+        assert!(!is_loopback_host(Some(&HeaderValue::from_static("localhost:8080")), 8081));
+        assert!(!is_loopback_host(Some(&HeaderValue::from_static("localhost")), 8081));
+        assert!(!is_loopback_host(Some(&HeaderValue::from_static("attacker.example:8081")), 8081));
+        assert!(!is_loopback_host(None, 8081));
+    }
+}
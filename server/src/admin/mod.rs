@@ -0,0 +1,390 @@
+//! Administration of the live game by the server's operators. The same [`Command`]s may be entered into the interactive
+//! console (see [`console`]) or sent to the local-only HTTP API (see [`http`]) and every command performed is recorded
+//! in the audit log (see [`audit`]).
+//!
+//! Actions on a player entity (e.g. teleporting it) are only possible while its player is connected. They are sent
+//! on the map changes broadcast channel and performed by the task responsible for that player entity (in the same way
+//! as the effects of bomb blasts).
+
+pub mod audit;
+pub mod console;
+pub mod http;
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use shared::{gems::Gem, items::QuantitativeItem, maps::TileCoords, messages::SessionEndReason, Id};
+use strum::IntoEnumIterator;
+use tokio::sync::broadcast;
+
+use crate::{
+    handling::SharedState,
+    maps::{self, persistence, ServerMap},
+    sessions::ClientRegistry,
+    storage::{self, Storage},
+    Shared
+};
+
+/// Usage of every command (shown by the 'help' command).
+const HELP_TEXT: &str = "Commands:
+  help                                          Show this list of commands
+  players                                       List the connected players
+  chunks                                        List the loaded chunks and how many clients have each loaded
+  save                                          Save all loaded chunks and player entities
+  teleport <entity ID> <x> <y>                  Move a player entity to (or near to) the given tile coordinates
+  give-gems <entity ID> <gem> <quantity>        Give gems (emerald, ruby or diamond) to a player
+  take-gems <entity ID> <gem> <quantity>        Take gems from a player
+  give-items <entity ID> <item> <quantity>      Give items (bomb, energy-drink, speed-trap or theft-trap) to a player
+  take-items <entity ID> <item> <quantity>      Take items from a player
+  kick <client ID>                              Disconnect a client
+  ban <client ID>                               Disconnect a client and refuse its future connections
+  unban <client ID>                             Lift the ban of a client";
+
+/// An action performed on a connected player's entity by the task responsible for that player entity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayerAction {
+    /// Move the player entity to the suitable position nearest to the given position.
+    Teleport(TileCoords),
+    GiveGems(Gem, u32),
+    /// Take up to the given quantity of gems (players are never left with a negative quantity).
+    TakeGems(Gem, u32),
+    GiveItems(QuantitativeItem, u32),
+    /// Take up to the given quantity of items (players are never left with a negative quantity).
+    TakeItems(QuantitativeItem, u32)
+}
+
+impl fmt::Display for PlayerAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlayerAction::Teleport(position) => write!(f, "teleport to {}", position),
+            PlayerAction::GiveGems(gem, quantity) => write!(f, "give {} {:?} gems", quantity, gem),
+            PlayerAction::TakeGems(gem, quantity) => write!(f, "take {} {:?} gems", quantity, gem),
+            PlayerAction::GiveItems(item, quantity) => write!(f, "give {} {:?} items", quantity, item),
+            PlayerAction::TakeItems(item, quantity) => write!(f, "take {} {:?} items", quantity, item)
+        }
+    }
+}
+
+/// A command entered by one of the server's operators.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Help,
+    Players,
+    Chunks,
+    Save,
+    Player { entity_id: Id, action: PlayerAction },
+    Kick(Id),
+    Ban(Id),
+    Unban(Id)
+}
+
+impl Command {
+    /// Whether the command is only passed on to be performed later by another task (i.e. actions on a player entity,
+    /// which are performed by the task responsible for that player entity) rather than performed immediately.
+    pub fn is_request(&self) -> bool {
+        matches!(self, Command::Player { .. })
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (*name, args),
+            None => return Err("No command given".to_string())
+        };
+
+        let expected_args = match name {
+            "help" | "players" | "chunks" | "save" => 0,
+            "kick" | "ban" | "unban" => 1,
+            "teleport" | "give-gems" | "take-gems" | "give-items" | "take-items" => 3,
+            _ => return Err(format!("Unknown command '{}'", name))
+        };
+
+        if args.len() != expected_args {
+            return Err(format!(
+                "Command '{}' expects {} arguments but {} were given",
+                name,
+                expected_args,
+                args.len()
+            ));
+        }
+
+        let command = match name {
+            "help" => Command::Help,
+            "players" => Command::Players,
+            "chunks" => Command::Chunks,
+            "save" => Command::Save,
+            "kick" => Command::Kick(parse_id(args[0])?),
+            "ban" => Command::Ban(parse_id(args[0])?),
+            "unban" => Command::Unban(parse_id(args[0])?),
+            _ => {
+                let action = match name {
+                    "teleport" => {
+                        PlayerAction::Teleport(TileCoords { x: parse_number(args[1])?, y: parse_number(args[2])? })
+                    }
+                    "give-gems" => PlayerAction::GiveGems(parse_gem(args[1])?, parse_number(args[2])?),
+                    "take-gems" => PlayerAction::TakeGems(parse_gem(args[1])?, parse_number(args[2])?),
+                    "give-items" => PlayerAction::GiveItems(parse_item(args[1])?, parse_number(args[2])?),
+                    _ => PlayerAction::TakeItems(parse_item(args[1])?, parse_number(args[2])?)
+                };
+                Command::Player { entity_id: parse_id(args[0])?, action }
+            }
+        };
+
+        Ok(command)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Help => write!(f, "help"),
+            Command::Players => write!(f, "players"),
+            Command::Chunks => write!(f, "chunks"),
+            Command::Save => write!(f, "save"),
+            Command::Player { entity_id, action } => {
+                let id = entity_id.encode();
+                match action {
+                    PlayerAction::Teleport(position) => write!(f, "teleport {} {} {}", id, position.x, position.y),
+                    PlayerAction::GiveGems(gem, quantity) => {
+                        write!(f, "give-gems {} {} {}", id, gem_name(*gem), quantity)
+                    }
+                    PlayerAction::TakeGems(gem, quantity) => {
+                        write!(f, "take-gems {} {} {}", id, gem_name(*gem), quantity)
+                    }
+                    PlayerAction::GiveItems(item, quantity) => {
+                        write!(f, "give-items {} {} {}", id, item_name(*item), quantity)
+                    }
+                    PlayerAction::TakeItems(item, quantity) => {
+                        write!(f, "take-items {} {} {}", id, item_name(*item), quantity)
+                    }
+                }
+            }
+            Command::Kick(client_id) => write!(f, "kick {}", client_id.encode()),
+            Command::Ban(client_id) => write!(f, "ban {}", client_id.encode()),
+            Command::Unban(client_id) => write!(f, "unban {}", client_id.encode())
+        }
+    }
+}
+
+fn parse_id(s: &str) -> std::result::Result<Id, String> {
+    Id::decode(s).ok_or_else(|| format!("'{}' is not a valid ID", s))
+}
+
+fn parse_number<T: FromStr>(s: &str) -> std::result::Result<T, String> {
+    s.parse().map_err(|_| format!("'{}' is not a valid number", s))
+}
+
+fn gem_name(gem: Gem) -> String {
+    format!("{:?}", gem).to_lowercase()
+}
+
+fn parse_gem(s: &str) -> std::result::Result<Gem, String> {
+    Gem::iter().find(|gem| gem_name(*gem) == s).ok_or_else(|| format!("Unknown gem '{}'", s))
+}
+
+const ITEMS: [QuantitativeItem; 4] =
+    [QuantitativeItem::Bomb, QuantitativeItem::EnergyDrink, QuantitativeItem::SpeedTrap, QuantitativeItem::TheftTrap];
+
+fn item_name(item: QuantitativeItem) -> &'static str {
+    match item {
+        QuantitativeItem::Bomb => "bomb",
+        QuantitativeItem::EnergyDrink => "energy-drink",
+        QuantitativeItem::SpeedTrap => "speed-trap",
+        QuantitativeItem::TheftTrap => "theft-trap"
+    }
+}
+
+fn parse_item(s: &str) -> std::result::Result<QuantitativeItem, String> {
+    ITEMS.iter().copied().find(|item| item_name(*item) == s).ok_or_else(|| format!("Unknown item '{}'", s))
+}
+
+/// Performs the commands of the server's operators. Shared between the console and the HTTP API.
+pub struct Admin {
+    game_map: Shared<ServerMap>,
+    storage: Arc<dyn Storage>,
+    map_changes_sender: broadcast::Sender<maps::MapChange>,
+    client_registry: Arc<ClientRegistry>,
+    persistence_counters: Arc<persistence::Counters>,
+    audit_log: audit::AuditLog
+}
+
+impl Admin {
+    pub fn new(
+        shared_state: &SharedState, persistence_counters: Arc<persistence::Counters>, audit_log: audit::AuditLog
+    ) -> Self {
+        Admin {
+            game_map: Arc::clone(&shared_state.game_map),
+            storage: Arc::clone(&shared_state.storage),
+            map_changes_sender: shared_state.map_changes_sender.clone(),
+            client_registry: Arc::clone(&shared_state.client_registry),
+            persistence_counters,
+            audit_log
+        }
+    }
+
+    /// Perform the given command, recording it in the audit log along with the given description of where the command
+    /// came from (e.g. 'console'). Returns the text to be shown to the operator.
+    pub async fn execute(&self, command: Command, source: &str) -> Result<String> {
+        let result = self.perform(&command).await;
+        self.audit_log.record(source, &command, &result);
+        result
+    }
+
+    async fn perform(&self, command: &Command) -> Result<String> {
+        match command {
+            Command::Help => Ok(HELP_TEXT.to_string()),
+
+            Command::Players => Ok(self.list_players()),
+
+            Command::Chunks => Ok(self.list_chunks()),
+
+            Command::Save => {
+                self.game_map.lock().mark_all_dirty();
+
+                match persistence::flush(&self.game_map, self.storage.as_ref(), &self.persistence_counters).await {
+                    0 => Ok("Saved all loaded chunks and player entities".to_string()),
+                    failures => Err(Error::SaveFailed(failures))
+                }
+            }
+
+            Command::Player { entity_id, action } => {
+                if !self.game_map.lock().player_entities().contains_key(entity_id) {
+                    return Err(Error::PlayerNotOnline(*entity_id));
+                }
+
+                // Sending only fails should no connection handler be left to receive the action:
+                self.map_changes_sender
+                    .send(maps::MapChange {
+                        sent_by: None,
+                        modification: maps::Modification::AdminAction { entity_id: *entity_id, action: *action }
+                    })
+                    .map_err(|_| Error::PlayerNotOnline(*entity_id))?;
+
+                Ok(format!("Requested that player {} {}", entity_id.encode(), action))
+            }
+
+            Command::Kick(client_id) => {
+                if self.client_registry.end_session(*client_id, SessionEndReason::Kicked) {
+                    Ok(format!("Kicked client {}", client_id.encode()))
+                }
+                else {
+                    Err(Error::ClientNotConnected(*client_id))
+                }
+            }
+
+            Command::Ban(client_id) => {
+                self.storage.ban_client(*client_id, storage::current_timestamp()).await?;
+                let was_connected = self.client_registry.end_session(*client_id, SessionEndReason::Banned);
+
+                Ok(format!(
+                    "Banned client {}{}",
+                    client_id.encode(),
+                    if was_connected { " and ended its session" } else { "" }
+                ))
+            }
+
+            Command::Unban(client_id) => {
+                self.storage.unban_client(*client_id).await?;
+                Ok(format!("Lifted the ban of client {}", client_id.encode()))
+            }
+        }
+    }
+
+    fn list_players(&self) -> String {
+        let client_ids = self.client_registry.client_ids_by_player_id();
+        let map = self.game_map.lock();
+
+        let mut lines = vec![format!("{} players connected", map.player_entities().len())];
+
+        let mut players: Vec<_> = map.player_entities().iter().collect();
+        players.sort_by_key(|(entity_id, _)| **entity_id);
+
+        for (entity_id, entity) in players {
+            lines.push(format!(
+                "  entity {} (client {}) at ({}, {}) with score {}",
+                entity_id.encode(),
+                client_ids.get(entity_id).map(Id::encode).unwrap_or_else(|| "unknown".to_string()),
+                entity.pos.x,
+                entity.pos.y,
+                entity.gem_collection.score()
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    fn list_chunks(&self) -> String {
+        let map = self.game_map.lock();
+
+        let mut usage: Vec<_> = map.chunk_usage().iter().filter(|(_, count)| **count > 0).collect();
+        usage.sort_by_key(|(coords, _)| (coords.x, coords.y));
+
+        let mut lines = vec![format!("{} chunks in use", usage.len())];
+
+        for (coords, count) in usage {
+            lines.push(format!("  ({}, {}) loaded by {} clients", coords.x, coords.y, count));
+        }
+
+        lines.join("\n")
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("No player entity with ID {0} is on the map (its player must be connected)")]
+    PlayerNotOnline(Id),
+    #[error("No connection is using client ID {0}")]
+    ClientNotConnected(Id),
+    #[error("Failed to save {0} chunks and/or player entities")]
+    SaveFailed(u64),
+    #[error("{0}")]
+    Storage(#[from] storage::Error)
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        let id = crate::id::generate_random();
+
+        let commands = vec![
+            Command::Help,
+            Command::Players,
+            Command::Chunks,
+            Command::Save,
+            Command::Player { entity_id: id, action: PlayerAction::Teleport(TileCoords { x: -3, y: 12 }) },
+            Command::Player { entity_id: id, action: PlayerAction::GiveGems(Gem::Diamond, 5) },
+            Command::Player { entity_id: id, action: PlayerAction::TakeGems(Gem::Emerald, 1) },
+            Command::Player { entity_id: id, action: PlayerAction::GiveItems(QuantitativeItem::EnergyDrink, 2) },
+            Command::Player { entity_id: id, action: PlayerAction::TakeItems(QuantitativeItem::TheftTrap, 3) },
+            Command::Kick(id),
+            Command::Ban(id),
+            Command::Unban(id),
+        ];
+
+        // Each command should be parsed from its displayed form:
+        for command in commands {
+            assert_eq!(command.to_string().parse::<Command>(), Ok(command));
+        }
+
+        assert_eq!(
+            format!("  give-gems   {}  ruby 7 ", id.encode()).parse(),
+            Ok(Command::Player { entity_id: id, action: PlayerAction::GiveGems(Gem::Ruby, 7) })
+        );
+
+        assert!("".parse::<Command>().is_err());
+        assert!("dance".parse::<Command>().is_err());
+        assert!("kick".parse::<Command>().is_err());
+        assert!("kick notanid".parse::<Command>().is_err());
+        assert!(format!("give-gems {} sapphire 1", id.encode()).parse::<Command>().is_err());
+        assert!(format!("take-items {} bomb -1", id.encode()).parse::<Command>().is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Server-side moderation of chat messages: a configurable word filter and per-client rate limiting.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs, io,
+    path::Path
+};
+
+use tokio::time::{Duration, Instant};
+
+/// The maximum number of chat messages that a single client may send within [`RATE_LIMIT_PERIOD`].
+pub const RATE_LIMIT_MESSAGES: usize = 5;
+
+/// The period of time over which chat messages are counted for the purpose of rate limiting.
+pub const RATE_LIMIT_PERIOD: Duration = Duration::from_secs(10);
+
+/// Replaces filtered words in chat messages with asterisks. Words are matched case-insensitively and only in their
+/// entirety (e.g. filtering 'bad' does not affect 'badge').
+#[derive(Default)]
+pub struct WordFilter {
+    words: HashSet<String>
+}
+
+impl WordFilter {
+    /// Create a filter from the given words.
+    pub fn new<'a>(words: impl IntoIterator<Item = &'a str>) -> Self {
+        WordFilter {
+            words: words.into_iter().map(str::trim).filter(|w| !w.is_empty()).map(str::to_lowercase).collect()
+        }
+    }
+
+    /// Load a filter from the file at the given path which is expected to contain one word per line. Blank lines and
+    /// lines beginning with '#' are ignored.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Ok(WordFilter::new(contents.lines().filter(|line| !line.trim_start().starts_with('#'))))
+    }
+
+    /// The number of words being filtered.
+    pub fn word_count(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Produce a copy of the given text with each filtered word replaced by asterisks.
+    pub fn apply(&self, text: &str) -> String {
+        if self.words.is_empty() {
+            return text.to_string();
+        }
+
+        let mut filtered = String::with_capacity(text.len());
+        let mut word = String::new();
+
+        for c in text.chars().chain(std::iter::once(' ')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+            }
+            else {
+                if self.words.contains(&word.to_lowercase()) {
+                    filtered.extend(word.chars().map(|_| '*'));
+                }
+                else {
+                    filtered.push_str(&word);
+                }
+                word.clear();
+
+                filtered.push(c);
+            }
+        }
+
+        filtered.pop(); // Remove the space added to the end of the text above.
+        filtered
+    }
+}
+
+/// Tracks when a client sent its recent chat messages so as to limit how many it may send within a period of time.
+#[derive(Default)]
+pub struct RateLimiter {
+    /// Points in time at which the messages sent within the last [`RATE_LIMIT_PERIOD`] were sent (oldest first).
+    recent_instants: VecDeque<Instant>
+}
+
+impl RateLimiter {
+    /// Returns whether or not a message may be sent at the given point in time. Should the message be allowed then it
+    /// is counted towards the limit.
+    pub fn try_send(&mut self, now: Instant) -> bool {
+        while matches!(self.recent_instants.front(), Some(instant) if now.duration_since(*instant) >= RATE_LIMIT_PERIOD)
+        {
+            self.recent_instants.pop_front();
+        }
+
+        if self.recent_instants.len() < RATE_LIMIT_MESSAGES {
+            self.recent_instants.push_back(now);
+            true
+        }
+        else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_filter() {
+        let filter = WordFilter::new(vec!["bad", " Worse "]);
+        assert_eq!(filter.word_count(), 2);
+
+        assert_eq!(filter.apply("This is BAD, worse & badge!"), "This is ***, ***** & badge!");
+        assert_eq!(filter.apply("bad"), "***");
+        assert_eq!(filter.apply("nothing to see"), "nothing to see");
+        assert_eq!(filter.apply(&shared::chat::sanitise("not\nbad")), "not ***");
+        assert_eq!(WordFilter::default().apply("bad"), "bad");
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+
+        for _ in 0..RATE_LIMIT_MESSAGES {
+            assert!(limiter.try_send(start));
+        }
+        assert!(!limiter.try_send(start + Duration::from_secs(1)));
+
+        // Messages sent longer ago than the rate limiting period no longer count:
+        assert!(limiter.try_send(start + RATE_LIMIT_PERIOD));
+    }
+}
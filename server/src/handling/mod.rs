@@ -1,38 +1,124 @@
 mod tests;
 
-use std::{convert::Into, net::SocketAddr};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Into,
+    net::SocketAddr,
+    sync::Arc
+};
 
 use rand::Rng;
 use shared::{
+    chat,
+    effects::StatusEffect,
+    gems,
     items::{self, Item},
-    maps::{ChunkCoords, Map, TileCoords},
+    maps::{
+        entities::{Appearance, Direction, Entity, FacialExpression},
+        ChunkCoords, Map, PlacedTrap, TileCoords
+    },
     messages, Id
 };
+use strum::IntoEnumIterator;
 use thiserror::Error;
-use tokio::{net::TcpStream, sync::broadcast};
+use tokio::{
+    net::TcpStream,
+    sync::broadcast,
+    time::{self, Duration, Instant}
+};
 use tokio_tungstenite::tungstenite;
 
 use crate::{
-    maps::{self, entities, EntityMovement, ServerMap},
+    admin::PlayerAction,
+    chat::{RateLimiter, WordFilter},
+    leaderboard::{Leaderboard, Standings},
+    maps::{self, entities, BlastEffect, EntityMovement, ServerMap},
+    metrics::METRICS,
     networking::{self, Connection},
+    rate_limiting::{self, MessageLimiter, Verdict},
+    sessions::{ClientRegistry, DuplicatePolicy, Session},
+    shutdown,
+    storage::{self, Storage},
+    tokens::TokenIssuer,
     Shared
 };
 
 const MAX_LOADED_CHUNKS_PER_CLIENT: usize = 12;
 
+/// The maximum number of movement requests that may be waiting to be performed at once. Any further requests received
+/// while the queue is full are refused.
+const MAX_QUEUED_MOVEMENTS: usize = 10;
+
+/// The percentage of emeralds held by a player that are stolen from them when they trigger a theft trap.
+const THEFT_TRAP_STOLEN_EMERALDS_PERCENTAGE: u32 = 25;
+
+/// The amount of time after which a facial expression set by a player reverts to neutral.
+const FACIAL_EXPRESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The amount of time for which an emote is shown before the player's facial expression reverts to neutral.
+const EMOTE_DURATION: Duration = Duration::from_millis(2500);
+
+/// The minimum amount of time between changes to the appearance of a player's entity.
+const APPEARANCE_CHANGE_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// The percentage of each type of gem held by a player that is lost when their player entity is killed.
+const DEATH_LOST_GEMS_PERCENTAGE: u32 = 50;
+
+/// State shared between the main task and all connection tasks (each connection task is given its own clone).
+#[derive(Clone)]
+pub struct SharedState {
+    pub game_map: Shared<ServerMap>,
+    pub storage: Arc<dyn Storage>,
+    /// Used to notify every task of changes made to the game world.
+    pub map_changes_sender: broadcast::Sender<maps::MapChange>,
+    pub chat_filter: Arc<WordFilter>,
+    pub leaderboard: Shared<Leaderboard>,
+    pub client_registry: Arc<ClientRegistry>,
+    /// What happens when a client connects while already connected.
+    pub duplicate_policy: DuplicatePolicy,
+    pub token_issuer: Arc<TokenIssuer>,
+    /// The time (as a Unix timestamp in seconds) until which clients may present legacy client IDs instead of session
+    /// tokens. Legacy client IDs are not accepted should this be `None`.
+    pub legacy_client_ids_until: Option<i64>,
+    /// Limits on how quickly each connection may send messages.
+    pub rate_limits: Arc<rate_limiting::Config>,
+    /// The maximum size in bytes of a WebSocket message received from a client.
+    pub max_message_size: usize
+}
+
 /// Creates a new [`Handler`] instance and then calls its [`Handler::handle`] method.
 pub async fn handle_connection(
-    stream: TcpStream, address: SocketAddr, game_map: Shared<ServerMap>, db_pool: sqlx::PgPool,
-    map_changes_sender: broadcast::Sender<maps::Modification>,
-    map_changes_receiver: broadcast::Receiver<maps::Modification>
+    stream: TcpStream, address: SocketAddr, shared_state: SharedState, shutdown_listener: shutdown::Listener
 ) {
+    let map_changes_receiver = shared_state.map_changes_sender.subscribe();
+
     let mut handler = Handler {
         address,
-        game_map,
-        db_pool,
-        map_changes_sender,
+        game_map: shared_state.game_map,
+        storage: shared_state.storage,
+        map_changes_sender: shared_state.map_changes_sender,
         map_changes_receiver,
-        remote_loaded_chunk_coords: Vec::new()
+        remote_loaded_chunk_coords: Vec::new(),
+        queued_movements: VecDeque::new(),
+        next_movement_instant: Instant::now(),
+        status_effects_updated_instant: Instant::now(),
+        status_effects_expiry_instant: None,
+        facial_expression_expiry_instant: None,
+        next_appearance_change_instant: Instant::now(),
+        shutdown_listener,
+        shutdown_deadline: None,
+        chat_filter: shared_state.chat_filter,
+        chat_rate_limiter: RateLimiter::default(),
+        leaderboard: shared_state.leaderboard,
+        leaderboard_score_option: None,
+        watching_leaderboard: false,
+        sent_standings_option: None,
+        client_registry: shared_state.client_registry,
+        duplicate_policy: shared_state.duplicate_policy,
+        token_issuer: shared_state.token_issuer,
+        legacy_client_ids_until: shared_state.legacy_client_ids_until,
+        message_limiter: MessageLimiter::new(shared_state.rate_limits),
+        max_message_size: shared_state.max_message_size
     };
 
     handler.handle(stream).await;
@@ -44,13 +130,60 @@ struct Handler {
     address: SocketAddr,
     /// Arc mutex containing the game map.
     game_map: Shared<ServerMap>,
-    /// The database connection pool.
-    db_pool: sqlx::PgPool,
-    map_changes_sender: broadcast::Sender<maps::Modification>,
-    map_changes_receiver: broadcast::Receiver<maps::Modification>,
+    /// Where chunks and player entities are loaded from and saved to.
+    storage: Arc<dyn Storage>,
+    map_changes_sender: broadcast::Sender<maps::MapChange>,
+    map_changes_receiver: broadcast::Receiver<maps::MapChange>,
     /// Set used to track of the coordinates of chunks that this handler's remote client has loaded. Stored as a vector
     /// so that chunk coordinate pairs can stored in from least to most recently loaded.
-    remote_loaded_chunk_coords: Vec<ChunkCoords>
+    remote_loaded_chunk_coords: Vec<ChunkCoords>,
+    /// Movement requests (request number and direction pairs) received from the remote client before its player
+    /// entity was allowed to move again. These are performed in the order they were received as soon as the required
+    /// amount of time has passed.
+    queued_movements: VecDeque<(u32, Direction)>,
+    /// The earliest point in time at which this handler's player entity may next move. Determined by the time taken
+    /// for the entity to move onto the tile of its most recent movement (see
+    /// [`shared::maps::entities::Entity::movement_time`]).
+    next_movement_instant: Instant,
+    /// The point in time at which the status effects of this handler's player entity were last updated (i.e. had
+    /// their remaining times decreased).
+    status_effects_updated_instant: Instant,
+    /// The point in time at which the next of the status effects currently applied to this handler's player entity
+    /// will expire. Is `None` when no status effects are active.
+    status_effects_expiry_instant: Option<Instant>,
+    /// The point in time at which the facial expression of this handler's player entity reverts to neutral. Is `None`
+    /// while the expression is neutral.
+    facial_expression_expiry_instant: Option<Instant>,
+    /// The earliest point in time at which the remote client may next change the appearance of its player entity.
+    next_appearance_change_instant: Instant,
+    /// Used to be notified should the server begin shutting down. Dropped only once this handler has finished.
+    shutdown_listener: shutdown::Listener,
+    /// The point in time at which this handler must close its connection as the server is shutting down. Is `None`
+    /// unless a shutdown has been initiated.
+    shutdown_deadline: Option<Instant>,
+    /// Applied to all chat messages sent by the remote client before they are delivered to other clients.
+    chat_filter: Arc<WordFilter>,
+    /// Limits how frequently the remote client may send chat messages.
+    chat_rate_limiter: RateLimiter,
+    leaderboard: Shared<Leaderboard>,
+    /// The score of this handler's player entity as last recorded on the leaderboard. Is `None` until the player
+    /// entity has been placed on the map.
+    leaderboard_score_option: Option<u64>,
+    /// Whether or not the remote client wishes to receive updates to the leaderboard.
+    watching_leaderboard: bool,
+    /// The leaderboard standings most recently sent to the remote client (so that unchanged standings are not sent
+    /// again). Is `None` while the remote client is not watching the leaderboard.
+    sent_standings_option: Option<Standings>,
+    /// Used to ensure that each client ID is only in use by a single connection at a time.
+    client_registry: Arc<ClientRegistry>,
+    duplicate_policy: DuplicatePolicy,
+    /// Used to verify the session token presented by the remote client and to issue it a new one.
+    token_issuer: Arc<TokenIssuer>,
+    /// The time until which the remote client may present a legacy client ID (see [`SharedState`]).
+    legacy_client_ids_until: Option<i64>,
+    /// Applies rate limits to the messages received from the remote client.
+    message_limiter: MessageLimiter,
+    max_message_size: usize
 }
 
 impl Handler {
@@ -58,7 +191,7 @@ impl Handler {
     async fn handle(&mut self, stream: TcpStream) {
         // Perform the WebSocket handshake:
 
-        match Connection::new(stream).await {
+        match Connection::new(stream, self.max_message_size).await {
             Ok(ws) => {
                 self.log("Performed WebSocket handshake successfully");
 
@@ -90,92 +223,199 @@ impl Handler {
     }
 
     /// This function is to be called after the WebSocket connection handshake finishes. It is the role of this function
-    /// to complete the exchange of 'hello' and 'welcome' messages between client and server before passing control onto
-    /// the [`Self::handle_established_connection`] method.
+    /// to complete the exchange of 'hello' and 'welcome' messages between client and server (refusing clients speaking
+    /// an unsupported protocol version) before passing control onto the [`Self::handle_established_connection`] method.
     async fn handle_websocket_connection(&mut self, mut ws: Connection) -> Result<()> {
         // Expect a 'hello' message from the client:
 
-        if let Some(messages::ToServer::Hello { client_id_option }) = ws.receive().await? {
-            let (client_id, player_id, player_entity) = {
-                let mut db = self.db_pool.acquire().await?;
+        let hello_option = ws.receive_hello().await?;
+
+        // Refuse clients speaking an unsupported protocol version before anything else is done on their behalf:
+
+        if let Some(networking::Hello::Unsupported { protocol_version, reason }) = hello_option {
+            self.log_warn(&format!(
+                "Refused connection as client speaks unsupported protocol version {} ({:?})",
+                protocol_version, reason
+            ));
+
+            ws.send(&messages::FromServer::ProtocolRejected {
+                reason,
+                min_supported_version: messages::MIN_SUPPORTED_PROTOCOL_VERSION,
+                max_supported_version: messages::PROTOCOL_VERSION
+            })
+            .await?;
+            return ws.close().await.map_err(Into::into);
+        }
+
+        if let Some(networking::Hello::Supported { credentials_option }) = hello_option {
+            let client_id_option = match credentials_option {
+                Some(credentials) => match self.authenticate(credentials).await? {
+                    Some(client_id) => Some(client_id),
+                    None => {
+                        let reason = messages::SessionEndReason::InvalidCredentials;
+                        ws.send(&messages::FromServer::SessionEnded(reason)).await?;
+                        return ws.close().await.map_err(Into::into);
+                    }
+                },
+                None => None
+            };
+
+            // Refuse banned clients:
 
-                if let Some(client_id) = client_id_option {
+            if let Some(client_id) = client_id_option {
+                if let Some(banned_at) = self.storage.client_banned_at(client_id).await? {
+                    self.log_warn(&format!(
+                        "Refused connection as client ID {} was banned at {}",
+                        client_id, banned_at
+                    ));
+
+                    ws.send(&messages::FromServer::SessionEnded(messages::SessionEndReason::Banned)).await?;
+                    return ws.close().await.map_err(Into::into);
+                }
+            }
+
+            let client_id = match client_id_option {
+                Some(client_id) => {
                     self.log(&format!("Existing client ID provided: {}", client_id));
+                    client_id
+                }
+                None => {
+                    let new_id = crate::id::generate_random();
+                    self.log(&format!("Generated new client ID {}", new_id));
+                    new_id
+                }
+            };
+
+            // Ensure no other connection is using the same client ID (and so the same player entity) before the player
+            // entity is loaded:
+
+            if self.client_registry.is_active(client_id) && self.duplicate_policy == DuplicatePolicy::TakeOver {
+                self.log(&format!("Client ID {} is already connected so taking over from that connection", client_id));
+            }
+
+            let mut session = match self.client_registry.register(client_id, self.duplicate_policy).await {
+                Some(session) => session,
+                None => {
+                    self.log_warn(&format!("Refused connection as client ID {} is already connected", client_id));
 
-                    // Get the client their existing player entity (if any) from database:
+                    ws.send(&messages::FromServer::SessionEnded(messages::SessionEndReason::AlreadyConnected)).await?;
+                    return ws.close().await.map_err(Into::into);
+                }
+            };
 
-                    if let Some((entity_id, entity)) = entities::player_from_database(client_id, &mut db).await? {
-                        (client_id, entity_id, entity)
+            let (player_id, player_entity) = {
+                let storage = self.storage.as_ref();
+
+                if client_id_option.is_some() {
+                    // Get the client their existing player entity (if any) from storage:
+
+                    if let Some((entity_id, entity)) = entities::player_from_storage(client_id, storage).await? {
+                        (entity_id, entity)
                     }
                     else {
                         self.log_warn(&format!(
-                            "Could not find in database a player entity associated with client ID {}",
+                            "Could not find in storage a player entity associated with client ID {}",
                             client_id
                         ));
 
-                        let (entity_id, entity) = entities::new_player_in_database(client_id, &mut db).await?;
-                        (client_id, entity_id, entity)
+                        entities::new_player_in_storage(client_id, storage).await?
                     }
                 }
                 else {
-                    let new_id = crate::id::generate_random();
-                    self.log(&format!("Generated new client ID {}", new_id));
-
-                    // Create a new entity for this client and insert into the database:
-
-                    let (new_entity_id, new_entity) = entities::new_player_in_database(new_id, &mut db).await?;
-                    (new_id, new_entity_id, new_entity)
+                    // Create a new entity for this client and add it to storage:
+                    entities::new_player_in_storage(client_id, storage).await?
                 }
             };
 
-            // Send a 'welcome' message to the client:
-            ws.send(&messages::FromServer::Welcome {
-                version: shared::VERSION.to_string(),
-                your_client_id: client_id,
-                your_entity_with_id: (player_id, player_entity.clone())
-            })
-            .await?;
+            // Record that this client has been seen so that their player entity is not pruned (should it have been
+            // pruned since being loaded then the client is given a new player entity instead):
+
+            let (player_id, mut player_entity) =
+                match self.storage.record_player_seen(player_id, storage::current_timestamp()).await {
+                    Err(storage::Error::PlayerNotFound(_)) => {
+                        self.log_warn(&format!("Player entity {} was pruned while being loaded", player_id));
+                        entities::new_player_in_storage(client_id, self.storage.as_ref()).await?
+                    }
+                    result => {
+                        result?;
+                        (player_id, player_entity)
+                    }
+                };
 
-            // Provide all the chunks surrounding the player entity plus any entities that may be in those chunks:
+            session.set_player_id(player_id);
 
-            let chunks_and_entities = self
-                .provide_chunks_at_and_surrounding_with_entities(player_entity.pos.as_chunk_coords(), player_id)
-                .await?;
+            // Place this client's player entity on the game map at a suitable position (its saved position may since
+            // have become blocked, for example by another player):
 
-            for msg in chunks_and_entities {
-                ws.send(&msg).await?;
-            }
+            let player_entity = entities::place_at_spawn_position(
+                self.storage.as_ref(),
+                &self.game_map,
+                player_entity.pos,
+                move |map, spawn_position| {
+                    let moved = spawn_position != player_entity.pos;
+                    player_entity.pos = spawn_position;
 
-            // Place this client's player entity on the game map:
-            self.game_map.lock().add_entity(player_id, player_entity);
+                    map.add_entity(player_id, player_entity.clone());
+                    if moved {
+                        map.mark_player_dirty(player_id);
+                    }
+
+                    player_entity
+                }
+            )
+            .await?;
 
             // Inform other tasks that a new entity now exists on the game map:
-            self.map_changes_sender.send(maps::Modification::EntityAdded(player_id)).unwrap();
-            self.map_changes_receiver.recv().await.unwrap();
+            self.broadcast_map_change(maps::Modification::EntityAdded(player_id), player_id);
 
-            // Begin main connection loop:
-            let result = self.handle_established_connection(&mut ws, player_id).await;
+            // Welcome the client then begin the main connection loop (the player entity is removed from the map below
+            // should either fail):
+            let result = match self.welcome_client(&mut ws, client_id, player_id, player_entity).await {
+                Ok(()) => self.handle_established_connection(&mut ws, player_id, &mut session).await,
+                Err(e) => Err(e)
+            };
 
-            // Ensure the game map knows that this client's loaded chunks are no longer needed by this task:
+            // Ensure the game map knows that this client's loaded chunks are no longer needed by this task (a chunk
+            // that fails to save does not prevent the rest of the clean up below):
             for coords in &self.remote_loaded_chunk_coords {
-                self.chunk_not_needed(*coords).await?;
+                if let Err(e) = self.chunk_not_needed(*coords).await {
+                    self.log_error(&format!("Failed to save chunk at {} once no longer needed - {}", coords, e));
+                }
             }
 
-            // Remove this client's player entity from the game world and update database with changes to said entity:
+            // Remove this client's player entity from the game world and update storage with changes to said entity
+            // (while no flush is saving an older copy of it):
+            let save_locks = self.game_map.lock().save_locks();
+            let _save_guard = save_locks.players.lock(player_id).await;
+
             let entity_option = self.game_map.lock().remove_entity(player_id);
-            if let Some(player_entity) = entity_option {
-                {
-                    let mut db = self.db_pool.acquire().await?;
-                    entities::update_database_for_player(&player_entity, client_id, &mut db).await?;
+            let save_result = match entity_option {
+                Some(player_entity) => {
+                    let save_result = async {
+                        entities::update_storage_for_player(&player_entity, player_id, self.storage.as_ref()).await?;
+                        self.storage.record_player_seen(player_id, storage::current_timestamp()).await
+                    }
+                    .await;
+
+                    if let Err(e) = &save_result {
+                        self.log_error(&format!("Failed to save player entity {} - {}", player_id, e));
+                    }
+
+                    // Inform other tasks that an entity has been removed from the game map:
+                    let modification_msg =
+                        maps::Modification::EntityRemoved(player_id, player_entity.pos.as_chunk_coords());
+                    self.broadcast_map_change(modification_msg, player_id);
+
+                    save_result
                 }
+                None => Ok(())
+            };
 
-                // Inform other tasks that an entity has been removed from the game map:
-                let modification_msg =
-                    maps::Modification::EntityRemoved(player_id, player_entity.pos.as_chunk_coords());
-                self.map_changes_sender.send(modification_msg).unwrap();
-            }
+            // Only now that saving the player entity has been attempted may another connection with the same client ID
+            // proceed:
+            drop(session);
 
-            result
+            result.and(save_result.map_err(Into::into))
         }
         else {
             self.log_error("Did not receive 'hello' message after establishing a WebSocket connection");
@@ -183,24 +423,125 @@ impl Handler {
         }
     }
 
+    /// Determine the client ID of the remote client from the credentials given in its 'hello' message. Returns `None`
+    /// should the credentials not be accepted.
+    async fn authenticate(&self, credentials: messages::Credentials) -> Result<Option<Id>> {
+        let now = storage::current_timestamp();
+
+        let (client_id, issued_at) = match credentials {
+            messages::Credentials::SessionToken(token) => match self.token_issuer.verify(&token, now) {
+                Ok(claims) => (claims.client_id, claims.issued_at),
+                Err(e) => {
+                    self.log_warn(&format!("Refused session token - {}", e));
+                    return Ok(None);
+                }
+            },
+
+            messages::Credentials::LegacyClientId(client_id) => {
+                if !matches!(self.legacy_client_ids_until, Some(until) if now <= until) {
+                    self.log_warn(&format!("Refused legacy client ID {} as they are no longer accepted", client_id));
+                    return Ok(None);
+                }
+
+                // Legacy client IDs are treated as though they were issued before any revocation so that they are
+                // refused for good once the client's tokens have been revoked:
+                (client_id, i64::MIN)
+            }
+        };
+
+        match self.storage.tokens_revoked_at(client_id).await? {
+            Some(revoked_at) if issued_at <= revoked_at => {
+                self.log_warn(&format!("Refused revoked credentials of client ID {}", client_id));
+                Ok(None)
+            }
+            _ => Ok(Some(client_id))
+        }
+    }
+
+    /// Send a 'welcome' message to the remote client (including a new session token that replaces any it presented)
+    /// followed by the chunks surrounding its newly placed player entity plus any entities in those chunks.
+    async fn welcome_client(
+        &mut self, ws: &mut Connection, client_id: Id, player_id: Id, player_entity: Entity
+    ) -> Result<()> {
+        let chunk_coords = player_entity.pos.as_chunk_coords();
+
+        ws.send(&messages::FromServer::Welcome {
+            version: shared::VERSION.to_string(),
+            your_client_id: client_id,
+            your_session_token: self.token_issuer.issue(client_id, storage::current_timestamp()),
+            your_entity_with_id: (player_id, player_entity)
+        })
+        .await?;
+
+        for msg in self.provide_chunks_at_and_surrounding_with_entities(chunk_coords, player_id).await? {
+            ws.send(&msg).await?;
+        }
+
+        // Ensure the player is on the leaderboard (new players will not yet be):
+        self.update_leaderboard_score(player_id).await;
+
+        Ok(())
+    }
+
     /// A connection is considered 'established' once the WebSocket handshake and the exchange of 'hello' & 'welcome'
     /// messages have completed.
-    async fn handle_established_connection(&mut self, ws: &mut Connection, player_id: Id) -> Result<()> {
+    async fn handle_established_connection(
+        &mut self, ws: &mut Connection, player_id: Id, session: &mut Session
+    ) -> Result<()> {
         loop {
             // Wait for incoming messages on both the WebSocket connection and the world modifications channel (or close
-            // connection on Ctrl-C signal):
+            // connection once the server is shutting down):
             tokio::select!(
                 res = ws.receive() => {
                     if let Some(msg) = res? {
                         self.log(&format!("Message received: {}", msg));
 
-                        // Handle and respond to received message:
+                        let verdict = self.message_limiter.check((&msg).into(), Instant::now());
+
+                        if verdict != Verdict::Allow {
+                            METRICS.message_rate_limited(&msg, verdict == Verdict::Disconnect);
+                        }
+
+                        match verdict {
+                            Verdict::Allow => {
+                                // Handle and respond to received message:
+
+                                let responses = self.handle_message(msg, player_id).await?;
+
+                                for response in responses {
+                                    self.log(&format!("Response message: {}", response));
+                                    ws.send(&response).await?;
+                                }
+                            }
+
+                            Verdict::Discard => {
+                                self.log(&format!("Discarded message as it exceeds a rate limit: {}", msg));
+
+                                for response in self.discarded_message_responses(&msg, player_id) {
+                                    ws.send(&response).await?;
+                                }
+                            }
+
+                            Verdict::Warn => {
+                                self.log_warn(&format!(
+                                    "Warning client as it continues to exceed rate limits: {}",
+                                    msg
+                                ));
+
+                                for response in self.discarded_message_responses(&msg, player_id) {
+                                    ws.send(&response).await?;
+                                }
+                                ws.send(&messages::FromServer::RateLimitWarning).await?;
+                            }
 
-                        let responses = self.handle_message(msg, player_id).await?;
+                            Verdict::Disconnect => {
+                                self.log_warn("Closing connection as the client continued to exceed rate limits");
 
-                        for response in responses {
-                            self.log(&format!("Response message: {}", response));
-                            ws.send(&response).await?;
+                                let reason = messages::SessionEndReason::TooManyMessages;
+                                ws.send(&messages::FromServer::SessionEnded(reason)).await?;
+                                ws.close().await?;
+                                break;
+                            }
                         }
                     }
                     else {
@@ -209,10 +550,59 @@ impl Handler {
                     }
                 }
 
+                _ = time::sleep_until(self.next_movement_instant), if !self.queued_movements.is_empty() => {
+                    // Enough time has passed since the player entity's previous movement so perform the next queued
+                    // movement:
+
+                    let responses = self.perform_next_queued_movement(player_id).await?;
+
+                    for response in responses {
+                        self.log(&format!("Response message: {}", response));
+                        ws.send(&response).await?;
+                    }
+                }
+
+                _ = time::sleep_until(self.status_effects_expiry_instant.unwrap_or_else(Instant::now)),
+                    if self.status_effects_expiry_instant.is_some() => {
+                    // A status effect applied to the player entity is due to expire:
+
+                    let responses = self.update_player_status_effects(player_id).await;
+
+                    for response in responses {
+                        self.log(&format!("Response message: {}", response));
+                        ws.send(&response).await?;
+                    }
+                }
+
+                _ = time::sleep_until(self.facial_expression_expiry_instant.unwrap_or_else(Instant::now)),
+                    if self.facial_expression_expiry_instant.is_some() => {
+                    // The player entity's facial expression has been shown for long enough:
+
+                    let responses = self.set_player_facial_expression(FacialExpression::Neutral, None, player_id).await;
+
+                    for response in responses {
+                        self.log(&format!("Response message: {}", response));
+                        ws.send(&response).await?;
+                    }
+                }
+
                 res = self.map_changes_receiver.recv() => {
                     match res {
-                        Ok(modification) => {
-                            if let Some(response) = self.handle_map_change(modification).await {
+                        Ok(map_change) => {
+                            let responses = match map_change.modification {
+                                // This task's player entity was caught in the blast of bombs detonated by another
+                                // player so apply the effect:
+                                maps::Modification::CaughtInBlast { entity_id, effect } if entity_id == player_id => {
+                                    self.player_caught_in_blast(effect, player_id).await?
+                                }
+                                // The server's operators wish to perform an action on this task's player:
+                                maps::Modification::AdminAction { entity_id, action } if entity_id == player_id => {
+                                    self.perform_admin_action(action, player_id).await?
+                                }
+                                _ => self.handle_map_change(map_change, player_id).await
+                            };
+
+                            for response in responses {
                                 self.log(&format!("Informing client of change to game world: {}", response));
                                 ws.send(&response).await?;
                             }
@@ -220,6 +610,7 @@ impl Handler {
 
                         Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
                             self.log_warn(&format!("Skipped {} messages on the map modification channel", skipped));
+                            METRICS.broadcast_lagged(skipped);
                         }
 
                         Err(channel_err) => {
@@ -229,12 +620,45 @@ impl Handler {
                     }
                 }
 
-                _ = tokio::signal::ctrl_c() => {
-                    self.log("Closing connection due to Ctrl-C signal");
+                notice = self.shutdown_listener.notified(), if self.shutdown_deadline.is_none() => {
+                    // Inform the client that the connection will soon be closed:
+
+                    self.log(&format!("Server shutting down in {} seconds", notice.seconds_remaining()));
+
+                    let msg = messages::FromServer::ServerShuttingDown {
+                        seconds: notice.seconds_remaining(),
+                        reason: notice.reason
+                    };
+                    ws.send(&msg).await?;
+
+                    self.shutdown_deadline = Some(notice.deadline);
+                }
+
+                _ = time::sleep_until(self.shutdown_deadline.unwrap_or_else(Instant::now)),
+                    if self.shutdown_deadline.is_some() => {
+                    self.log("Closing connection as the server is shutting down");
+                    ws.close().await?;
+                    break;
+                }
+
+                reason = session.ended() => {
+                    self.log(&format!("Closing connection as the session has ended: {:?}", reason));
+
+                    let msg = messages::FromServer::SessionEnded(reason);
+                    ws.send(&msg).await?;
                     ws.close().await?;
                     break;
                 }
             );
+
+            // Any of the above may have changed the player entity's gem collection so keep the leaderboard up to date:
+
+            let responses = self.update_leaderboard_score(player_id).await;
+
+            for response in responses {
+                self.log(&format!("Response message: {}", response));
+                ws.send(&response).await?;
+            }
         }
 
         Ok(())
@@ -249,82 +673,25 @@ impl Handler {
             }
 
             messages::ToServer::MoveMyEntity { request_number, direction } => {
-                let mut responses = Vec::new();
-
-                // TODO: Prevent player exceeding movement rate.
-
-                let movement_option = self.game_map.lock().move_entity_towards(player_id, direction);
-
-                if let Some(EntityMovement { old_position, new_position, smashed_tile_option }) = movement_option {
-                    // If moving into a new chunk, ensure chunks adjacent to the destination chunk are loaded and create
-                    // message(s) to provide them to the client:
-                    if old_position.as_chunk_coords() != new_position.as_chunk_coords() {
-                        let msgs = self
-                            .provide_chunks_at_and_surrounding_with_entities(new_position.as_chunk_coords(), player_id)
-                            .await?;
-
-                        responses.extend(msgs);
-                    }
-
-                    // Inform other tasks of the entity's movement:
-                    self.map_changes_sender
-                        .send(maps::Modification::EntityMoved {
-                            entity_id: player_id,
-                            old_position,
-                            new_position,
-                            direction
-                        })
-                        .unwrap();
-
-                    // Confirm to the remote client that the movement could go ahead:
-                    responses.push(messages::FromServer::YourEntityMoved { request_number, new_position });
-
-                    if let Some(smashed_tile) = smashed_tile_option {
-                        self.log(&format!("Smashed tile {:?} at {}", smashed_tile, new_position));
-
-                        // If the smashed tile yields gems, calculate a quantity within the determined range, provide
-                        // that quantity of gems to the player on the server side, and send a message to the remote
-                        // client informing them of how many more gems they now have:
-
-                        if let Some(gem_yield) = smashed_tile.get_gem_yield() {
-                            // Random gem quantity within the range specified by the yield specific by the tile type:
-                            let quantity_increase = rand::thread_rng()
-                                .gen_range(gem_yield.minimum_quantity..(gem_yield.maximum_quantity + 1));
-
-                            // Increase gem quantity on the server side:
-                            if let Some(entity) = self.game_map.lock().entity_by_id_mut(player_id) {
-                                entity.gem_collection.increase_quantity(gem_yield.gem, quantity_increase);
-                            }
-
-                            // Produce message to send to the remote client to inform them of how many more gems they
-                            // now have:
-                            responses.push(messages::FromServer::YouCollectedGems {
-                                gem_type: gem_yield.gem,
-                                quantity_increase
-                            });
-
-                            self.log(&format!(
-                                "Obtained an additional {} gems of type {:?}",
-                                quantity_increase, gem_yield.gem
-                            ));
-                        }
-                    }
+                if self.queued_movements.is_empty() && Instant::now() >= self.next_movement_instant {
+                    self.move_player_entity(request_number, direction, player_id).await
                 }
+                else if self.queued_movements.len() < MAX_QUEUED_MOVEMENTS {
+                    // The player entity is not yet allowed to move again (or earlier movements are still waiting to
+                    // be performed) so queue the movement to be performed once enough time has passed:
 
-                if responses.is_empty() {
-                    // The `responses` vector will only be empty if the movement was not allowed. In that case, inform
-                    // the remote client:
+                    self.log(&format!("Queued movement request #{}", request_number));
+                    self.queued_movements.push_back((request_number, direction));
 
-                    let new_position = self.game_map.lock().entity_by_id(player_id).unwrap().pos;
-                    Ok(vec![messages::FromServer::YourEntityMoved { request_number, new_position }])
+                    Ok(vec![])
                 }
                 else {
-                    // The `responses` vector will be populated only if the movement could go ahead. If it did then a
-                    // message will be sent to all tasks informing them of the entity movement. That message isn't
-                    // however relevant to the task that sent it so immediately receive and discard:
-                    self.map_changes_receiver.recv().await.unwrap();
+                    self.log_warn(&format!(
+                        "Refused movement request #{} as {} movements are already queued",
+                        request_number, MAX_QUEUED_MOVEMENTS
+                    ));
 
-                    Ok(responses)
+                    Ok(self.refused_movement_responses(request_number, player_id))
                 }
             }
 
@@ -342,11 +709,7 @@ impl Handler {
                     self.game_map.lock().set_bomb_at(pos, player_id);
 
                     // Inform other tasks that a bomb has been placed:
-                    self.map_changes_sender.send(maps::Modification::BombPlaced(pos, player_id)).unwrap();
-
-                    // The client that placed the bomb obviously does not need to be informed by the server that a bomb
-                    // has been placed so immediately discarded map modification message on this task:
-                    self.map_changes_receiver.recv().await.unwrap();
+                    self.broadcast_map_change(maps::Modification::BombPlaced(pos, player_id), player_id);
 
                     // Remove the placed bomb from the player's inventory and update bombs placed count:
                     if let Some(player) = self.game_map.lock().entity_by_id_mut(player_id) {
@@ -359,24 +722,80 @@ impl Handler {
             }
 
             messages::ToServer::DetonateBombs => {
-                // Remove bombs from map server-side and update player's bombs placed count:
-                {
+                // Remove bombs from map server-side, update player's bombs placed count, destroy tiles, and identify
+                // the entities caught in the blast:
+                let (detonated_positions, destroyed_tiles, caught_in_blast) = {
                     let mut map = self.game_map.lock();
 
                     let coords = map.entity_by_id(player_id).map(|e| e.pos.as_chunk_coords()).unwrap_or_default();
-                    let detonated_count = map.take_bombs_placed_by_in_and_around_chunk(player_id, coords).len();
+                    let detonated_positions = map.take_bombs_placed_by_in_and_around_chunk(player_id, coords);
 
                     if let Some(entity) = map.entity_by_id_mut(player_id) {
-                        entity.bombs_placed_count -= detonated_count as i32;
+                        entity.bombs_placed_count -= detonated_positions.len() as i32;
+                    }
+
+                    let destroyed_tiles = map.destroy_tiles_in_blast(&detonated_positions);
+                    let caught_in_blast = map.entities_caught_in_blast(&detonated_positions);
+
+                    (detonated_positions, destroyed_tiles, caught_in_blast)
+                };
+
+                // Inform other tasks of the exact positions of the detonated bombs:
+
+                self.broadcast_map_change(
+                    maps::Modification::BombsDetonated { placed_by: player_id, positions: detonated_positions },
+                    player_id
+                );
+
+                let mut responses = Vec::new();
+
+                // Inform other tasks & this task's remote client of the destroyed tiles and total up the gems yielded
+                // by any destroyed rocks:
+
+                let mut gems_yielded: HashMap<gems::Gem, u32> = HashMap::new();
+
+                for (position, old_tile, new_tile) in destroyed_tiles {
+                    self.broadcast_map_change(maps::Modification::TileChanged(position, new_tile), player_id);
+
+                    if self.remote_loaded_chunk_coords.contains(&position.as_chunk_coords()) {
+                        responses.push(messages::FromServer::ChangeTile(position, new_tile));
+                    }
+
+                    if let Some(gem_yield) = old_tile.get_gem_yield() {
+                        *gems_yielded.entry(gem_yield.gem).or_default() += random_yield_quantity(&gem_yield);
                     }
                 }
 
-                // Inform other tasks of detonated bombs:
+                // Give the yielded gems to the player that detonated the bombs:
 
-                self.map_changes_sender.send(maps::Modification::BombsDetonated(player_id)).unwrap();
-                self.map_changes_receiver.recv().await.unwrap();
+                for (gem_type, quantity_increase) in gems_yielded {
+                    METRICS.gems_mined(gem_type, quantity_increase);
 
-                Ok(vec![])
+                    if let Some(entity) = self.game_map.lock().entity_by_id_mut(player_id) {
+                        entity.gem_collection.increase_quantity(gem_type, quantity_increase);
+                    }
+
+                    responses.push(messages::FromServer::YouCollectedGems { gem_type, quantity_increase });
+
+                    self.log(&format!(
+                        "Obtained an additional {} gems of type {:?} from bombs",
+                        quantity_increase, gem_type
+                    ));
+                }
+
+                // Apply blast effects - the effects on other players' entities are applied by the tasks responsible for
+                // those entities:
+
+                for (entity_id, effect) in caught_in_blast {
+                    if entity_id == player_id {
+                        responses.extend(self.player_caught_in_blast(effect, player_id).await?);
+                    }
+                    else {
+                        self.broadcast_map_change(maps::Modification::CaughtInBlast { entity_id, effect }, player_id);
+                    }
+                }
+
+                Ok(responses)
             }
 
             messages::ToServer::PurchaseSingleItem(item) => {
@@ -411,16 +830,691 @@ impl Handler {
 
                 Ok(vec![])
             }
+
+            messages::ToServer::PlaceTrap { trap, disguise } => {
+                // Get player's position & check if the player actually posses a trap of the given type to place:
+                let (can_place_trap, pos) = self
+                    .game_map
+                    .lock()
+                    .entity_by_id(player_id)
+                    .map(|player| (player.item_inventory.has_how_many(trap.as_item()) >= 1, player.pos))
+                    .unwrap_or((false, TileCoords { x: 0, y: 0 }));
+
+                if can_place_trap {
+                    let placed_trap = PlacedTrap { trap, placed_by: player_id, disguise };
+
+                    // Place the trap (server-side) provided there isn't already a trap or gem on the ground at that
+                    // position:
+                    if self.game_map.lock().set_trap_at(pos, placed_trap) {
+                        // Inform other tasks that a trap has been placed:
+                        self.broadcast_map_change(maps::Modification::TrapPlaced(pos, placed_trap), player_id);
+
+                        // Remove the placed trap from the player's inventory:
+                        if let Some(player) = self.game_map.lock().entity_by_id_mut(player_id) {
+                            player.item_inventory.take_quantity(trap.as_item(), 1);
+                        }
+                    }
+                    else {
+                        self.log_warn(&format!("Cannot place trap at {} as there is already a trap or gem there", pos));
+                    }
+                }
+
+                Ok(vec![])
+            }
+
+            messages::ToServer::ConsumeEnergyDrink => {
+                // Remove an energy drink from the player's inventory (provided they actually have one):
+                let had_energy_drink = {
+                    let mut map = self.game_map.lock();
+                    let entity_option = map.entity_by_id_mut(player_id);
+
+                    match entity_option {
+                        Some(entity)
+                            if entity.item_inventory.has_how_many(items::QuantitativeItem::EnergyDrink) >= 1 =>
+                        {
+                            entity.item_inventory.take_quantity(items::QuantitativeItem::EnergyDrink, 1);
+                            true
+                        }
+                        _ => false
+                    }
+                };
+
+                if had_energy_drink {
+                    Ok(self.apply_player_status_effect(StatusEffect::SpeedBoost, player_id).await)
+                }
+                else {
+                    self.log_warn("Player attempted to consume an energy drink without having one");
+                    Ok(vec![])
+                }
+            }
+
+            messages::ToServer::SendChatMessage { channel, text } => {
+                Ok(self.send_chat_message(channel, &text, player_id).await)
+            }
+
+            messages::ToServer::SetFacialExpression(expression) => {
+                Ok(self.set_player_facial_expression(expression, Some(FACIAL_EXPRESSION_TIMEOUT), player_id).await)
+            }
+
+            messages::ToServer::PlayEmote(expression) => {
+                Ok(self.set_player_facial_expression(expression, Some(EMOTE_DURATION), player_id).await)
+            }
+
+            messages::ToServer::ChangeAppearance(appearance) => {
+                Ok(self.change_player_appearance(appearance, player_id).await)
+            }
+
+            messages::ToServer::WatchLeaderboard(watch) => {
+                self.watching_leaderboard = watch;
+                self.sent_standings_option = None;
+
+                Ok(self.leaderboard_update(player_id))
+            }
+        }
+    }
+
+    /// Change the facial expression of this handler's player entity and inform other tasks. Unless the new expression
+    /// is neutral, it reverts to neutral once the given amount of time has passed. Produces the message to be sent to
+    /// the remote client.
+    async fn set_player_facial_expression(
+        &mut self, expression: FacialExpression, duration_option: Option<Duration>, player_id: Id
+    ) -> Vec<messages::FromServer> {
+        self.facial_expression_expiry_instant = match expression {
+            FacialExpression::Neutral => None,
+            _ => duration_option.map(|duration| Instant::now() + duration)
+        };
+
+        let changed = self
+            .game_map
+            .lock()
+            .entity_by_id_mut(player_id)
+            .map(|entity| std::mem::replace(&mut entity.facial_expression, expression) != expression)
+            .unwrap_or(false);
+
+        if changed {
+            self.log(&format!("Facial expression changed to {}", expression));
+
+            // Inform other tasks:
+            self.broadcast_map_change(maps::Modification::FacialExpressionChanged(player_id, expression), player_id);
+        }
+
+        vec![messages::FromServer::FacialExpressionChanged { entity_id: player_id, expression }]
+    }
+
+    /// Change the appearance of this handler's player entity (ensuring it is saved) and inform other tasks. The change
+    /// is refused should the previous change have been too recent. Either way, the player entity's resulting appearance
+    /// is returned to be sent to the remote client.
+    async fn change_player_appearance(&mut self, appearance: Appearance, player_id: Id) -> Vec<messages::FromServer> {
+        let on_cooldown = Instant::now() < self.next_appearance_change_instant;
+
+        let (current_appearance, changed) = {
+            let mut map = self.game_map.lock();
+
+            let entity = match map.entity_by_id_mut(player_id) {
+                Some(entity) => entity,
+                None => return vec![]
+            };
+
+            let changed = !on_cooldown && entity.appearance() != appearance;
+            if changed {
+                entity.set_appearance(appearance);
+            }
+            let current_appearance = entity.appearance();
+
+            if changed {
+                map.mark_player_dirty(player_id);
+            }
+
+            (current_appearance, changed)
+        };
+
+        if on_cooldown {
+            self.log_warn("Refused appearance change as the previous change was too recent");
+        }
+
+        if changed {
+            self.next_appearance_change_instant = Instant::now() + APPEARANCE_CHANGE_COOLDOWN;
+
+            self.log(&format!("Appearance changed to {}", appearance));
+
+            // Inform other tasks:
+            self.broadcast_map_change(maps::Modification::AppearanceChanged(player_id, appearance), player_id);
+        }
+
+        vec![messages::FromServer::AppearanceChanged { entity_id: player_id, appearance: current_appearance }]
+    }
+
+    /// Record the current score of this handler's player entity on the leaderboard should it have changed since last
+    /// recorded, informing other tasks so that the leaderboards of their clients can be refreshed. Produces an updated
+    /// leaderboard to be sent to the remote client should it be watching the leaderboard.
+    async fn update_leaderboard_score(&mut self, player_id: Id) -> Vec<messages::FromServer> {
+        let score_option = self.game_map.lock().entity_by_id(player_id).map(|entity| entity.gem_collection.score());
+
+        match score_option {
+            Some(score) if score_option != self.leaderboard_score_option => {
+                self.leaderboard_score_option = Some(score);
+                self.leaderboard.lock().set_score(player_id, score);
+
+                // Inform other tasks:
+                self.broadcast_map_change(maps::Modification::LeaderboardChanged, player_id);
+
+                self.leaderboard_update(player_id)
+            }
+            _ => vec![]
+        }
+    }
+
+    /// Produces a message containing the current leaderboard and the rank of this handler's player should the remote
+    /// client be watching the leaderboard and should it differ from the one most recently sent.
+    fn leaderboard_update(&mut self, player_id: Id) -> Vec<messages::FromServer> {
+        if !self.watching_leaderboard {
+            return vec![];
+        }
+
+        let standings_option = self.leaderboard.lock().standings_of(player_id);
+
+        match standings_option {
+            Some(standings) if Some(&standings) != self.sent_standings_option.as_ref() => {
+                self.sent_standings_option = Some(standings.clone());
+
+                vec![messages::FromServer::Leaderboard {
+                    top_scores: standings.top_scores,
+                    your_rank: standings.rank,
+                    your_score: standings.score
+                }]
+            }
+            _ => vec![]
+        }
+    }
+
+    /// Check that the given chat message text is acceptable, apply the word filter, and inform other tasks so that it
+    /// can be delivered to their clients. Produces the message to be sent to the remote client in response - either
+    /// the filtered chat message (so that the client displays what other players see) or the reason for rejection.
+    async fn send_chat_message(
+        &mut self, channel: chat::Channel, text: &str, player_id: Id
+    ) -> Vec<messages::FromServer> {
+        let text = chat::sanitise(text);
+
+        if let Err(rejection) = chat::validate(&text) {
+            self.log_warn(&format!("Rejected chat message ({:?})", rejection));
+            return vec![messages::FromServer::ChatMessageRejected(rejection)];
+        }
+
+        if !self.chat_rate_limiter.try_send(Instant::now()) {
+            self.log_warn("Rejected chat message as too many were sent recently");
+            return vec![messages::FromServer::ChatMessageRejected(chat::Rejection::RateLimited)];
+        }
+
+        let text = self.chat_filter.apply(&text);
+
+        let position_option = self.game_map.lock().entity_by_id(player_id).map(|entity| entity.pos);
+
+        if let Some(position) = position_option {
+            log::info!("Chat ({:?}) from entity {}: {}", channel, player_id, text);
+
+            // Inform other tasks:
+            self.broadcast_map_change(
+                maps::Modification::ChatMessage { sender: player_id, position, channel, text: text.clone() },
+                player_id
+            );
+        }
+
+        vec![messages::FromServer::ChatMessage { channel, sender_entity_id: player_id, text }]
+    }
+
+    /// Produces the message(s) that are to be sent to the client in response to a message of theirs that was discarded
+    /// due to exceeding a rate limit. Only movement requests are answered, as the client waits for a reply to each.
+    fn discarded_message_responses(&self, msg: &messages::ToServer, player_id: Id) -> Vec<messages::FromServer> {
+        match *msg {
+            messages::ToServer::MoveMyEntity { request_number, .. } => {
+                self.refused_movement_responses(request_number, player_id)
+            }
+            _ => vec![]
+        }
+    }
+
+    /// Produces the reply to a movement request that will not be performed, which gives the player entity's current
+    /// (unchanged) position so that the client can correct its own.
+    fn refused_movement_responses(&self, request_number: u32, player_id: Id) -> Vec<messages::FromServer> {
+        let new_position = self.game_map.lock().entity_by_id(player_id).unwrap().pos;
+        vec![messages::FromServer::YourEntityMoved { request_number, new_position }]
+    }
+
+    /// Perform the oldest of the movements that were queued due to being requested before the player entity was
+    /// allowed to move again.
+    async fn perform_next_queued_movement(&mut self, player_id: Id) -> Result<Vec<messages::FromServer>> {
+        if let Some((request_number, direction)) = self.queued_movements.pop_front() {
+            self.log(&format!("Performing queued movement request #{}", request_number));
+            self.move_player_entity(request_number, direction, player_id).await
+        }
+        else {
+            Ok(vec![])
+        }
+    }
+
+    /// Attempt to move this handler's player entity in the specified direction. Produces the message(s) that are to be
+    /// sent to the remote client in response - this will always include a [`messages::FromServer::YourEntityMoved`]
+    /// message with the given request number regardless of whether or not the movement could go ahead.
+    async fn move_player_entity(
+        &mut self, request_number: u32, direction: Direction, player_id: Id
+    ) -> Result<Vec<messages::FromServer>> {
+        let mut responses = Vec::new();
+
+        let movement_option = self.game_map.lock().move_entity_towards(player_id, direction);
+
+        if let Some(EntityMovement {
+            old_position,
+            new_position,
+            smashed_tile_option,
+            movement_time,
+            triggered_trap_option,
+            collected_gem_option
+        }) = movement_option
+        {
+            // The player entity may not move again until it has finished moving onto the destination tile:
+            self.next_movement_instant = Instant::now() + Duration::from_secs_f32(movement_time);
+
+            // If moving into a new chunk, ensure chunks adjacent to the destination chunk are loaded and create
+            // message(s) to provide them to the client:
+            if old_position.as_chunk_coords() != new_position.as_chunk_coords() {
+                let msgs = self
+                    .provide_chunks_at_and_surrounding_with_entities(new_position.as_chunk_coords(), player_id)
+                    .await?;
+
+                responses.extend(msgs);
+            }
+
+            // Inform other tasks of the entity's movement:
+            self.broadcast_map_change(
+                maps::Modification::EntityMoved { entity_id: player_id, old_position, new_position, direction },
+                player_id
+            );
+
+            // Confirm to the remote client that the movement could go ahead:
+            responses.push(messages::FromServer::YourEntityMoved { request_number, new_position });
+
+            if let Some(smashed_tile) = smashed_tile_option {
+                self.log(&format!("Smashed tile {:?} at {}", smashed_tile, new_position));
+
+                // If the smashed tile yields gems, calculate a quantity within the determined range, provide
+                // that quantity of gems to the player on the server side, and send a message to the remote
+                // client informing them of how many more gems they now have:
+
+                if let Some(gem_yield) = smashed_tile.get_gem_yield() {
+                    // Random gem quantity within the range specified by the yield specific by the tile type:
+                    let quantity_increase = random_yield_quantity(&gem_yield);
+                    METRICS.gems_mined(gem_yield.gem, quantity_increase);
+
+                    // Increase gem quantity on the server side:
+                    if let Some(entity) = self.game_map.lock().entity_by_id_mut(player_id) {
+                        entity.gem_collection.increase_quantity(gem_yield.gem, quantity_increase);
+                    }
+
+                    // Produce message to send to the remote client to inform them of how many more gems they
+                    // now have:
+                    responses
+                        .push(messages::FromServer::YouCollectedGems { gem_type: gem_yield.gem, quantity_increase });
+
+                    self.log(&format!("Obtained an additional {} gems of type {:?}", quantity_increase, gem_yield.gem));
+                }
+            }
+
+            if let Some(placed_trap) = triggered_trap_option {
+                let msgs = self.player_triggered_trap(new_position, placed_trap, player_id).await;
+                responses.extend(msgs);
+            }
+
+            if let Some(gem) = collected_gem_option {
+                self.log(&format!("Collected gem {:?} from the ground at {}", gem, new_position));
+
+                // The gem will have already been added to the player's gem collection so inform the remote client of
+                // that and of the gem's removal from the ground:
+                responses.push(messages::FromServer::GroundGemCollected {
+                    position: new_position,
+                    collected_by_entity_id: player_id
+                });
+                responses.push(messages::FromServer::YouCollectedGems { gem_type: gem, quantity_increase: 1 });
+
+                // Inform other tasks:
+                self.broadcast_map_change(
+                    maps::Modification::GroundGemCollected { position: new_position, collected_by: player_id },
+                    player_id
+                );
+            }
+        }
+
+        if responses.is_empty() {
+            // The `responses` vector will only be empty if the movement was not allowed. In that case, inform
+            // the remote client:
+
+            let new_position = self.game_map.lock().entity_by_id(player_id).unwrap().pos;
+            Ok(vec![messages::FromServer::YourEntityMoved { request_number, new_position }])
+        }
+        else {
+            Ok(responses)
+        }
+    }
+
+    /// Apply the effect of a trap (which will have already been removed from the map) triggered by this handler's
+    /// player entity and inform other tasks. Produces the message(s) that are to be sent to the remote client.
+    async fn player_triggered_trap(
+        &mut self, position: TileCoords, placed_trap: PlacedTrap, player_id: Id
+    ) -> Vec<messages::FromServer> {
+        self.log(&format!(
+            "Triggered {:?} trap at {} placed by entity {}",
+            placed_trap.trap, position, placed_trap.placed_by
+        ));
+
+        let mut responses = vec![messages::FromServer::TrapTriggered {
+            position,
+            trap: placed_trap.trap,
+            triggered_by_entity_id: player_id
+        }];
+
+        let emeralds_stolen = match placed_trap.trap {
+            items::Trap::Speed => {
+                let msgs = self.apply_player_status_effect(StatusEffect::Slowed, player_id).await;
+                responses.extend(msgs);
+
+                0
+            }
+
+            items::Trap::Theft => {
+                let mut map = self.game_map.lock();
+
+                // Stolen emeralds can only be given to the player that placed the trap if their entity is still
+                // present on the map:
+                if map.entity_by_id(placed_trap.placed_by).is_some() {
+                    let quantity = map
+                        .entity_by_id_mut(player_id)
+                        .map(|entity| {
+                            let quantity = entity.gem_collection.get_quantity(gems::Gem::Emerald)
+                                * THEFT_TRAP_STOLEN_EMERALDS_PERCENTAGE
+                                / 100;
+                            entity.gem_collection.decrease_quantity(gems::Gem::Emerald, quantity);
+                            quantity
+                        })
+                        .unwrap_or(0);
+
+                    if let Some(setter) = map.entity_by_id_mut(placed_trap.placed_by) {
+                        setter.gem_collection.increase_quantity(gems::Gem::Emerald, quantity);
+                    }
+
+                    quantity
+                }
+                else {
+                    0
+                }
+            }
+        };
+
+        if emeralds_stolen > 0 {
+            self.log(&format!("Lost {} emeralds to entity {}", emeralds_stolen, placed_trap.placed_by));
+
+            responses.push(messages::FromServer::YouLostGems {
+                gem_type: gems::Gem::Emerald,
+                quantity_decrease: emeralds_stolen
+            });
+        }
+
+        // Inform other tasks (including that of the player who placed the trap):
+        self.broadcast_map_change(
+            maps::Modification::TrapTriggered { position, placed_trap, triggered_by: player_id, emeralds_stolen },
+            player_id
+        );
+
+        responses
+    }
+
+    /// Apply the effect of a bomb blast to this handler's player entity. Produces the message(s) that are to be sent to
+    /// the remote client.
+    async fn player_caught_in_blast(
+        &mut self, effect: BlastEffect, player_id: Id
+    ) -> Result<Vec<messages::FromServer>> {
+        match effect {
+            BlastEffect::Killed => self.kill_player(player_id).await,
+            BlastEffect::Stunned => Ok(self.apply_player_status_effect(StatusEffect::Stunned, player_id).await)
+        }
+    }
+
+    /// Kill this handler's player entity - it loses a portion of each type of gem it holds (which are scattered on the
+    /// ground around where it died) before respawning at the suitable position nearest to the spawn position. Other
+    /// tasks are informed and the message(s) that are to be sent to the remote client are produced (including those
+    /// providing the chunks surrounding the respawn position).
+    async fn kill_player(&mut self, player_id: Id) -> Result<Vec<messages::FromServer>> {
+        let mut responses = Vec::new();
+
+        let (lost_gems, old_position_option, new_position) = entities::place_at_spawn_position(
+            self.storage.as_ref(),
+            &self.game_map,
+            entities::SPAWN_POSITION,
+            |map, new_position| {
+                let lost_gems: Vec<(gems::Gem, u32)> = map
+                    .entity_by_id_mut(player_id)
+                    .map(|entity| {
+                        gems::Gem::iter()
+                            .map(|gem| {
+                                let quantity =
+                                    entity.gem_collection.get_quantity(gem) * DEATH_LOST_GEMS_PERCENTAGE / 100;
+                                entity.gem_collection.decrease_quantity(gem, quantity);
+                                (gem, quantity)
+                            })
+                            .filter(|(_, quantity)| *quantity > 0)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                (lost_gems, map.teleport_entity(player_id, new_position), new_position)
+            }
+        )
+        .await?;
+
+        if let Some(old_position) = old_position_option {
+            self.log(&format!("Player entity killed at {} and respawned at {}", old_position, new_position));
+
+            // Scatter the lost gems on the ground around where the player entity died:
+            let placed_gems = self.game_map.lock().scatter_ground_gems(old_position, &lost_gems);
+
+            for (gem_type, quantity_decrease) in lost_gems {
+                responses.push(messages::FromServer::YouLostGems { gem_type, quantity_decrease });
+            }
+
+            responses.push(messages::FromServer::YouDied { respawn_position: new_position });
+
+            // Ensure the chunks surrounding the respawn position are loaded and provided to the remote client:
+            let msgs =
+                self.provide_chunks_at_and_surrounding_with_entities(new_position.as_chunk_coords(), player_id).await?;
+            responses.extend(msgs);
+
+            // Inform the remote client of the scattered gems within its loaded chunks:
+            for (position, gem) in &placed_gems {
+                if self.remote_loaded_chunk_coords.contains(&position.as_chunk_coords()) {
+                    responses.push(messages::FromServer::GroundGemPlaced { position: *position, gem: *gem });
+                }
+            }
+
+            // Inform other tasks of the death and of the scattered gems:
+
+            self.broadcast_map_change(
+                maps::Modification::EntityDied { entity_id: player_id, old_position, new_position },
+                player_id
+            );
+
+            if !placed_gems.is_empty() {
+                self.broadcast_map_change(maps::Modification::GroundGemsPlaced(placed_gems), player_id);
+            }
+        }
+
+        Ok(responses)
+    }
+
+    /// Perform an action requested by the server's operators on this handler's player entity. Produces the message(s)
+    /// that are to be sent to the remote client.
+    async fn perform_admin_action(&mut self, action: PlayerAction, player_id: Id) -> Result<Vec<messages::FromServer>> {
+        self.log(&format!("Performing administrative action: {}", action));
+
+        let responses = match action {
+            PlayerAction::Teleport(target) => return self.teleport_player(target, player_id).await,
+
+            PlayerAction::GiveGems(gem_type, quantity_increase) => self
+                .game_map
+                .lock()
+                .entity_by_id_mut(player_id)
+                .map(|entity| {
+                    entity.gem_collection.increase_quantity(gem_type, quantity_increase);
+                    messages::FromServer::YouCollectedGems { gem_type, quantity_increase }
+                })
+                .into_iter()
+                .collect(),
+
+            PlayerAction::TakeGems(gem_type, quantity) => self
+                .game_map
+                .lock()
+                .entity_by_id_mut(player_id)
+                .map(|entity| {
+                    // Players cannot be left with a negative quantity of gems:
+                    let quantity_decrease = quantity.min(entity.gem_collection.get_quantity(gem_type));
+                    entity.gem_collection.decrease_quantity(gem_type, quantity_decrease);
+                    messages::FromServer::YouLostGems { gem_type, quantity_decrease }
+                })
+                .into_iter()
+                .collect(),
+
+            PlayerAction::GiveItems(item, quantity) => self
+                .game_map
+                .lock()
+                .entity_by_id_mut(player_id)
+                .map(|entity| {
+                    entity.item_inventory.give_quantity(item, quantity);
+                    messages::FromServer::YourInventoryChanged(entity.item_inventory.clone())
+                })
+                .into_iter()
+                .collect(),
+
+            PlayerAction::TakeItems(item, quantity) => self
+                .game_map
+                .lock()
+                .entity_by_id_mut(player_id)
+                .map(|entity| {
+                    // Players cannot be left with a negative quantity of items:
+                    let quantity = quantity.min(entity.item_inventory.has_how_many(item));
+                    entity.item_inventory.take_quantity(item, quantity);
+                    messages::FromServer::YourInventoryChanged(entity.item_inventory.clone())
+                })
+                .into_iter()
+                .collect()
+        };
+
+        Ok(responses)
+    }
+
+    /// Instantly move this handler's player entity to the suitable position nearest to the given target position.
+    /// Other tasks are informed and the message(s) that are to be sent to the remote client are produced (including
+    /// those providing the chunks surrounding the new position).
+    async fn teleport_player(&mut self, target: TileCoords, player_id: Id) -> Result<Vec<messages::FromServer>> {
+        let mut responses = Vec::new();
+
+        let (old_position_option, new_position) =
+            entities::place_at_spawn_position(self.storage.as_ref(), &self.game_map, target, |map, new_position| {
+                (map.teleport_entity(player_id, new_position), new_position)
+            })
+            .await?;
+
+        if let Some(old_position) = old_position_option {
+            self.log(&format!("Player entity teleported from {} to {}", old_position, new_position));
+
+            // Movements requested before the teleport would no longer be from the expected position:
+            self.queued_movements.clear();
+
+            responses.push(messages::FromServer::YouTeleported { position: new_position });
+
+            let msgs =
+                self.provide_chunks_at_and_surrounding_with_entities(new_position.as_chunk_coords(), player_id).await?;
+            responses.extend(msgs);
+
+            // Inform other tasks of the teleport:
+            self.broadcast_map_change(
+                maps::Modification::EntityTeleported { entity_id: player_id, old_position, new_position },
+                player_id
+            );
+        }
+
+        Ok(responses)
+    }
+
+    /// Apply the given status effect to this handler's player entity (or refresh its remaining time should it already
+    /// be active) and inform other tasks. Produces the message(s) that are to be sent to the remote client.
+    async fn apply_player_status_effect(&mut self, effect: StatusEffect, player_id: Id) -> Vec<messages::FromServer> {
+        // Ensure the remaining times of any already active effects are up to date before applying the new effect:
+        let mut responses = self.update_player_status_effects(player_id).await;
+
+        let time_until_next_expiry = self.game_map.lock().entity_by_id_mut(player_id).and_then(|entity| {
+            entity.status_effects.apply(effect);
+            entity.status_effects.time_until_next_expiry()
+        });
+        self.status_effects_expiry_instant =
+            time_until_next_expiry.map(|secs| self.status_effects_updated_instant + Duration::from_secs_f32(secs));
+
+        self.log(&format!("Status effect {} applied to player entity", effect));
+
+        // Inform other tasks of the applied effect:
+        self.broadcast_map_change(maps::Modification::StatusEffectStarted(player_id, effect), player_id);
+
+        responses.push(messages::FromServer::StatusEffectStarted { entity_id: player_id, effect });
+        responses
+    }
+
+    /// Decrease the remaining times of the status effects applied to this handler's player entity based on how much
+    /// time has passed since they were last updated. Other tasks are informed of any effects that have expired and
+    /// messages are produced to inform the remote client of them also.
+    async fn update_player_status_effects(&mut self, player_id: Id) -> Vec<messages::FromServer> {
+        let now = Instant::now();
+        let delta = now.duration_since(self.status_effects_updated_instant).as_secs_f32();
+        self.status_effects_updated_instant = now;
+
+        let (expired_effects, time_until_next_expiry) = self
+            .game_map
+            .lock()
+            .entity_by_id_mut(player_id)
+            .map(|entity| (entity.status_effects.update(delta), entity.status_effects.time_until_next_expiry()))
+            .unwrap_or_default();
+
+        self.status_effects_expiry_instant = time_until_next_expiry.map(|secs| now + Duration::from_secs_f32(secs));
+
+        let mut responses = Vec::new();
+
+        for effect in expired_effects {
+            self.log(&format!("Status effect {} applied to player entity has expired", effect));
+
+            self.broadcast_map_change(maps::Modification::StatusEffectEnded(player_id, effect), player_id);
+
+            responses.push(messages::FromServer::StatusEffectEnded { entity_id: player_id, effect });
         }
+
+        responses
     }
 
-    /// May produce a message that is to be sent to the client based on map modification messages received from other
-    /// connection handling tasks.
-    async fn handle_map_change(&mut self, modification: maps::Modification) -> Option<messages::FromServer> {
-        match modification {
+    /// Informs every task (including this one, which ignores it) of the given change made by this task to the game
+    /// map.
+    fn broadcast_map_change(&self, modification: maps::Modification, player_id: Id) {
+        let map_change = maps::MapChange { sent_by: Some(player_id), modification };
+
+        // Sending cannot fail as this task holds a receiver:
+        self.map_changes_sender.send(map_change).unwrap();
+    }
+
+    /// May produce message(s) that are to be sent to the client based on map modification messages received from
+    /// other connection handling tasks.
+    async fn handle_map_change(&mut self, map_change: maps::MapChange, player_id: Id) -> Vec<messages::FromServer> {
+        // This task has already dealt with the changes that it made itself:
+        if map_change.sent_by == Some(player_id) {
+            return vec![];
+        }
+
+        match map_change.modification {
             maps::Modification::TileChanged(position, tile) => {
                 let is_position_loaded = self.remote_loaded_chunk_coords.contains(&position.as_chunk_coords());
-                is_position_loaded.then(|| messages::FromServer::ChangeTile(position, tile))
+                is_position_loaded.then(|| messages::FromServer::ChangeTile(position, tile)).into_iter().collect()
             }
 
             maps::Modification::EntityMoved { entity_id, old_position, new_position, direction } => {
@@ -429,11 +1523,11 @@ impl Handler {
 
                 if was_in_loaded && is_in_loaded {
                     // Entity moving within the bounds of the client's loaded chunks:
-                    Some(messages::FromServer::MoveEntity(entity_id, new_position, direction))
+                    vec![messages::FromServer::MoveEntity(entity_id, new_position, direction)]
                 }
                 else if was_in_loaded {
                     // Entity moved out of the client's loaded chunks:
-                    Some(messages::FromServer::ShouldUnloadEntity(entity_id))
+                    vec![messages::FromServer::ShouldUnloadEntity(entity_id)]
                 }
                 else if is_in_loaded {
                     // Entity just moved into the client's loaded chunks:
@@ -441,37 +1535,203 @@ impl Handler {
                         .lock()
                         .entity_by_id(entity_id)
                         .map(|entity| messages::FromServer::ProvideEntity(entity_id, entity.clone()))
+                        .into_iter()
+                        .collect()
                 }
                 else {
-                    None
+                    vec![]
                 }
             }
 
-            maps::Modification::EntityAdded(entity_id) => {
-                self.game_map.lock().entity_by_id(entity_id).and_then(|entity| {
+            maps::Modification::EntityAdded(entity_id) => self
+                .game_map
+                .lock()
+                .entity_by_id(entity_id)
+                .and_then(|entity| {
                     self.remote_loaded_chunk_coords
                         .contains(&entity.pos.as_chunk_coords())
                         .then(|| messages::FromServer::ProvideEntity(entity_id, entity.clone()))
                 })
-            }
+                .into_iter()
+                .collect(),
 
             maps::Modification::EntityRemoved(entity_id, chunk_coords) => self
                 .remote_loaded_chunk_coords
                 .contains(&chunk_coords)
-                .then(|| messages::FromServer::ShouldUnloadEntity(entity_id)),
+                .then(|| messages::FromServer::ShouldUnloadEntity(entity_id))
+                .into_iter()
+                .collect(),
 
             maps::Modification::BombPlaced(position, placed_by_entity_id) => self
                 .remote_loaded_chunk_coords
                 .contains(&position.as_chunk_coords())
-                .then(|| messages::FromServer::BombPlaced { placed_by_entity_id, position }),
+                .then(|| messages::FromServer::BombPlaced { placed_by_entity_id, position })
+                .into_iter()
+                .collect(),
+
+            maps::Modification::BombsDetonated { placed_by, positions } => {
+                // Only inform the client of detonated bombs within its loaded chunks:
+                let positions: Vec<TileCoords> = positions
+                    .into_iter()
+                    .filter(|pos| self.remote_loaded_chunk_coords.contains(&pos.as_chunk_coords()))
+                    .collect();
+
+                if positions.is_empty() {
+                    vec![]
+                }
+                else {
+                    vec![messages::FromServer::BombsDetonated { placed_by_entity_id: placed_by, positions }]
+                }
+            }
+
+            // Effects of bomb blasts and administrative actions are applied by the task responsible for the affected
+            // entity (see `Handler::handle_established_connection`) and are otherwise irrelevant:
+            maps::Modification::CaughtInBlast { .. } | maps::Modification::AdminAction { .. } => vec![],
+
+            maps::Modification::EntityDied { entity_id, old_position, new_position } => {
+                let mut msgs = Vec::new();
+
+                if self.remote_loaded_chunk_coords.contains(&old_position.as_chunk_coords()) {
+                    msgs.push(messages::FromServer::EntityDied { entity_id, position: old_position });
+                }
 
-            maps::Modification::BombsDetonated(placed_by_entity_id) => {
-                self.game_map.lock().entity_by_id(placed_by_entity_id).map(|entity| {
-                    messages::FromServer::BombsDetonated {
-                        placed_by_entity_id,
-                        in_and_around_chunk_coords: entity.pos.as_chunk_coords()
+                // Provide the respawned entity should it now be within the client's loaded chunks:
+                if self.remote_loaded_chunk_coords.contains(&new_position.as_chunk_coords()) {
+                    if let Some(entity) = self.game_map.lock().entity_by_id(entity_id) {
+                        msgs.push(messages::FromServer::ProvideEntity(entity_id, entity.clone()));
                     }
+                }
+
+                msgs
+            }
+
+            maps::Modification::TrapPlaced(position, placed_trap) => {
+                // Traps placed by other players are disguised as gems on the ground:
+                let is_position_loaded = self.remote_loaded_chunk_coords.contains(&position.as_chunk_coords());
+                let is_own_trap = placed_trap.placed_by == player_id;
+
+                (is_position_loaded && !is_own_trap)
+                    .then(|| messages::FromServer::GroundGemPlaced { position, gem: placed_trap.disguise })
+                    .into_iter()
+                    .collect()
+            }
+
+            maps::Modification::TrapTriggered { position, placed_trap, triggered_by, emeralds_stolen } => {
+                let mut msgs = Vec::new();
+
+                if self.remote_loaded_chunk_coords.contains(&position.as_chunk_coords()) {
+                    msgs.push(messages::FromServer::TrapTriggered {
+                        position,
+                        trap: placed_trap.trap,
+                        triggered_by_entity_id: triggered_by
+                    });
+                }
+
+                // The stolen emeralds will have already been given to the player that placed the trap so inform their
+                // remote client:
+                if placed_trap.placed_by == player_id && emeralds_stolen > 0 {
+                    msgs.push(messages::FromServer::YouCollectedGems {
+                        gem_type: gems::Gem::Emerald,
+                        quantity_increase: emeralds_stolen
+                    });
+                }
+
+                msgs
+            }
+
+            maps::Modification::GroundGemCollected { position, collected_by } => {
+                if self.remote_loaded_chunk_coords.contains(&position.as_chunk_coords()) {
+                    vec![messages::FromServer::GroundGemCollected { position, collected_by_entity_id: collected_by }]
+                }
+                else {
+                    vec![]
+                }
+            }
+
+            maps::Modification::GroundGemsPlaced(placed_gems) => placed_gems
+                .into_iter()
+                .filter(|(position, _)| self.remote_loaded_chunk_coords.contains(&position.as_chunk_coords()))
+                .map(|(position, gem)| messages::FromServer::GroundGemPlaced { position, gem })
+                .collect(),
+
+            maps::Modification::StatusEffectStarted(entity_id, effect) => self
+                .game_map
+                .lock()
+                .entity_by_id(entity_id)
+                .and_then(|entity| {
+                    self.remote_loaded_chunk_coords
+                        .contains(&entity.pos.as_chunk_coords())
+                        .then(|| messages::FromServer::StatusEffectStarted { entity_id, effect })
+                })
+                .into_iter()
+                .collect(),
+
+            maps::Modification::StatusEffectEnded(entity_id, effect) => self
+                .game_map
+                .lock()
+                .entity_by_id(entity_id)
+                .and_then(|entity| {
+                    self.remote_loaded_chunk_coords
+                        .contains(&entity.pos.as_chunk_coords())
+                        .then(|| messages::FromServer::StatusEffectEnded { entity_id, effect })
+                })
+                .into_iter()
+                .collect(),
+
+            maps::Modification::ChatMessage { sender, position, channel, text } => {
+                // Proximity chat is only delivered should the sender be within the client's loaded chunks:
+                let is_deliverable = match channel {
+                    chat::Channel::Proximity => self.remote_loaded_chunk_coords.contains(&position.as_chunk_coords()),
+                    chat::Channel::Global => true
+                };
+
+                is_deliverable
+                    .then(|| messages::FromServer::ChatMessage { channel, sender_entity_id: sender, text })
+                    .into_iter()
+                    .collect()
+            }
+
+            maps::Modification::FacialExpressionChanged(entity_id, expression) => self
+                .game_map
+                .lock()
+                .entity_by_id(entity_id)
+                .and_then(|entity| {
+                    self.remote_loaded_chunk_coords
+                        .contains(&entity.pos.as_chunk_coords())
+                        .then(|| messages::FromServer::FacialExpressionChanged { entity_id, expression })
+                })
+                .into_iter()
+                .collect(),
+
+            maps::Modification::AppearanceChanged(entity_id, appearance) => self
+                .game_map
+                .lock()
+                .entity_by_id(entity_id)
+                .and_then(|entity| {
+                    self.remote_loaded_chunk_coords
+                        .contains(&entity.pos.as_chunk_coords())
+                        .then(|| messages::FromServer::AppearanceChanged { entity_id, appearance })
                 })
+                .into_iter()
+                .collect(),
+
+            maps::Modification::LeaderboardChanged => self.leaderboard_update(player_id),
+
+            maps::Modification::EntityTeleported { entity_id, old_position, new_position } => {
+                let mut msgs = Vec::new();
+
+                if self.remote_loaded_chunk_coords.contains(&old_position.as_chunk_coords()) {
+                    msgs.push(messages::FromServer::ShouldUnloadEntity(entity_id));
+                }
+
+                // Provide the teleported entity should it now be within the client's loaded chunks:
+                if self.remote_loaded_chunk_coords.contains(&new_position.as_chunk_coords()) {
+                    if let Some(entity) = self.game_map.lock().entity_by_id(entity_id) {
+                        msgs.push(messages::FromServer::ProvideEntity(entity_id, entity.clone()));
+                    }
+                }
+
+                msgs
             }
         }
     }
@@ -502,9 +1762,9 @@ impl Handler {
             // the chunks and any entities in that chunk:
 
             let chunk =
-                maps::chunks::get_or_load_or_generate_chunk(self.db_pool.acquire().await?, &self.game_map, coords)
-                    .await;
-            msgs.push(messages::FromServer::ProvideChunk(coords, chunk));
+                maps::chunks::get_or_load_or_generate_chunk(self.storage.as_ref(), &self.game_map, coords).await?;
+            // Traps placed by other players should appear to the remote client as gems on the ground:
+            msgs.push(messages::FromServer::ProvideChunk(coords, chunk.as_seen_by(player_id)));
 
             // Get entities in the chunk but filter out this task's own player entity:
             let entities_in_chunk =
@@ -559,12 +1819,16 @@ impl Handler {
 
     /// Informs the game map that the chunk at the specified chunk coordinates is no longer loaded by this task's
     /// remote client. If it is found that the chunk is at that point not loaded by any clients, then it is saved to
-    /// the database and removed from the server's loaded chunks collection.
-    async fn chunk_not_needed(&self, coords: ChunkCoords) -> maps::chunks::Result<()> {
+    /// storage and removed from the server's loaded chunks collection.
+    async fn chunk_not_needed(&self, coords: ChunkCoords) -> storage::Result<()> {
+        // Held until the unloaded chunk is saved so that no flush saves an older copy of it meanwhile:
+        let save_locks = self.game_map.lock().save_locks();
+        let _save_guard = save_locks.chunks.lock(coords).await;
+
         let unloaded_chunk_option = self.game_map.lock().chunk_not_in_use(coords);
 
         if let Some(unloaded_chunk) = unloaded_chunk_option {
-            maps::chunks::save_chunk(self.db_pool.acquire().await?, coords, &unloaded_chunk).await?;
+            maps::chunks::save_chunk(self.storage.as_ref(), coords, &unloaded_chunk).await?;
         }
 
         Ok(())
@@ -583,14 +1847,19 @@ impl Handler {
     }
 }
 
+/// Random gem quantity within the range specified by the given gem yield (inclusive).
+fn random_yield_quantity(gem_yield: &gems::Yield) -> u32 {
+    rand::thread_rng().gen_range(gem_yield.minimum_quantity..(gem_yield.maximum_quantity + 1))
+}
+
 #[derive(Error, Debug)]
 enum Error {
     #[error("Networking error - {0}")]
     Network(#[from] networking::Error),
-    #[error("Database error - {0}")]
-    Database(#[from] sqlx::Error),
-    #[error("Chunk access error - {0}")]
-    Chunk(#[from] maps::chunks::Error)
+    #[error("Storage error - {0}")]
+    Storage(#[from] storage::Error),
+    #[error("Spawning error - {0}")]
+    Spawn(#[from] entities::Error)
 }
 
 type Result<T> = std::result::Result<T, Error>;
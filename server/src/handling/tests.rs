@@ -7,9 +7,12 @@ use std::{
 
 use parking_lot::Mutex;
 use shared::{
+    effects::StatusEffects,
     gems, items,
     maps::{
-        entities::{ClothingColour, Direction, Entity, FacialExpression, HairColour, HairStyle, SkinColour},
+        entities::{
+            Appearance, ClothingColour, Direction, Entity, FacialExpression, HairColour, HairStyle, SkinColour
+        },
         Chunk, ChunkCoords, OffsetCoords, Tile, TileCoords, CHUNK_WIDTH
     }
 };
@@ -17,15 +20,35 @@ use shared::{
 use super::*;
 
 async fn make_test_handler() -> Handler {
-    let (map_changes_sender, map_changes_receiver) = broadcast::channel(5);
+    let (map_changes_sender, map_changes_receiver) = broadcast::channel(crate::MAP_CHANGES_CHANNEL_CAPACITY);
 
     super::Handler {
         address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0),
-        db_pool: sqlx::postgres::PgPoolOptions::new().connect_lazy("postgres://").unwrap(),
+        storage: Arc::new(crate::storage::MemoryStorage::default()),
         game_map: Arc::new(Mutex::new(ServerMap::new_with_default_generator(0))),
         map_changes_sender,
         map_changes_receiver,
-        remote_loaded_chunk_coords: Vec::new()
+        remote_loaded_chunk_coords: Vec::new(),
+        queued_movements: VecDeque::new(),
+        next_movement_instant: Instant::now(),
+        status_effects_updated_instant: Instant::now(),
+        status_effects_expiry_instant: None,
+        facial_expression_expiry_instant: None,
+        next_appearance_change_instant: Instant::now(),
+        shutdown_listener: crate::shutdown::Coordinator::new().listener(),
+        shutdown_deadline: None,
+        chat_filter: Arc::new(crate::chat::WordFilter::new(vec!["bad"])),
+        chat_rate_limiter: crate::chat::RateLimiter::default(),
+        leaderboard: Arc::new(Mutex::new(Leaderboard::default())),
+        leaderboard_score_option: None,
+        watching_leaderboard: false,
+        sent_standings_option: None,
+        client_registry: Arc::new(crate::sessions::ClientRegistry::default()),
+        duplicate_policy: DuplicatePolicy::TakeOver,
+        token_issuer: Arc::new(TokenIssuer::new(vec![b"secret".to_vec()], 60 * 60)),
+        legacy_client_ids_until: None,
+        message_limiter: MessageLimiter::new(Arc::new(rate_limiting::Config::default())),
+        max_message_size: 4096
     }
 }
 
@@ -43,7 +66,8 @@ impl Handler {
             hair_colour: HairColour::Black,
             gem_collection: gems::Collection::default(),
             item_inventory: items::Inventory::default(),
-            bombs_placed_count: 0
+            bombs_placed_count: 0,
+            status_effects: StatusEffects::default()
         };
         self.game_map.lock().add_entity(entity_id, entity);
 
@@ -58,6 +82,19 @@ impl Handler {
         self.game_map.lock().add_chunk(coords, chunk);
         self.remote_loaded_chunk_coords.push(coords);
     }
+
+    /// Ensure that every change broadcast by this handler is ignored when received by this handler.
+    async fn assert_own_changes_ignored(&mut self, player_id: Id) {
+        while let Ok(map_change) = self.map_changes_receiver.try_recv() {
+            assert_eq!(map_change.sent_by, Some(player_id));
+            assert!(self.handle_map_change(map_change, player_id).await.is_empty());
+        }
+    }
+}
+
+/// Wraps the given modification as if it were broadcast by the task of some other player.
+fn from_other_task(modification: maps::Modification) -> maps::MapChange {
+    maps::MapChange { sent_by: Some(crate::id::generate_with_timestamp()), modification }
 }
 
 /// Ensure that no response is provided when an unexpected (i.e. sent after connection establishment) 'hello' message is
@@ -67,11 +104,48 @@ async fn handle_unexpected_hello_msg() {
     let mut handler = make_test_handler().await;
 
     let id = crate::id::generate_random();
-    let msg = messages::ToServer::Hello { client_id_option: None };
+    let msg = messages::ToServer::Hello { protocol_version: messages::PROTOCOL_VERSION, credentials_option: None };
 
     assert!(handler.handle_message(msg, id).await.unwrap().is_empty());
 }
 
+/// Ensure that a client speaking a newer protocol version (whose 'hello' message has a layout this server does not
+/// know) is refused with a 'protocol rejected' message rather than failing to decode.
+#[tokio::test(flavor = "multi_thread")]
+async fn refuse_too_new_hello() {
+    use futures_util::{SinkExt, StreamExt};
+
+    let mut handler = make_test_handler().await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let client = tokio::spawn(async move {
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (mut ws, _) = tokio_tungstenite::client_async(format!("ws://{}", address), stream).await.unwrap();
+
+        // The variant index and protocol version of a 'hello' message followed by fields unknown to this server:
+        let mut encoded = bincode::serialize(&(0u32, messages::PROTOCOL_VERSION + 1)).unwrap();
+        encoded.extend_from_slice(&[7, 0, 0, 0, 1, 2, 3]);
+        ws.send(tungstenite::Message::Binary(encoded)).await.unwrap();
+
+        match ws.next().await {
+            Some(Ok(tungstenite::Message::Binary(bytes))) => bincode::deserialize(&bytes).unwrap(),
+            other => panic!("Expected a binary message but received {:?}", other)
+        }
+    });
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let connection = Connection::new(stream, 1024).await.unwrap();
+    handler.handle_websocket_connection(connection).await.unwrap();
+
+    let response: messages::FromServer = client.await.unwrap();
+    assert!(matches!(
+        response,
+        messages::FromServer::ProtocolRejected { reason: messages::ProtocolRejection::ClientTooNew, .. }
+    ));
+}
+
 /// Ensure that a 'move my entity' message causes the entity's position on the game map to be appropriately updated, a
 /// response message is created, and that a message on the map changes broadcast channel is sent to inform other tasks
 /// of the change.
@@ -96,11 +170,11 @@ async fn handle_move_my_entity() {
     // Ensure the entity's position was changed appropriately:
     assert_eq!(handler.game_map.lock().entity_by_id(player_id).unwrap().pos, TileCoords { x: 6, y: 5 });
 
-    // A message should not be broadcast to this task's map changes recevier:
-    assert!(matches!(handler.map_changes_receiver.try_recv(), Err(broadcast::error::TryRecvError::Empty)));
+    // The message broadcast to this task's own map changes receiver should be ignored:
+    handler.assert_own_changes_ignored(player_id).await;
 
     // A message should have been sent to all the map changes receviers of other tasks:
-    let change = other_map_changes_receiver.recv().await.unwrap();
+    let change = other_map_changes_receiver.recv().await.unwrap().modification;
 
     assert!(matches!(
         change,
@@ -113,6 +187,88 @@ async fn handle_move_my_entity() {
     ));
 }
 
+/// Ensure that a 'move my entity' message received before the player entity is allowed to move again is queued rather
+/// than performed immediately, and that the queued movement is performed (with the correct request number) once the
+/// required amount of time has passed.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_move_my_entity_exceeding_movement_rate() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+
+    let first_msg = messages::ToServer::MoveMyEntity { request_number: 0, direction: Direction::Right };
+    assert_eq!(handler.handle_message(first_msg, player_id).await.unwrap().len(), 1);
+
+    // Second movement is sent immediately after the first so should be queued:
+    let second_msg = messages::ToServer::MoveMyEntity { request_number: 1, direction: Direction::Right };
+    assert!(handler.handle_message(second_msg, player_id).await.unwrap().is_empty());
+
+    assert_eq!(handler.queued_movements.len(), 1);
+    assert_eq!(handler.game_map.lock().entity_by_id(player_id).unwrap().pos, TileCoords { x: 6, y: 5 });
+
+    // Wait until the player entity is allowed to move again and then perform the queued movement:
+    time::sleep_until(handler.next_movement_instant).await;
+    let responses = handler.perform_next_queued_movement(player_id).await.unwrap();
+
+    assert_eq!(responses.len(), 1);
+    assert!(matches!(
+        responses[0],
+        messages::FromServer::YourEntityMoved { request_number: 1, new_position: TileCoords { x: 7, y: 5 } }
+    ));
+
+    assert!(handler.queued_movements.is_empty());
+    assert_eq!(handler.game_map.lock().entity_by_id(player_id).unwrap().pos, TileCoords { x: 7, y: 5 });
+}
+
+/// Ensure that movement requests exceeding the maximum queue length are refused and that the remote client is informed
+/// of its player entity's actual position.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_move_my_entity_queue_full() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 0, y: 0 });
+
+    // Prevent the player entity from moving for the duration of the test:
+    handler.next_movement_instant = Instant::now() + Duration::from_secs(60);
+
+    for request_number in 0..MAX_QUEUED_MOVEMENTS as u32 {
+        let msg = messages::ToServer::MoveMyEntity { request_number, direction: Direction::Up };
+        assert!(handler.handle_message(msg, player_id).await.unwrap().is_empty());
+    }
+
+    let request_number = MAX_QUEUED_MOVEMENTS as u32;
+    let msg = messages::ToServer::MoveMyEntity { request_number, direction: Direction::Up };
+    let responses = handler.handle_message(msg, player_id).await.unwrap();
+
+    assert_eq!(responses.len(), 1);
+    assert!(matches!(
+        responses[0],
+        messages::FromServer::YourEntityMoved { request_number: n, new_position: TileCoords { x: 0, y: 0 } }
+            if n == request_number
+    ));
+    assert_eq!(handler.queued_movements.len(), MAX_QUEUED_MOVEMENTS);
+}
+
+/// Ensure that a movement request discarded due to exceeding a rate limit is still answered with the player entity's
+/// actual position, while other discarded messages are not answered.
+#[tokio::test(flavor = "multi_thread")]
+async fn discarded_move_my_entity_answered() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 3, y: 4 });
+
+    let msg = messages::ToServer::MoveMyEntity { request_number: 9, direction: Direction::Left };
+    assert!(matches!(
+        handler.discarded_message_responses(&msg, player_id).as_slice(),
+        [messages::FromServer::YourEntityMoved { request_number: 9, new_position: TileCoords { x: 3, y: 4 } }]
+    ));
+
+    assert!(handler.discarded_message_responses(&messages::ToServer::PlaceBomb, player_id).is_empty());
+}
+
 /// Ensure that a 'move my entity' message that would fail due to a blocking tile or entity being in the way does
 /// not modify the player entity's position, and does *not* send a message on the map modifications channel. Also
 /// ensures that the client is sent a message informing them that their entity movement could not go ahead.
@@ -163,9 +319,11 @@ async fn handle_entity_moved_within_loaded_chunk() {
         direction: Direction::Right
     };
 
+    let player_id = crate::id::generate_random();
+
     assert!(matches!(
-        handler.handle_map_change(modification).await.unwrap(),
-        messages::FromServer::MoveEntity(id, TileCoords { x: 6, y: 5 }, _) if id == entity_id
+        handler.handle_map_change(from_other_task(modification), player_id).await.as_slice(),
+        [messages::FromServer::MoveEntity(id, TileCoords { x: 6, y: 5 }, _)] if *id == entity_id
     ));
 }
 
@@ -185,9 +343,11 @@ async fn handle_entity_moved_into_loaded_chunk() {
         direction: Direction::Left
     };
 
+    let player_id = crate::id::generate_random();
+
     assert!(matches!(
-        handler.handle_map_change(modification).await.unwrap(),
-        messages::FromServer::ProvideEntity(id, _) if id == entity_id
+        handler.handle_map_change(from_other_task(modification), player_id).await.as_slice(),
+        [messages::FromServer::ProvideEntity(id, _)] if *id == entity_id
     ));
 }
 
@@ -207,9 +367,11 @@ async fn handle_entity_moved_leaving_loaded_chunk() {
         direction: Direction::Left
     };
 
+    let player_id = crate::id::generate_random();
+
     assert!(matches!(
-        handler.handle_map_change(modification).await.unwrap(),
-        messages::FromServer::ShouldUnloadEntity(id) if id == entity_id
+        handler.handle_map_change(from_other_task(modification), player_id).await.as_slice(),
+        [messages::FromServer::ShouldUnloadEntity(id)] if *id == entity_id
     ));
 }
 
@@ -228,7 +390,9 @@ async fn handle_entity_moved_outside_loaded_chunk() {
         direction: Direction::Left
     };
 
-    assert!(handler.handle_map_change(modification).await.is_none());
+    let player_id = crate::id::generate_random();
+
+    assert!(handler.handle_map_change(from_other_task(modification), player_id).await.is_empty());
 }
 
 /// Ensure that a task produces a provide entity message to send to its remote client when it is informed via the map
@@ -242,9 +406,11 @@ async fn handle_entity_added_within_loaded_chunks() {
 
     let modification = maps::Modification::EntityAdded(entity_id);
 
+    let player_id = crate::id::generate_random();
+
     assert!(matches!(
-        handler.handle_map_change(modification).await.unwrap(),
-        messages::FromServer::ProvideEntity(id, _) if id == entity_id
+        handler.handle_map_change(from_other_task(modification), player_id).await.as_slice(),
+        [messages::FromServer::ProvideEntity(id, _)] if *id == entity_id
     ));
 }
 
@@ -259,9 +425,11 @@ async fn handle_entity_removed_within_loaded_chunks() {
 
     let modification = maps::Modification::EntityRemoved(entity_id, ChunkCoords { x: 0, y: 0 });
 
+    let player_id = crate::id::generate_random();
+
     assert!(matches!(
-        handler.handle_map_change(modification).await.unwrap(),
-        messages::FromServer::ShouldUnloadEntity(id) if id == entity_id
+        handler.handle_map_change(from_other_task(modification), player_id).await.as_slice(),
+        [messages::FromServer::ShouldUnloadEntity(id)] if *id == entity_id
     ));
 }
 
@@ -274,3 +442,1263 @@ async fn handle_smashed_rock_within_loaded_chunks() {
 async fn handle_smashed_rock_outside_loaded_chunks() {
     // TODO
 }
+
+/// Ensure that consuming an energy drink applies the speed boost status effect to the player entity and that the
+/// effect is later removed once it has expired.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_consume_energy_drink() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 0, y: 0 });
+
+    if let Some(entity) = handler.game_map.lock().entity_by_id_mut(player_id) {
+        entity.item_inventory.give_quantity(items::QuantitativeItem::EnergyDrink, 1);
+    }
+
+    let responses = handler.handle_message(messages::ToServer::ConsumeEnergyDrink, player_id).await.unwrap();
+
+    assert_eq!(responses.len(), 1);
+    assert!(matches!(
+        responses[0],
+        messages::FromServer::StatusEffectStarted { entity_id, effect: StatusEffect::SpeedBoost }
+            if entity_id == player_id
+    ));
+
+    {
+        let map = handler.game_map.lock();
+        let entity = map.entity_by_id(player_id).unwrap();
+        assert!(entity.status_effects.is_active(StatusEffect::SpeedBoost));
+        assert_eq!(entity.item_inventory.has_how_many(items::QuantitativeItem::EnergyDrink), 0);
+    }
+    assert!(handler.status_effects_expiry_instant.is_some());
+
+    // Pretend the full duration of the effect has passed since the status effects were last updated:
+    handler.status_effects_updated_instant -= Duration::from_secs_f32(StatusEffect::SpeedBoost.duration());
+
+    let responses = handler.update_player_status_effects(player_id).await;
+
+    assert_eq!(responses.len(), 1);
+    assert!(matches!(
+        responses[0],
+        messages::FromServer::StatusEffectEnded { entity_id, effect: StatusEffect::SpeedBoost }
+            if entity_id == player_id
+    ));
+
+    assert!(!handler
+        .game_map
+        .lock()
+        .entity_by_id(player_id)
+        .unwrap()
+        .status_effects
+        .is_active(StatusEffect::SpeedBoost));
+    assert!(handler.status_effects_expiry_instant.is_none());
+}
+
+/// Ensure that an attempt to consume an energy drink by a player that does not have one is ignored.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_consume_energy_drink_without_any() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 0, y: 0 });
+
+    let responses = handler.handle_message(messages::ToServer::ConsumeEnergyDrink, player_id).await.unwrap();
+
+    assert!(responses.is_empty());
+    assert!(!handler
+        .game_map
+        .lock()
+        .entity_by_id(player_id)
+        .unwrap()
+        .status_effects
+        .is_active(StatusEffect::SpeedBoost));
+}
+
+/// Ensure that placing a trap removes it from the player's inventory, adds it to the map, and informs other tasks.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_place_trap() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+
+    if let Some(entity) = handler.game_map.lock().entity_by_id_mut(player_id) {
+        entity.item_inventory.give_quantity(items::QuantitativeItem::SpeedTrap, 1);
+    }
+
+    let msg = messages::ToServer::PlaceTrap { trap: items::Trap::Speed, disguise: gems::Gem::Ruby };
+    assert!(handler.handle_message(msg, player_id).await.unwrap().is_empty());
+
+    {
+        let map = handler.game_map.lock();
+        let placed_trap = map.trap_at(TileCoords { x: 5, y: 5 }).unwrap();
+        assert_eq!(placed_trap.placed_by, player_id);
+        assert_eq!(
+            map.entity_by_id(player_id).unwrap().item_inventory.has_how_many(items::QuantitativeItem::SpeedTrap),
+            0
+        );
+    }
+
+    assert!(matches!(
+        other_map_changes_receiver.recv().await.unwrap().modification,
+        maps::Modification::TrapPlaced(TileCoords { x: 5, y: 5 }, PlacedTrap { trap: items::Trap::Speed, .. })
+    ));
+
+    // The trap should appear as a gem on the ground to other players but not to the player that placed it:
+    let other_player_id = crate::id::generate_random();
+    let modification = maps::Modification::TrapPlaced(
+        TileCoords { x: 5, y: 5 },
+        PlacedTrap { trap: items::Trap::Speed, placed_by: player_id, disguise: gems::Gem::Ruby }
+    );
+
+    assert!(matches!(
+        handler.handle_map_change(from_other_task(modification.clone()), other_player_id).await.as_slice(),
+        [messages::FromServer::GroundGemPlaced { position: TileCoords { x: 5, y: 5 }, gem: gems::Gem::Ruby }]
+    ));
+    assert!(handler.handle_map_change(from_other_task(modification), player_id).await.is_empty());
+}
+
+/// Ensure that a gem on the ground is collected when moved onto - it is removed from the (now dirty) chunk and added
+/// to the player's gem collection, the remote client is informed of both, and other tasks are informed of the gem's
+/// removal. A trap cannot be placed where there is a gem on the ground.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_move_my_entity_collecting_ground_gem() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+    let other_player_id = handler.add_test_entity(TileCoords { x: 0, y: 0 });
+
+    {
+        let mut map = handler.game_map.lock();
+        assert!(map.set_ground_gem_at(TileCoords { x: 6, y: 5 }, gems::Gem::Ruby));
+
+        let placed_trap =
+            PlacedTrap { trap: items::Trap::Speed, placed_by: other_player_id, disguise: gems::Gem::Ruby };
+        assert!(!map.set_trap_at(TileCoords { x: 6, y: 5 }, placed_trap));
+
+        map.take_dirty_chunk_coords();
+    }
+
+    let msg = messages::ToServer::MoveMyEntity { request_number: 0, direction: Direction::Right };
+    let responses = handler.handle_message(msg, player_id).await.unwrap();
+
+    assert!(responses.iter().any(|response| matches!(
+        response,
+        messages::FromServer::GroundGemCollected { position: TileCoords { x: 6, y: 5 }, collected_by_entity_id }
+            if *collected_by_entity_id == player_id
+    )));
+    assert!(responses.iter().any(|response| matches!(
+        response,
+        messages::FromServer::YouCollectedGems { gem_type: gems::Gem::Ruby, quantity_increase: 1 }
+    )));
+
+    {
+        let mut map = handler.game_map.lock();
+        assert!(map.ground_gem_at(TileCoords { x: 6, y: 5 }).is_none());
+        assert_eq!(map.entity_by_id(player_id).unwrap().gem_collection.get_quantity(gems::Gem::Ruby), 1);
+        assert_eq!(map.take_dirty_chunk_coords(), vec![ChunkCoords { x: 0, y: 0 }]);
+    }
+
+    let modification = other_map_changes_receiver.recv().await.unwrap().modification;
+    assert!(matches!(modification, maps::Modification::EntityMoved { .. }));
+
+    let modification = other_map_changes_receiver.recv().await.unwrap().modification;
+    assert!(matches!(
+        modification,
+        maps::Modification::GroundGemCollected { position: TileCoords { x: 6, y: 5 }, collected_by }
+            if collected_by == player_id
+    ));
+
+    // Other tasks inform their remote clients of the gem's removal:
+    assert!(matches!(
+        handler.handle_map_change(from_other_task(modification), other_player_id).await.as_slice(),
+        [messages::FromServer::GroundGemCollected { position: TileCoords { x: 6, y: 5 }, .. }]
+    ));
+}
+
+/// Ensure that a speed trap placed by another player is triggered when moved onto, slowing the player entity and
+/// removing the trap from the map.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_move_my_entity_triggering_speed_trap() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+    let other_player_id = handler.add_test_entity(TileCoords { x: 0, y: 0 });
+
+    handler.game_map.lock().set_trap_at(
+        TileCoords { x: 6, y: 5 },
+        PlacedTrap { trap: items::Trap::Speed, placed_by: other_player_id, disguise: gems::Gem::Emerald }
+    );
+
+    let msg = messages::ToServer::MoveMyEntity { request_number: 0, direction: Direction::Right };
+    let responses = handler.handle_message(msg, player_id).await.unwrap();
+
+    assert!(responses.iter().any(|response| matches!(
+        response,
+        messages::FromServer::TrapTriggered { trap: items::Trap::Speed, triggered_by_entity_id, .. }
+            if *triggered_by_entity_id == player_id
+    )));
+    assert!(responses.iter().any(|response| matches!(
+        response,
+        messages::FromServer::StatusEffectStarted { effect: StatusEffect::Slowed, .. }
+    )));
+
+    let map = handler.game_map.lock();
+    assert!(map.trap_at(TileCoords { x: 6, y: 5 }).is_none());
+    assert!(map.entity_by_id(player_id).unwrap().status_effects.is_active(StatusEffect::Slowed));
+}
+
+/// Ensure that triggering a theft trap transfers a portion of the player's emeralds to the player that placed it.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_move_my_entity_triggering_theft_trap() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+    let other_player_id = handler.add_test_entity(TileCoords { x: 0, y: 0 });
+
+    {
+        let mut map = handler.game_map.lock();
+        map.entity_by_id_mut(player_id).unwrap().gem_collection.increase_quantity(gems::Gem::Emerald, 100);
+        map.set_trap_at(
+            TileCoords { x: 6, y: 5 },
+            PlacedTrap { trap: items::Trap::Theft, placed_by: other_player_id, disguise: gems::Gem::Diamond }
+        );
+    }
+
+    let msg = messages::ToServer::MoveMyEntity { request_number: 0, direction: Direction::Right };
+    let responses = handler.handle_message(msg, player_id).await.unwrap();
+
+    assert!(responses.iter().any(|response| matches!(
+        response,
+        messages::FromServer::YouLostGems { gem_type: gems::Gem::Emerald, quantity_decrease: 25 }
+    )));
+
+    let map = handler.game_map.lock();
+    assert_eq!(map.entity_by_id(player_id).unwrap().gem_collection.get_quantity(gems::Gem::Emerald), 75);
+    assert_eq!(map.entity_by_id(other_player_id).unwrap().gem_collection.get_quantity(gems::Gem::Emerald), 25);
+}
+
+/// Ensure that a player does not trigger their own trap and that their trap is not disguised in chunks provided to
+/// them.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_move_my_entity_onto_own_trap() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+
+    handler.game_map.lock().set_trap_at(
+        TileCoords { x: 6, y: 5 },
+        PlacedTrap { trap: items::Trap::Speed, placed_by: player_id, disguise: gems::Gem::Emerald }
+    );
+
+    let msg = messages::ToServer::MoveMyEntity { request_number: 0, direction: Direction::Right };
+    let responses = handler.handle_message(msg, player_id).await.unwrap();
+
+    assert_eq!(responses.len(), 1);
+    assert!(matches!(responses[0], messages::FromServer::YourEntityMoved { .. }));
+
+    let map = handler.game_map.lock();
+    assert!(map.trap_at(TileCoords { x: 6, y: 5 }).is_some());
+
+    let chunk = map.loaded_chunk_at(ChunkCoords { x: 0, y: 0 }).unwrap();
+    assert_eq!(chunk.as_seen_by(player_id).get_traps().count(), 1);
+    assert_eq!(chunk.as_seen_by(player_id).get_ground_gems().count(), 0);
+    assert_eq!(chunk.as_seen_by(crate::id::generate_random()).get_traps().count(), 0);
+    assert_eq!(chunk.as_seen_by(crate::id::generate_random()).get_ground_gems().count(), 1);
+}
+
+/// Ensure that detonating bombs informs the tasks responsible for any entities caught in the blast of how they are
+/// affected based on their distance from the detonated bombs.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_detonate_bombs_catching_entities_in_blast() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 0, y: 0 });
+    let killed_id = handler.add_test_entity(TileCoords { x: 6, y: 6 });
+    let stunned_id = handler.add_test_entity(TileCoords { x: 8, y: 5 });
+    handler.add_test_entity(TileCoords { x: 10, y: 5 }); // Outside of the blast.
+
+    {
+        let mut map = handler.game_map.lock();
+        map.set_bomb_at(TileCoords { x: 5, y: 5 }, player_id);
+        map.entity_by_id_mut(player_id).unwrap().bombs_placed_count = 1;
+    }
+
+    assert!(handler.handle_message(messages::ToServer::DetonateBombs, player_id).await.unwrap().is_empty());
+    assert_eq!(handler.game_map.lock().entity_by_id(player_id).unwrap().bombs_placed_count, 0);
+
+    assert!(matches!(
+        other_map_changes_receiver.recv().await.unwrap().modification,
+        maps::Modification::BombsDetonated { placed_by, .. } if placed_by == player_id
+    ));
+
+    let mut caught_in_blast = Vec::new();
+    while let Ok(maps::Modification::CaughtInBlast { entity_id, effect }) =
+        other_map_changes_receiver.try_recv().map(|change| change.modification)
+    {
+        caught_in_blast.push((entity_id, effect));
+    }
+
+    assert_eq!(caught_in_blast.len(), 2);
+    assert!(caught_in_blast.contains(&(killed_id, BlastEffect::Killed)));
+    assert!(caught_in_blast.contains(&(stunned_id, BlastEffect::Stunned)));
+}
+
+/// Ensure that a player entity killed by a bomb blast loses half of its gems (which are scattered on the ground around
+/// where it died) and respawns at the spawn position.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_player_killed_by_blast() {
+    let mut handler = make_test_handler().await;
+
+    // Have the chunks surrounding the spawn position already loaded by the remote client:
+    for x in -1..2 {
+        for y in -1..2 {
+            handler.add_empty_chunk(ChunkCoords { x, y });
+        }
+    }
+    let player_id = handler.add_test_entity(TileCoords { x: 7, y: 7 });
+
+    if let Some(entity) = handler.game_map.lock().entity_by_id_mut(player_id) {
+        entity.gem_collection.increase_quantity(gems::Gem::Emerald, 10);
+        entity.gem_collection.increase_quantity(gems::Gem::Ruby, 3);
+    }
+
+    let responses = handler.player_caught_in_blast(BlastEffect::Killed, player_id).await.unwrap();
+
+    assert_eq!(responses.len(), 9);
+    assert!(responses.iter().any(|response| matches!(
+        response,
+        messages::FromServer::YouLostGems { gem_type: gems::Gem::Emerald, quantity_decrease: 5 }
+    )));
+    assert!(responses.iter().any(|response| matches!(
+        response,
+        messages::FromServer::YouLostGems { gem_type: gems::Gem::Ruby, quantity_decrease: 1 }
+    )));
+    assert!(matches!(
+        responses[2],
+        messages::FromServer::YouDied { respawn_position } if respawn_position == entities::SPAWN_POSITION
+    ));
+
+    let map = handler.game_map.lock();
+    let entity = map.entity_by_id(player_id).unwrap();
+    assert_eq!(entity.pos, entities::SPAWN_POSITION);
+    assert_eq!(entity.gem_collection.get_quantity(gems::Gem::Emerald), 5);
+    assert_eq!(entity.gem_collection.get_quantity(gems::Gem::Ruby), 2);
+
+    // The lost gems are scattered on the ground, the most valuable where the player entity died, and the remote client
+    // is informed of them:
+    assert_eq!(map.ground_gem_at(TileCoords { x: 7, y: 7 }), Some(&gems::Gem::Ruby));
+    assert_eq!(map.loaded_chunk_at(ChunkCoords { x: 0, y: 0 }).unwrap().get_ground_gems().count(), 6);
+    assert_eq!(
+        responses
+            .iter()
+            .filter(|response| matches!(
+                response,
+                messages::FromServer::GroundGemPlaced { gem: gems::Gem::Emerald, .. }
+            ))
+            .count(),
+        5
+    );
+}
+
+/// Ensure that the gems lost by a killed player entity are only scattered at free positions near where it died (any
+/// left over are not placed) and that other tasks are informed of them.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_player_killed_lost_gems_scattered() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    for x in -1..2 {
+        for y in -1..2 {
+            handler.add_empty_chunk(ChunkCoords { x, y });
+        }
+    }
+    let player_id = handler.add_test_entity(TileCoords { x: 7, y: 7 });
+    let other_player_id = handler.add_test_entity(TileCoords { x: 8, y: 8 });
+
+    {
+        let mut map = handler.game_map.lock();
+        map.set_loaded_tile_at(TileCoords { x: 6, y: 6 }, Tile::Water);
+        map.set_ground_gem_at(TileCoords { x: 8, y: 6 }, gems::Gem::Ruby);
+
+        let entity = map.entity_by_id_mut(player_id).unwrap();
+        entity.gem_collection.increase_quantity(gems::Gem::Emerald, 20);
+        entity.gem_collection.increase_quantity(gems::Gem::Diamond, 40);
+    }
+
+    handler.player_caught_in_blast(BlastEffect::Killed, player_id).await.unwrap();
+
+    let modification = other_map_changes_receiver.recv().await.unwrap().modification;
+    assert!(matches!(modification, maps::Modification::EntityDied { .. }));
+
+    let modification = other_map_changes_receiver.recv().await.unwrap().modification;
+    let placed_gems = match &modification {
+        maps::Modification::GroundGemsPlaced(placed_gems) => placed_gems.clone(),
+        _ => panic!("Expected gems to have been placed on the ground")
+    };
+
+    // Of the 25 positions within range, 3 are occupied by a blocking tile, another entity or a gem on the ground so
+    // only 22 of the 30 lost gems are placed (all 20 diamonds but only 2 of the 10 emeralds):
+    assert_eq!(placed_gems.len(), 22);
+    assert_eq!(placed_gems.iter().filter(|(_, gem)| *gem == gems::Gem::Diamond).count(), 20);
+    assert_eq!(placed_gems.iter().filter(|(_, gem)| *gem == gems::Gem::Emerald).count(), 2);
+
+    for (position, _) in &placed_gems {
+        assert!(position.distance_to(TileCoords { x: 7, y: 7 }) <= maps::LOST_GEMS_SCATTER_RADIUS);
+        assert!(![TileCoords { x: 6, y: 6 }, TileCoords { x: 8, y: 8 }, TileCoords { x: 8, y: 6 }].contains(position));
+    }
+    assert_eq!(handler.game_map.lock().ground_gem_at(TileCoords { x: 8, y: 6 }), Some(&gems::Gem::Ruby));
+
+    // Other tasks inform their remote clients of the gems placed within their loaded chunks:
+    let msgs = handler.handle_map_change(from_other_task(modification), other_player_id).await;
+    assert_eq!(msgs.len(), 22);
+    assert!(msgs.iter().all(|msg| matches!(msg, messages::FromServer::GroundGemPlaced { .. })));
+}
+
+/// Ensure that a stunned player entity is unable to move.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_move_my_entity_while_stunned() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+
+    let responses = handler.player_caught_in_blast(BlastEffect::Stunned, player_id).await.unwrap();
+    assert!(matches!(
+        responses.as_slice(),
+        [messages::FromServer::StatusEffectStarted { effect: StatusEffect::Stunned, .. }]
+    ));
+
+    let msg = messages::ToServer::MoveMyEntity { request_number: 0, direction: Direction::Right };
+    let responses = handler.handle_message(msg, player_id).await.unwrap();
+
+    assert!(matches!(
+        responses.as_slice(),
+        [messages::FromServer::YourEntityMoved { request_number: 0, new_position: TileCoords { x: 5, y: 5 } }]
+    ));
+}
+
+/// Ensure that observers are told to remove an entity that died within their loaded chunks and are provided with that
+/// entity should it respawn within their loaded chunks.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_entity_died_within_loaded_chunks() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let entity_id = handler.add_test_entity(TileCoords { x: 0, y: 0 });
+
+    let player_id = crate::id::generate_random();
+    let modification = maps::Modification::EntityDied {
+        entity_id,
+        old_position: TileCoords { x: 5, y: 5 },
+        new_position: TileCoords { x: 0, y: 0 }
+    };
+
+    let msgs = handler.handle_map_change(from_other_task(modification), player_id).await;
+
+    assert!(matches!(
+        msgs.as_slice(),
+        [
+            messages::FromServer::EntityDied { entity_id: died_id, position: TileCoords { x: 5, y: 5 } },
+            messages::FromServer::ProvideEntity(provided_id, _)
+        ] if *died_id == entity_id && *provided_id == entity_id
+    ));
+}
+
+/// Ensure that detonating bombs destroys breakable tiles within the blast area, informs other tasks & the remote client
+/// of the tile changes, and gives the player the gems yielded by destroyed rocks.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_detonate_bombs_destroying_tiles() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 0, y: 0 });
+
+    {
+        let mut map = handler.game_map.lock();
+        map.set_loaded_tile_at(TileCoords { x: 4, y: 4 }, Tile::RockDiamond);
+        map.set_loaded_tile_at(TileCoords { x: 6, y: 5 }, Tile::Shrub);
+        map.set_loaded_tile_at(TileCoords { x: 5, y: 6 }, Tile::Stones);
+        map.set_loaded_tile_at(TileCoords { x: 7, y: 5 }, Tile::Rock); // Outside of the blast.
+
+        map.set_bomb_at(TileCoords { x: 5, y: 5 }, player_id);
+        map.entity_by_id_mut(player_id).unwrap().bombs_placed_count = 1;
+    }
+
+    let responses = handler.handle_message(messages::ToServer::DetonateBombs, player_id).await.unwrap();
+
+    let tile_changes: Vec<(TileCoords, Tile)> = responses
+        .iter()
+        .filter_map(|response| match response {
+            messages::FromServer::ChangeTile(position, tile) => Some((*position, *tile)),
+            _ => None
+        })
+        .collect();
+
+    assert_eq!(tile_changes.len(), 3);
+    assert!(tile_changes.contains(&(TileCoords { x: 4, y: 4 }, Tile::RockSmashed)));
+    assert!(tile_changes.contains(&(TileCoords { x: 6, y: 5 }, Tile::Grass)));
+    assert!(tile_changes.contains(&(TileCoords { x: 5, y: 6 }, Tile::Grass)));
+
+    assert!(responses.iter().any(|response| matches!(
+        response,
+        messages::FromServer::YouCollectedGems { gem_type: gems::Gem::Diamond, quantity_increase: 1 }
+    )));
+
+    {
+        let map = handler.game_map.lock();
+        assert_eq!(map.loaded_tile_at(TileCoords { x: 4, y: 4 }), Some(Tile::RockSmashed));
+        assert_eq!(map.loaded_tile_at(TileCoords { x: 7, y: 5 }), Some(Tile::Rock));
+        assert_eq!(map.entity_by_id(player_id).unwrap().gem_collection.get_quantity(gems::Gem::Diamond), 1);
+    }
+
+    // Other tasks should be informed of each tile change after the detonation itself:
+    assert!(matches!(
+        other_map_changes_receiver.recv().await.unwrap().modification,
+        maps::Modification::BombsDetonated { .. }
+    ));
+    for _ in 0..3 {
+        assert!(matches!(
+            other_map_changes_receiver.recv().await.unwrap().modification,
+            maps::Modification::TileChanged(..)
+        ));
+    }
+}
+
+/// Ensure that an observer is only told of the detonated bombs within its loaded chunks, including when bombs are
+/// detonated either side of a chunk boundary, and regardless of where the player that detonated them now is.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_bombs_detonated_at_chunk_edge() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+
+    let player_id = crate::id::generate_random();
+    let placed_by = crate::id::generate_random(); // Not present on the map (e.g. has since disconnected).
+
+    let modification = maps::Modification::BombsDetonated {
+        placed_by,
+        positions: vec![
+            TileCoords { x: CHUNK_WIDTH - 1, y: 3 }, // chunk at 0, 0
+            TileCoords { x: CHUNK_WIDTH, y: 3 },     // chunk at 1, 0
+            TileCoords { x: 0, y: -1 },              // chunk at 0, -1
+        ]
+    };
+
+    assert!(matches!(
+        handler.handle_map_change(from_other_task(modification), player_id).await.as_slice(),
+        [messages::FromServer::BombsDetonated { placed_by_entity_id, positions }]
+            if *placed_by_entity_id == placed_by && positions == &[TileCoords { x: CHUNK_WIDTH - 1, y: 3 }]
+    ));
+}
+
+/// Ensure that no message is produced for an observer without any of the detonated bombs' chunks loaded.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_bombs_detonated_outside_loaded_chunks() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+
+    let player_id = crate::id::generate_random();
+    let modification = maps::Modification::BombsDetonated {
+        placed_by: crate::id::generate_random(),
+        positions: vec![TileCoords { x: -1, y: 0 }, TileCoords { x: 0, y: CHUNK_WIDTH }]
+    };
+
+    assert!(handler.handle_map_change(from_other_task(modification), player_id).await.is_empty());
+}
+
+/// Ensure that player entities and chunks modified by handling a message are marked as needing to be saved, and that
+/// taking them clears them.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_move_my_entity_marks_dirty() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+
+    {
+        let mut map = handler.game_map.lock();
+        map.set_loaded_tile_at(TileCoords { x: 6, y: 5 }, Tile::Rock);
+
+        // Clear anything marked dirty while setting up the test:
+        map.take_dirty_chunk_coords();
+        map.take_dirty_player_ids();
+    }
+
+    let msg = messages::ToServer::MoveMyEntity { request_number: 0, direction: Direction::Right };
+    handler.handle_message(msg, player_id).await.unwrap();
+
+    let mut map = handler.game_map.lock();
+
+    assert_eq!(map.take_dirty_chunk_coords(), vec![ChunkCoords { x: 0, y: 0 }]);
+    assert_eq!(map.take_dirty_player_ids(), vec![player_id]);
+
+    assert!(map.take_dirty_chunk_coords().is_empty());
+    assert!(map.take_dirty_player_ids().is_empty());
+}
+
+/// Ensure that a chunk is loaded from storage (rather than newly generated) when provided to a client, and that it is
+/// saved back to storage once no longer needed.
+#[tokio::test(flavor = "multi_thread")]
+async fn provide_and_unload_stored_chunk() {
+    let mut handler = make_test_handler().await;
+    let player_id = crate::id::generate_with_timestamp();
+    let coords = ChunkCoords { x: 3, y: -2 };
+
+    let mut stored_chunk = Chunk::default();
+    stored_chunk.set_tile_at_offset(OffsetCoords { x: 1, y: 1 }, Tile::Rock);
+    handler.storage.save_chunk(coords, &stored_chunk).await.unwrap();
+
+    let msgs = handler.provide_chunk_with_entities(coords, player_id).await.unwrap();
+
+    if let [messages::FromServer::ProvideChunk(provided_coords, provided_chunk)] = msgs.as_slice() {
+        assert_eq!(*provided_coords, coords);
+        assert_eq!(provided_chunk.tile_at_offset(OffsetCoords { x: 1, y: 1 }), Tile::Rock);
+    }
+    else {
+        panic!("Expected only a chunk to be provided but {} messages were returned", msgs.len());
+    }
+
+    // Modify the loaded chunk before it is unloaded:
+    handler
+        .game_map
+        .lock()
+        .set_loaded_tile_at(TileCoords { x: 3 * CHUNK_WIDTH + 1, y: -2 * CHUNK_WIDTH + 1 }, Tile::Grass);
+    handler.chunk_not_needed(coords).await.unwrap();
+
+    assert!(handler.game_map.lock().loaded_chunk_at(coords).is_none());
+
+    let saved_chunk = handler.storage.load_chunk(coords).await.unwrap().unwrap();
+    assert_eq!(saved_chunk.tile_at_offset(OffsetCoords { x: 1, y: 1 }), Tile::Grass);
+}
+
+/// Ensure that the target position is used for spawning when it is suitable and that the nearest suitable position is
+/// used when the target is occupied or a blocking tile.
+#[tokio::test(flavor = "multi_thread")]
+async fn find_spawn_position_near_target() {
+    let mut handler = make_test_handler().await;
+
+    for x in -1..2 {
+        for y in -1..2 {
+            handler.add_empty_chunk(ChunkCoords { x, y });
+        }
+    }
+    let storage = Arc::clone(&handler.storage);
+
+    let target = TileCoords { x: 5, y: 5 };
+    assert_eq!(
+        entities::place_at_spawn_position(storage.as_ref(), &handler.game_map, target, |_, pos| pos).await.unwrap(),
+        target
+    );
+
+    // Occupied by another entity:
+    handler.add_test_entity(target);
+    assert_eq!(
+        entities::place_at_spawn_position(storage.as_ref(), &handler.game_map, target, |_, pos| pos).await.unwrap(),
+        TileCoords { x: 4, y: 4 }
+    );
+
+    // Blocking tiles at the target and at the nearest positions:
+    for pos in &[TileCoords { x: 4, y: 4 }, TileCoords { x: 5, y: 4 }, TileCoords { x: 6, y: 4 }] {
+        handler.game_map.lock().set_loaded_tile_at(*pos, Tile::Water);
+    }
+    assert_eq!(
+        entities::place_at_spawn_position(storage.as_ref(), &handler.game_map, target, |_, pos| pos).await.unwrap(),
+        TileCoords { x: 4, y: 6 }
+    );
+}
+
+/// Ensure that an entity will not spawn in a small area enclosed by blocking tiles.
+#[tokio::test(flavor = "multi_thread")]
+async fn find_spawn_position_not_enclosed() {
+    let mut handler = make_test_handler().await;
+
+    for x in -1..2 {
+        for y in -1..2 {
+            handler.add_empty_chunk(ChunkCoords { x, y });
+        }
+    }
+    let storage = Arc::clone(&handler.storage);
+
+    // Surround a 3x3 area around the target with water:
+    let target = TileCoords { x: 5, y: 5 };
+    {
+        let mut map = handler.game_map.lock();
+
+        for x in 3..8 {
+            for y in 3..8 {
+                if x == 3 || x == 7 || y == 3 || y == 7 {
+                    map.set_loaded_tile_at(TileCoords { x, y }, Tile::Water);
+                }
+            }
+        }
+    }
+
+    assert!(!handler.game_map.lock().is_suitable_spawn_position(target));
+    assert_eq!(
+        entities::place_at_spawn_position(storage.as_ref(), &handler.game_map, target, |_, pos| pos).await.unwrap(),
+        TileCoords { x: 2, y: 2 }
+    );
+}
+
+/// Ensure that the nearest free position is used should no suitable position be found, and that spawning fails should
+/// there be no free position at all (rather than an entity being placed on a blocking tile).
+#[tokio::test(flavor = "multi_thread")]
+async fn spawn_position_fallback() {
+    let mut handler = make_test_handler().await;
+
+    // Fill every chunk within the search distance with water other than a single enclosed tile:
+    let mut chunk = Chunk::default();
+    for x in 0..CHUNK_WIDTH {
+        for y in 0..CHUNK_WIDTH {
+            chunk.set_tile_at_offset(OffsetCoords { x: x as u8, y: y as u8 }, Tile::Water);
+        }
+    }
+    for x in -6..7 {
+        for y in -6..7 {
+            handler.add_chunk(ChunkCoords { x, y }, chunk.clone());
+        }
+    }
+    let storage = Arc::clone(&handler.storage);
+
+    let free_position = TileCoords { x: 40, y: -35 };
+    handler.game_map.lock().set_loaded_tile_at(free_position, Tile::Grass);
+
+    let target = TileCoords { x: 5, y: 5 };
+    assert_eq!(
+        entities::place_at_spawn_position(storage.as_ref(), &handler.game_map, target, |_, pos| pos).await.unwrap(),
+        free_position
+    );
+
+    handler.game_map.lock().set_loaded_tile_at(free_position, Tile::Water);
+    assert!(matches!(
+        entities::place_at_spawn_position(storage.as_ref(), &handler.game_map, target, |_, pos| pos).await,
+        Err(entities::Error::NoFreePosition(pos)) if pos == target
+    ));
+}
+
+/// Ensure that an entity placed by one spawn search is taken into account by the next search for the same target.
+#[tokio::test(flavor = "multi_thread")]
+async fn spawn_positions_not_shared() {
+    let mut handler = make_test_handler().await;
+
+    for x in -1..2 {
+        for y in -1..2 {
+            handler.add_empty_chunk(ChunkCoords { x, y });
+        }
+    }
+    let storage = Arc::clone(&handler.storage);
+    let entity_id = handler.add_test_entity(TileCoords { x: 0, y: 0 });
+    let entity = handler.game_map.lock().entity_by_id(entity_id).unwrap().clone();
+
+    let target = TileCoords { x: 5, y: 5 };
+    let place = |map: &mut ServerMap, pos| {
+        map.add_entity(crate::id::generate_with_timestamp(), Entity { pos, ..entity.clone() });
+        pos
+    };
+
+    let first = entities::place_at_spawn_position(storage.as_ref(), &handler.game_map, target, place).await.unwrap();
+    let second = entities::place_at_spawn_position(storage.as_ref(), &handler.game_map, target, place).await.unwrap();
+
+    assert_eq!(first, target);
+    assert_ne!(second, target);
+}
+
+/// Ensure that the chunks loaded while searching for a spawn position are unloaded afterwards other than those
+/// containing and surrounding the position found.
+#[tokio::test(flavor = "multi_thread")]
+async fn spawn_search_chunks_unloaded() {
+    let mut handler = make_test_handler().await;
+
+    // Fill the chunk containing the target with water so that positions in other chunks must be searched:
+    let mut chunk = Chunk::default();
+    for x in 0..CHUNK_WIDTH {
+        for y in 0..CHUNK_WIDTH {
+            chunk.set_tile_at_offset(OffsetCoords { x: x as u8, y: y as u8 }, Tile::Water);
+        }
+    }
+    handler.add_chunk(ChunkCoords { x: 0, y: 0 }, chunk);
+    let storage = Arc::clone(&handler.storage);
+
+    let target = TileCoords { x: 5, y: 5 };
+    let found =
+        entities::place_at_spawn_position(storage.as_ref(), &handler.game_map, target, |_, pos| pos).await.unwrap();
+    assert_ne!(found.as_chunk_coords(), target.as_chunk_coords());
+
+    let found_chunk_coords = found.as_chunk_coords();
+    let map = handler.game_map.lock();
+
+    for x in -6..7 {
+        for y in -6..7 {
+            let coords = ChunkCoords { x, y };
+            let kept = coords == target.as_chunk_coords()
+                || ((coords.x - found_chunk_coords.x).abs() <= 1 && (coords.y - found_chunk_coords.y).abs() <= 1);
+
+            assert_eq!(map.loaded_chunk_at(coords).is_some(), kept, "chunk at {}", coords);
+        }
+    }
+}
+
+/// Ensure that the player entities of clients not seen within the retention period are removed along with their bombs
+/// (unless they are currently on the map).
+#[tokio::test]
+async fn prune_abandoned_players() {
+    let mut handler = make_test_handler().await;
+    let storage = Arc::clone(&handler.storage);
+    let now = crate::storage::current_timestamp();
+    let retention_period = Duration::from_secs(90 * 24 * 60 * 60);
+
+    // An abandoned player, a recently seen player, and an abandoned player that is currently on the map:
+
+    let mut players = Vec::new();
+    for last_seen in &[now - 100 * 24 * 60 * 60, now - 60, now - 100 * 24 * 60 * 60] {
+        let client_id = crate::id::generate_random();
+        let (entity_id, entity) = entities::new_player_in_storage(client_id, storage.as_ref()).await.unwrap();
+        storage.record_player_seen(entity_id, *last_seen).await.unwrap();
+        players.push((entity_id, entity));
+    }
+    let (abandoned_id, recent_id) = (players[0].0, players[1].0);
+    let (online_id, online_entity) = players.pop().unwrap();
+    handler.game_map.lock().add_entity(online_id, online_entity);
+
+    // Bombs placed by the abandoned and recent players in a loaded chunk:
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    {
+        let mut map = handler.game_map.lock();
+        map.set_bomb_at(TileCoords { x: 1, y: 1 }, abandoned_id);
+        map.set_bomb_at(TileCoords { x: 2, y: 2 }, recent_id);
+        map.take_dirty_chunk_coords();
+    }
+
+    // Bomb placed by the abandoned player in a chunk that is only stored:
+    let stored_coords = ChunkCoords { x: 3, y: 3 };
+    let stored_bomb_pos = TileCoords { x: 3 * CHUNK_WIDTH + 1, y: 3 * CHUNK_WIDTH + 1 };
+    {
+        let mut other_map = ServerMap::new_with_default_generator(0);
+        other_map.add_chunk(stored_coords, Chunk::default());
+        other_map.set_bomb_at(stored_bomb_pos, abandoned_id);
+        let chunk = other_map.remove_chunk(stored_coords).unwrap();
+        storage.save_chunk(stored_coords, &chunk).await.unwrap();
+    }
+
+    let removed_ids = maps::pruning::prune(&handler.game_map, storage.as_ref(), retention_period).await.unwrap();
+    assert_eq!(removed_ids, vec![abandoned_id]);
+
+    let remaining: Vec<Id> = storage.players_not_seen_since(now).await.unwrap().into_iter().map(|(id, _)| id).collect();
+    assert!(!remaining.contains(&abandoned_id));
+    assert!(remaining.contains(&recent_id) && remaining.contains(&online_id));
+
+    // Only the bombs of the abandoned player should have been removed:
+    {
+        let mut map = handler.game_map.lock();
+        let loaded_chunk = map.loaded_chunk_at(ChunkCoords { x: 0, y: 0 }).unwrap();
+        assert_eq!(loaded_chunk.get_undetonated_bomb_positions().collect::<Vec<_>>(), vec![&TileCoords { x: 2, y: 2 }]);
+        assert_eq!(map.take_dirty_chunk_coords().len(), 1);
+    }
+
+    let stored_chunk = storage.load_chunk(stored_coords).await.unwrap().unwrap();
+    assert_eq!(stored_chunk.get_undetonated_bomb_positions().count(), 0);
+}
+
+/// Ensure that a chat message is filtered, echoed back to the sender, and broadcast to other tasks.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_send_chat_message() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+
+    let msg = messages::ToServer::SendChatMessage { channel: chat::Channel::Global, text: " Not bad!\n".to_string() };
+    let responses = handler.handle_message(msg, player_id).await.unwrap();
+
+    assert_eq!(responses.len(), 1);
+    assert!(matches!(
+        &responses[0],
+        messages::FromServer::ChatMessage { channel: chat::Channel::Global, sender_entity_id, text }
+            if *sender_entity_id == player_id && text == "Not ***!"
+    ));
+
+    assert!(matches!(
+        other_map_changes_receiver.try_recv().map(|change| change.modification),
+        Ok(maps::Modification::ChatMessage { position: TileCoords { x: 5, y: 5 }, text, .. }) if text == "Not ***!"
+    ));
+    handler.assert_own_changes_ignored(player_id).await;
+}
+
+/// Ensure that empty, overly long, and overly frequent chat messages are rejected and not broadcast.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_send_chat_message_rejected() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+
+    for (text, rejection) in &[
+        (" \t ".to_string(), chat::Rejection::Empty),
+        ("a".repeat(chat::MAX_MESSAGE_LENGTH + 1), chat::Rejection::TooLong)
+    ] {
+        let msg = messages::ToServer::SendChatMessage { channel: chat::Channel::Proximity, text: text.clone() };
+        let responses = handler.handle_message(msg, player_id).await.unwrap();
+        assert!(matches!(responses.as_slice(), [messages::FromServer::ChatMessageRejected(r)] if r == rejection));
+    }
+    assert!(matches!(other_map_changes_receiver.try_recv(), Err(broadcast::error::TryRecvError::Empty)));
+
+    for _ in 0..crate::chat::RATE_LIMIT_MESSAGES {
+        let msg = messages::ToServer::SendChatMessage { channel: chat::Channel::Proximity, text: "hi".to_string() };
+        handler.handle_message(msg, player_id).await.unwrap();
+    }
+
+    let msg = messages::ToServer::SendChatMessage { channel: chat::Channel::Proximity, text: "hi".to_string() };
+    assert!(matches!(
+        handler.handle_message(msg, player_id).await.unwrap().as_slice(),
+        [messages::FromServer::ChatMessageRejected(chat::Rejection::RateLimited)]
+    ));
+}
+
+/// Ensure that proximity chat messages are only delivered when the sender is within the client's loaded chunks while
+/// global chat messages are always delivered.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_chat_message_delivery() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+    let sender = crate::id::generate_with_timestamp();
+
+    for (position, channel, delivered) in &[
+        (TileCoords { x: 1, y: 1 }, chat::Channel::Proximity, true),
+        (TileCoords { x: 100, y: 100 }, chat::Channel::Proximity, false),
+        (TileCoords { x: 100, y: 100 }, chat::Channel::Global, true)
+    ] {
+        let modification =
+            maps::Modification::ChatMessage { sender, position: *position, channel: *channel, text: "hi".to_string() };
+        assert_eq!(
+            handler.handle_map_change(from_other_task(modification), player_id).await.len(),
+            *delivered as usize
+        );
+    }
+}
+
+/// Ensure that a change received from the task that broadcast it is ignored by that task while the same change sent by
+/// another task (or by the admin console) is not.
+#[tokio::test(flavor = "multi_thread")]
+async fn own_map_changes_ignored() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+
+    let modification = maps::Modification::ChatMessage {
+        sender: player_id,
+        position: TileCoords { x: 5, y: 5 },
+        channel: chat::Channel::Global,
+        text: "hi".to_string()
+    };
+
+    let own_change = maps::MapChange { sent_by: Some(player_id), modification: modification.clone() };
+    assert!(handler.handle_map_change(own_change, player_id).await.is_empty());
+
+    let unsent_change = maps::MapChange { sent_by: None, modification: modification.clone() };
+    assert_eq!(handler.handle_map_change(unsent_change, player_id).await.len(), 1);
+
+    assert_eq!(handler.handle_map_change(from_other_task(modification), player_id).await.len(), 1);
+}
+
+/// Ensure that setting a facial expression updates the player entity, informs other tasks, and schedules the
+/// expression to revert to neutral (sooner for emotes).
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_set_facial_expression() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+
+    let msg = messages::ToServer::SetFacialExpression(FacialExpression::Angry);
+    let responses = handler.handle_message(msg, player_id).await.unwrap();
+
+    assert!(matches!(
+        responses.as_slice(),
+        [messages::FromServer::FacialExpressionChanged { entity_id, expression: FacialExpression::Angry }]
+            if *entity_id == player_id
+    ));
+    assert_eq!(handler.game_map.lock().entity_by_id(player_id).unwrap().facial_expression, FacialExpression::Angry);
+    assert!(matches!(
+        other_map_changes_receiver.try_recv().map(|change| change.modification),
+        Ok(maps::Modification::FacialExpressionChanged(_, FacialExpression::Angry))
+    ));
+    handler.assert_own_changes_ignored(player_id).await;
+
+    let held_expiry = handler.facial_expression_expiry_instant.unwrap();
+    assert!(held_expiry > Instant::now() + EMOTE_DURATION);
+
+    // An emote reverts sooner:
+    let msg = messages::ToServer::PlayEmote(FacialExpression::Shocked);
+    handler.handle_message(msg, player_id).await.unwrap();
+    assert!(handler.facial_expression_expiry_instant.unwrap() < held_expiry);
+
+    // Reverting to neutral cancels the timeout:
+    let msg = messages::ToServer::SetFacialExpression(FacialExpression::Neutral);
+    handler.handle_message(msg, player_id).await.unwrap();
+    assert!(handler.facial_expression_expiry_instant.is_none());
+    assert_eq!(handler.game_map.lock().entity_by_id(player_id).unwrap().facial_expression, FacialExpression::Neutral);
+}
+
+/// Ensure that the client is only informed of facial expression changes of entities within its loaded chunks.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_facial_expression_changed() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+    let nearby_id = handler.add_test_entity(TileCoords { x: 6, y: 6 });
+    let distant_id = handler.add_test_entity(TileCoords { x: 100, y: 100 });
+
+    let modification = maps::Modification::FacialExpressionChanged(nearby_id, FacialExpression::Skeptical);
+    assert_eq!(handler.handle_map_change(from_other_task(modification), player_id).await.len(), 1);
+
+    let modification = maps::Modification::FacialExpressionChanged(distant_id, FacialExpression::Skeptical);
+    assert!(handler.handle_map_change(from_other_task(modification), player_id).await.is_empty());
+}
+
+/// Ensure that changing appearance updates (and marks dirty) the player entity and informs other tasks, and that
+/// changes made too soon after the previous change are refused.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_change_appearance() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+    handler.game_map.lock().take_dirty_player_ids();
+
+    let appearance =
+        Appearance { hair_style: HairStyle::Mohawk, hair_colour: HairColour::Green, skin_colour: SkinColour::Pale };
+
+    let msg = messages::ToServer::ChangeAppearance(appearance);
+    let responses = handler.handle_message(msg, player_id).await.unwrap();
+
+    assert!(matches!(
+        responses.as_slice(),
+        [messages::FromServer::AppearanceChanged { entity_id, appearance: a }]
+            if *entity_id == player_id && *a == appearance
+    ));
+    assert!(matches!(
+        other_map_changes_receiver.try_recv().map(|change| change.modification),
+        Ok(maps::Modification::AppearanceChanged(id, a)) if id == player_id && a == appearance
+    ));
+    handler.assert_own_changes_ignored(player_id).await;
+
+    {
+        let mut map = handler.game_map.lock();
+        assert_eq!(map.entity_by_id(player_id).unwrap().appearance(), appearance);
+
+        assert_eq!(map.take_dirty_player_ids(), vec![player_id]);
+    }
+
+    // A further change straight away is refused and the client is told of the unchanged appearance:
+    let refused_appearance = Appearance { hair_style: HairStyle::Fringe, ..appearance };
+
+    let msg = messages::ToServer::ChangeAppearance(refused_appearance);
+    let responses = handler.handle_message(msg, player_id).await.unwrap();
+
+    assert!(matches!(
+        responses.as_slice(),
+        [messages::FromServer::AppearanceChanged { appearance: a, .. }] if *a == appearance
+    ));
+    assert!(other_map_changes_receiver.try_recv().is_err());
+    assert_eq!(handler.game_map.lock().entity_by_id(player_id).unwrap().hair_style, HairStyle::Mohawk);
+}
+
+/// Ensure that the client is only informed of appearance changes of entities within its loaded chunks.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_appearance_changed() {
+    let mut handler = make_test_handler().await;
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+    let nearby_id = handler.add_test_entity(TileCoords { x: 6, y: 6 });
+    let distant_id = handler.add_test_entity(TileCoords { x: 100, y: 100 });
+
+    let appearance =
+        Appearance { hair_style: HairStyle::Fringe, hair_colour: HairColour::Blue, skin_colour: SkinColour::Brown };
+
+    let modification = maps::Modification::AppearanceChanged(nearby_id, appearance);
+    assert_eq!(handler.handle_map_change(from_other_task(modification), player_id).await.len(), 1);
+
+    let modification = maps::Modification::AppearanceChanged(distant_id, appearance);
+    assert!(handler.handle_map_change(from_other_task(modification), player_id).await.is_empty());
+}
+
+/// Ensure that changes to a player's gems are recorded on the leaderboard and that a watching client is sent the
+/// leaderboard only when it has changed.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_leaderboard() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    handler.add_empty_chunk(ChunkCoords { x: 0, y: 0 });
+    let player_id = handler.add_test_entity(TileCoords { x: 5, y: 5 });
+    let other_id = handler.add_test_entity(TileCoords { x: 6, y: 6 });
+    handler.leaderboard.lock().set_score(other_id, 30);
+
+    // Newly added player is placed on the leaderboard without the client being sent anything:
+    assert!(handler.update_leaderboard_score(player_id).await.is_empty());
+    assert_eq!(handler.leaderboard.lock().rank_of(player_id), Some((2, 0)));
+    assert!(matches!(
+        other_map_changes_receiver.try_recv().map(|change| change.modification),
+        Ok(maps::Modification::LeaderboardChanged)
+    ));
+
+    let responses = handler.handle_message(messages::ToServer::WatchLeaderboard(true), player_id).await.unwrap();
+    assert!(matches!(
+        responses.as_slice(),
+        [messages::FromServer::Leaderboard { top_scores, your_rank: 2, your_score: 0 }]
+            if *top_scores == vec![(other_id, 30), (player_id, 0)]
+    ));
+
+    // Nothing is sent should neither the leaderboard nor the player's rank have changed:
+    assert!(handler.update_leaderboard_score(player_id).await.is_empty());
+    assert!(handler
+        .handle_map_change(from_other_task(maps::Modification::LeaderboardChanged), player_id)
+        .await
+        .is_empty());
+
+    handler.game_map.lock().entity_by_id_mut(player_id).unwrap().gem_collection.increase_quantity(gems::Gem::Ruby, 4);
+
+    let responses = handler.update_leaderboard_score(player_id).await;
+    assert!(matches!(responses.as_slice(), [messages::FromServer::Leaderboard { your_rank: 1, your_score: 40, .. }]));
+    assert!(matches!(
+        other_map_changes_receiver.try_recv().map(|change| change.modification),
+        Ok(maps::Modification::LeaderboardChanged)
+    ));
+
+    // Changes to other players' scores are sent once notified by other tasks:
+    handler.leaderboard.lock().set_score(other_id, 100);
+    let responses = handler.handle_map_change(from_other_task(maps::Modification::LeaderboardChanged), player_id).await;
+    assert!(matches!(responses.as_slice(), [messages::FromServer::Leaderboard { your_rank: 2, .. }]));
+
+    handler.handle_message(messages::ToServer::WatchLeaderboard(false), player_id).await.unwrap();
+    handler.leaderboard.lock().set_score(other_id, 10);
+    assert!(handler
+        .handle_map_change(from_other_task(maps::Modification::LeaderboardChanged), player_id)
+        .await
+        .is_empty());
+}
+
+/// Ensure that session tokens are only accepted should they be valid and unrevoked, and that legacy client IDs are only
+/// accepted within the migration window.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_authentication() {
+    let mut handler = make_test_handler().await;
+    let client_id = crate::id::generate_random();
+    let now = storage::current_timestamp();
+
+    let token = handler.token_issuer.issue(client_id, now - 20);
+    let credentials = messages::Credentials::SessionToken(token.clone());
+    assert_eq!(handler.authenticate(credentials).await.unwrap(), Some(client_id));
+
+    // Expired tokens and those not signed by the server are refused:
+
+    let expired_token = handler.token_issuer.issue(client_id, now - 2 * 60 * 60);
+    let credentials = messages::Credentials::SessionToken(expired_token);
+    assert_eq!(handler.authenticate(credentials).await.unwrap(), None);
+
+    let forged_token = TokenIssuer::new(vec![b"guess".to_vec()], 60 * 60).issue(client_id, now);
+    let credentials = messages::Credentials::SessionToken(forged_token);
+    assert_eq!(handler.authenticate(credentials).await.unwrap(), None);
+
+    // Legacy client IDs are only accepted until the end of the migration window:
+
+    let legacy_credentials = messages::Credentials::LegacyClientId(client_id);
+    assert_eq!(handler.authenticate(legacy_credentials.clone()).await.unwrap(), None);
+
+    handler.legacy_client_ids_until = Some(now + 60);
+    assert_eq!(handler.authenticate(legacy_credentials.clone()).await.unwrap(), Some(client_id));
+
+    handler.legacy_client_ids_until = Some(now - 60);
+    assert_eq!(handler.authenticate(legacy_credentials.clone()).await.unwrap(), None);
+
+    // Once revoked, neither previously issued tokens nor the legacy client ID are accepted (the latter no matter how
+    // long ago the revocation was):
+
+    handler.legacy_client_ids_until = Some(now + 60);
+    handler.storage.revoke_tokens(client_id, now - 10).await.unwrap();
+
+    let credentials = messages::Credentials::SessionToken(token);
+    assert_eq!(handler.authenticate(credentials).await.unwrap(), None);
+    assert_eq!(handler.authenticate(legacy_credentials).await.unwrap(), None);
+
+    let new_token = handler.token_issuer.issue(client_id, now);
+    let credentials = messages::Credentials::SessionToken(new_token);
+    assert_eq!(handler.authenticate(credentials).await.unwrap(), Some(client_id));
+}
+
+/// Ensure that actions performed by the server's operators on a player entity are applied and that gems and items
+/// taken never leave the player with a negative quantity.
+#[tokio::test(flavor = "multi_thread")]
+async fn handle_admin_actions() {
+    let mut handler = make_test_handler().await;
+    let mut other_map_changes_receiver = handler.map_changes_sender.subscribe();
+
+    for x in -1..2 {
+        for y in -1..2 {
+            handler.add_empty_chunk(ChunkCoords { x, y });
+        }
+    }
+    let player_id = handler.add_test_entity(TileCoords { x: 1, y: 1 });
+
+    let responses = handler.perform_admin_action(PlayerAction::GiveGems(gems::Gem::Ruby, 5), player_id).await.unwrap();
+    assert!(matches!(
+        responses[..],
+        [messages::FromServer::YouCollectedGems { gem_type: gems::Gem::Ruby, quantity_increase: 5 }]
+    ));
+
+    let responses = handler.perform_admin_action(PlayerAction::TakeGems(gems::Gem::Ruby, 8), player_id).await.unwrap();
+    assert!(matches!(
+        responses[..],
+        [messages::FromServer::YouLostGems { gem_type: gems::Gem::Ruby, quantity_decrease: 5 }]
+    ));
+
+    let bomb = items::QuantitativeItem::Bomb;
+    handler.perform_admin_action(PlayerAction::GiveItems(bomb, 3), player_id).await.unwrap();
+    let responses = handler.perform_admin_action(PlayerAction::TakeItems(bomb, 1), player_id).await.unwrap();
+    match &responses[..] {
+        [messages::FromServer::YourInventoryChanged(inventory)] => assert_eq!(inventory.has_how_many(bomb), 2),
+        _ => panic!("Expected inventory to be provided")
+    }
+    handler.perform_admin_action(PlayerAction::TakeItems(bomb, 10), player_id).await.unwrap();
+
+    {
+        let map = handler.game_map.lock();
+        let entity = map.entity_by_id(player_id).unwrap();
+        assert_eq!(entity.gem_collection.get_quantity(gems::Gem::Ruby), 0);
+        assert_eq!(entity.item_inventory.has_how_many(bomb), 0);
+    }
+
+    // Teleporting moves the entity, informs the client, and informs other tasks:
+
+    let target = TileCoords { x: 5, y: 5 };
+    let responses = handler.perform_admin_action(PlayerAction::Teleport(target), player_id).await.unwrap();
+
+    assert!(matches!(responses[0], messages::FromServer::YouTeleported { position } if position == target));
+    assert_eq!(handler.game_map.lock().entity_by_id(player_id).unwrap().pos, target);
+    assert!(matches!(
+        other_map_changes_receiver.recv().await.unwrap().modification,
+        maps::Modification::EntityTeleported { entity_id, old_position: TileCoords { x: 1, y: 1 }, new_position }
+            if entity_id == player_id && new_position == target
+    ));
+}
@@ -0,0 +1,118 @@
+//! Ranking of all players (both those currently connected and those only in storage) by the value of their gem
+//! collections (see [`shared::gems::Collection::score`]).
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap}
+};
+
+use shared::Id;
+
+use crate::storage::{self, Storage};
+
+/// The number of players with the highest scores that are included in the leaderboard sent to clients.
+pub const LEADERBOARD_SIZE: usize = 10;
+
+/// The highest scores on the leaderboard along with the rank and score of a particular player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standings {
+    /// Entity IDs and scores of the [`LEADERBOARD_SIZE`] players with the highest scores (highest first).
+    pub top_scores: Vec<(Id, u64)>,
+    pub rank: u32,
+    pub score: u64
+}
+
+/// Keeps the scores of all players ordered so that the highest scores and the rank of any given player can be found
+/// without sorting. Scores are updated individually as they change.
+#[derive(Default)]
+pub struct Leaderboard {
+    scores: HashMap<Id, u64>,
+    /// Pairs of scores and entity IDs ordered highest score first. Players with equal scores are ordered by entity ID.
+    ranking: BTreeSet<(Reverse<u64>, Id)>
+}
+
+impl Leaderboard {
+    /// Create a leaderboard containing every player entity in the given storage.
+    pub async fn load(storage: &dyn Storage) -> storage::Result<Self> {
+        let mut leaderboard = Leaderboard::default();
+
+        for (entity_id, gem_collection) in storage.player_gem_collections().await? {
+            leaderboard.set_score(entity_id, gem_collection.score());
+        }
+
+        Ok(leaderboard)
+    }
+
+    /// Set the score of the player with the given entity ID (adding them to the leaderboard if not already present).
+    pub fn set_score(&mut self, entity_id: Id, score: u64) {
+        if let Some(old_score) = self.scores.insert(entity_id, score) {
+            self.ranking.remove(&(Reverse(old_score), entity_id));
+        }
+        self.ranking.insert((Reverse(score), entity_id));
+    }
+
+    /// Remove the player with the given entity ID from the leaderboard (e.g. once pruned from storage).
+    pub fn remove(&mut self, entity_id: Id) {
+        if let Some(score) = self.scores.remove(&entity_id) {
+            self.ranking.remove(&(Reverse(score), entity_id));
+        }
+    }
+
+    /// The entity IDs and scores of the given number of players with the highest scores (highest first).
+    pub fn top(&self, count: usize) -> Vec<(Id, u64)> {
+        self.ranking.iter().take(count).map(|(Reverse(score), entity_id)| (*entity_id, *score)).collect()
+    }
+
+    /// The rank (starting at 1) and score of the player with the given entity ID. Players with equal scores share the
+    /// same rank.
+    pub fn rank_of(&self, entity_id: Id) -> Option<(u32, u64)> {
+        let score = *self.scores.get(&entity_id)?;
+        let higher_count = self.ranking.iter().take_while(|(Reverse(other_score), _)| *other_score > score).count();
+
+        Some((higher_count as u32 + 1, score))
+    }
+
+    /// The highest scores along with the rank and score of the player with the given entity ID (or `None` should that
+    /// player not be on the leaderboard).
+    pub fn standings_of(&self, entity_id: Id) -> Option<Standings> {
+        let (rank, score) = self.rank_of(entity_id)?;
+        Some(Standings { top_scores: self.top(LEADERBOARD_SIZE), rank, score })
+    }
+
+    /// The number of players on the leaderboard.
+    pub fn player_count(&self) -> usize {
+        self.scores.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranking() {
+        let mut leaderboard = Leaderboard::default();
+        let ids: Vec<Id> = (0..4).map(Id::new).collect();
+
+        leaderboard.set_score(ids[0], 50);
+        leaderboard.set_score(ids[1], 120);
+        leaderboard.set_score(ids[2], 50);
+        leaderboard.set_score(ids[3], 7);
+        assert_eq!(leaderboard.player_count(), 4);
+
+        assert_eq!(leaderboard.top(3), vec![(ids[1], 120), (ids[0], 50), (ids[2], 50)]);
+        assert_eq!(leaderboard.rank_of(ids[1]), Some((1, 120)));
+        assert_eq!(leaderboard.rank_of(ids[2]), Some((2, 50)));
+        assert_eq!(leaderboard.rank_of(ids[3]), Some((4, 7)));
+
+        // Updating a score moves the player rather than adding them again:
+        leaderboard.set_score(ids[3], 500);
+        assert_eq!(leaderboard.player_count(), 4);
+        assert_eq!(leaderboard.top(1), vec![(ids[3], 500)]);
+        assert_eq!(leaderboard.rank_of(ids[1]), Some((2, 120)));
+
+        leaderboard.remove(ids[3]);
+        assert_eq!(leaderboard.rank_of(ids[3]), None);
+        assert_eq!(leaderboard.top(LEADERBOARD_SIZE).len(), 3);
+    }
+}
@@ -1,14 +1,23 @@
+mod admin;
+mod chat;
 mod handling;
 mod id;
+mod leaderboard;
 mod maps;
+mod metrics;
 mod networking;
+mod rate_limiting;
+mod sessions;
+mod shutdown;
+mod storage;
+mod tokens;
 
-use std::{path::PathBuf, sync::Arc};
+use std::{path::PathBuf, str::FromStr, sync::Arc};
 
 use maps::ServerMap;
 use parking_lot::Mutex;
 use structopt::StructOpt;
-use tokio::{net::TcpListener, sync::broadcast};
+use tokio::{net::TcpListener, sync::broadcast, time::Duration};
 
 /// Create an [`sqlx::query::Query`] instance using the SQL query in the specified file with the `.sql` extension
 /// (`server/db/` directory). In a database argument is provided then a query execution future is created.
@@ -68,6 +77,36 @@ async fn main() {
     }
     logger.start().expect("Failed to initialise logger");
 
+    // Apply pending database migrations and exit should the 'migrate' subcommand have been given:
+
+    if let Some(Command::Migrate) = options.command {
+        if let StorageBackend::Postgres = options.storage {
+            match connect_to_database(&options).await.migrate(true).await {
+                Ok(count) => log::info!("Applied {} database migrations", count),
+                Err(e) => exit_with_error(&format!("Failed to migrate database schema - {}", e))
+            }
+        }
+        else {
+            log::warn!("Database migrations only apply to the PostgreSQL storage backend");
+        }
+        return;
+    }
+
+    // Revoke the session tokens of a client and exit should the 'revoke-tokens' subcommand have been given:
+
+    if let Some(Command::RevokeTokens { client_id }) = options.command {
+        if let StorageBackend::Memory = options.storage {
+            log::warn!("Session tokens cannot be revoked when using the in-memory storage backend");
+        }
+        else {
+            match prepare_storage(&options).await.revoke_tokens(client_id, storage::current_timestamp()).await {
+                Ok(_) => log::info!("Revoked all session tokens issued to client ID {}", client_id),
+                Err(e) => exit_with_error(&format!("Failed to revoke session tokens - {}", e))
+            }
+        }
+        return;
+    }
+
     // Bind socket and handle connections:
 
     let host_address = format!("0.0.0.0:{}", options.port);
@@ -75,33 +114,158 @@ async fn main() {
     let listener = TcpListener::bind(&host_address).await.expect("Failed to create TCP/IP listener");
     log::info!("Created TCP/IP listener bound to address: {}", host_address);
 
-    // Connect to database:
+    // Prepare storage backend:
+
+    let storage = prepare_storage(&options).await;
 
-    let db_pool_options = sqlx::postgres::PgPoolOptions::new().max_connections(options.max_database_connections);
-    let db_pool =
-        db_pool_options.connect(&options.database_connection_string).await.expect("Failed to connect to database");
+    if let Some(world) = &options.world {
+        log::info!("Using world '{}'", world);
+    }
+
+    log::info!("Using {} storage backend", storage.name());
+
+    // Load/create game map that is to be shared between threads:
+
+    let contained_map = ServerMap::load_or_new(storage.as_ref(), options.seed).await.unwrap();
+    log::info!("Prepared game map with seed {}", contained_map.seed());
+    let map: Shared<ServerMap> = Arc::new(Mutex::new(contained_map));
+
+    // Rank all stored players by score:
+
+    let contained_leaderboard = leaderboard::Leaderboard::load(storage.as_ref()).await.unwrap();
+    log::info!("Prepared leaderboard of {} players", contained_leaderboard.player_count());
+    let leaderboard: Shared<leaderboard::Leaderboard> = Arc::new(Mutex::new(contained_leaderboard));
+
+    // Periodically save modified chunks and player entities in the background:
+
+    let persistence_counters = Arc::new(maps::persistence::Counters::default());
+
+    if options.flush_interval > 0 {
+        tokio::spawn(maps::persistence::flush_periodically(
+            Arc::clone(&map),
+            Arc::clone(&storage),
+            Duration::from_secs(options.flush_interval),
+            Arc::clone(&persistence_counters)
+        ));
+
+        log::info!("Modified chunks and player entities will be saved every {} seconds", options.flush_interval);
+    }
+    else {
+        log::warn!("Periodic saving of modified chunks and player entities is disabled");
+    }
+
+    // Periodically remove abandoned player entities in the background:
+
+    if options.player_retention_days > 0 {
+        tokio::spawn(maps::pruning::prune_periodically(
+            Arc::clone(&map),
+            Arc::clone(&storage),
+            Duration::from_secs(options.player_retention_days * 24 * 60 * 60),
+            Arc::clone(&leaderboard)
+        ));
+
+        log::info!("Player entities of clients not seen for {} days will be removed", options.player_retention_days);
+    }
+    else {
+        log::warn!("Removal of abandoned player entities is disabled");
+    }
+
+    // Load the word filter applied to chat messages:
+
+    let chat_filter = Arc::new(match &options.chat_filter {
+        Some(path) => {
+            let filter = chat::WordFilter::load(path).expect("Failed to load chat word filter");
+            log::info!("Loaded chat word filter of {} words from: {}", filter.word_count(), path.display());
+            filter
+        }
+        None => chat::WordFilter::default()
+    });
+
+    // Load (or generate) the secret used to sign session tokens:
+
+    let token_issuer = tokens::TokenIssuer::load_or_generate(
+        &options.session_secret_file,
+        options.session_token_lifetime_days as i64 * 24 * 60 * 60
+    )
+    .unwrap_or_else(|e| exit_with_error(&format!("Failed to prepare session tokens - {}", e)));
 
     log::info!(
-        "Created connection pool with maximum of {} simultaneous connections to database",
-        options.max_database_connections
+        "Session tokens will be valid for {} days and are accepted from {} secret(s)",
+        options.session_token_lifetime_days,
+        token_issuer.secret_count()
     );
 
-    db_query_from_file!("client_entities/create table", &db_pool).await.unwrap();
-    db_query_from_file!("map/create table", &db_pool).await.unwrap();
-    db_query_from_file!("map_chunks/create table", &db_pool).await.unwrap();
+    if options.legacy_client_ids_until >= storage::current_timestamp() {
+        log::info!("Legacy client IDs will be accepted until Unix time {}", options.legacy_client_ids_until);
+    }
+    else {
+        log::info!("Legacy client IDs are not accepted");
+    }
 
-    log::info!("Prepared necessary database tables");
+    // Prepare the limits on how quickly clients may send messages:
 
-    // Load/create game map that is to be shared between threads:
+    let rate_limits = rate_limiting::Config {
+        connection: options.connection_rate_limit,
+        warn_after: options.rate_limit_warn_after,
+        disconnect_after: options.rate_limit_disconnect_after,
+        ..rate_limiting::Config::default()
+    }
+    .with_message_limits(&options.message_rate_limits);
 
-    let contained_map = ServerMap::load_or_new(&db_pool).await.unwrap();
-    let map: Shared<ServerMap> = Arc::new(Mutex::new(contained_map));
-    log::info!("Prepared game map");
+    log::info!(
+        "Clients may send {} messages at once and {} per second thereafter (with {} types of message limited further)",
+        rate_limits.connection.burst,
+        rate_limits.connection.per_second,
+        rate_limits.messages.len()
+    );
 
     // Create multi-producer, multi-consumer channel so that each task may notify every other task of changes made to
     // the game world:
 
-    let (map_changes_sender, mut map_changes_receiver) = broadcast::channel(5);
+    let (map_changes_sender, mut map_changes_receiver) = broadcast::channel(MAP_CHANGES_CHANNEL_CAPACITY);
+
+    let shared_state = handling::SharedState {
+        game_map: Arc::clone(&map),
+        storage: Arc::clone(&storage),
+        map_changes_sender,
+        chat_filter,
+        leaderboard,
+        client_registry: Arc::new(sessions::ClientRegistry::default()),
+        duplicate_policy: options.duplicate_sessions,
+        token_issuer: Arc::new(token_issuer),
+        legacy_client_ids_until: Some(options.legacy_client_ids_until),
+        rate_limits: Arc::new(rate_limits),
+        max_message_size: options.max_message_size
+    };
+
+    // Expose runtime metrics for scraping by Prometheus:
+
+    if let Some(port) = options.metrics_port {
+        tokio::spawn(metrics::serve(port, Arc::clone(&map), Arc::clone(&storage), Arc::clone(&persistence_counters)));
+    }
+
+    // Accept commands from the server's operators:
+
+    if options.admin_console || options.admin_port.is_some() {
+        let audit_log = admin::audit::AuditLog::open(&options.admin_audit_log)
+            .unwrap_or_else(|e| exit_with_error(&format!("Failed to open admin audit log - {}", e)));
+
+        log::info!("Admin commands will be recorded in: {}", options.admin_audit_log.display());
+
+        let admin = Arc::new(admin::Admin::new(&shared_state, Arc::clone(&persistence_counters), audit_log));
+
+        if options.admin_console {
+            tokio::spawn(admin::console::run(Arc::clone(&admin)));
+            log::info!("Admin console enabled (enter 'help' for a list of commands)");
+        }
+
+        if let Some(port) = options.admin_port {
+            tokio::spawn(admin::http::serve(admin, port));
+        }
+    }
+
+    // Used to notify connection tasks of a shutdown and then to wait for them to finish:
+    let shutdown_coordinator = shutdown::Coordinator::new();
 
     log::info!("Listening for incoming TCP/IP connections...");
 
@@ -119,10 +283,8 @@ async fn main() {
                 tokio::spawn(handling::handle_connection(
                     stream,
                     address,
-                    Arc::clone(&map),
-                    db_pool.clone(),
-                    map_changes_sender.clone(),
-                    map_changes_sender.subscribe()
+                    shared_state.clone(),
+                    shutdown_coordinator.listener()
                 ));
             }
             _ = map_changes_receiver.recv() => {} // Discard the broadcasted world modification message.
@@ -131,8 +293,94 @@ async fn main() {
     }
 
     log::info!("No longer listening for connections");
+    drop(map_changes_receiver);
+
+    // Inform connection tasks of the shutdown and give them the grace period in which to close their connections
+    // (which involves saving their player entities and unloading their chunks):
+
+    let grace_period = Duration::from_secs(options.shutdown_grace_period);
+    shutdown_coordinator.initiate(SHUTDOWN_REASON.to_string(), grace_period);
+
+    log::info!("Shutting down in {} seconds...", options.shutdown_grace_period);
+
+    tokio::select!(
+        finished = shutdown_coordinator.wait_for_listeners(grace_period + SHUTDOWN_HANDLER_TIMEOUT) => {
+            if finished {
+                log::info!("All connection tasks have finished");
+            }
+            else {
+                log::warn!("Not all connection tasks finished within the grace period");
+            }
+        }
+        _ = tokio::signal::ctrl_c() => log::warn!("Ctrl-C pressed again so no longer waiting for connection tasks")
+    );
+
+    // Save everything that remains loaded:
+
+    map.lock().mark_all_dirty();
+    let failures = maps::persistence::flush(&map, storage.as_ref(), &persistence_counters).await;
+
+    if failures == 0 {
+        log::info!("Saved all loaded chunks and player entities");
+    }
+    else {
+        exit_with_error(&format!("Failed to save {} loaded chunks and/or player entities", failures));
+    }
+}
+
+/// The number of messages that may be queued on the map changes broadcast channel before slower receivers begin to
+/// miss messages. Modifications are often sent in bursts (e.g. every tile destroyed by a bomb blast is sent separately)
+/// so this must be large enough that tasks are able to keep up.
+const MAP_CHANGES_CHANNEL_CAPACITY: usize = 128;
+
+/// Prepare the storage backend specified by the given command-line options.
+async fn prepare_storage(options: &Options) -> Arc<dyn storage::Storage> {
+    match options.storage {
+        StorageBackend::Postgres => {
+            let postgres_storage = connect_to_database(options).await;
+
+            // Refuse to start should the schema be newer than this version of the server supports or (when automatic
+            // migration is disabled) should there be pending migrations:
+            if let Err(e) = postgres_storage.migrate(!options.no_migrate).await {
+                exit_with_error(&format!("Failed to prepare database schema - {}", e));
+            }
+
+            Arc::new(postgres_storage)
+        }
+        StorageBackend::File => Arc::new(
+            storage::FileStorage::open(options.map_directory.clone(), options.world.as_deref())
+                .await
+                .expect("Failed to prepare map directory")
+        ),
+        StorageBackend::Memory => Arc::new(storage::MemoryStorage::default())
+    }
 }
 
+/// Connect to the PostgreSQL database as specified by the given command-line options.
+async fn connect_to_database(options: &Options) -> storage::PostgresStorage {
+    storage::PostgresStorage::connect(
+        &options.database_connection_string,
+        options.max_database_connections,
+        options.world.as_deref()
+    )
+    .await
+    .expect("Failed to connect to database")
+}
+
+/// Log the given error message and exit the process with a non-zero exit code.
+fn exit_with_error(msg: &str) -> ! {
+    log::error!("{}", msg);
+    log::logger().flush();
+    std::process::exit(1);
+}
+
+/// The reason given to clients when the server is shut down by pressing Ctrl-C.
+const SHUTDOWN_REASON: &str = "The server is restarting or undergoing maintenance";
+
+/// The additional amount of time after the shutdown grace period that connection tasks are given to save their player
+/// entities and chunks before a final save is performed regardless.
+const SHUTDOWN_HANDLER_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Alias for a [`Mutex`] wrapped in an [`Arc`].
 type Shared<T> = Arc<Mutex<T>>;
 
@@ -140,15 +388,34 @@ type Shared<T> = Arc<Mutex<T>>;
 #[derive(StructOpt, Debug)]
 #[structopt(name = "GemGame Server")]
 struct Options {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
     /// The port on which listen for incoming connections.
     #[structopt(short, long, default_value = "5678")]
     port: u16,
 
-    /// Directory containing game map data.
+    /// Where to store the game map and player entities: 'postgres' (a PostgreSQL database), 'file' (files in the map
+    /// directory) or 'memory' (kept in memory only so lost once the server stops).
+    #[structopt(long, default_value = "postgres", possible_values = &["postgres", "file", "memory"])]
+    storage: StorageBackend,
+
+    /// Seed used to generate the terrain of a newly created world. A random seed is used if not specified. Ignored
+    /// should the world already exist.
+    #[structopt(long, allow_hyphen_values = true)]
+    seed: Option<i32>,
+
+    /// Name of the world to be used (created should it not already exist) instead of the default world. Each world is
+    /// stored separately, allowing a new world to be started beside existing ones. Names may only contain lowercase
+    /// letters, digits and underscores.
+    #[structopt(long, parse(try_from_str = parse_world_name))]
+    world: Option<String>,
+
+    /// Directory containing game map data (when using the file storage backend).
     #[structopt(long, default_value = "map/", parse(from_os_str))]
     map_directory: PathBuf,
 
-    /// Specify how to connect to the database.
+    /// Specify how to connect to the database (when using the PostgreSQL storage backend).
     #[structopt(long, default_value = "postgres://localhost/gemgame")]
     database_connection_string: String,
 
@@ -157,6 +424,93 @@ struct Options {
     #[structopt(long, default_value = "25")]
     max_database_connections: u32,
 
+    /// Do not apply pending database migrations on startup. The server will instead refuse to start should any
+    /// migrations be pending (these can be applied using the 'migrate' subcommand).
+    #[structopt(long)]
+    no_migrate: bool,
+
+    /// The interval in seconds at which modified chunks and player entities are saved to storage (in addition to
+    /// being saved when they are unloaded). Specify 0 to disable periodic saving.
+    #[structopt(long, default_value = "30")]
+    flush_interval: u64,
+
+    /// The number of seconds that clients are given notice of before being disconnected when the server is shut down.
+    #[structopt(long, default_value = "10")]
+    shutdown_grace_period: u64,
+
+    /// The number of days after which the player entity of a client that has not connected is removed (along with any
+    /// bombs it left undetonated). Specify 0 to keep player entities indefinitely.
+    #[structopt(long, default_value = "90")]
+    player_retention_days: u64,
+
+    /// File containing words (one per line) that are to be replaced with asterisks in chat messages.
+    #[structopt(long, parse(from_os_str))]
+    chat_filter: Option<PathBuf>,
+
+    /// What happens when a client connects while it is already connected (e.g. in another browser tab): 'take-over'
+    /// (the existing connection is closed) or 'reject' (the new connection is refused).
+    #[structopt(long, default_value = "take-over", possible_values = &["take-over", "reject"])]
+    duplicate_sessions: sessions::DuplicatePolicy,
+
+    /// File containing the secrets (one Base64-encoded secret per line) used to sign session tokens. A new secret is
+    /// generated should the file not exist. The first secret is used to sign new tokens while tokens signed with any
+    /// of the others are still accepted (so the secret can be rotated by adding a new line to the top of the file).
+    #[structopt(long, default_value = "session_secrets.txt", parse(from_os_str))]
+    session_secret_file: PathBuf,
+
+    /// The number of days for which a session token remains valid. Clients are issued a new token each time they
+    /// connect.
+    #[structopt(long, default_value = "30")]
+    session_token_lifetime_days: u64,
+
+    /// Accept the client IDs stored by clients that predate session tokens until the given time (as a Unix timestamp
+    /// in seconds) so that those clients are able to migrate to session tokens. Defaults to the start of April 2027
+    /// while 0 stops legacy client IDs from being accepted at all.
+    #[structopt(long, default_value = "1806537600")]
+    legacy_client_ids_until: i64,
+
+    /// Read admin commands (enter 'help' for a list) from standard input.
+    #[structopt(long)]
+    admin_console: bool,
+
+    /// The port on which to serve the admin HTTP API (only reachable from this machine). The API is not served should
+    /// this not be specified.
+    #[structopt(long)]
+    admin_port: Option<u16>,
+
+    /// File to which every admin command performed is appended.
+    #[structopt(long, default_value = "admin_audit.log", parse(from_os_str))]
+    admin_audit_log: PathBuf,
+
+    /// Limit on how quickly each client may send messages of any type, of the form <burst>:<per second> (i.e. the
+    /// number of messages that may be sent at once followed by the rate at which they may be sent thereafter).
+    #[structopt(long, default_value = "40:20")]
+    connection_rate_limit: rate_limiting::Limit,
+
+    /// Replace the default limit on how quickly each client may send a particular type of message. Of the form
+    /// <message type>=<burst>:<per second> (e.g. 'PlaceBomb=5:2'). May be given multiple times.
+    #[structopt(long = "message-rate-limit", number_of_values = 1)]
+    message_rate_limits: Vec<rate_limiting::MessageLimit>,
+
+    /// The number of messages discarded for exceeding rate limits after which a client is warned (the count is reset
+    /// once the client stops exceeding its limits for 10 seconds).
+    #[structopt(long, default_value = "10")]
+    rate_limit_warn_after: u32,
+
+    /// The number of messages discarded for exceeding rate limits after which a client is disconnected.
+    #[structopt(long, default_value = "100")]
+    rate_limit_disconnect_after: u32,
+
+    /// The maximum size in bytes of a WebSocket message (or frame) received from a client. Clients sending larger
+    /// messages are disconnected.
+    #[structopt(long, default_value = "4096")]
+    max_message_size: usize,
+
+    /// The port on which to serve metrics in the Prometheus text format (at `/metrics`). Metrics are not served should
+    /// this not be specified.
+    #[structopt(long)]
+    metrics_port: Option<u16>,
+
     /// Display all debugging logger messages.
     #[structopt(long, conflicts_with = "log-trace")]
     log_debug: bool,
@@ -169,3 +523,59 @@ struct Options {
     #[structopt(long)]
     log_to_file: bool
 }
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Apply any pending migrations to the database schema (of the world specified by `--world`) and then exit.
+    Migrate,
+    /// Revoke all session tokens issued so far to the given client (of the world specified by `--world`) and then
+    /// exit. The client will be unable to continue playing as its pre-existing character.
+    RevokeTokens {
+        /// The client ID (encoded using Base64) whose session tokens are to be revoked.
+        #[structopt(parse(try_from_str = parse_client_id))]
+        client_id: shared::Id
+    }
+}
+
+/// Storage backends selectable using the `--storage` command-line option.
+#[derive(Debug, Clone, Copy)]
+enum StorageBackend {
+    Postgres,
+    File,
+    Memory
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "postgres" => Ok(StorageBackend::Postgres),
+            "file" => Ok(StorageBackend::File),
+            "memory" => Ok(StorageBackend::Memory),
+            _ => Err(format!("Unknown storage backend '{}'", s))
+        }
+    }
+}
+
+/// Decode a client ID given as a command-line argument.
+fn parse_client_id(s: &str) -> Result<shared::Id, String> {
+    shared::Id::decode(s).ok_or_else(|| format!("'{}' is not a valid client ID", s))
+}
+
+/// The maximum length of a world name.
+const MAX_WORLD_NAME_LENGTH: usize = 32;
+
+/// Ensure the given world name is non-empty and contains only lowercase ASCII letters, digits and underscores so that
+/// it is safe to use as part of a database schema name or a directory name.
+fn parse_world_name(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.len() > MAX_WORLD_NAME_LENGTH {
+        Err(format!("World name must be between 1 and {} characters long", MAX_WORLD_NAME_LENGTH))
+    }
+    else if !s.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+        Err("World name may only contain lowercase letters, digits and underscores".to_string())
+    }
+    else {
+        Ok(s.to_string())
+    }
+}
@@ -1,96 +1,130 @@
-//! Hold functions for saving/loading chunks to/from the database.  These functions are not methods of
+//! Hold functions for saving/loading chunks to/from storage.  These functions are not methods of
 //! [`super::ServerMap`] so that the mutex that that object is contained in is locked for only shortest required period
 //! of time.
 
-use std::convert;
-
 use shared::maps::{Chunk, ChunkCoords, Map};
-use sqlx::Row;
+use tokio::time::Instant;
 
-use crate::{db_query_from_file, Shared};
+use crate::{
+    metrics::METRICS,
+    storage::{self, Storage},
+    Shared
+};
 
 /// This function will try the following steps until one succeeds:
 /// * Fetch the chunk at the specified coordinates from the given map object's loaded chunks.
-/// * Read the chunk at the given coordinates from the filesystem before inserting it into the given map's loaded
-///   chunks.
+/// * Read the chunk at the given coordinates from storage before inserting it into the given map's loaded chunks.
 /// * Newly generate a chunk before inserting it into the given map's loaded chunks.
 /// Once a chunk is obtained from any of the above steps, it is cloned before being returned from this function.
+///
+/// A chunk is only generated should none be stored. Should reading a stored chunk fail then an error is returned rather
+/// than a chunk being generated in its place, as that generated chunk would later be saved over the stored chunk.
+///
+/// The chunk's save lock is held from before storage is read until the chunk is in the map's loaded chunks. This means
+/// that a stored copy is never read while a newer copy is being saved, and that concurrent calls for the same
+/// coordinates load the chunk only once.
 pub async fn get_or_load_or_generate_chunk(
-    db: sqlx::pool::PoolConnection<sqlx::Postgres>, map: &Shared<super::ServerMap>, coords: ChunkCoords
-) -> Chunk {
+    storage: &dyn Storage, map: &Shared<super::ServerMap>, coords: ChunkCoords
+) -> Result<Chunk> {
     let loaded_chunk_option = map.lock().loaded_chunk_at(coords).cloned();
 
     if let Some(loaded_chunk) = loaded_chunk_option {
         log::debug!("Chunk at {} already loaded", coords);
 
-        loaded_chunk
+        return Ok(loaded_chunk);
     }
-    else {
-        // Chunk is not already in memory so needs to either be fetched from the database or newly generated before
-        // being loaded into the map.
 
-        let new_chunk = load_chunk(db, coords).await.unwrap_or_else(|_| {
+    let save_locks = map.lock().save_locks();
+    let _save_guard = save_locks.chunks.lock(coords).await;
+
+    // Another task may have loaded the chunk while the lock was being waited on:
+    let loaded_chunk_option = map.lock().loaded_chunk_at(coords).cloned();
+
+    if let Some(loaded_chunk) = loaded_chunk_option {
+        log::debug!("Chunk at {} was loaded while waiting for its save lock", coords);
+
+        Ok(loaded_chunk)
+    }
+    else {
+        // Chunk is not already in memory so needs to either be fetched from storage or newly generated before being
+        // loaded into the map.
+
+        let stored_chunk_option = match load_chunk(storage, coords).await {
+            Ok(stored_chunk_option) => stored_chunk_option,
+            Err(e) => {
+                log::error!("Failed to load chunk at {} from storage - {}", coords, e);
+                return Err(e);
+            }
+        };
+
+        let new_chunk = stored_chunk_option.unwrap_or_else(|| {
             let generator = &map.lock().generator;
 
             log::debug!(
-                "Chunk at {} could not be loaded from the database so will be newly generated using generator '{}'",
+                "Chunk at {} could not be loaded from storage so will be newly generated using generator '{}'",
                 coords,
                 generator.name()
             );
 
-            generator.generate(coords)
+            let start = Instant::now();
+            let chunk = generator.generate(coords);
+            METRICS.chunk_generate_duration.observe_since(start);
+
+            chunk
         });
 
         // Add the new chunk to map's loaded chunks:
         map.lock().add_chunk(coords, new_chunk.clone());
 
-        new_chunk
+        Ok(new_chunk)
     }
 }
 
-/// Attempt to asynchronously read data from the file system for the chunk at the specified coordinates.
-pub async fn load_chunk(mut db: sqlx::pool::PoolConnection<sqlx::Postgres>, coords: ChunkCoords) -> Result<Chunk> {
-    log::trace!("Attempting to load chunk at {} from database", coords);
+/// Attempt to asynchronously read the chunk at the specified coordinates from storage. Returns `None` should that chunk
+/// not have been stored yet.
+pub async fn load_chunk(storage: &dyn Storage, coords: ChunkCoords) -> Result<Option<Chunk>> {
+    log::trace!("Attempting to load chunk at {} from storage", coords);
 
-    let res = db_query_from_file!("map_chunks/select row")
-        .bind(coords.x)
-        .bind(coords.y)
-        .map(|row| {
-            let data: &[u8] = row.get("data");
-            Ok(bincode::deserialize(data)?)
-        })
-        .fetch_one(&mut db)
-        .await?;
+    let start = Instant::now();
+    let res = storage.load_chunk(coords).await?;
+    METRICS.chunk_load_duration.observe_since(start);
 
-    log::debug!("Successfully loaded chunk at {} from database", coords);
+    if res.is_some() {
+        log::debug!("Successfully loaded chunk at {} from storage", coords);
+    }
 
-    res
+    Ok(res)
 }
 
-/// Attempt to asynchronously write the data comprising the provided chunk to the file system.
-pub async fn save_chunk(
-    mut db: sqlx::pool::PoolConnection<sqlx::Postgres>, coords: ChunkCoords, chunk: &Chunk
-) -> Result<()> {
-    log::trace!("Attempting to save chunk at {} to database", coords);
-
-    db_query_from_file!("map_chunks/replace row")
-        .bind(coords.x)
-        .bind(coords.y)
-        .bind(bincode::serialize(chunk)?)
-        .execute(&mut db)
-        .await
-        .map(|_| {
-            log::debug!("Successfully wrote chunk at {} to database", coords);
-        })
-        .map_err(convert::Into::into) // Map error type.
+/// Attempt to asynchronously write the provided chunk to storage.
+pub async fn save_chunk(storage: &dyn Storage, coords: ChunkCoords, chunk: &Chunk) -> Result<()> {
+    log::trace!("Attempting to save chunk at {} to storage", coords);
+
+    let start = Instant::now();
+    storage.save_chunk(coords, chunk).await?;
+    METRICS.chunk_save_duration.observe_since(start);
+
+    log::debug!("Successfully wrote chunk at {} to storage", coords);
+
+    Ok(())
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error("Failed to access database - {0}")]
-    DatabaseError(#[from] sqlx::Error),
-    #[error("Failed to (de)serialise chunk data with Bincode - {0}")]
-    BincodeError(#[from] bincode::Error)
+/// Unload the chunk at the given coordinates should no remote clients have it loaded nor any entities be in it (e.g. a
+/// chunk that was loaded only to be searched), saving it to storage once unloaded.
+pub async fn unload_chunk_if_unused(
+    storage: &dyn Storage, map: &Shared<super::ServerMap>, coords: ChunkCoords
+) -> Result<()> {
+    // Held until the unloaded chunk is saved so that no flush saves an older copy of it meanwhile:
+    let save_locks = map.lock().save_locks();
+    let _save_guard = save_locks.chunks.lock(coords).await;
+
+    let unloaded_chunk_option = map.lock().unload_chunk_if_unused(coords);
+
+    if let Some(unloaded_chunk) = unloaded_chunk_option {
+        save_chunk(storage, coords, &unloaded_chunk).await?;
+    }
+
+    Ok(())
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = storage::Result<T>;
@@ -1,25 +1,40 @@
-//! Includes functions to handle the fetching/saving of player entities from/to the database.
+//! Includes functions to handle the fetching/saving of player entities from/to storage as well as the placement of
+//! player entities when they spawn.
+
+use std::collections::HashSet;
 
 use rand::seq::IteratorRandom;
 use shared::{
+    effects::StatusEffects,
     gems, items,
     maps::{
         entities::{Direction, Entity, FacialExpression},
-        TileCoords
+        ChunkCoords, Map, TileCoords
     },
     Id
 };
-use sqlx::Row;
 use strum::IntoEnumIterator;
 
-use crate::db_query_from_file;
+use super::{chunks, ServerMap};
+use crate::{
+    storage::{self, Storage},
+    Shared
+};
+
+/// The position around which new player entities are placed and around which killed player entities respawn (see
+/// [`place_at_spawn_position`]).
+pub const SPAWN_POSITION: TileCoords = TileCoords { x: 0, y: 0 };
+
+/// The maximum distance in tiles (including diagonally) from the target position that is searched when finding a
+/// position for a player entity to spawn at.
+pub const MAX_SPAWN_SEARCH_DISTANCE: i32 = 64;
 
-/// Create a new player entity that will be stored in the database.
-pub async fn new_player_in_database(client_id: Id, db: &mut sqlx::PgConnection) -> sqlx::Result<(Id, Entity)> {
+/// Create a new player entity that will be kept in storage.
+pub async fn new_player_in_storage(client_id: Id, storage: &dyn Storage) -> storage::Result<(Id, Entity)> {
     let entity_id = crate::id::generate_with_timestamp();
 
     let entity = Entity {
-        pos: TileCoords { x: 0, y: 0 }, // TODO: Nearest free position.
+        pos: SPAWN_POSITION, // Moved to the nearest suitable position when added to the map.
         direction: Direction::Down,
         facial_expression: FacialExpression::Neutral,
         hair_style: random_variant(),
@@ -28,96 +43,154 @@ pub async fn new_player_in_database(client_id: Id, db: &mut sqlx::PgConnection)
         hair_colour: random_variant(),
         gem_collection: gems::Collection::default(),
         item_inventory: items::Inventory::default(),
-        bombs_placed_count: 0
+        bombs_placed_count: 0,
+        status_effects: StatusEffects::default()
     };
 
-    bind_entity_data(db_query_from_file!("client_entities/create row"), &entity)
-        .bind(client_id.encode())
-        .bind(entity_id.encode())
-        .execute(db)
-        .await?;
+    storage.create_player(client_id, entity_id, &entity).await?;
 
     Ok((entity_id, entity))
 }
 
-/// Fetch an existing player entity from the database.
-pub async fn player_from_database(client_id: Id, db: &mut sqlx::PgConnection) -> sqlx::Result<Option<(Id, Entity)>> {
-    let res = db_query_from_file!("client_entities/select row")
-        .bind(client_id.encode())
-        .map(|row: sqlx::postgres::PgRow| {
-            (
-                Id::decode(row.get("entity_id")).unwrap(),
-                Entity {
-                    pos: TileCoords { x: row.get("tile_x"), y: row.get("tile_y") },
-                    direction: Direction::Down,
-                    facial_expression: FacialExpression::Neutral,
-                    hair_style: decode_variant(row.get("hair_style")),
-                    clothing_colour: decode_variant(row.get("clothing_colour")),
-                    skin_colour: decode_variant(row.get("skin_colour")),
-                    hair_colour: decode_variant(row.get("hair_colour")),
-                    gem_collection: bincode::deserialize(row.get("gem_collection")).unwrap_or_default(),
-                    item_inventory: bincode::deserialize(row.get("item_inventory")).unwrap_or_default(),
-                    bombs_placed_count: row.get("bombs_placed_count")
-                }
-            )
-        })
-        .fetch_optional(db)
-        .await;
+/// Fetch an existing player entity from storage. Transient state (direction, facial expression and status effects) is
+/// reset.
+pub async fn player_from_storage(client_id: Id, storage: &dyn Storage) -> storage::Result<Option<(Id, Entity)>> {
+    let player_option = storage.load_player(client_id).await?;
+
+    Ok(player_option.map(|(entity_id, mut entity)| {
+        entity.direction = Direction::Down;
+        entity.facial_expression = FacialExpression::Neutral;
+        entity.status_effects = StatusEffects::default();
 
-    res
+        (entity_id, entity)
+    }))
 }
 
-/// Update an existing player entity in the database.
-pub async fn update_database_for_player(
-    entity: &Entity, client_id: Id, db: &mut sqlx::PgConnection
-) -> sqlx::Result<()> {
-    bind_entity_data(db_query_from_file!("client_entities/update row"), entity)
-        .bind(client_id.encode())
-        .execute(db)
-        .await
-        .map(|result| {
-            let rows_changed = result.rows_affected();
-            if rows_changed != 1 {
-                log::warn!(
-                    "Modified {} rows when update player entity data for client with ID {}",
-                    rows_changed,
-                    client_id
-                );
-            }
-        })
+/// Update an existing player entity (identified by its entity ID) in storage.
+pub async fn update_storage_for_player(entity: &Entity, entity_id: Id, storage: &dyn Storage) -> storage::Result<()> {
+    storage.update_player(entity_id, entity).await
 }
 
-/// Binds all the components of a player entity to the given database query (excluding the entity ID & client ID).
-fn bind_entity_data<'a>(
-    query: sqlx::query::Query<'a, sqlx::Postgres, sqlx::postgres::PgArguments>, entity: &Entity
-) -> sqlx::query::Query<'a, sqlx::Postgres, sqlx::postgres::PgArguments> {
-    query
-        .bind(entity.pos.x)
-        .bind(entity.pos.y)
-        .bind(encode_variant(entity.hair_style))
-        .bind(encode_variant(entity.clothing_colour))
-        .bind(encode_variant(entity.skin_colour))
-        .bind(encode_variant(entity.hair_colour))
-        .bind(bincode::serialize(&entity.gem_collection).unwrap_or_default())
-        .bind(bincode::serialize(&entity.item_inventory).unwrap_or_default())
-        .bind(entity.bombs_placed_count)
+/// Find the position nearest to the given target position that is suitable for a player entity to spawn at (see
+/// [`ServerMap::is_suitable_spawn_position`]) and call `place` with that position to place the entity there. Positions
+/// are searched in square rings of increasing distance around the target with the chunks containing and surrounding
+/// each position being loaded from storage or generated as necessary. Should no suitable position be found within
+/// [`MAX_SPAWN_SEARCH_DISTANCE`] tiles then the nearest position within that distance that is merely free (see
+/// [`Map::is_position_free`]) is used instead. Fails should there not even be such a position or should a chunk fail
+/// to load from storage.
+///
+/// The map remains locked from when the position is found until `place` returns so that no other entity can be placed
+/// at the same position meanwhile. The chunks loaded by the search are unloaded again afterwards (unless in use by a
+/// remote client) other than those containing and surrounding the position found.
+pub async fn place_at_spawn_position<T>(
+    storage: &dyn Storage, map: &Shared<ServerMap>, target: TileCoords,
+    place: impl FnOnce(&mut ServerMap, TileCoords) -> T
+) -> Result<T> {
+    let mut newly_loaded_chunk_coords = HashSet::new();
+    let search_result = search_and_place(storage, map, target, place, &mut newly_loaded_chunk_coords).await;
+
+    let kept_chunk_coords: HashSet<ChunkCoords> = match &search_result {
+        Ok((_, position)) => chunk_coords_at_and_surrounding(position.as_chunk_coords()).collect(),
+        Err(_) => HashSet::new()
+    };
+
+    for coords in newly_loaded_chunk_coords.difference(&kept_chunk_coords) {
+        chunks::unload_chunk_if_unused(storage, map, *coords).await?;
+    }
+
+    search_result.map(|(placed, _)| placed)
 }
 
-/// Encode an enum variant as a 16-bit integer.
-fn encode_variant<T: IntoEnumIterator + PartialEq>(val: T) -> i16 {
-    T::iter().position(|x| x == val).unwrap() as i16
+/// Perform the search described by [`place_at_spawn_position`], recording the coordinates of any chunks that were not
+/// already loaded before the search loaded them.
+async fn search_and_place<T>(
+    storage: &dyn Storage, map: &Shared<ServerMap>, target: TileCoords,
+    place: impl FnOnce(&mut ServerMap, TileCoords) -> T, newly_loaded_chunk_coords: &mut HashSet<ChunkCoords>
+) -> Result<(T, TileCoords)> {
+    let mut searched_chunk_coords = HashSet::new();
+
+    for distance in 0..MAX_SPAWN_SEARCH_DISTANCE + 1 {
+        let ring = ring_positions(target, distance);
+
+        for pos in &ring {
+            for coords in chunk_coords_at_and_surrounding(pos.as_chunk_coords()) {
+                if searched_chunk_coords.insert(coords) {
+                    if map.lock().loaded_chunk_at(coords).is_none() {
+                        newly_loaded_chunk_coords.insert(coords);
+                    }
+                    chunks::get_or_load_or_generate_chunk(storage, map, coords).await?;
+                }
+            }
+        }
+
+        let mut map = map.lock();
+
+        if let Some(found) = ring.into_iter().find(|pos| map.is_suitable_spawn_position(*pos)) {
+            if found != target {
+                log::debug!("Position {} is unsuitable for spawning so using nearby position {}", target, found);
+            }
+            return Ok((place(&mut map, found), found));
+        }
+    }
+
+    log::warn!("Could not find a suitable spawn position within {} tiles of {}", MAX_SPAWN_SEARCH_DISTANCE, target);
+
+    // Fall back on the nearest free position (all chunks within the search distance were loaded above):
+
+    let mut map = map.lock();
+
+    let found_option = (0..MAX_SPAWN_SEARCH_DISTANCE + 1)
+        .flat_map(|distance| ring_positions(target, distance))
+        .find(|pos| map.is_position_free(*pos));
+
+    match found_option {
+        Some(found) => Ok((place(&mut map, found), found)),
+        None => Err(Error::NoFreePosition(target))
+    }
 }
 
-/// Decodes a 16-bit integer into a variant of a given enum type. If the given integer does not corespond to a variant
-/// of the given enum type, then a random variant is returned and a warning message is printed.
-fn decode_variant<T: IntoEnumIterator>(val: i16) -> T {
-    T::iter().nth(val as usize).unwrap_or_else(|| {
-        log::warn!("Failed to decode 32-bit integer {} into enum variant of type {}", val, std::any::type_name::<T>());
-        random_variant()
+/// The given chunk coordinates and the 8 sets of chunk coordinates surrounding them.
+fn chunk_coords_at_and_surrounding(coords: ChunkCoords) -> impl Iterator<Item = ChunkCoords> {
+    (-1..2).flat_map(move |x_offset| {
+        (-1..2).map(move |y_offset| ChunkCoords { x: coords.x + x_offset, y: coords.y + y_offset })
     })
 }
 
+/// The positions exactly the given distance (including diagonally) from the centre position, starting from the top-left
+/// corner.
+pub(super) fn ring_positions(centre: TileCoords, distance: i32) -> Vec<TileCoords> {
+    if distance == 0 {
+        return vec![centre];
+    }
+
+    let mut positions = Vec::new();
+
+    // Top and bottom edges (including corners):
+    for x in centre.x - distance..centre.x + distance + 1 {
+        positions.push(TileCoords { x, y: centre.y - distance });
+        positions.push(TileCoords { x, y: centre.y + distance });
+    }
+
+    // Left and right edges (excluding corners):
+    for y in centre.y - distance + 1..centre.y + distance {
+        positions.push(TileCoords { x: centre.x - distance, y });
+        positions.push(TileCoords { x: centre.x + distance, y });
+    }
+
+    positions
+}
+
 /// Returns a random variant of the specified enum type.
-fn random_variant<T: IntoEnumIterator>() -> T {
+pub fn random_variant<T: IntoEnumIterator>() -> T {
     T::iter().choose(&mut rand::thread_rng()).unwrap()
 }
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Storage(#[from] storage::Error),
+    #[error("No free position to spawn at within {} tiles of {0}", MAX_SPAWN_SEARCH_DISTANCE)]
+    NoFreePosition(TileCoords)
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,23 +1,52 @@
 pub mod chunks;
 pub mod entities;
 pub mod generators;
+pub mod persistence;
+pub mod pruning;
 
 use std::{
-    collections::{HashMap, HashSet},
-    fmt
+    cmp,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    sync::Arc
 };
 
 use generators::Generator;
 use shared::{
+    chat,
+    effects::StatusEffect,
+    gems::Gem,
+    items::Trap,
     maps::{
-        entities::{Direction, Entity},
-        Chunk, ChunkCoords, Chunks, Map, Tile, TileCoords
+        entities::{Appearance, Direction, Entity, FacialExpression},
+        Chunk, ChunkCoords, Chunks, Map, PlacedTrap, Tile, TileCoords
     },
     Id
 };
-use sqlx::Row;
 
-use crate::db_query_from_file;
+use crate::{
+    admin::PlayerAction,
+    storage::{self, Storage}
+};
+
+/// Entities within this many tiles of a detonated bomb (including diagonally) are killed.
+pub const BOMB_KILL_RADIUS: i32 = 1;
+
+/// Entities within this many tiles of a detonated bomb (including diagonally) that are not killed are stunned.
+pub const BOMB_STUN_RADIUS: i32 = 3;
+
+/// The gems lost by a killed entity are scattered on the ground within this many tiles (including diagonally) of where
+/// that entity died.
+pub const LOST_GEMS_SCATTER_RADIUS: i32 = 2;
+
+/// Breakable tiles within this many tiles of a detonated bomb (including diagonally) are destroyed. This matches the
+/// area covered by the bomb explosion animation drawn by clients.
+pub const BOMB_DESTRUCTION_RADIUS: i32 = 1;
+
+/// The minimum number of walkable tiles (including the position itself) that must be reachable from a position for it
+/// to be considered suitable for an entity to spawn at. Prevents entities from spawning in small enclosed areas (e.g. a
+/// patch of grass surrounded by water).
+pub const MIN_SPAWN_REACHABLE_TILES: usize = 25;
 
 /// The context in which gameplay takes place. This structure manages all loaded tile chunks and player entities.
 pub struct ServerMap {
@@ -38,29 +67,41 @@ pub struct ServerMap {
 
     /// Chunk coordinates mapped to sets of entity IDs. This hash map exists to allow the efficient look up of which
     /// entities exists in which chunks.
-    chunk_coords_to_player_ids: HashMap<ChunkCoords, HashSet<Id>>
+    chunk_coords_to_player_ids: HashMap<ChunkCoords, HashSet<Id>>,
+
+    /// Coordinates of loaded chunks that may have been modified since they were last saved to storage. Any
+    /// mutable access to a loaded chunk marks it as dirty.
+    dirty_chunk_coords: HashSet<ChunkCoords>,
+
+    /// IDs of player entities that may have been modified since they were last saved to storage. Any mutable
+    /// access to a player entity marks it as dirty.
+    dirty_player_ids: HashSet<Id>,
+
+    /// Locks held while chunks and player entities of this map are saved to storage.
+    save_locks: Arc<persistence::SaveLocks>
 }
 
 impl ServerMap {
-    pub async fn load_or_new(db_pool: &sqlx::PgPool) -> sqlx::Result<Self> {
-        let existing_map_option = db_query_from_file!("map/select row")
-            .map(|row: sqlx::postgres::PgRow| ServerMap::new_with_default_generator(row.get("seed")))
-            .fetch_optional(db_pool)
-            .await?;
-
-        if let Some(existing_map) = existing_map_option {
-            log::debug!("Existing map loaded from database");
+    /// Load the existing map from storage or, should there not be one, create a new map and add it to storage. The new
+    /// map uses the given seed or a random seed if `None` is given.
+    pub async fn load_or_new(storage: &dyn Storage, new_seed_option: Option<i32>) -> storage::Result<Self> {
+        if let Some(seed) = storage.load_map_seed().await? {
+            log::debug!("Existing map loaded from storage");
+
+            if matches!(new_seed_option, Some(new_seed) if new_seed != seed) {
+                log::warn!("Specified seed is ignored as the map already exists");
+            }
 
-            sqlx::Result::Ok(existing_map)
+            Ok(ServerMap::new_with_default_generator(seed))
         }
         else {
-            let new_map = ServerMap::new_with_default_generator(0); // TODO: Random seed.
+            let seed = new_seed_option.unwrap_or_else(rand::random);
+            let new_map = ServerMap::new_with_default_generator(seed);
 
-            db_query_from_file!("map/create row").bind(new_map.seed).execute(db_pool).await.map(|_| {
-                log::debug!("Inserted newly generated map into database");
+            storage.save_map_seed(seed).await?;
+            log::debug!("Saved newly created map to storage");
 
-                new_map
-            })
+            Ok(new_map)
         }
     }
 
@@ -71,7 +112,10 @@ impl ServerMap {
             loaded_chunks: HashMap::new(),
             chunk_usage: HashMap::new(),
             player_entities: HashMap::new(),
-            chunk_coords_to_player_ids: HashMap::new()
+            chunk_coords_to_player_ids: HashMap::new(),
+            dirty_chunk_coords: HashSet::new(),
+            dirty_player_ids: HashSet::new(),
+            save_locks: Arc::default()
         }
     }
 
@@ -79,16 +123,43 @@ impl ServerMap {
         ServerMap::new(seed, Box::new(generators::DefaultGenerator::new(seed as u32)))
     }
 
+    /// Seed used by the generator.
+    pub fn seed(&self) -> i32 {
+        self.seed
+    }
+
+    /// Player entities currently on the map (i.e. those of connected players) mapped to by their entity IDs.
+    pub fn player_entities(&self) -> &HashMap<Id, Entity> {
+        &self.player_entities
+    }
+
+    /// The number of chunks currently loaded.
+    pub fn loaded_chunk_count(&self) -> usize {
+        self.loaded_chunks.len()
+    }
+
+    /// The coordinates of each loaded chunk mapped to the number of remote clients that have that chunk loaded.
+    pub fn chunk_usage(&self) -> &HashMap<ChunkCoords, usize> {
+        &self.chunk_usage
+    }
+
     /// Move an entity in a specified direction. This method checks if the desintation position is already occupied or
     /// a blocking tile (note that tile positions in unloaded chunks are considered blocking) - if it is then `None` is
     /// returned (`None` is also returned should an entity with the specified ID not be found). If the movement is
     /// deemed okay to go ahead, the entity's old position and new position (i.e. position after the movement is
-    /// applied) are returned. The hash map that keeps track of which entities reside in which chunks is updated
-    /// also.
+    /// applied) are returned along with the time taken to perform the movement. The hash map that keeps track of which
+    /// entities reside in which chunks is updated also.
     ///
     /// If the movement is on to a smashable tile (e.g. diamond rock) then the tile is updated. The caller does not have
     /// to notify their client nor the tasks of other clients of the tile change as it is the responsiblity of each
     /// client to infer a tile change whenever some entity moves onto a smashable tile.
+    ///
+    /// If the destination position has a trap placed by some other entity then that trap is removed and returned so
+    /// that the caller may apply its effect. Theft traps are only triggered should the entity that placed them be
+    /// present on the map (i.e. their player is connected) as otherwise there is no one to give stolen gems to.
+    ///
+    /// Should there instead be a gem on the ground at the destination position then that gem is collected by the
+    /// entity (removed from the map and added to the entity's gem collection) and returned.
     pub fn move_entity_towards(&mut self, entity_id: Id, direction: Direction) -> Option<EntityMovement> {
         // Ensure entity with the given ID actually exists:
         if self.player_entities.contains_key(&entity_id) {
@@ -98,12 +169,20 @@ impl ServerMap {
                 let entity = self.entity_by_id(entity_id).unwrap();
 
                 let new_pos = direction.apply(entity.pos);
-                (entity.pos, self.is_position_free(new_pos).then(|| new_pos))
+                (entity.pos, (entity.can_move() && self.is_position_free(new_pos)).then(|| new_pos))
+            };
+
+            // Time taken to move onto the destination tile (must be determined before any smashable tile is smashed):
+            let movement_time = {
+                let dest_tile = new_position_option.and_then(|pos| self.loaded_tile_at(pos)).unwrap_or_default();
+                self.player_entities[&entity_id].movement_time(dest_tile)
             };
 
             let entity_mut = self.player_entities.get_mut(&entity_id).unwrap();
 
             if let Some(new_position) = new_position_option {
+                self.dirty_player_ids.insert(entity_id);
+
                 // Apply the new position & direction:
                 entity_mut.pos = new_position;
                 entity_mut.direction = direction;
@@ -128,7 +207,38 @@ impl ServerMap {
                     self.set_loaded_tile_at(new_position, Tile::RockSmashed);
                 }
 
-                Some(EntityMovement { old_position, new_position, smashed_tile_option })
+                // Determine whether a trap is triggered by the movement and remove it if so:
+                let is_trap_triggered = self
+                    .trap_at(new_position)
+                    .map(|placed_trap| {
+                        placed_trap.placed_by != entity_id
+                            && (placed_trap.trap != Trap::Theft
+                                || self.player_entities.contains_key(&placed_trap.placed_by))
+                    })
+                    .unwrap_or(false);
+
+                let triggered_trap_option = if is_trap_triggered { self.take_trap_at(new_position) } else { None };
+
+                // Collect the gem on the ground at the destination (if any):
+                let collected_gem_option = if self.ground_gem_at(new_position).is_some() {
+                    self.take_ground_gem_at(new_position)
+                }
+                else {
+                    None
+                };
+
+                if let Some(gem) = collected_gem_option {
+                    self.player_entities.get_mut(&entity_id).unwrap().gem_collection.increase_quantity(gem, 1);
+                }
+
+                Some(EntityMovement {
+                    old_position,
+                    new_position,
+                    smashed_tile_option,
+                    movement_time,
+                    triggered_trap_option,
+                    collected_gem_option
+                })
             }
             else {
                 None // Movement not allowed due to blocking tile or entity at destination.
@@ -139,6 +249,171 @@ impl ServerMap {
         }
     }
 
+    /// Instantly move the entity with the given ID to the specified position without performing any checks (e.g. when
+    /// respawning). The entity's previous position is returned (or `None` should no entity with that ID exist).
+    pub fn teleport_entity(&mut self, entity_id: Id, new_position: TileCoords) -> Option<TileCoords> {
+        let entity = self.player_entities.get_mut(&entity_id)?;
+        let old_position = entity.pos;
+        entity.pos = new_position;
+
+        self.dirty_player_ids.insert(entity_id);
+
+        self.chunk_coords_to_player_ids.entry(old_position.as_chunk_coords()).and_modify(|x| {
+            x.remove(&entity_id);
+        });
+        self.chunk_coords_to_player_ids.entry(new_position.as_chunk_coords()).or_default().insert(entity_id);
+
+        Some(old_position)
+    }
+
+    /// Whether an entity could spawn at the given position - the tile there must not be blocking nor occupied by
+    /// another entity, and at least [`MIN_SPAWN_REACHABLE_TILES`] walkable tiles must be reachable from it. Tiles
+    /// in chunks that are not loaded are considered blocking so the chunks around the position should be loaded
+    /// beforehand.
+    pub fn is_suitable_spawn_position(&self, coords: TileCoords) -> bool {
+        if !self.is_position_free(coords) {
+            return false;
+        }
+
+        // Flood fill outwards across walkable tiles until enough have been found (other entities are ignored as they
+        // are able to move out of the way):
+
+        let mut visited = HashSet::new();
+        visited.insert(coords);
+
+        let mut queue = VecDeque::new();
+        queue.push_back(coords);
+
+        while let Some(pos) = queue.pop_front() {
+            for direction in &[Direction::Up, Direction::Down, Direction::Left, Direction::Right] {
+                let neighbour = direction.apply(pos);
+
+                if !self.is_blocking_tile_at(neighbour) && visited.insert(neighbour) {
+                    if visited.len() >= MIN_SPAWN_REACHABLE_TILES {
+                        return true;
+                    }
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Identify the entities caught in the blast of bombs detonated at the given positions along with how each entity
+    /// is affected. Entities within [`BOMB_KILL_RADIUS`] tiles of any of the positions are killed while entities
+    /// further away but within [`BOMB_STUN_RADIUS`] tiles are stunned.
+    pub fn entities_caught_in_blast(&self, detonated_positions: &[TileCoords]) -> Vec<(Id, BlastEffect)> {
+        self.player_entities
+            .iter()
+            .filter_map(|(entity_id, entity)| {
+                let nearest_distance = detonated_positions.iter().map(|pos| pos.distance_to(entity.pos)).min()?;
+
+                if nearest_distance <= BOMB_KILL_RADIUS {
+                    Some((*entity_id, BlastEffect::Killed))
+                }
+                else if nearest_distance <= BOMB_STUN_RADIUS {
+                    Some((*entity_id, BlastEffect::Stunned))
+                }
+                else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Destroy the breakable tiles (see [`Tile::destroyed_by_blast`]) within [`BOMB_DESTRUCTION_RADIUS`] tiles of any
+    /// of the given detonated bomb positions. Only tiles within loaded chunks are affected. The position of each
+    /// destroyed tile is returned along with its tile type before and after being destroyed.
+    pub fn destroy_tiles_in_blast(&mut self, detonated_positions: &[TileCoords]) -> Vec<(TileCoords, Tile, Tile)> {
+        let mut destroyed_tiles = Vec::new();
+
+        for bomb_pos in detonated_positions {
+            for x_offset in -BOMB_DESTRUCTION_RADIUS..BOMB_DESTRUCTION_RADIUS + 1 {
+                for y_offset in -BOMB_DESTRUCTION_RADIUS..BOMB_DESTRUCTION_RADIUS + 1 {
+                    let pos = TileCoords { x: bomb_pos.x + x_offset, y: bomb_pos.y + y_offset };
+
+                    // Tiles already destroyed by another bomb in the same detonation will not be destroyed again:
+                    if let Some(old_tile) = self.loaded_tile_at(pos) {
+                        if let Some(new_tile) = old_tile.destroyed_by_blast() {
+                            self.set_loaded_tile_at(pos, new_tile);
+                            destroyed_tiles.push((pos, old_tile, new_tile));
+                        }
+                    }
+                }
+            }
+        }
+
+        destroyed_tiles
+    }
+
+    /// Scatter the given quantities of gems on the ground around the specified position, one gem per position with the
+    /// most valuable gems placed nearest. Gems are only placed within [`LOST_GEMS_SCATTER_RADIUS`] tiles at free
+    /// positions in loaded chunks where there is neither a trap nor a gem on the ground already - any gems left over
+    /// once those positions run out are not placed. The positions and types of the placed gems are returned.
+    pub fn scatter_ground_gems(&mut self, centre: TileCoords, gems: &[(Gem, u32)]) -> Vec<(TileCoords, Gem)> {
+        let mut sorted_gems = gems.to_vec();
+        sorted_gems.sort_by_key(|(gem, _)| cmp::Reverse(gem.value()));
+
+        let mut remaining_gems = sorted_gems.into_iter().flat_map(|(gem, quantity)| (0..quantity).map(move |_| gem));
+
+        let mut placed_gems = Vec::new();
+
+        for pos in (0..LOST_GEMS_SCATTER_RADIUS + 1).flat_map(|distance| entities::ring_positions(centre, distance)) {
+            if self.is_position_free(pos) && self.ground_gem_at(pos).is_none() && self.trap_at(pos).is_none() {
+                match remaining_gems.next() {
+                    Some(gem) => {
+                        self.set_ground_gem_at(pos, gem);
+                        placed_gems.push((pos, gem));
+                    }
+                    None => break
+                }
+            }
+        }
+
+        placed_gems
+    }
+
+    /// Take (i.e. clear and return) the coordinates of the loaded chunks that have been marked as dirty since this
+    /// method was last called. The caller is expected to save those chunks to storage and to call
+    /// [`Self::mark_chunk_dirty`] for any that could not be saved.
+    pub fn take_dirty_chunk_coords(&mut self) -> Vec<ChunkCoords> {
+        self.dirty_chunk_coords.drain().collect()
+    }
+
+    /// Take (i.e. clear and return) the IDs of the player entities that have been marked as dirty since this method was
+    /// last called. The caller is expected to save those player entities to storage and to call
+    /// [`Self::mark_player_dirty`] for any that could not be saved.
+    pub fn take_dirty_player_ids(&mut self) -> Vec<Id> {
+        self.dirty_player_ids.drain().collect()
+    }
+
+    /// The locks to be held while saving chunks and player entities of this map (see [`persistence`]).
+    pub fn save_locks(&self) -> Arc<persistence::SaveLocks> {
+        Arc::clone(&self.save_locks)
+    }
+
+    /// Mark the chunk at the given coordinates as needing to be saved (provided it is still loaded).
+    pub fn mark_chunk_dirty(&mut self, coords: ChunkCoords) {
+        if self.loaded_chunks.contains_key(&coords) {
+            self.dirty_chunk_coords.insert(coords);
+        }
+    }
+
+    /// Mark the player entity with the given ID as needing to be saved (provided it is still present on the map).
+    pub fn mark_player_dirty(&mut self, entity_id: Id) {
+        if self.player_entities.contains_key(&entity_id) {
+            self.dirty_player_ids.insert(entity_id);
+        }
+    }
+
+    /// Mark every loaded chunk and player entity as needing to be saved (e.g. so that everything is saved by a final
+    /// flush before the server shuts down).
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty_chunk_coords.extend(self.loaded_chunks.keys().copied());
+        self.dirty_player_ids.extend(self.player_entities.keys().copied());
+    }
+
     /// Get all entity IDs and entities in the chunk at the given chunk coordinates.
     pub fn entities_in_chunk(&self, coords: ChunkCoords) -> Vec<(Id, Entity)> {
         let mut entities = Vec::new();
@@ -159,6 +434,29 @@ impl ServerMap {
         *self.chunk_usage.entry(coords).or_default() += 1;
     }
 
+    /// Remove from all loaded chunks the undetonated bombs placed by any of the entities with the given IDs. Returns
+    /// the number of bombs removed.
+    pub fn remove_bombs_placed_by(&mut self, placed_by_ids: &HashSet<Id>) -> usize {
+        let loaded_coords: Vec<ChunkCoords> = self.loaded_chunks.keys().copied().collect();
+
+        loaded_coords.into_iter().map(|coords| self.remove_bombs_placed_by_in_chunk(coords, placed_by_ids)).sum()
+    }
+
+    /// Remove from the loaded chunk at the given coordinates (should it be loaded) the undetonated bombs placed by any
+    /// of the entities with the given IDs. Returns the number of bombs removed.
+    pub fn remove_bombs_placed_by_in_chunk(&mut self, coords: ChunkCoords, placed_by_ids: &HashSet<Id>) -> usize {
+        let count = match self.loaded_chunks.get_mut(&coords) {
+            Some(chunk) => placed_by_ids.iter().map(|id| chunk.take_bombs_placed_by(*id).len()).sum(),
+            None => 0
+        };
+
+        if count > 0 {
+            self.dirty_chunk_coords.insert(coords);
+        }
+
+        count
+    }
+
     /// To be called by a client task whenever their remote client is told to unload a certain chunk. Will remove the
     /// chunk from this map's collection of loaded chunks & return it if it has been determined that no remote clients
     /// have that chunk loaded.
@@ -166,7 +464,7 @@ impl ServerMap {
         let entry = self.chunk_usage.entry(coords).or_default();
         *entry -= 1;
 
-        // If no clients have the chunk loaded, then save to database & unloaded the chunk:
+        // If no clients have the chunk loaded, then save to storage & unloaded the chunk:
         if *entry == 0 {
             self.remove_chunk(coords)
         }
@@ -174,6 +472,20 @@ impl ServerMap {
             None
         }
     }
+
+    /// Remove the chunk at the given coordinates from this map's collection of loaded chunks & return it provided no
+    /// remote clients have that chunk loaded and no entities are in it.
+    pub fn unload_chunk_if_unused(&mut self, coords: ChunkCoords) -> Option<Chunk> {
+        let in_use = self.chunk_usage.get(&coords).copied().unwrap_or(0) > 0;
+        let occupied = self.chunk_coords_to_player_ids.get(&coords).map_or(0, HashSet::len) > 0;
+
+        if in_use || occupied {
+            None
+        }
+        else {
+            self.remove_chunk(coords)
+        }
+    }
 }
 
 impl Map for ServerMap {
@@ -182,7 +494,12 @@ impl Map for ServerMap {
     }
 
     fn loaded_chunk_at_mut(&mut self, coords: ChunkCoords) -> Option<&mut Chunk> {
-        self.loaded_chunks.get_mut(&coords)
+        let chunk_option = self.loaded_chunks.get_mut(&coords);
+
+        if chunk_option.is_some() {
+            self.dirty_chunk_coords.insert(coords);
+        }
+        chunk_option
     }
 
     fn add_chunk(&mut self, coords: ChunkCoords, chunk: Chunk) {
@@ -193,6 +510,9 @@ impl Map for ServerMap {
     fn remove_chunk(&mut self, coords: ChunkCoords) -> Option<Chunk> {
         log::debug!("Chunk at {} unloaded", coords);
 
+        // Chunks are saved by the caller once removed so need not be flushed:
+        self.dirty_chunk_coords.remove(&coords);
+
         self.chunk_coords_to_player_ids.remove(&coords);
         self.loaded_chunks.remove(&coords)
     }
@@ -202,7 +522,12 @@ impl Map for ServerMap {
     }
 
     fn entity_by_id_mut(&mut self, id: Id) -> Option<&mut Entity> {
-        self.player_entities.get_mut(&id)
+        let entity_option = self.player_entities.get_mut(&id);
+
+        if entity_option.is_some() {
+            self.dirty_player_ids.insert(id);
+        }
+        entity_option
     }
 
     fn add_entity(&mut self, id: Id, entity: Entity) {
@@ -223,6 +548,9 @@ impl Map for ServerMap {
         log::debug!("Removing player entity with ID {} from game map", id);
         let opt = self.player_entities.remove(&id);
 
+        // Player entities are saved by the caller once removed so need not be flushed:
+        self.dirty_player_ids.remove(&id);
+
         // Remove the association between the entity and the chunk that entity was in:
         if let Some(entity) = &opt {
             self.chunk_coords_to_player_ids.entry(entity.pos.as_chunk_coords()).and_modify(|x| {
@@ -253,14 +581,33 @@ impl Map for ServerMap {
 pub struct EntityMovement {
     pub old_position: TileCoords,
     pub new_position: TileCoords,
-    pub smashed_tile_option: Option<Tile>
+    pub smashed_tile_option: Option<Tile>,
+    /// The time (in seconds) taken for the entity to move to its new position. The entity should not be allowed to
+    /// move again until this amount of time has passed.
+    pub movement_time: f32,
+    /// The trap (if any) that was triggered by the entity moving onto it. The trap will have been removed from the
+    /// map.
+    pub triggered_trap_option: Option<PlacedTrap>,
+    /// The gem on the ground (if any) that was collected by the entity moving onto it. The gem will have been removed
+    /// from the map and added to the entity's gem collection.
+    pub collected_gem_option: Option<Gem>
+}
+
+/// How an entity caught in the blast of a detonated bomb is affected.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlastEffect {
+    /// The entity is killed - it loses some of its gems before respawning.
+    Killed,
+    /// The entity is temporarily unable to move.
+    Stunned
 }
 
 /// Represents a change made to the game map (tiles and entities). This enum is used by client tasks to inform other
 /// tasks of changes made to the game map.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum Modification {
-    #[allow(dead_code)]
+    /// Indicates that the tile at the given coordinates has been changed (e.g. destroyed by a bomb blast). Note that
+    /// rock smashing caused by entity movement is inferred by each client and so is not sent as a tile change.
     TileChanged(TileCoords, Tile),
 
     EntityMoved {
@@ -285,8 +632,70 @@ pub enum Modification {
     /// Indicates that a bomb has been placed at the given coordinates by the entity with the specified ID.
     BombPlaced(TileCoords, Id),
 
-    /// The player with the specified ID detonated their placed bombs.
-    BombsDetonated(Id)
+    /// The player with the specified ID detonated their placed bombs at the given positions.
+    BombsDetonated { placed_by: Id, positions: Vec<TileCoords> },
+
+    /// Indicates that the entity with the specified ID was caught in the blast of bombs detonated by another player.
+    /// The effect is applied by the task responsible for the affected entity.
+    CaughtInBlast { entity_id: Id, effect: BlastEffect },
+
+    /// Indicates that the entity with the specified ID was killed at its old position and has since respawned at its
+    /// new position.
+    EntityDied { entity_id: Id, old_position: TileCoords, new_position: TileCoords },
+
+    /// Indicates that a trap has been placed at the given coordinates.
+    TrapPlaced(TileCoords, PlacedTrap),
+
+    /// Indicates that the given trap at the specified coordinates was triggered by the entity with the specified ID.
+    /// The number of emeralds stolen from that entity is included should the trap be a theft trap.
+    TrapTriggered { position: TileCoords, placed_trap: PlacedTrap, triggered_by: Id, emeralds_stolen: u32 },
+
+    /// Indicates that the gem on the ground at the given coordinates was collected by the entity with the specified
+    /// ID.
+    GroundGemCollected { position: TileCoords, collected_by: Id },
+
+    /// Indicates that gems of the given types have been placed on the ground at the specified coordinates (i.e. the
+    /// gems lost by a killed entity were scattered around where it died).
+    GroundGemsPlaced(Vec<(TileCoords, Gem)>),
+
+    /// Indicates that the given status effect has been applied to the entity with the specified ID.
+    StatusEffectStarted(Id, StatusEffect),
+
+    /// Indicates that the given status effect applied to the entity with the specified ID has expired.
+    StatusEffectEnded(Id, StatusEffect),
+
+    /// Indicates that the player with the specified ID sent a chat message (already filtered) on the given channel.
+    /// The position of the sender's player entity is included so that each task can decide whether to deliver a
+    /// proximity chat message based on their client's loaded chunks.
+    ChatMessage { sender: Id, position: TileCoords, channel: chat::Channel, text: String },
+
+    /// Indicates that the facial expression of the entity with the specified ID has changed.
+    FacialExpressionChanged(Id, FacialExpression),
+
+    /// Indicates that the appearance of the entity with the specified ID has been customised by its player.
+    AppearanceChanged(Id, Appearance),
+
+    /// Indicates that the score of a player has changed and so the leaderboard (and the ranks of other players) may
+    /// have changed.
+    LeaderboardChanged,
+
+    /// Indicates that the server's operators wish to perform the given action on the player with the specified ID.
+    /// The action is performed by the task responsible for that player's entity.
+    AdminAction { entity_id: Id, action: PlayerAction },
+
+    /// Indicates that the entity with the specified ID was instantly moved from its old position to its new position
+    /// (i.e. by the server's operators).
+    EntityTeleported { entity_id: Id, old_position: TileCoords, new_position: TileCoords }
+}
+
+/// A modification to the game map along with the ID of the player entity whose task sent it. Each task receives its
+/// own broadcasts so the sender's ID allows a task to ignore changes that it has already dealt with itself.
+#[derive(Debug, Clone)]
+pub struct MapChange {
+    /// The ID of the player entity of the task that sent this change or `None` should it not have been sent by a
+    /// connection task (e.g. sent by the admin console).
+    pub sent_by: Option<Id>,
+    pub modification: Modification
 }
 
 impl fmt::Display for Modification {
@@ -309,8 +718,50 @@ impl fmt::Display for Modification {
             Modification::BombPlaced(pos, placed_by) => {
                 write!(f, "bomb placed at {} by entity with ID {}", pos, placed_by)
             }
-            Modification::BombsDetonated(placed_by) => {
-                write!(f, "bombs placed by {} detonated", placed_by)
+            Modification::BombsDetonated { placed_by, positions } => {
+                write!(f, "{} bombs placed by {} detonated", positions.len(), placed_by)
+            }
+            Modification::CaughtInBlast { entity_id, effect } => {
+                write!(f, "entity {} caught in bomb blast with effect {:?}", entity_id, effect)
+            }
+            Modification::EntityDied { entity_id, old_position, new_position } => {
+                write!(f, "entity {} died at {} and respawned at {}", entity_id, old_position, new_position)
+            }
+            Modification::TrapPlaced(pos, placed_trap) => {
+                write!(f, "{:?} trap placed at {} by entity with ID {}", placed_trap.trap, pos, placed_trap.placed_by)
+            }
+            Modification::TrapTriggered { position, placed_trap, triggered_by, emeralds_stolen } => {
+                write!(
+                    f,
+                    "{:?} trap at {} placed by {} triggered by {} ({} emeralds stolen)",
+                    placed_trap.trap, position, placed_trap.placed_by, triggered_by, emeralds_stolen
+                )
+            }
+            Modification::GroundGemCollected { position, collected_by } => {
+                write!(f, "gem on the ground at {} collected by {}", position, collected_by)
+            }
+            Modification::GroundGemsPlaced(placed_gems) => write!(f, "{} gems placed on the ground", placed_gems.len()),
+            Modification::StatusEffectStarted(id, effect) => {
+                write!(f, "status effect {} started for entity {}", effect, id)
+            }
+            Modification::StatusEffectEnded(id, effect) => {
+                write!(f, "status effect {} ended for entity {}", effect, id)
+            }
+            Modification::ChatMessage { sender, position, channel, text } => {
+                write!(f, "{:?} chat message from entity {} at {} - '{}'", channel, sender, position, text)
+            }
+            Modification::FacialExpressionChanged(id, expression) => {
+                write!(f, "facial expression of entity {} changed to {}", id, expression)
+            }
+            Modification::AppearanceChanged(id, appearance) => {
+                write!(f, "appearance of entity {} changed to {}", id, appearance)
+            }
+            Modification::LeaderboardChanged => write!(f, "leaderboard changed"),
+            Modification::AdminAction { entity_id, action } => {
+                write!(f, "administrative action '{}' performed on entity {}", action, entity_id)
+            }
+            Modification::EntityTeleported { entity_id, old_position, new_position } => {
+                write!(f, "entity {} teleported from {} to {}", entity_id, old_position, new_position)
             }
         }
     }
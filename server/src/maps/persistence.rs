@@ -0,0 +1,270 @@
+//! Write-behind persistence of the game map. Chunks and player entities are otherwise only saved when they are unloaded
+//! (i.e. when no longer needed by any client or when a client disconnects), so changes made to them are periodically
+//! flushed to storage by a background task to limit what is lost should the server stop unexpectedly.
+//!
+//! As flushes and unloads may save the same chunk or player entity at the same time, each save is made while holding
+//! the [`SaveLocks`] lock for that chunk or player entity. The copy that is saved is taken only once that lock is held
+//! so that an older copy is never written over a newer one. Chunks are also loaded from storage while holding their
+//! lock so that a stored copy is never read while a newer copy is still being saved.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc
+    }
+};
+
+use parking_lot::Mutex;
+use shared::{
+    maps::{ChunkCoords, Map},
+    Id
+};
+use tokio::{
+    sync::{Mutex as AsyncMutex, OwnedMutexGuard},
+    time::{self, Duration}
+};
+
+use super::{chunks, entities};
+use crate::{storage::Storage, Shared};
+
+/// Locks held while saving chunks and player entities (see the module documentation).
+#[derive(Default)]
+pub struct SaveLocks {
+    pub chunks: KeyedLocks<ChunkCoords>,
+    pub players: KeyedLocks<Id>
+}
+
+/// A lock for each key currently in use. Locks are removed once no longer held or waited on.
+pub struct KeyedLocks<K> {
+    locks: Mutex<HashMap<K, Arc<AsyncMutex<()>>>>
+}
+
+impl<K: Hash + Eq + Copy> KeyedLocks<K> {
+    /// Wait until the lock for the given key is free before taking it. The lock is held until the returned guard is
+    /// dropped.
+    pub async fn lock(&self, key: K) -> KeyedLockGuard<'_, K> {
+        let lock = Arc::clone(self.locks.lock().entry(key).or_default());
+        let guard = lock.lock_owned().await;
+
+        KeyedLockGuard { locks: self, key, guard_option: Some(guard) }
+    }
+}
+
+impl<K> Default for KeyedLocks<K> {
+    fn default() -> Self {
+        KeyedLocks { locks: Mutex::new(HashMap::new()) }
+    }
+}
+
+pub struct KeyedLockGuard<'a, K: Hash + Eq + Copy> {
+    locks: &'a KeyedLocks<K>,
+    key: K,
+    guard_option: Option<OwnedMutexGuard<()>>
+}
+
+impl<K: Hash + Eq + Copy> Drop for KeyedLockGuard<'_, K> {
+    fn drop(&mut self) {
+        let mut locks = self.locks.locks.lock();
+        self.guard_option.take();
+
+        // Remove the lock should nothing else be waiting on it (only the map of locks holds a reference):
+        if matches!(locks.get(&self.key), Some(lock) if Arc::strong_count(lock) == 1) {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+/// Counts of the flushes performed by the background persistence task.
+#[derive(Debug, Default)]
+pub struct Counters {
+    /// Number of flushes that have been performed (regardless of whether or not they succeeded).
+    pub flushes: AtomicU64,
+    /// Number of chunks successfully saved by flushes.
+    pub chunks_saved: AtomicU64,
+    /// Number of player entities successfully saved by flushes.
+    pub players_saved: AtomicU64,
+    /// Number of individual chunk or player entity saves that failed.
+    pub failures: AtomicU64
+}
+
+/// Flush dirty chunks and player entities to storage every interval. This function never returns and so should
+/// be spawned as its own task.
+pub async fn flush_periodically(
+    map: Shared<super::ServerMap>, storage: Arc<dyn Storage>, interval: Duration, counters: Arc<Counters>
+) {
+    let mut interval = time::interval(interval);
+    interval.tick().await; // The first tick completes immediately.
+
+    loop {
+        interval.tick().await;
+        flush(&map, storage.as_ref(), &counters).await;
+    }
+}
+
+/// Save all chunks and player entities marked as dirty in the given map to storage. Anything that fails to save
+/// is marked as dirty again so that it will be retried by the next flush. Returns the number of chunks and player
+/// entities that failed to save.
+pub async fn flush(map: &Shared<super::ServerMap>, storage: &dyn Storage, counters: &Counters) -> u64 {
+    let (dirty_chunk_coords, dirty_player_ids, save_locks) = {
+        let mut map = map.lock();
+        (map.take_dirty_chunk_coords(), map.take_dirty_player_ids(), map.save_locks())
+    };
+
+    let mut chunks_saved = 0;
+    let mut players_saved = 0;
+    let mut failures = 0;
+
+    for coords in dirty_chunk_coords {
+        let _save_guard = save_locks.chunks.lock(coords).await;
+
+        // A copy is taken so that the map's mutex is not held while waiting on storage. Chunks unloaded since being
+        // marked dirty were saved when unloaded:
+        let chunk = match map.lock().loaded_chunk_at(coords) {
+            Some(chunk) => chunk.clone(),
+            None => continue
+        };
+
+        if let Err(e) = chunks::save_chunk(storage, coords, &chunk).await {
+            log::warn!("Failed to flush chunk at {} to storage - {}", coords, e);
+            map.lock().mark_chunk_dirty(coords);
+            failures += 1;
+        }
+        else {
+            chunks_saved += 1;
+        }
+    }
+
+    for entity_id in dirty_player_ids {
+        let _save_guard = save_locks.players.lock(entity_id).await;
+
+        // Likewise, player entities removed from the map since being marked dirty were saved when removed:
+        let entity = match map.lock().entity_by_id(entity_id) {
+            Some(entity) => entity.clone(),
+            None => continue
+        };
+
+        if let Err(e) = entities::update_storage_for_player(&entity, entity_id, storage).await {
+            log::warn!("Failed to flush player entity {} to storage - {}", entity_id, e);
+            map.lock().mark_player_dirty(entity_id);
+            failures += 1;
+        }
+        else {
+            players_saved += 1;
+        }
+    }
+
+    let flush_number = counters.flushes.fetch_add(1, Ordering::Relaxed) + 1;
+    counters.chunks_saved.fetch_add(chunks_saved, Ordering::Relaxed);
+    counters.players_saved.fetch_add(players_saved, Ordering::Relaxed);
+    let total_failures = counters.failures.fetch_add(failures, Ordering::Relaxed) + failures;
+
+    log::debug!(
+        "Flush #{} saved {} chunks and {} player entities ({} failures, {} in total)",
+        flush_number,
+        chunks_saved,
+        players_saved,
+        failures,
+        total_failures
+    );
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use shared::maps::{Chunk, OffsetCoords, Tile, TileCoords};
+
+    use super::*;
+    use crate::{maps::ServerMap, storage::MemoryStorage};
+
+    /// Ensure that a flush waiting on the save of an unloaded chunk does not then overwrite that save with an older
+    /// copy of the chunk.
+    #[tokio::test]
+    async fn flush_does_not_overwrite_newer_save() {
+        let storage = Arc::new(MemoryStorage::default());
+        let map = Arc::new(Mutex::new(ServerMap::new_with_default_generator(0)));
+        let coords = ChunkCoords { x: 0, y: 0 };
+
+        map.lock().add_chunk(coords, Chunk::default());
+        map.lock().set_loaded_tile_at(TileCoords { x: 1, y: 1 }, Tile::Rock);
+
+        // Begin unloading the chunk (holding its save lock) and let the flush take the dirty chunk:
+        let save_locks = map.lock().save_locks();
+        let save_guard = save_locks.chunks.lock(coords).await;
+
+        let counters = Arc::new(Counters::default());
+        let flush_task = {
+            let (map, storage, counters) = (Arc::clone(&map), Arc::clone(&storage), Arc::clone(&counters));
+            tokio::spawn(async move { flush(&map, storage.as_ref(), &counters).await })
+        };
+        tokio::task::yield_now().await;
+
+        // The chunk is changed further before being unloaded and saved:
+        let unloaded_chunk = {
+            let mut map = map.lock();
+            map.set_loaded_tile_at(TileCoords { x: 1, y: 1 }, Tile::RockSmashed);
+            map.remove_chunk(coords).unwrap()
+        };
+        storage.save_chunk(coords, &unloaded_chunk).await.unwrap();
+        drop(save_guard);
+
+        // The flush should have skipped the chunk as it was saved when unloaded:
+        assert_eq!(flush_task.await.unwrap(), 0);
+        assert_eq!(counters.chunks_saved.load(Ordering::Relaxed), 0);
+
+        let stored_chunk = storage.load_chunk(coords).await.unwrap().unwrap();
+        assert_eq!(stored_chunk.tile_at_offset(OffsetCoords { x: 1, y: 1 }), Tile::RockSmashed);
+    }
+
+    /// Ensure that a chunk being loaded while it is still being saved after being unloaded is read only once that save
+    /// is complete (rather than an older stored copy being read).
+    #[tokio::test]
+    async fn load_waits_for_save() {
+        let storage = Arc::new(MemoryStorage::default());
+        let map = Arc::new(Mutex::new(ServerMap::new_with_default_generator(0)));
+        let coords = ChunkCoords { x: 0, y: 0 };
+
+        let mut chunk = Chunk::default();
+        chunk.set_tile_at_offset(OffsetCoords { x: 1, y: 1 }, Tile::Rock);
+        storage.save_chunk(coords, &chunk).await.unwrap();
+        map.lock().add_chunk(coords, chunk);
+
+        // Begin unloading the changed chunk (holding its save lock) and then request that it be loaded again:
+        let save_locks = map.lock().save_locks();
+        let save_guard = save_locks.chunks.lock(coords).await;
+
+        let unloaded_chunk = {
+            let mut map = map.lock();
+            map.set_loaded_tile_at(TileCoords { x: 1, y: 1 }, Tile::RockSmashed);
+            map.remove_chunk(coords).unwrap()
+        };
+
+        let load_task = {
+            let (map, storage) = (Arc::clone(&map), Arc::clone(&storage));
+            tokio::spawn(async move { chunks::get_or_load_or_generate_chunk(storage.as_ref(), &map, coords).await })
+        };
+        tokio::task::yield_now().await;
+
+        storage.save_chunk(coords, &unloaded_chunk).await.unwrap();
+        drop(save_guard);
+
+        let loaded_chunk = load_task.await.unwrap().unwrap();
+        assert_eq!(loaded_chunk.tile_at_offset(OffsetCoords { x: 1, y: 1 }), Tile::RockSmashed);
+        assert!(map.lock().is_chunk_loaded(coords));
+    }
+
+    #[tokio::test]
+    async fn keyed_locks_removed_once_free() {
+        let locks = KeyedLocks::default();
+
+        let guard = locks.lock(1).await;
+        assert_eq!(locks.locks.lock().len(), 1);
+
+        drop(guard);
+        assert!(locks.locks.lock().is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+//! Removal of abandoned player entities (i.e. those whose clients have not connected for longer than the retention
+//! period) along with any bombs they left undetonated.
+
+use std::{collections::HashSet, sync::Arc};
+
+use shared::{maps::Map, Id};
+use tokio::time::{self, Duration};
+
+use super::chunks;
+use crate::{
+    leaderboard::Leaderboard,
+    storage::{self, Storage},
+    Shared
+};
+
+/// How often abandoned player entities are pruned.
+pub const PRUNING_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Prune abandoned player entities now and then every [`PRUNING_INTERVAL`], also removing them from the leaderboard.
+/// This function never returns and so should be spawned as its own task.
+pub async fn prune_periodically(
+    map: Shared<super::ServerMap>, storage: Arc<dyn Storage>, retention_period: Duration,
+    leaderboard: Shared<Leaderboard>
+) {
+    let mut interval = time::interval(PRUNING_INTERVAL);
+
+    loop {
+        interval.tick().await; // The first tick completes immediately.
+
+        match prune(&map, storage.as_ref(), retention_period).await {
+            Ok(removed_ids) => {
+                let mut leaderboard = leaderboard.lock();
+                for entity_id in removed_ids {
+                    leaderboard.remove(entity_id);
+                }
+            }
+            Err(e) => log::error!("Failed to prune abandoned player entities - {}", e)
+        }
+    }
+}
+
+/// Remove from storage all player entities whose clients were last seen longer ago than the given retention period
+/// (excluding any that are currently on the map) and remove the undetonated bombs they placed from both loaded and
+/// stored chunks. Returns the entity IDs of the removed player entities.
+pub async fn prune(
+    map: &Shared<super::ServerMap>, storage: &dyn Storage, retention_period: Duration
+) -> storage::Result<Vec<Id>> {
+    let now = storage::current_timestamp();
+    let cutoff = now - retention_period.as_secs() as i64;
+    let candidates = storage.players_not_seen_since(cutoff).await?;
+
+    // Player entities whose clients are connected are never removed:
+    let abandoned: Vec<(Id, i64)> = {
+        let map = map.lock();
+        candidates.into_iter().filter(|(entity_id, _)| map.entity_by_id(*entity_id).is_none()).collect()
+    };
+
+    // A client may connect after the above check so each player entity is only removed should its client still not
+    // have been seen:
+
+    let mut removed_ids = HashSet::new();
+
+    for (entity_id, last_seen) in abandoned {
+        if storage.remove_player_not_seen_since(entity_id, cutoff).await? {
+            log::info!(
+                "Removed abandoned player entity {} (last seen {} days ago)",
+                entity_id,
+                (now - last_seen) / SECONDS_PER_DAY
+            );
+
+            removed_ids.insert(entity_id);
+        }
+        else {
+            log::debug!("Player entity {} not pruned as its client has since been seen", entity_id);
+        }
+    }
+
+    if removed_ids.is_empty() {
+        log::debug!("No abandoned player entities to prune");
+        return Ok(Vec::new());
+    }
+
+    // Remove undetonated bombs from loaded chunks (which will be saved as they are marked as dirty):
+    let mut removed_bomb_count = map.lock().remove_bombs_placed_by(&removed_ids);
+
+    // Remove undetonated bombs from stored chunks that are not loaded:
+
+    for coords in storage.stored_chunk_coords().await? {
+        // Held from before the stored chunk is read until it is saved again so that the chunk is neither loaded nor
+        // saved by another task meanwhile:
+        let save_locks = map.lock().save_locks();
+        let _save_guard = save_locks.chunks.lock(coords).await;
+
+        // Should the chunk have been loaded since the bombs were removed from the loaded chunks above then the bombs
+        // are removed from the loaded chunk instead:
+        let loaded_count_option = {
+            let mut map = map.lock();
+            map.is_chunk_loaded(coords).then(|| map.remove_bombs_placed_by_in_chunk(coords, &removed_ids))
+        };
+
+        if let Some(loaded_count) = loaded_count_option {
+            removed_bomb_count += loaded_count;
+        }
+        else if let Some(mut chunk) = chunks::load_chunk(storage, coords).await? {
+            let count: usize = removed_ids.iter().map(|id| chunk.take_bombs_placed_by(*id).len()).sum();
+
+            if count > 0 {
+                chunks::save_chunk(storage, coords, &chunk).await?;
+                removed_bomb_count += count;
+            }
+        }
+    }
+
+    log::info!(
+        "Pruned {} abandoned player entities and removed {} of their undetonated bombs",
+        removed_ids.len(),
+        removed_bomb_count
+    );
+
+    Ok(removed_ids.into_iter().collect())
+}
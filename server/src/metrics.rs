@@ -0,0 +1,431 @@
+//! Runtime metrics of the server, served over HTTP in the Prometheus text exposition format (see [`serve`]).
+//!
+//! Events are counted as they happen in the process-wide [`METRICS`] registry (which, unlike the rest of the server's
+//! shared state, is global so that deeply nested functions such as those of [`crate::maps::chunks`] can record to it
+//! without it being passed through every caller). Values that can be read directly from the game map, storage backend
+//! and persistence task are instead sampled each time the metrics are scraped.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt::{self, Write},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc
+    }
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode
+};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use shared::{gems::Gem, messages};
+use strum::IntoEnumIterator;
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    maps::{persistence, ServerMap},
+    storage::Storage,
+    Shared
+};
+
+/// The process-wide metrics registry.
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+/// Upper bounds (in seconds) of the buckets of each [`Histogram`].
+const HISTOGRAM_BUCKETS: [f64; 12] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+/// Counts of events that have occurred since the server started.
+#[derive(Default)]
+pub struct Metrics {
+    /// Number of currently open WebSocket connections.
+    connected_clients: AtomicI64,
+    /// Messages received from clients mapped to by the names of their [`messages::ToServer`] variants.
+    messages_received: Mutex<HashMap<&'static str, u64>>,
+    /// Messages sent to clients mapped to by the names of their [`messages::FromServer`] variants.
+    messages_sent: Mutex<HashMap<&'static str, u64>>,
+    /// Number of times a connection task fell behind on the map changes broadcast channel.
+    broadcast_lagged: AtomicU64,
+    /// Number of map changes broadcast channel messages missed due to connection tasks falling behind.
+    broadcast_skipped: AtomicU64,
+    /// WebSocket messages refused by [`crate::networking::Connection`] mapped to by the reasons they were refused.
+    messages_refused: Mutex<HashMap<&'static str, u64>>,
+    /// Messages discarded for exceeding rate limits mapped to by the names of their [`messages::ToServer`] variants.
+    messages_rate_limited: Mutex<HashMap<&'static str, u64>>,
+    /// Number of connections closed for continuing to exceed rate limits.
+    rate_limit_disconnects: AtomicU64,
+    /// Gems obtained by players from smashing rocks (whether by moving or by bomb blasts).
+    gems_mined: Mutex<HashMap<Gem, u64>>,
+    pub chunk_generate_duration: Histogram,
+    pub chunk_load_duration: Histogram,
+    pub chunk_save_duration: Histogram
+}
+
+impl Metrics {
+    /// Record that a WebSocket connection has been opened. The connection is considered closed once the returned
+    /// value is dropped.
+    pub fn connection_opened(&'static self) -> OpenConnection {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+        OpenConnection { metrics: self }
+    }
+
+    pub fn message_received(&self, msg: &messages::ToServer) {
+        *self.messages_received.lock().entry(msg.into()).or_default() += 1;
+    }
+
+    pub fn message_sent(&self, msg: &messages::FromServer) {
+        *self.messages_sent.lock().entry(msg.into()).or_default() += 1;
+    }
+
+    /// Record that a WebSocket message was refused for the given reason (e.g. `too_large`).
+    pub fn message_refused(&self, reason: &'static str) {
+        *self.messages_refused.lock().entry(reason).or_default() += 1;
+    }
+
+    /// Record that a message was discarded for exceeding a rate limit (and whether or not its connection was closed as
+    /// a result).
+    pub fn message_rate_limited(&self, msg: &messages::ToServer, disconnected: bool) {
+        *self.messages_rate_limited.lock().entry(msg.into()).or_default() += 1;
+
+        if disconnected {
+            self.rate_limit_disconnects.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record that a connection task missed the given number of messages on the map changes broadcast channel.
+    pub fn broadcast_lagged(&self, skipped: u64) {
+        self.broadcast_lagged.fetch_add(1, Ordering::Relaxed);
+        self.broadcast_skipped.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    pub fn gems_mined(&self, gem: Gem, quantity: u32) {
+        *self.gems_mined.lock().entry(gem).or_default() += quantity as u64;
+    }
+
+    /// Produce the metrics in the Prometheus text exposition format, sampling the remaining values from the given game
+    /// map, storage backend and persistence counters.
+    pub fn render(&self, map: &Shared<ServerMap>, storage: &dyn Storage, counters: &persistence::Counters) -> String {
+        let mut out = String::new();
+
+        let (loaded_chunks, players_online) = {
+            let map = map.lock();
+            (map.loaded_chunk_count(), map.player_entities().len())
+        };
+
+        write_value(
+            &mut out,
+            "connected_clients",
+            "gauge",
+            "Number of open WebSocket connections.",
+            self.connected_clients.load(Ordering::Relaxed)
+        );
+        write_value(&mut out, "players_online", "gauge", "Number of player entities on the map.", players_online);
+        write_value(&mut out, "loaded_chunks", "gauge", "Number of chunks loaded into memory.", loaded_chunks);
+
+        write_metric(
+            &mut out,
+            "messages_received_total",
+            "counter",
+            "Messages received from clients by type.",
+            &labelled_counts("message", &self.messages_received.lock())
+        );
+        write_metric(
+            &mut out,
+            "messages_sent_total",
+            "counter",
+            "Messages sent to clients by type.",
+            &labelled_counts("message", &self.messages_sent.lock())
+        );
+
+        write_metric(
+            &mut out,
+            "messages_refused_total",
+            "counter",
+            "WebSocket messages refused by reason.",
+            &labelled_counts("reason", &self.messages_refused.lock())
+        );
+        write_metric(
+            &mut out,
+            "messages_rate_limited_total",
+            "counter",
+            "Messages discarded for exceeding rate limits by type.",
+            &labelled_counts("message", &self.messages_rate_limited.lock())
+        );
+        write_value(
+            &mut out,
+            "rate_limit_disconnects_total",
+            "counter",
+            "Connections closed for continuing to exceed rate limits.",
+            self.rate_limit_disconnects.load(Ordering::Relaxed)
+        );
+
+        write_value(
+            &mut out,
+            "broadcast_lagged_total",
+            "counter",
+            "Times a connection task fell behind on the map changes channel.",
+            self.broadcast_lagged.load(Ordering::Relaxed)
+        );
+        write_value(
+            &mut out,
+            "broadcast_skipped_messages_total",
+            "counter",
+            "Map changes channel messages missed by connection tasks that fell behind.",
+            self.broadcast_skipped.load(Ordering::Relaxed)
+        );
+
+        let gems_mined: Vec<_> = {
+            let gems_mined = self.gems_mined.lock();
+            Gem::iter()
+                .map(|gem| {
+                    let label = format!("{{gem=\"{}\"}}", format!("{:?}", gem).to_lowercase());
+                    (label, gems_mined.get(&gem).copied().unwrap_or(0).to_string())
+                })
+                .collect()
+        };
+        write_metric(&mut out, "gems_mined_total", "counter", "Gems obtained by players from rocks.", &gems_mined);
+
+        self.chunk_generate_duration.write(
+            &mut out,
+            "chunk_generate_duration_seconds",
+            "Time taken to generate a chunk."
+        );
+        self.chunk_load_duration.write(
+            &mut out,
+            "chunk_load_duration_seconds",
+            "Time taken to load a chunk from storage."
+        );
+        self.chunk_save_duration.write(
+            &mut out,
+            "chunk_save_duration_seconds",
+            "Time taken to save a chunk to storage."
+        );
+
+        write_value(
+            &mut out,
+            "persistence_flushes_total",
+            "counter",
+            "Flushes of modified chunks and player entities to storage.",
+            counters.flushes.load(Ordering::Relaxed)
+        );
+        write_value(
+            &mut out,
+            "persistence_chunks_saved_total",
+            "counter",
+            "Chunks saved by flushes.",
+            counters.chunks_saved.load(Ordering::Relaxed)
+        );
+        write_value(
+            &mut out,
+            "persistence_players_saved_total",
+            "counter",
+            "Player entities saved by flushes.",
+            counters.players_saved.load(Ordering::Relaxed)
+        );
+        write_value(
+            &mut out,
+            "persistence_failures_total",
+            "counter",
+            "Chunks and player entities that flushes failed to save.",
+            counters.failures.load(Ordering::Relaxed)
+        );
+
+        if let Some(usage) = storage.connection_pool_usage() {
+            write_value(
+                &mut out,
+                "database_pool_connections",
+                "gauge",
+                "Connections held by the database connection pool.",
+                usage.connections
+            );
+            write_value(
+                &mut out,
+                "database_pool_idle_connections",
+                "gauge",
+                "Idle connections held by the database connection pool.",
+                usage.idle_connections
+            );
+            write_value(
+                &mut out,
+                "database_pool_max_connections",
+                "gauge",
+                "Maximum number of connections the database connection pool may hold.",
+                usage.max_connections
+            );
+        }
+
+        out
+    }
+}
+
+/// Decrements the number of connected clients when dropped (see [`Metrics::connection_opened`]).
+pub struct OpenConnection {
+    metrics: &'static Metrics
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.metrics.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Distribution of durations over the buckets given by [`HISTOGRAM_BUCKETS`].
+#[derive(Default)]
+pub struct Histogram {
+    /// Number of observations in each bucket (not cumulative) with the final element counting those exceeding the
+    /// largest bucket.
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS.len() + 1],
+    /// Total of all observations in microseconds.
+    sum_micros: AtomicU64
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let index = HISTOGRAM_BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(HISTOGRAM_BUCKETS.len());
+
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Record the time elapsed since the given instant.
+    pub fn observe_since(&self, start: Instant) {
+        self.observe(start.elapsed());
+    }
+
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+        let mut samples = Vec::new();
+        let mut cumulative = 0;
+
+        for (index, count) in self.buckets.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+
+            let bound = HISTOGRAM_BUCKETS.get(index).map(ToString::to_string).unwrap_or_else(|| "+Inf".to_string());
+            samples.push((format!("_bucket{{le=\"{}\"}}", bound), cumulative.to_string()));
+        }
+
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        samples.push(("_sum".to_string(), sum.to_string()));
+        samples.push(("_count".to_string(), cumulative.to_string()));
+
+        write_metric(out, name, "histogram", help, &samples);
+    }
+}
+
+/// Write a metric with the given name (which is prefixed with `gemgame_`) and type. Each sample is a suffix to the
+/// name (e.g. labels) along with a value.
+fn write_metric<S: AsRef<str>>(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(S, String)]) {
+    writeln!(out, "# HELP gemgame_{} {}", name, help).unwrap();
+    writeln!(out, "# TYPE gemgame_{} {}", name, kind).unwrap();
+
+    for (suffix, value) in samples {
+        writeln!(out, "gemgame_{}{} {}", name, suffix.as_ref(), value).unwrap();
+    }
+}
+
+/// Write a metric with a single unlabelled sample of the given value.
+fn write_value<T: fmt::Display>(out: &mut String, name: &str, kind: &str, help: &str, value: T) {
+    write_metric(out, name, kind, help, &[("", value.to_string())]);
+}
+
+/// Produce samples labelled with the given label name from the given counts (sorted by label value).
+fn labelled_counts(label: &str, counts: &HashMap<&'static str, u64>) -> Vec<(String, String)> {
+    let mut samples: Vec<_> =
+        counts.iter().map(|(value, count)| (format!("{{{}=\"{}\"}}", label, value), count.to_string())).collect();
+    samples.sort();
+    samples
+}
+
+/// Serve the metrics at `/metrics` on the given port of all interfaces until the server stops.
+pub async fn serve(port: u16, map: Shared<ServerMap>, storage: Arc<dyn Storage>, counters: Arc<persistence::Counters>) {
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+
+    let make_service = make_service_fn(move |_| {
+        let (map, storage, counters) = (Arc::clone(&map), Arc::clone(&storage), Arc::clone(&counters));
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let response = if request.method() == Method::GET && request.uri().path() == "/metrics" {
+                    let mut response = Response::new(Body::from(METRICS.render(&map, storage.as_ref(), &counters)));
+                    response
+                        .headers_mut()
+                        .insert(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4".parse().unwrap());
+                    response
+                }
+                else {
+                    let mut response = Response::new(Body::from("Not found\n"));
+                    *response.status_mut() = StatusCode::NOT_FOUND;
+                    response
+                };
+
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            log::error!("Failed to bind metrics endpoint to address {} - {}", address, e);
+            return;
+        }
+    };
+
+    log::info!("Serving metrics at: http://{}/metrics", address);
+
+    if let Err(e) = server.await {
+        log::error!("Metrics endpoint stopped - {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(200));
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_secs(5));
+
+        let mut out = String::new();
+        histogram.write(&mut out, "test_seconds", "Test.");
+
+        assert!(out.contains("# TYPE gemgame_test_seconds histogram\n"));
+        assert!(out.contains("gemgame_test_seconds_bucket{le=\"0.0005\"} 1\n"));
+        assert!(out.contains("gemgame_test_seconds_bucket{le=\"0.0025\"} 1\n"));
+        assert!(out.contains("gemgame_test_seconds_bucket{le=\"0.005\"} 2\n"));
+        assert!(out.contains("gemgame_test_seconds_bucket{le=\"2.5\"} 2\n"));
+        assert!(out.contains("gemgame_test_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("gemgame_test_seconds_sum 5.0032\n"));
+        assert!(out.contains("gemgame_test_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        let map = Arc::new(Mutex::new(ServerMap::new_with_default_generator(0)));
+        let storage = crate::storage::MemoryStorage::default();
+
+        metrics.message_received(&messages::ToServer::DetonateBombs);
+        metrics.message_received(&messages::ToServer::DetonateBombs);
+        metrics.broadcast_lagged(7);
+        metrics.gems_mined(Gem::Ruby, 3);
+
+        let out = metrics.render(&map, &storage, &persistence::Counters::default());
+
+        assert!(out.contains("gemgame_messages_received_total{message=\"DetonateBombs\"} 2\n"));
+        assert!(out.contains("gemgame_broadcast_lagged_total 1\n"));
+        assert!(out.contains("gemgame_broadcast_skipped_messages_total 7\n"));
+        assert!(out.contains("gemgame_gems_mined_total{gem=\"ruby\"} 3\n"));
+        assert!(out.contains("gemgame_gems_mined_total{gem=\"diamond\"} 0\n"));
+        assert!(out.contains("gemgame_loaded_chunks 0\n"));
+
+        // The in-memory storage backend has no connection pool:
+        assert!(!out.contains("database_pool"));
+    }
+}
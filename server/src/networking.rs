@@ -1,34 +1,94 @@
 use std::convert;
 
+use bincode::Options;
 use futures_util::{SinkExt, StreamExt};
 use shared::messages;
 use thiserror::Error;
 use tokio::net::TcpStream;
-use tokio_tungstenite::{tungstenite, WebSocketStream};
+use tokio_tungstenite::{
+    tungstenite::{self, protocol::WebSocketConfig},
+    WebSocketStream
+};
 
-/// Manages a WebSocket connection and simplifies the process of sending and receiving bincode messages.
+use crate::metrics::{self, METRICS};
+
+/// Manages a WebSocket connection and simplifies the process of sending and receiving bincode messages. Messages sent
+/// and received are counted in the server's metrics.
 pub struct Connection {
-    ws: WebSocketStream<TcpStream>
+    ws: WebSocketStream<TcpStream>,
+    _open_connection: metrics::OpenConnection
 }
 
 impl Connection {
-    pub async fn new(stream: TcpStream) -> tungstenite::Result<Self> {
-        Ok(Connection { ws: tokio_tungstenite::accept_async(stream).await? })
+    /// Perform the WebSocket handshake over the given stream. WebSocket messages (and frames) received that are larger
+    /// than the given number of bytes cause the connection to fail.
+    pub async fn new(stream: TcpStream, max_message_size: usize) -> tungstenite::Result<Self> {
+        let config = WebSocketConfig {
+            max_message_size: Some(max_message_size),
+            max_frame_size: Some(max_message_size),
+            ..WebSocketConfig::default()
+        };
+
+        let ws = tokio_tungstenite::accept_async_with_config(stream, Some(config)).await?;
+        Ok(Connection { ws, _open_connection: METRICS.connection_opened() })
     }
 
     pub async fn send(&mut self, msg: &messages::FromServer) -> Result<()> {
         let encoded = bincode::serialize(msg)?;
         self.ws.send(tungstenite::Message::Binary(encoded)).await?;
+        METRICS.message_sent(msg);
 
         Ok(())
     }
 
+    /// Receive the next message. Each WebSocket message must be binary and contain exactly one [`messages::ToServer`]
+    /// (trailing bytes are refused so that a client cannot have several messages handled at once). Refused WebSocket
+    /// messages are counted in the server's metrics.
     pub async fn receive(&mut self) -> Result<Option<messages::ToServer>> {
+        match self.receive_bytes().await? {
+            Some(bytes) => Ok(Some(decode_counted(&bytes)?)),
+            None => Ok(None)
+        }
+    }
+
+    /// Receive the first message of the connection, which is expected to be a 'hello' message. The protocol version at
+    /// the start of that message is checked (see [`messages::check_protocol_version`]) before the rest of the message
+    /// is decoded as clients speaking other protocol versions may lay out the rest of the message differently. Returns
+    /// `None` should the connection close or the message not be a 'hello' message.
+    pub async fn receive_hello(&mut self) -> Result<Option<Hello>> {
+        let bytes = match self.receive_bytes().await? {
+            Some(bytes) => bytes,
+            None => return Ok(None)
+        };
+
+        if let Some(protocol_version) = hello_protocol_version(&bytes) {
+            if let Err(reason) = messages::check_protocol_version(protocol_version) {
+                METRICS.message_refused("unsupported_protocol");
+                return Ok(Some(Hello::Unsupported { protocol_version, reason }));
+            }
+        }
+
+        match decode_counted(&bytes)? {
+            messages::ToServer::Hello { credentials_option, .. } => Ok(Some(Hello::Supported { credentials_option })),
+            _ => Ok(None)
+        }
+    }
+
+    /// Receive the bytes of the next WebSocket message, which must be binary.
+    async fn receive_bytes(&mut self) -> Result<Option<Vec<u8>>> {
         if let Some(some_result) = self.ws.next().await {
-            match some_result? {
-                tungstenite::Message::Binary(bytes_vec) => Ok(Some(bincode::deserialize(bytes_vec.as_slice())?)),
-                tungstenite::Message::Close(_) => Ok(None),
-                not_binary_msg => Err(Error::MessageNotBinary(not_binary_msg))
+            match some_result {
+                Ok(tungstenite::Message::Binary(bytes_vec)) => Ok(Some(bytes_vec)),
+                Ok(tungstenite::Message::Close(_)) => Ok(None),
+                Ok(not_binary_msg) => {
+                    METRICS.message_refused("not_binary");
+                    Err(Error::MessageNotBinary(not_binary_msg))
+                }
+                Err(tungstenite::Error::Capacity(e)) => {
+                    METRICS.message_refused("too_large");
+                    Err(tungstenite::Error::Capacity(e).into())
+                }
+                Err(e) => Err(e.into())
             }
         }
         else {
@@ -41,6 +101,49 @@ impl Connection {
     }
 }
 
+/// A 'hello' message received by [`Connection::receive_hello`].
+#[derive(Debug)]
+pub enum Hello {
+    /// The client speaks a supported protocol version and presented the given credentials (if any).
+    Supported { credentials_option: Option<messages::Credentials> },
+    /// The client speaks an unsupported protocol version (so the rest of its message was not decoded).
+    Unsupported { protocol_version: u32, reason: messages::ProtocolRejection }
+}
+
+/// The protocol version of the given encoded message should it be a 'hello' message. Only the variant index and
+/// protocol version at the start of the message are read as every version of the protocol begins 'hello' messages with
+/// those (see [`messages::ToServer::Hello`]).
+fn hello_protocol_version(bytes: &[u8]) -> Option<u32> {
+    let options = bincode::DefaultOptions::new().with_fixint_encoding().allow_trailing_bytes();
+
+    match options.deserialize::<(u32, u32)>(bytes) {
+        Ok((HELLO_VARIANT_INDEX, protocol_version)) => Some(protocol_version),
+        _ => None
+    }
+}
+
+/// The index of the [`messages::ToServer::Hello`] variant (which is always the first variant).
+const HELLO_VARIANT_INDEX: u32 = 0;
+
+/// Decode a message with [`decode`], counting it in the server's metrics as either received or refused.
+fn decode_counted(bytes: &[u8]) -> bincode::Result<messages::ToServer> {
+    match decode(bytes) {
+        Ok(msg) => {
+            METRICS.message_received(&msg);
+            Ok(msg)
+        }
+        Err(e) => {
+            METRICS.message_refused("malformed");
+            Err(e)
+        }
+    }
+}
+
+/// Decode a message encoded by [`bincode::serialize`] (as used by the client) while refusing any trailing bytes.
+fn decode(bytes: &[u8]) -> bincode::Result<messages::ToServer> {
+    bincode::DefaultOptions::new().with_fixint_encoding().reject_trailing_bytes().deserialize(bytes)
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Encoding failed - {0}")]
@@ -52,3 +155,38 @@ pub enum Error {
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_single_message() {
+        let msg = messages::ToServer::PlaceBomb;
+        let mut encoded = bincode::serialize(&msg).unwrap();
+        assert_eq!(decode(&encoded).unwrap(), msg);
+
+        // A second message following the first should cause the whole WebSocket message to be refused:
+        encoded.extend(bincode::serialize(&msg).unwrap());
+        assert!(decode(&encoded).is_err());
+    }
+
+    #[test]
+    fn hello_begins_with_protocol_version() {
+        let msg = messages::ToServer::Hello {
+            protocol_version: messages::PROTOCOL_VERSION,
+            credentials_option: Some(messages::Credentials::SessionToken("token".to_string()))
+        };
+
+        // Any version of the server should be able to read the variant index followed by the protocol version without
+        // knowing the layout of the rest of the message:
+        let encoded = bincode::serialize(&msg).unwrap();
+        let (variant_index, protocol_version): (u32, u32) = bincode::deserialize(&encoded).unwrap();
+
+        assert_eq!(variant_index, HELLO_VARIANT_INDEX);
+        assert_eq!(protocol_version, messages::PROTOCOL_VERSION);
+        assert_eq!(hello_protocol_version(&encoded), Some(messages::PROTOCOL_VERSION));
+
+        assert_eq!(hello_protocol_version(&bincode::serialize(&messages::ToServer::PlaceBomb).unwrap()), None);
+    }
+}
@@ -0,0 +1,319 @@
+//! Limits on how quickly each connection may send messages to the server, so that a hostile client cannot flood the
+//! server with (potentially expensive) messages. Each connection has a token bucket for all of its messages plus a
+//! token bucket for each type of message (see [`Config`]). Messages exceeding either limit are discarded and, should a
+//! client continue to exceed its limits, it is first warned and then disconnected (see [`Verdict`]).
+//!
+//! Chat messages are additionally subject to the stricter limit of [`crate::chat::RateLimiter`] as exceeding that
+//! limit is something ordinary players do and so is reported to them rather than treated as abuse.
+
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use shared::messages::ToServer;
+use strum::VariantNames;
+use tokio::time::{Duration, Instant};
+
+/// Once a client has not exceeded its limits for this long, its count of violations is reset.
+const VIOLATION_RESET_PERIOD: Duration = Duration::from_secs(10);
+
+/// A token bucket limit: up to `burst` messages may be sent at once after which messages may only be sent at a rate of
+/// `per_second`. Parsed from the form `<burst>:<per second>` (e.g. `10:2.5`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    pub burst: u32,
+    pub per_second: f32
+}
+
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Rate limit '{}' is not of the form <burst>:<per second>", s);
+
+        let (burst, per_second) = {
+            let mut parts = s.splitn(2, ':');
+            (parts.next().ok_or_else(invalid)?, parts.next().ok_or_else(invalid)?)
+        };
+
+        let limit = Limit {
+            burst: burst.trim().parse().map_err(|_| invalid())?,
+            per_second: per_second.trim().parse().map_err(|_| invalid())?
+        };
+
+        if limit.burst == 0 || !limit.per_second.is_finite() || limit.per_second <= 0.0 {
+            Err(format!("Rate limit '{}' must allow a burst of at least 1 and a positive rate", s))
+        }
+        else {
+            Ok(limit)
+        }
+    }
+}
+
+/// A limit for a particular type of message. Parsed from the form `<message type>=<burst>:<per second>` where the
+/// message type is the name of a [`ToServer`] variant (e.g. `PlaceBomb=5:2`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageLimit {
+    pub message: &'static str,
+    pub limit: Limit
+}
+
+impl FromStr for MessageLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+
+        let (name, limit) = match (parts.next(), parts.next()) {
+            (Some(name), Some(limit)) => (name.trim(), limit),
+            _ => {
+                return Err(format!(
+                    "Message rate limit '{}' is not of the form <message type>=<burst>:<per second>",
+                    s
+                ))
+            }
+        };
+
+        let message = ToServer::VARIANTS.iter().copied().find(|variant| *variant == name).ok_or_else(|| {
+            format!("Unknown message type '{}' (expected one of: {})", name, ToServer::VARIANTS.join(", "))
+        })?;
+
+        Ok(MessageLimit { message, limit: limit.parse()? })
+    }
+}
+
+/// The limits applied to every connection along with how clients exceeding them are dealt with.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Limit on all messages sent by a connection.
+    pub connection: Limit,
+    /// Limits on particular types of message mapped to by the names of their [`ToServer`] variants. Types of message
+    /// without an entry here are only subject to the connection limit.
+    pub messages: HashMap<&'static str, Limit>,
+    /// The number of violations (i.e. discarded messages) after which the client is warned.
+    pub warn_after: u32,
+    /// The number of violations after which the client is disconnected.
+    pub disconnect_after: u32
+}
+
+impl Config {
+    /// Replace the default limits of the given types of message.
+    pub fn with_message_limits(mut self, message_limits: &[MessageLimit]) -> Self {
+        for message_limit in message_limits {
+            self.messages.insert(message_limit.message, message_limit.limit);
+        }
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let messages = vec![
+            ("MoveMyEntity", Limit { burst: 20, per_second: 15.0 }),
+            ("PlaceBomb", Limit { burst: 5, per_second: 2.0 }),
+            ("DetonateBombs", Limit { burst: 3, per_second: 1.0 }),
+            ("PurchaseSingleItem", Limit { burst: 5, per_second: 1.0 }),
+            ("PurchaseItemQuantity", Limit { burst: 5, per_second: 1.0 }),
+            ("ConsumeEnergyDrink", Limit { burst: 3, per_second: 1.0 }),
+            ("PlaceTrap", Limit { burst: 5, per_second: 2.0 }),
+            ("SendChatMessage", Limit { burst: 10, per_second: 2.0 }),
+            ("SetFacialExpression", Limit { burst: 5, per_second: 2.0 }),
+            ("PlayEmote", Limit { burst: 5, per_second: 2.0 }),
+            ("ChangeAppearance", Limit { burst: 3, per_second: 0.5 }),
+            ("WatchLeaderboard", Limit { burst: 5, per_second: 1.0 }),
+        ];
+
+        Config {
+            connection: Limit { burst: 40, per_second: 20.0 },
+            messages: messages.into_iter().collect(),
+            warn_after: 10,
+            disconnect_after: 100
+        }
+    }
+}
+
+/// What is to be done with a message checked by [`MessageLimiter::check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The message is within the limits and so should be handled.
+    Allow,
+    /// The message exceeds a limit and so should be discarded.
+    Discard,
+    /// The message exceeds a limit and so should be discarded, and the client should be warned that it will be
+    /// disconnected should it continue.
+    Warn,
+    /// The message exceeds a limit and the client has continued to exceed its limits after being warned so should be
+    /// disconnected.
+    Disconnect
+}
+
+struct TokenBucket {
+    tokens: f32,
+    updated: Instant
+}
+
+impl TokenBucket {
+    fn new(limit: Limit, now: Instant) -> Self {
+        TokenBucket { tokens: limit.burst as f32, updated: now }
+    }
+
+    /// Add the tokens accumulated since this bucket was last refilled.
+    fn refill(&mut self, limit: Limit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f32();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f32);
+        self.updated = now;
+    }
+}
+
+/// Applies the limits of a [`Config`] to the messages received by a single connection.
+pub struct MessageLimiter {
+    config: Arc<Config>,
+    connection_bucket: TokenBucket,
+    message_buckets: HashMap<&'static str, TokenBucket>,
+    /// The number of messages discarded since the client last went [`VIOLATION_RESET_PERIOD`] without exceeding its
+    /// limits.
+    violations: u32,
+    last_violation_instant: Option<Instant>
+}
+
+impl MessageLimiter {
+    pub fn new(config: Arc<Config>) -> Self {
+        let now = Instant::now();
+
+        MessageLimiter {
+            connection_bucket: TokenBucket::new(config.connection, now),
+            config,
+            message_buckets: HashMap::new(),
+            violations: 0,
+            last_violation_instant: None
+        }
+    }
+
+    /// Decide what should be done with a message of the given type (the name of its [`ToServer`] variant) received at
+    /// the given point in time. Should the message be allowed then it is counted towards the limits.
+    pub fn check(&mut self, message: &'static str, now: Instant) -> Verdict {
+        let connection_limit = self.config.connection;
+        self.connection_bucket.refill(connection_limit, now);
+
+        let message_bucket_option = match self.config.messages.get(message).copied() {
+            Some(limit) => {
+                let bucket = self.message_buckets.entry(message).or_insert_with(|| TokenBucket::new(limit, now));
+                bucket.refill(limit, now);
+                Some(bucket)
+            }
+            None => None
+        };
+
+        // Tokens are only taken should the message be within both limits:
+        let within_limits = self.connection_bucket.tokens >= 1.0
+            && message_bucket_option.as_ref().map(|bucket| bucket.tokens >= 1.0).unwrap_or(true);
+
+        if within_limits {
+            self.connection_bucket.tokens -= 1.0;
+            if let Some(bucket) = message_bucket_option {
+                bucket.tokens -= 1.0;
+            }
+
+            return Verdict::Allow;
+        }
+
+        let violations_expired = self
+            .last_violation_instant
+            .map(|instant| now.saturating_duration_since(instant) >= VIOLATION_RESET_PERIOD)
+            .unwrap_or(false);
+
+        if violations_expired {
+            self.violations = 0;
+        }
+        self.violations += 1;
+        self.last_violation_instant = Some(now);
+
+        if self.violations >= self.config.disconnect_after {
+            Verdict::Disconnect
+        }
+        else if self.violations == self.config.warn_after {
+            Verdict::Warn
+        }
+        else {
+            Verdict::Discard
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> Config {
+        Config {
+            connection: Limit { burst: 10, per_second: 5.0 },
+            messages: vec![("PlaceBomb", Limit { burst: 2, per_second: 1.0 })].into_iter().collect(),
+            warn_after: 2,
+            disconnect_after: 4
+        }
+    }
+
+    #[test]
+    fn message_and_connection_limits() {
+        let mut limiter = MessageLimiter::new(Arc::new(test_config()));
+        let start = Instant::now();
+
+        // The message type's burst is allowed after which that type of message is limited to its rate:
+        assert_eq!(limiter.check("PlaceBomb", start), Verdict::Allow);
+        assert_eq!(limiter.check("PlaceBomb", start), Verdict::Allow);
+        assert_eq!(limiter.check("PlaceBomb", start), Verdict::Discard);
+        assert_eq!(limiter.check("PlaceBomb", start + Duration::from_secs(1)), Verdict::Allow);
+
+        // Other types of message are only subject to the connection limit (which was refilled to its burst of 10 before
+        // another token was used):
+        for _ in 0..9 {
+            assert_eq!(limiter.check("MoveMyEntity", start + Duration::from_secs(1)), Verdict::Allow);
+        }
+        assert_ne!(limiter.check("DetonateBombs", start + Duration::from_secs(1)), Verdict::Allow);
+        assert_eq!(limiter.check("DetonateBombs", start + Duration::from_millis(1200)), Verdict::Allow);
+    }
+
+    #[test]
+    fn escalation() {
+        let mut limiter = MessageLimiter::new(Arc::new(test_config()));
+        let start = Instant::now();
+
+        limiter.check("PlaceBomb", start);
+        limiter.check("PlaceBomb", start);
+
+        assert_eq!(limiter.check("PlaceBomb", start), Verdict::Discard);
+        assert_eq!(limiter.check("PlaceBomb", start), Verdict::Warn);
+        assert_eq!(limiter.check("PlaceBomb", start), Verdict::Discard);
+        assert_eq!(limiter.check("PlaceBomb", start), Verdict::Disconnect);
+
+        // Violations are forgotten after a period without any:
+        let mut limiter = MessageLimiter::new(Arc::new(test_config()));
+        limiter.check("PlaceBomb", start);
+        limiter.check("PlaceBomb", start);
+        assert_eq!(limiter.check("PlaceBomb", start), Verdict::Discard);
+
+        let later = start + VIOLATION_RESET_PERIOD;
+        limiter.check("PlaceBomb", later);
+        limiter.check("PlaceBomb", later);
+        assert_eq!(limiter.check("PlaceBomb", later), Verdict::Discard);
+    }
+
+    #[test]
+    fn parse_limits() {
+        assert_eq!("10:2.5".parse(), Ok(Limit { burst: 10, per_second: 2.5 }));
+        assert!("10".parse::<Limit>().is_err());
+        assert!("0:1".parse::<Limit>().is_err());
+        assert!("5:0".parse::<Limit>().is_err());
+        assert!("5:-1".parse::<Limit>().is_err());
+
+        assert_eq!(
+            "PlaceBomb=3:1".parse(),
+            Ok(MessageLimit { message: "PlaceBomb", limit: Limit { burst: 3, per_second: 1.0 } })
+        );
+        assert!("Explode=3:1".parse::<MessageLimit>().is_err());
+        assert!("PlaceBomb".parse::<MessageLimit>().is_err());
+
+        // Default limits should only name message types that exist:
+        for message in Config::default().messages.keys() {
+            assert!(ToServer::VARIANTS.contains(message));
+        }
+    }
+}
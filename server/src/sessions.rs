@@ -0,0 +1,244 @@
+//! Registry of the client IDs currently in use by connections, ensuring that a client's player entity is only ever
+//! loaded by a single connection at a time. Should a client connect again while already connected (e.g. from a second
+//! browser tab) then either the existing connection is closed so that the new one can take over, or the new connection
+//! is rejected, depending on the [`DuplicatePolicy`]. Sessions can also be ended by the server's operators (see
+//! [`ClientRegistry::end_session`]).
+
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use parking_lot::Mutex;
+use shared::{messages::SessionEndReason, Id};
+use tokio::sync::{self, watch};
+
+/// Determines what happens when a client connects using a client ID that is already in use by another connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// The existing connection is closed and the new connection proceeds once the existing connection has saved its
+    /// player entity.
+    TakeOver,
+    /// The new connection is refused while the existing connection remains.
+    Reject
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "take-over" => Ok(DuplicatePolicy::TakeOver),
+            "reject" => Ok(DuplicatePolicy::Reject),
+            _ => Err(format!("Unknown duplicate session policy '{}'", s))
+        }
+    }
+}
+
+/// Tracks which client IDs are in use. Shared between all connection tasks.
+#[derive(Default)]
+pub struct ClientRegistry {
+    clients: Mutex<HashMap<Id, Entry>>
+}
+
+struct Entry {
+    /// Held by the active session of the client for the session's duration so that a session taking over can wait
+    /// for the previous session to finish.
+    lock: Arc<sync::Mutex<()>>,
+    /// Number of the most recently registered session of the client along with the reason that session is to end
+    /// (should it have been ended). Sessions also end once superseded by a newer one.
+    latest_session_sender: watch::Sender<(u64, Option<SessionEndReason>)>,
+    latest_session_receiver: watch::Receiver<(u64, Option<SessionEndReason>)>,
+    /// The entity ID of the player entity loaded by the active session (if any).
+    player_id_option: Option<Id>
+}
+
+impl ClientRegistry {
+    /// Register a new session for the given client ID. Should the client ID already be in use then, depending on the
+    /// given policy, either `None` is returned immediately or the existing session is told to end (see
+    /// [`Session::superseded`]) and this function waits for it to finish before returning.
+    pub async fn register(self: &Arc<Self>, client_id: Id, policy: DuplicatePolicy) -> Option<Session> {
+        let (lock, latest_session_receiver, session_number) = {
+            let mut clients = self.clients.lock();
+
+            let entry = clients.entry(client_id).or_insert_with(|| {
+                let (latest_session_sender, latest_session_receiver) = watch::channel((0, None));
+                Entry {
+                    lock: Arc::new(sync::Mutex::new(())),
+                    latest_session_sender,
+                    latest_session_receiver,
+                    player_id_option: None
+                }
+            });
+
+            // The entry's lock is only shared should another session be active or waiting to begin:
+            if Arc::strong_count(&entry.lock) > 1 && policy == DuplicatePolicy::Reject {
+                return None;
+            }
+
+            let session_number = entry.latest_session_receiver.borrow().0 + 1;
+            entry.latest_session_sender.send((session_number, None)).ok();
+
+            (Arc::clone(&entry.lock), entry.latest_session_receiver.clone(), session_number)
+        };
+
+        let guard = lock.lock_owned().await;
+
+        Some(Session {
+            client_id,
+            session_number,
+            latest_session_receiver,
+            guard_option: Some(guard),
+            registry: Arc::clone(self)
+        })
+    }
+
+    /// Whether or not the given client ID is currently in use by a session.
+    pub fn is_active(&self, client_id: Id) -> bool {
+        self.clients.lock().contains_key(&client_id)
+    }
+
+    /// Tell the most recently registered session of the given client ID to end for the given reason (see
+    /// [`Session::ended`]). Returns whether or not there was such a session.
+    pub fn end_session(&self, client_id: Id, reason: SessionEndReason) -> bool {
+        match self.clients.lock().get(&client_id) {
+            Some(entry) => {
+                let session_number = entry.latest_session_receiver.borrow().0;
+                entry.latest_session_sender.send((session_number, Some(reason))).is_ok()
+            }
+            None => false
+        }
+    }
+
+    /// The client IDs of the active sessions that have loaded player entities mapped to by the entity IDs of those
+    /// player entities.
+    pub fn client_ids_by_player_id(&self) -> HashMap<Id, Id> {
+        self.clients
+            .lock()
+            .iter()
+            .filter_map(|(client_id, entry)| entry.player_id_option.map(|player_id| (player_id, *client_id)))
+            .collect()
+    }
+}
+
+/// The registration of a client ID by a connection. The client ID remains in use until this is dropped.
+pub struct Session {
+    client_id: Id,
+    session_number: u64,
+    latest_session_receiver: watch::Receiver<(u64, Option<SessionEndReason>)>,
+    guard_option: Option<sync::OwnedMutexGuard<()>>,
+    registry: Arc<ClientRegistry>
+}
+
+impl Session {
+    /// Completes once this session is to end (in which case the connection of this session should be closed), either
+    /// because a newer session for the same client ID has been registered or because the session was ended by
+    /// [`ClientRegistry::end_session`]. The reason the session ended is returned.
+    pub async fn ended(&mut self) -> SessionEndReason {
+        loop {
+            let (latest_session_number, end_reason_option) = *self.latest_session_receiver.borrow();
+
+            if latest_session_number != self.session_number {
+                return SessionEndReason::ConnectedElsewhere;
+            }
+            if let Some(reason) = end_reason_option {
+                return reason;
+            }
+
+            // Changes cannot fail to be received as the registry entry (and so the sender) is kept while this session
+            // exists:
+            if self.latest_session_receiver.changed().await.is_err() {
+                return SessionEndReason::ConnectedElsewhere;
+            }
+        }
+    }
+
+    /// Record the entity ID of the player entity loaded by this session.
+    pub fn set_player_id(&self, player_id: Id) {
+        if let Some(entry) = self.registry.clients.lock().get_mut(&self.client_id) {
+            entry.player_id_option = Some(player_id);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Allow any session waiting to take over to begin:
+        drop(self.guard_option.take());
+
+        // Remove the entry from the registry unless other sessions are waiting to use it:
+        let mut clients = self.registry.clients.lock();
+        if clients.get(&self.client_id).map(|entry| Arc::strong_count(&entry.lock) == 1).unwrap_or(false) {
+            clients.remove(&self.client_id);
+        }
+        else if let Some(entry) = clients.get_mut(&self.client_id) {
+            entry.player_id_option = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{self, Duration};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reject_duplicate() {
+        let registry = Arc::new(ClientRegistry::default());
+        let client_id = crate::id::generate_random();
+
+        let session = registry.register(client_id, DuplicatePolicy::Reject).await.unwrap();
+        assert!(registry.is_active(client_id));
+        assert!(registry.register(client_id, DuplicatePolicy::Reject).await.is_none());
+
+        drop(session);
+        assert!(!registry.is_active(client_id));
+        assert!(registry.register(client_id, DuplicatePolicy::Reject).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn take_over_duplicate() {
+        let registry = Arc::new(ClientRegistry::default());
+        let client_id = crate::id::generate_random();
+
+        let mut first_session = registry.register(client_id, DuplicatePolicy::TakeOver).await.unwrap();
+
+        let mut registering_task = {
+            let registry = Arc::clone(&registry);
+            tokio::spawn(async move { registry.register(client_id, DuplicatePolicy::TakeOver).await })
+        };
+
+        // The first session is told to end yet the second only begins once the first has been dropped:
+        let reason = time::timeout(Duration::from_secs(1), first_session.ended()).await.unwrap();
+        assert_eq!(reason, SessionEndReason::ConnectedElsewhere);
+        assert!(time::timeout(Duration::from_millis(50), &mut registering_task).await.is_err());
+
+        drop(first_session);
+        let mut second_session = registering_task.await.unwrap().unwrap();
+        assert!(registry.is_active(client_id));
+
+        // The second session has not been superseded:
+        assert!(time::timeout(Duration::from_millis(50), second_session.ended()).await.is_err());
+
+        drop(second_session);
+        assert!(!registry.is_active(client_id));
+    }
+
+    #[tokio::test]
+    async fn end_session() {
+        let registry = Arc::new(ClientRegistry::default());
+        let client_id = crate::id::generate_random();
+        let player_id = crate::id::generate_with_timestamp();
+
+        assert!(!registry.end_session(client_id, SessionEndReason::Kicked));
+
+        let mut session = registry.register(client_id, DuplicatePolicy::Reject).await.unwrap();
+        session.set_player_id(player_id);
+        assert_eq!(registry.client_ids_by_player_id().get(&player_id), Some(&client_id));
+
+        assert!(registry.end_session(client_id, SessionEndReason::Kicked));
+        let reason = time::timeout(Duration::from_secs(1), session.ended()).await.unwrap();
+        assert_eq!(reason, SessionEndReason::Kicked);
+
+        drop(session);
+        assert!(registry.client_ids_by_player_id().is_empty());
+    }
+}
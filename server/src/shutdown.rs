@@ -0,0 +1,120 @@
+//! Coordination of a graceful server shutdown. When a shutdown is initiated, each connection handler is notified so
+//! that it can inform its client and then close its connection (saving its player entity and unloading its chunks) once
+//! the grace period has elapsed. The main task is able to wait for all handlers to finish doing so before performing a
+//! final save of the game map.
+
+use tokio::{
+    sync::{mpsc, watch},
+    time::{self, Duration, Instant}
+};
+
+/// Details of a shutdown that has been initiated.
+#[derive(Debug, Clone)]
+pub struct Notice {
+    /// Reason for the shutdown (passed on to clients).
+    pub reason: String,
+    /// The point in time at which handlers should close their connections.
+    pub deadline: Instant
+}
+
+impl Notice {
+    /// The number of seconds remaining until the deadline (rounded up).
+    pub fn seconds_remaining(&self) -> u32 {
+        self.deadline.saturating_duration_since(Instant::now()).as_secs_f32().ceil() as u32
+    }
+}
+
+/// Owned by the main task and used to notify all [`Listener`] instances of a shutdown and then wait for them to finish.
+pub struct Coordinator {
+    notice_sender: watch::Sender<Option<Notice>>,
+    /// Cloned into each listener. Holding onto a receiver also ensures that sending a notice never fails.
+    notice_receiver: watch::Receiver<Option<Notice>>,
+    /// Cloned into each listener. As the receiver only returns `None` once all senders have been dropped, it can be
+    /// used to determine when all listeners have finished.
+    finished_sender: mpsc::Sender<()>,
+    finished_receiver: mpsc::Receiver<()>
+}
+
+impl Coordinator {
+    pub fn new() -> Self {
+        let (notice_sender, notice_receiver) = watch::channel(None);
+        let (finished_sender, finished_receiver) = mpsc::channel(1);
+
+        Coordinator { notice_sender, notice_receiver, finished_sender, finished_receiver }
+    }
+
+    /// Create a new listener to be given to a connection handler. The listener should be dropped only once the handler
+    /// has finished.
+    pub fn listener(&self) -> Listener {
+        Listener { notice_receiver: self.notice_receiver.clone(), _finished_sender: self.finished_sender.clone() }
+    }
+
+    /// Notify all listeners that the server is shutting down for the given reason and that they have the specified
+    /// grace period in which to finish.
+    pub fn initiate(&self, reason: String, grace_period: Duration) {
+        let notice = Notice { reason, deadline: Instant::now() + grace_period };
+        self.notice_sender.send(Some(notice)).unwrap();
+    }
+
+    /// Wait for all listeners to be dropped or for the given timeout to elapse. Returns whether or not all listeners
+    /// finished within the timeout.
+    pub async fn wait_for_listeners(self, timeout: Duration) -> bool {
+        let Coordinator { finished_sender, mut finished_receiver, .. } = self;
+        drop(finished_sender);
+
+        time::timeout(timeout, finished_receiver.recv()).await.is_ok()
+    }
+}
+
+/// Held by each connection handler so that it can be notified of a shutdown.
+pub struct Listener {
+    notice_receiver: watch::Receiver<Option<Notice>>,
+    /// Never sent on - only dropped (see [`Coordinator::wait_for_listeners`]).
+    _finished_sender: mpsc::Sender<()>
+}
+
+impl Listener {
+    /// Wait until a shutdown is initiated and then return its notice. Should the coordinator no longer exist then a
+    /// notice with an immediate deadline is returned.
+    pub async fn notified(&mut self) -> Notice {
+        loop {
+            if let Some(notice) = self.notice_receiver.borrow().clone() {
+                return notice;
+            }
+
+            if self.notice_receiver.changed().await.is_err() {
+                return Notice { reason: "Server stopped".to_string(), deadline: Instant::now() };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn notify_and_wait_for_listeners() {
+        let coordinator = Coordinator::new();
+        let mut listener = coordinator.listener();
+
+        coordinator.initiate("Testing".to_string(), Duration::from_secs(5));
+
+        let notice = listener.notified().await;
+        assert_eq!(notice.reason, "Testing");
+        assert_eq!(notice.seconds_remaining(), 5);
+
+        let waiting = tokio::spawn(coordinator.wait_for_listeners(Duration::from_secs(5)));
+        drop(listener);
+
+        assert!(waiting.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn wait_for_listeners_timeout() {
+        let coordinator = Coordinator::new();
+        let _listener = coordinator.listener();
+
+        assert!(!coordinator.wait_for_listeners(Duration::from_millis(10)).await);
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    io,
+    path::{Path, PathBuf}
+};
+
+use futures_util::future::{BoxFuture, FutureExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use shared::{
+    gems,
+    maps::{entities::Entity, Chunk, ChunkCoords, LegacyChunk},
+    Id
+};
+use tokio::{fs, sync::Mutex};
+
+use super::{current_timestamp, Error, Result, Storage};
+
+/// Version of the layout of chunk files recorded in the `chunk_encoding` file. Chunk files in directories without that
+/// file may be in the layout used before the tiles of chunks were encoded (see [`LegacyChunk`]) and so are converted
+/// when the directory is opened.
+const CHUNK_ENCODING: u16 = 1;
+
+/// Stores data as Bincode-encoded files within a directory:
+/// * `map` - The seed of the game map.
+/// * `chunks/<x>_<y>` - Each chunk (named by its chunk coordinates).
+/// * `clients/<client ID>` - The entity ID of each client's player entity.
+/// * `players/<entity ID>` - Each player entity along with the ID of its client (see [`StoredPlayer`]).
+/// * `last_seen/<entity ID>` - The time at which the client of each player entity was last seen.
+/// * `revoked_tokens/<client ID>` - The time at which the session tokens of a client were most recently revoked.
+/// * `banned/<client ID>` - The time at which a banned client was banned.
+/// * `chunk_encoding` - The version of the layout of chunk files (see [`CHUNK_ENCODING`]).
+///
+/// The default world is stored directly within the map directory while each named world is stored in its own
+/// `worlds/<name>` subdirectory.
+pub struct FileStorage {
+    directory: PathBuf,
+    /// Held while the last seen time of a player entity is written or checked before removing that player entity.
+    last_seen_lock: Mutex<()>
+}
+
+impl FileStorage {
+    /// Use the given map directory for storage, creating it and any necessary subdirectories should they not already
+    /// exist. Should a world name be given then that world's subdirectory is used instead of the default world.
+    pub async fn open(map_directory: PathBuf, world_option: Option<&str>) -> Result<Self> {
+        let directory = match world_option {
+            Some(world) => map_directory.join("worlds").join(world),
+            None => map_directory
+        };
+
+        for subdirectory in &["chunks", "clients", "players", "last_seen", "revoked_tokens", "banned"] {
+            fs::create_dir_all(directory.join(subdirectory)).await?;
+        }
+
+        log::info!("Prepared storage directory: {}", directory.display());
+
+        let storage = FileStorage { directory, last_seen_lock: Mutex::new(()) };
+        storage.convert_legacy_chunks().await?;
+
+        Ok(storage)
+    }
+
+    /// Rewrite any chunk files in the legacy layout in the current layout should the `chunk_encoding` file not yet
+    /// exist. Files already in the current layout are left as they are so that a conversion interrupted part way
+    /// through (before the `chunk_encoding` file was written) is simply resumed the next time the directory is opened.
+    async fn convert_legacy_chunks(&self) -> Result<()> {
+        let chunk_encoding_path = self.directory.join("chunk_encoding");
+
+        if read_file::<u16>(&chunk_encoding_path).await?.is_some() {
+            return Ok(());
+        }
+
+        let mut converted_count = 0;
+
+        for coords in self.stored_chunk_coords().await? {
+            let path = self.chunk_path(coords);
+
+            if read_file::<Chunk>(&path).await.is_ok() {
+                continue;
+            }
+
+            if let Some(legacy_chunk) = read_file::<LegacyChunk>(&path).await? {
+                write_file(&path, &Chunk::from(legacy_chunk)).await?;
+                converted_count += 1;
+            }
+        }
+
+        if converted_count > 0 {
+            log::info!("Converted {} chunk files to the current encoding", converted_count);
+        }
+
+        write_file(&chunk_encoding_path, &CHUNK_ENCODING).await
+    }
+
+    fn map_path(&self) -> PathBuf {
+        self.directory.join("map")
+    }
+
+    fn chunk_path(&self, coords: ChunkCoords) -> PathBuf {
+        self.directory.join("chunks").join(format!("{}_{}", coords.x, coords.y))
+    }
+
+    fn client_path(&self, client_id: Id) -> PathBuf {
+        self.directory.join("clients").join(id_file_name(client_id))
+    }
+
+    fn player_path(&self, entity_id: Id) -> PathBuf {
+        self.directory.join("players").join(id_file_name(entity_id))
+    }
+
+    fn last_seen_path(&self, entity_id: Id) -> PathBuf {
+        self.directory.join("last_seen").join(id_file_name(entity_id))
+    }
+
+    fn revoked_tokens_path(&self, client_id: Id) -> PathBuf {
+        self.directory.join("revoked_tokens").join(id_file_name(client_id))
+    }
+
+    fn banned_path(&self, client_id: Id) -> PathBuf {
+        self.directory.join("banned").join(id_file_name(client_id))
+    }
+}
+
+/// Contents of a player file.
+#[derive(Serialize, Deserialize)]
+struct StoredPlayer {
+    client_id: Id,
+    entity: Entity
+}
+
+impl Storage for FileStorage {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn load_map_seed(&self) -> BoxFuture<'_, Result<Option<i32>>> {
+        async move { read_file(&self.map_path()).await }.boxed()
+    }
+
+    fn save_map_seed(&self, seed: i32) -> BoxFuture<'_, Result<()>> {
+        async move { write_file(&self.map_path(), &seed).await }.boxed()
+    }
+
+    fn load_chunk(&self, coords: ChunkCoords) -> BoxFuture<'_, Result<Option<Chunk>>> {
+        async move { read_file(&self.chunk_path(coords)).await }.boxed()
+    }
+
+    fn save_chunk<'a>(&'a self, coords: ChunkCoords, chunk: &'a Chunk) -> BoxFuture<'a, Result<()>> {
+        async move { write_file(&self.chunk_path(coords), chunk).await }.boxed()
+    }
+
+    fn load_player(&self, client_id: Id) -> BoxFuture<'_, Result<Option<(Id, Entity)>>> {
+        async move {
+            if let Some(entity_id) = read_file(&self.client_path(client_id)).await? {
+                let player_option: Option<StoredPlayer> = read_file(&self.player_path(entity_id)).await?;
+                Ok(player_option.map(|player| (entity_id, player.entity)))
+            }
+            else {
+                Ok(None)
+            }
+        }
+        .boxed()
+    }
+
+    fn create_player<'a>(&'a self, client_id: Id, entity_id: Id, entity: &'a Entity) -> BoxFuture<'a, Result<()>> {
+        async move {
+            // Player entity is written first so that a client file never refers to a missing player file:
+            let player = StoredPlayer { client_id, entity: entity.clone() };
+            write_file(&self.player_path(entity_id), &player).await?;
+            write_file(&self.last_seen_path(entity_id), &current_timestamp()).await?;
+            write_file(&self.client_path(client_id), &entity_id).await
+        }
+        .boxed()
+    }
+
+    fn update_player<'a>(&'a self, entity_id: Id, entity: &'a Entity) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let path = self.player_path(entity_id);
+
+            match read_file::<StoredPlayer>(&path).await? {
+                Some(player) => {
+                    write_file(&path, &StoredPlayer { client_id: player.client_id, entity: entity.clone() }).await
+                }
+                None => Err(Error::PlayerNotFound(entity_id))
+            }
+        }
+        .boxed()
+    }
+
+    fn record_player_seen(&self, entity_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let _last_seen_guard = self.last_seen_lock.lock().await;
+
+            if fs::metadata(self.player_path(entity_id)).await.is_ok() {
+                write_file(&self.last_seen_path(entity_id), &timestamp).await
+            }
+            else {
+                Err(Error::PlayerNotFound(entity_id))
+            }
+        }
+        .boxed()
+    }
+
+    fn players_not_seen_since(&self, timestamp: i64) -> BoxFuture<'_, Result<Vec<(Id, i64)>>> {
+        async move {
+            let mut players = Vec::new();
+
+            for file_name in list_directory(&self.directory.join("last_seen")).await? {
+                if let Some(entity_id) = id_from_file_name(&file_name) {
+                    if let Some(last_seen) = read_file::<i64>(&self.last_seen_path(entity_id)).await? {
+                        if last_seen < timestamp {
+                            players.push((entity_id, last_seen));
+                        }
+                    }
+                }
+            }
+
+            Ok(players)
+        }
+        .boxed()
+    }
+
+    fn remove_player_not_seen_since(&self, entity_id: Id, timestamp: i64) -> BoxFuture<'_, Result<bool>> {
+        async move {
+            let _last_seen_guard = self.last_seen_lock.lock().await;
+
+            match read_file::<i64>(&self.last_seen_path(entity_id)).await? {
+                Some(last_seen) if last_seen < timestamp => {}
+                _ => return Ok(false)
+            }
+
+            // Client file is removed first so that it never refers to a missing player file:
+            if let Some(player) = read_file::<StoredPlayer>(&self.player_path(entity_id)).await? {
+                remove_file(&self.client_path(player.client_id)).await?;
+            }
+
+            remove_file(&self.player_path(entity_id)).await?;
+            remove_file(&self.last_seen_path(entity_id)).await?;
+
+            Ok(true)
+        }
+        .boxed()
+    }
+
+    fn stored_chunk_coords(&self) -> BoxFuture<'_, Result<Vec<ChunkCoords>>> {
+        async move {
+            let file_names = list_directory(&self.directory.join("chunks")).await?;
+
+            Ok(file_names
+                .iter()
+                .filter_map(|file_name| {
+                    let mut parts = file_name.splitn(2, '_');
+                    Some(ChunkCoords { x: parts.next()?.parse().ok()?, y: parts.next()?.parse().ok()? })
+                })
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn player_gem_collections(&self) -> BoxFuture<'_, Result<Vec<(Id, gems::Collection)>>> {
+        async move {
+            let mut collections = Vec::new();
+
+            for file_name in list_directory(&self.directory.join("players")).await? {
+                if let Some(entity_id) = id_from_file_name(&file_name) {
+                    if let Some(player) = read_file::<StoredPlayer>(&self.player_path(entity_id)).await? {
+                        collections.push((entity_id, player.entity.gem_collection));
+                    }
+                }
+            }
+
+            Ok(collections)
+        }
+        .boxed()
+    }
+
+    fn tokens_revoked_at(&self, client_id: Id) -> BoxFuture<'_, Result<Option<i64>>> {
+        async move { read_file(&self.revoked_tokens_path(client_id)).await }.boxed()
+    }
+
+    fn revoke_tokens(&self, client_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>> {
+        async move { write_file(&self.revoked_tokens_path(client_id), &timestamp).await }.boxed()
+    }
+
+    fn client_banned_at(&self, client_id: Id) -> BoxFuture<'_, Result<Option<i64>>> {
+        async move { read_file(&self.banned_path(client_id)).await }.boxed()
+    }
+
+    fn ban_client(&self, client_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let path = self.banned_path(client_id);
+
+            if fs::metadata(&path).await.is_err() {
+                write_file(&path, &timestamp).await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn unban_client(&self, client_id: Id) -> BoxFuture<'_, Result<()>> {
+        async move { remove_file(&self.banned_path(client_id)).await }.boxed()
+    }
+}
+
+/// IDs are encoded using standard Base64 which may include the '/' character so that is replaced with '-' (which is not
+/// otherwise used by the encoding).
+fn id_file_name(id: Id) -> String {
+    id.encode().replace('/', "-")
+}
+
+/// Inverse of [`id_file_name`]. Returns `None` should the file name not be that of an ID.
+fn id_from_file_name(file_name: &str) -> Option<Id> {
+    Id::decode(&file_name.replace('-', "/"))
+}
+
+/// Names of all files in the given directory (excluding any temporary files left by [`write_file`]).
+async fn list_directory(directory: &Path) -> Result<Vec<String>> {
+    let mut entries = fs::read_dir(directory).await?;
+    let mut file_names = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(file_name) = entry.file_name().to_str() {
+            if !file_name.ends_with(".tmp") {
+                file_names.push(file_name.to_string());
+            }
+        }
+    }
+
+    Ok(file_names)
+}
+
+/// Remove the file at the given path (should it exist).
+async fn remove_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(())
+    }
+}
+
+/// Read and decode the file at the given path. Returns `None` should that file not exist.
+async fn read_file<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    match fs::read(path).await {
+        Ok(data) => Ok(Some(bincode::deserialize(&data)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into())
+    }
+}
+
+/// Encode and write the given value to the file at the given path. The data is first written to a temporary file that
+/// then replaces the original so that the file is never left partially written. Each write uses its own temporary file
+/// so that concurrent writes to the same path cannot interleave.
+async fn write_file<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<()> {
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let temp_path = path.with_file_name(format!("{}.{:016x}.tmp", file_name, rand::random::<u64>()));
+
+    fs::write(&temp_path, bincode::serialize(value)?).await?;
+    fs::rename(&temp_path, path).await.map_err(Into::into)
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use futures_util::future::{self, BoxFuture, FutureExt};
+use parking_lot::Mutex;
+use shared::{
+    gems,
+    maps::{entities::Entity, Chunk, ChunkCoords},
+    Id
+};
+
+use super::{current_timestamp, Error, Result, Storage};
+
+/// Stores data in memory only so nothing is kept once the server stops. Intended for testing and for running the
+/// server without any external services.
+#[derive(Default)]
+pub struct MemoryStorage {
+    contents: Mutex<Contents>
+}
+
+#[derive(Default)]
+struct Contents {
+    map_seed: Option<i32>,
+    chunks: HashMap<ChunkCoords, Chunk>,
+    /// Client IDs mapped to the entity IDs of their player entities.
+    client_entity_ids: HashMap<Id, Id>,
+    /// Entity IDs mapped to player entities.
+    player_entities: HashMap<Id, Entity>,
+    /// Entity IDs mapped to the times at which the clients of those player entities were last seen.
+    last_seen_timestamps: HashMap<Id, i64>,
+    /// Client IDs mapped to the times at which their session tokens were most recently revoked.
+    token_revocation_timestamps: HashMap<Id, i64>,
+    /// Client IDs of banned clients mapped to the times at which they were banned.
+    ban_timestamps: HashMap<Id, i64>
+}
+
+impl Storage for MemoryStorage {
+    fn name(&self) -> &'static str {
+        "in-memory"
+    }
+
+    fn load_map_seed(&self) -> BoxFuture<'_, Result<Option<i32>>> {
+        future::ready(Ok(self.contents.lock().map_seed)).boxed()
+    }
+
+    fn save_map_seed(&self, seed: i32) -> BoxFuture<'_, Result<()>> {
+        self.contents.lock().map_seed = Some(seed);
+        future::ready(Ok(())).boxed()
+    }
+
+    fn load_chunk(&self, coords: ChunkCoords) -> BoxFuture<'_, Result<Option<Chunk>>> {
+        future::ready(Ok(self.contents.lock().chunks.get(&coords).cloned())).boxed()
+    }
+
+    fn save_chunk<'a>(&'a self, coords: ChunkCoords, chunk: &'a Chunk) -> BoxFuture<'a, Result<()>> {
+        self.contents.lock().chunks.insert(coords, chunk.clone());
+        future::ready(Ok(())).boxed()
+    }
+
+    fn load_player(&self, client_id: Id) -> BoxFuture<'_, Result<Option<(Id, Entity)>>> {
+        let contents = self.contents.lock();
+
+        let player_option = contents
+            .client_entity_ids
+            .get(&client_id)
+            .and_then(|entity_id| contents.player_entities.get(entity_id).map(|entity| (*entity_id, entity.clone())));
+
+        future::ready(Ok(player_option)).boxed()
+    }
+
+    fn create_player<'a>(&'a self, client_id: Id, entity_id: Id, entity: &'a Entity) -> BoxFuture<'a, Result<()>> {
+        let mut contents = self.contents.lock();
+        contents.client_entity_ids.insert(client_id, entity_id);
+        contents.player_entities.insert(entity_id, entity.clone());
+        contents.last_seen_timestamps.insert(entity_id, current_timestamp());
+
+        future::ready(Ok(())).boxed()
+    }
+
+    fn update_player<'a>(&'a self, entity_id: Id, entity: &'a Entity) -> BoxFuture<'a, Result<()>> {
+        let res = match self.contents.lock().player_entities.get_mut(&entity_id) {
+            Some(stored_entity) => {
+                *stored_entity = entity.clone();
+                Ok(())
+            }
+            None => Err(Error::PlayerNotFound(entity_id))
+        };
+
+        future::ready(res).boxed()
+    }
+
+    fn record_player_seen(&self, entity_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>> {
+        let mut contents = self.contents.lock();
+
+        let res = if contents.player_entities.contains_key(&entity_id) {
+            contents.last_seen_timestamps.insert(entity_id, timestamp);
+            Ok(())
+        }
+        else {
+            Err(Error::PlayerNotFound(entity_id))
+        };
+
+        future::ready(res).boxed()
+    }
+
+    fn players_not_seen_since(&self, timestamp: i64) -> BoxFuture<'_, Result<Vec<(Id, i64)>>> {
+        let players = self
+            .contents
+            .lock()
+            .last_seen_timestamps
+            .iter()
+            .filter(|(_, last_seen)| **last_seen < timestamp)
+            .map(|(entity_id, last_seen)| (*entity_id, *last_seen))
+            .collect();
+
+        future::ready(Ok(players)).boxed()
+    }
+
+    fn remove_player_not_seen_since(&self, entity_id: Id, timestamp: i64) -> BoxFuture<'_, Result<bool>> {
+        let mut contents = self.contents.lock();
+
+        let not_seen =
+            matches!(contents.last_seen_timestamps.get(&entity_id), Some(last_seen) if *last_seen < timestamp);
+        if not_seen {
+            contents.client_entity_ids.retain(|_, id| *id != entity_id);
+            contents.player_entities.remove(&entity_id);
+            contents.last_seen_timestamps.remove(&entity_id);
+        }
+
+        future::ready(Ok(not_seen)).boxed()
+    }
+
+    fn stored_chunk_coords(&self) -> BoxFuture<'_, Result<Vec<ChunkCoords>>> {
+        future::ready(Ok(self.contents.lock().chunks.keys().copied().collect())).boxed()
+    }
+
+    fn player_gem_collections(&self) -> BoxFuture<'_, Result<Vec<(Id, gems::Collection)>>> {
+        let collections = self
+            .contents
+            .lock()
+            .player_entities
+            .iter()
+            .map(|(entity_id, entity)| (*entity_id, entity.gem_collection.clone()))
+            .collect();
+
+        future::ready(Ok(collections)).boxed()
+    }
+
+    fn tokens_revoked_at(&self, client_id: Id) -> BoxFuture<'_, Result<Option<i64>>> {
+        future::ready(Ok(self.contents.lock().token_revocation_timestamps.get(&client_id).copied())).boxed()
+    }
+
+    fn revoke_tokens(&self, client_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>> {
+        self.contents.lock().token_revocation_timestamps.insert(client_id, timestamp);
+        future::ready(Ok(())).boxed()
+    }
+
+    fn client_banned_at(&self, client_id: Id) -> BoxFuture<'_, Result<Option<i64>>> {
+        future::ready(Ok(self.contents.lock().ban_timestamps.get(&client_id).copied())).boxed()
+    }
+
+    fn ban_client(&self, client_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>> {
+        self.contents.lock().ban_timestamps.entry(client_id).or_insert(timestamp);
+        future::ready(Ok(())).boxed()
+    }
+
+    fn unban_client(&self, client_id: Id) -> BoxFuture<'_, Result<()>> {
+        self.contents.lock().ban_timestamps.remove(&client_id);
+        future::ready(Ok(())).boxed()
+    }
+}
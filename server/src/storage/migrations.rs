@@ -0,0 +1,118 @@
+//! Versioned migrations of the PostgreSQL database schema. Each migration is an SQL file in the `server/db/migrations/`
+//! directory and is applied at most once - the versions of applied migrations are recorded in the `schema_migrations`
+//! table. Migrations must never be modified once released; changes to the schema are made by adding a new migration
+//! with the next version number to the end of [`MIGRATIONS`].
+
+use sqlx::{Executor, Row};
+
+use super::{current_timestamp, Error, Result};
+use crate::db_query_from_file;
+
+/// A single change to the database schema.
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    sql: &'static str
+}
+
+/// Include the SQL of the migration file with the given name (excluding the `.sql` extension).
+macro_rules! migration_sql {
+    ($file:expr) => {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/db/migrations/", $file, ".sql"))
+    };
+}
+
+/// All migrations in the order in which they are to be applied. Versions start at 1 and increase by 1 each time.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, description: "Create tables", sql: migration_sql!("1 create tables") },
+    Migration { version: 2, description: "Add last seen column", sql: migration_sql!("2 add last seen column") },
+    Migration {
+        version: 3,
+        description: "Create revoked tokens table",
+        sql: migration_sql!("3 create revoked tokens table")
+    },
+    Migration {
+        version: 4,
+        description: "Create banned clients table",
+        sql: migration_sql!("4 create banned clients table")
+    },
+    Migration {
+        version: 5,
+        description: "Add chunk encoding column",
+        sql: migration_sql!("5 add chunk encoding column")
+    }
+];
+
+/// The version of the database schema that this build of the server expects.
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|migration| migration.version).unwrap_or(0)
+}
+
+/// Bring the database schema up to date by applying all pending migrations (should `apply_pending` be true) or simply
+/// check that it is already up to date (should `apply_pending` be false). Returns the number of migrations applied.
+///
+/// Fails should the schema be newer than this build of the server supports (i.e. it was migrated by a more recent
+/// version) or should there be pending migrations that are not to be applied.
+pub async fn run(pool: &sqlx::PgPool, apply_pending: bool) -> Result<usize> {
+    db_query_from_file!("schema_migrations/create table", pool).await?;
+
+    // All migrations are applied within a single transaction and the table of applied migrations is locked so that
+    // multiple server instances starting simultaneously do not attempt to apply the same migrations:
+
+    let mut transaction = pool.begin().await?;
+    db_query_from_file!("schema_migrations/lock table", &mut transaction).await?;
+
+    let current_version: i32 = db_query_from_file!("schema_migrations/select latest version")
+        .map(|row: sqlx::postgres::PgRow| row.get("version"))
+        .fetch_one(&mut transaction)
+        .await?;
+
+    log::info!("Database schema is at version {} (latest known version is {})", current_version, latest_version());
+
+    if current_version > latest_version() {
+        return Err(Error::SchemaTooNew { found: current_version, supported: latest_version() });
+    }
+
+    let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|migration| migration.version > current_version).collect();
+
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    if !apply_pending {
+        return Err(Error::PendingMigrations(pending.len()));
+    }
+
+    for migration in &pending {
+        log::info!("Applying database migration {} - {}", migration.version, migration.description);
+
+        // Executed as a simple (unprepared) query so that a migration may contain multiple statements:
+        transaction.execute(migration.sql).await?;
+
+        db_query_from_file!("schema_migrations/create row")
+            .bind(migration.version)
+            .bind(migration.description)
+            .bind(current_timestamp())
+            .execute(&mut transaction)
+            .await?;
+    }
+
+    transaction.commit().await?;
+
+    log::info!("Database schema migrated to version {}", latest_version());
+
+    Ok(pending.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migration_versions_are_sequential() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i32 + 1);
+            assert!(!migration.sql.trim().is_empty());
+        }
+    }
+}
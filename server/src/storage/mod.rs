@@ -0,0 +1,130 @@
+//! Persistent storage of the game map's seed, its chunks and player entities. The [`Storage`] trait abstracts over the
+//! available backends so that the rest of the server need not know how or where data is stored:
+//! * [`PostgresStorage`] - A PostgreSQL database.
+//! * [`FileStorage`] - Files within a directory on the local file system.
+//! * [`MemoryStorage`] - Held in memory only and so lost once the server stops (useful for testing).
+
+mod file;
+mod memory;
+pub mod migrations;
+mod postgres;
+#[cfg(test)]
+mod tests;
+
+use std::time;
+
+pub use file::FileStorage;
+use futures_util::future::BoxFuture;
+pub use memory::MemoryStorage;
+pub use postgres::PostgresStorage;
+use shared::{
+    gems,
+    maps::{entities::Entity, Chunk, ChunkCoords},
+    Id
+};
+
+/// Implemented by each storage backend. Methods return boxed futures so that the trait can be used as a trait object.
+///
+/// Backends need only store the persistent state of player entities - their direction, facial expression and status
+/// effects are reset when loaded (see [`crate::maps::entities::player_from_storage`]).
+pub trait Storage: Send + Sync {
+    /// Name of the backend used for logging purposes.
+    fn name(&self) -> &'static str;
+
+    /// Current usage of the backend's connection pool (or `None` should the backend not use a connection pool).
+    fn connection_pool_usage(&self) -> Option<PoolUsage> {
+        None
+    }
+
+    /// Fetch the seed of the stored game map (or `None` should no map have been stored yet).
+    fn load_map_seed(&self) -> BoxFuture<'_, Result<Option<i32>>>;
+
+    /// Store the seed of a newly created game map.
+    fn save_map_seed(&self, seed: i32) -> BoxFuture<'_, Result<()>>;
+
+    /// Fetch the chunk at the given coordinates (or `None` should that chunk not have been stored yet).
+    fn load_chunk(&self, coords: ChunkCoords) -> BoxFuture<'_, Result<Option<Chunk>>>;
+
+    /// Store the given chunk, replacing any chunk previously stored at the same coordinates.
+    fn save_chunk<'a>(&'a self, coords: ChunkCoords, chunk: &'a Chunk) -> BoxFuture<'a, Result<()>>;
+
+    /// Fetch the entity ID and player entity associated with the given client ID (or `None` should that client not
+    /// have a player entity).
+    fn load_player(&self, client_id: Id) -> BoxFuture<'_, Result<Option<(Id, Entity)>>>;
+
+    /// Store a new player entity associated with the given client ID.
+    fn create_player<'a>(&'a self, client_id: Id, entity_id: Id, entity: &'a Entity) -> BoxFuture<'a, Result<()>>;
+
+    /// Update an existing player entity (identified by its entity ID).
+    fn update_player<'a>(&'a self, entity_id: Id, entity: &'a Entity) -> BoxFuture<'a, Result<()>>;
+
+    /// Record the time (as a Unix timestamp in seconds) at which the client of the given player entity was last seen.
+    fn record_player_seen(&self, entity_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>>;
+
+    /// Fetch the entity IDs of all player entities whose clients were last seen before the given time (as a Unix
+    /// timestamp in seconds) along with the times at which they were last seen.
+    fn players_not_seen_since(&self, timestamp: i64) -> BoxFuture<'_, Result<Vec<(Id, i64)>>>;
+
+    /// Remove the given player entity and its association with its client provided its client was last seen before the
+    /// given time (as a Unix timestamp in seconds). The time at which the client was last seen is checked as part of
+    /// the removal so that a player entity whose client has since been seen is never removed. Returns whether the
+    /// player entity was removed.
+    fn remove_player_not_seen_since(&self, entity_id: Id, timestamp: i64) -> BoxFuture<'_, Result<bool>>;
+
+    /// Fetch the coordinates of every stored chunk.
+    fn stored_chunk_coords(&self) -> BoxFuture<'_, Result<Vec<ChunkCoords>>>;
+
+    /// Fetch the entity ID and gem collection of every stored player entity (used to build the leaderboard).
+    fn player_gem_collections(&self) -> BoxFuture<'_, Result<Vec<(Id, gems::Collection)>>>;
+
+    /// Fetch the time (as a Unix timestamp in seconds) at which the session tokens of the given client were most
+    /// recently revoked (or `None` should they never have been revoked).
+    fn tokens_revoked_at(&self, client_id: Id) -> BoxFuture<'_, Result<Option<i64>>>;
+
+    /// Revoke all session tokens issued to the given client at or before the given time (as a Unix timestamp in
+    /// seconds). The client need not have a player entity.
+    fn revoke_tokens(&self, client_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>>;
+
+    /// Fetch the time (as a Unix timestamp in seconds) at which the given client was banned (or `None` should that
+    /// client not be banned).
+    fn client_banned_at(&self, client_id: Id) -> BoxFuture<'_, Result<Option<i64>>>;
+
+    /// Ban the given client at the given time (as a Unix timestamp in seconds). Has no effect should the client
+    /// already be banned.
+    fn ban_client(&self, client_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>>;
+
+    /// Lift the ban of the given client (should it be banned).
+    fn unban_client(&self, client_id: Id) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Snapshot of the connections held by a storage backend's connection pool (see
+/// [`Storage::connection_pool_usage`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolUsage {
+    pub connections: u32,
+    pub idle_connections: usize,
+    pub max_connections: u32
+}
+
+/// The current time as a Unix timestamp in seconds (as used to record when clients were last seen).
+pub fn current_timestamp() -> i64 {
+    time::SystemTime::now().duration_since(time::UNIX_EPOCH).map(|duration| duration.as_secs() as i64).unwrap_or(0)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to access database - {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Failed to access file - {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to (de)serialise data with Bincode - {0}")]
+    Bincode(#[from] bincode::Error),
+    #[error("No player entity with ID {0} is stored")]
+    PlayerNotFound(Id),
+    #[error("Database schema version {found} is newer than the latest version supported ({supported})")]
+    SchemaTooNew { found: i32, supported: i32 },
+    #[error("Database schema is out of date - {0} migrations are pending")]
+    PendingMigrations(usize)
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,367 @@
+use futures_util::future::{BoxFuture, FutureExt};
+use shared::{
+    gems,
+    maps::{
+        entities::{Direction, Entity, FacialExpression},
+        Chunk, ChunkCoords, LegacyChunk, TileCoords
+    },
+    Id
+};
+use sqlx::{Executor, Row};
+use strum::IntoEnumIterator;
+
+use super::{migrations, Error, PoolUsage, Result, Storage};
+use crate::{db_query_from_file, maps::entities::random_variant};
+
+/// Value of the `encoding` column of the `map_chunks` table for chunks serialised in the layout used before their tiles
+/// were encoded (see [`LegacyChunk`]). Rows that predate the column are given this value.
+const LEGACY_CHUNK_ENCODING: i16 = 0;
+
+/// Value of the `encoding` column of the `map_chunks` table for chunks serialised in the current layout (see
+/// [`shared::maps::encoding`]).
+const CHUNK_ENCODING: i16 = 1;
+
+/// Stores data in a PostgreSQL database. The default world is stored in the tables of the default (`public`) schema
+/// while each named world has its own schema containing the same tables.
+pub struct PostgresStorage {
+    pool: sqlx::PgPool,
+    max_connections: u32
+}
+
+impl PostgresStorage {
+    /// Connect to the database with the given connection string. Should a world name be given then the tables of that
+    /// world's schema are used instead of those of the default world. The world name is expected to have already been
+    /// validated as only containing lowercase ASCII letters, digits and underscores.
+    ///
+    /// The schema is not prepared by this function - see [`Self::migrate`].
+    pub async fn connect(connection_string: &str, max_connections: u32, world_option: Option<&str>) -> Result<Self> {
+        let mut pool_options = sqlx::postgres::PgPoolOptions::new().max_connections(max_connections);
+
+        if let Some(world) = world_option {
+            // Ensure every connection uses the world's schema:
+
+            let statement = format!("CREATE SCHEMA IF NOT EXISTS world_{0}; SET search_path TO world_{0}", world);
+
+            pool_options = pool_options.after_connect(move |conn| {
+                let statement = statement.clone();
+                async move { conn.execute(statement.as_str()).await.map(|_| ()) }.boxed()
+            });
+
+            log::info!("Using database schema 'world_{}'", world);
+        }
+
+        let pool = pool_options.connect(connection_string).await?;
+
+        log::info!("Created connection pool with maximum of {} simultaneous connections to database", max_connections);
+
+        Ok(PostgresStorage { pool, max_connections })
+    }
+
+    /// Apply any pending migrations to the database schema (should `apply_pending` be true) or check that there are
+    /// none (should `apply_pending` be false). Returns the number of migrations applied. See [`migrations::run`].
+    pub async fn migrate(&self, apply_pending: bool) -> Result<usize> {
+        migrations::run(&self.pool, apply_pending).await
+    }
+}
+
+impl Storage for PostgresStorage {
+    fn name(&self) -> &'static str {
+        "PostgreSQL"
+    }
+
+    fn connection_pool_usage(&self) -> Option<PoolUsage> {
+        Some(PoolUsage {
+            connections: self.pool.size(),
+            idle_connections: self.pool.num_idle(),
+            max_connections: self.max_connections
+        })
+    }
+
+    fn load_map_seed(&self) -> BoxFuture<'_, Result<Option<i32>>> {
+        async move {
+            let seed = db_query_from_file!("map/select row")
+                .map(|row: sqlx::postgres::PgRow| row.get("seed"))
+                .fetch_optional(&self.pool)
+                .await?;
+
+            Ok(seed)
+        }
+        .boxed()
+    }
+
+    fn save_map_seed(&self, seed: i32) -> BoxFuture<'_, Result<()>> {
+        async move {
+            db_query_from_file!("map/create row").bind(seed).execute(&self.pool).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn load_chunk(&self, coords: ChunkCoords) -> BoxFuture<'_, Result<Option<Chunk>>> {
+        async move {
+            let row_option = db_query_from_file!("map_chunks/select row")
+                .bind(coords.x)
+                .bind(coords.y)
+                .map(|row: sqlx::postgres::PgRow| (row.get::<Vec<u8>, _>("data"), row.get::<i16, _>("encoding")))
+                .fetch_optional(&self.pool)
+                .await?;
+
+            match row_option {
+                Some((data, LEGACY_CHUNK_ENCODING)) => Ok(Some(bincode::deserialize::<LegacyChunk>(&data)?.into())),
+                Some((data, _)) => Ok(Some(bincode::deserialize(&data)?)),
+                None => Ok(None)
+            }
+        }
+        .boxed()
+    }
+
+    fn save_chunk<'a>(&'a self, coords: ChunkCoords, chunk: &'a Chunk) -> BoxFuture<'a, Result<()>> {
+        async move {
+            db_query_from_file!("map_chunks/replace row")
+                .bind(coords.x)
+                .bind(coords.y)
+                .bind(bincode::serialize(chunk)?)
+                .bind(CHUNK_ENCODING)
+                .execute(&self.pool)
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn load_player(&self, client_id: Id) -> BoxFuture<'_, Result<Option<(Id, Entity)>>> {
+        async move {
+            let player_option = db_query_from_file!("client_entities/select row")
+                .bind(client_id.encode())
+                .map(|row: sqlx::postgres::PgRow| {
+                    (
+                        Id::decode(row.get("entity_id")).unwrap(),
+                        Entity {
+                            pos: TileCoords { x: row.get("tile_x"), y: row.get("tile_y") },
+                            direction: Direction::Down,
+                            facial_expression: FacialExpression::Neutral,
+                            hair_style: decode_variant(row.get("hair_style")),
+                            clothing_colour: decode_variant(row.get("clothing_colour")),
+                            skin_colour: decode_variant(row.get("skin_colour")),
+                            hair_colour: decode_variant(row.get("hair_colour")),
+                            gem_collection: bincode::deserialize(row.get("gem_collection")).unwrap_or_default(),
+                            item_inventory: bincode::deserialize(row.get("item_inventory")).unwrap_or_default(),
+                            bombs_placed_count: row.get("bombs_placed_count"),
+                            status_effects: Default::default()
+                        }
+                    )
+                })
+                .fetch_optional(&self.pool)
+                .await?;
+
+            Ok(player_option)
+        }
+        .boxed()
+    }
+
+    fn create_player<'a>(&'a self, client_id: Id, entity_id: Id, entity: &'a Entity) -> BoxFuture<'a, Result<()>> {
+        async move {
+            bind_entity_data(db_query_from_file!("client_entities/create row"), entity)
+                .bind(client_id.encode())
+                .bind(entity_id.encode())
+                .execute(&self.pool)
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn update_player<'a>(&'a self, entity_id: Id, entity: &'a Entity) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let result = bind_entity_data(db_query_from_file!("client_entities/update row"), entity)
+                .bind(entity_id.encode())
+                .execute(&self.pool)
+                .await?;
+
+            match result.rows_affected() {
+                0 => Err(Error::PlayerNotFound(entity_id)),
+                1 => Ok(()),
+                rows_changed => {
+                    log::warn!(
+                        "Modified {} rows when updating data for player entity with ID {}",
+                        rows_changed,
+                        entity_id
+                    );
+                    Ok(())
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn record_player_seen(&self, entity_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let result = db_query_from_file!("client_entities/update last seen")
+                .bind(timestamp)
+                .bind(entity_id.encode())
+                .execute(&self.pool)
+                .await?;
+
+            if result.rows_affected() == 0 {
+                Err(Error::PlayerNotFound(entity_id))
+            }
+            else {
+                Ok(())
+            }
+        }
+        .boxed()
+    }
+
+    fn players_not_seen_since(&self, timestamp: i64) -> BoxFuture<'_, Result<Vec<(Id, i64)>>> {
+        async move {
+            let rows = db_query_from_file!("client_entities/select not seen since")
+                .bind(timestamp)
+                .map(|row: sqlx::postgres::PgRow| (row.get::<String, _>("entity_id"), row.get("last_seen")))
+                .fetch_all(&self.pool)
+                .await?;
+
+            Ok(rows
+                .into_iter()
+                .filter_map(|(encoded_id, last_seen)| Id::decode(&encoded_id).map(|entity_id| (entity_id, last_seen)))
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn remove_player_not_seen_since(&self, entity_id: Id, timestamp: i64) -> BoxFuture<'_, Result<bool>> {
+        async move {
+            let result = db_query_from_file!("client_entities/delete row not seen since")
+                .bind(entity_id.encode())
+                .bind(timestamp)
+                .execute(&self.pool)
+                .await?;
+
+            Ok(result.rows_affected() > 0)
+        }
+        .boxed()
+    }
+
+    fn stored_chunk_coords(&self) -> BoxFuture<'_, Result<Vec<ChunkCoords>>> {
+        async move {
+            let coords = db_query_from_file!("map_chunks/select coords")
+                .map(|row: sqlx::postgres::PgRow| ChunkCoords { x: row.get("chunk_x"), y: row.get("chunk_y") })
+                .fetch_all(&self.pool)
+                .await?;
+
+            Ok(coords)
+        }
+        .boxed()
+    }
+
+    fn player_gem_collections(&self) -> BoxFuture<'_, Result<Vec<(Id, gems::Collection)>>> {
+        async move {
+            let rows = db_query_from_file!("client_entities/select gem collections")
+                .map(|row: sqlx::postgres::PgRow| {
+                    (
+                        row.get::<String, _>("entity_id"),
+                        bincode::deserialize(row.get("gem_collection")).unwrap_or_default()
+                    )
+                })
+                .fetch_all(&self.pool)
+                .await?;
+
+            Ok(rows
+                .into_iter()
+                .filter_map(|(encoded_id, collection)| Id::decode(&encoded_id).map(|entity_id| (entity_id, collection)))
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn tokens_revoked_at(&self, client_id: Id) -> BoxFuture<'_, Result<Option<i64>>> {
+        async move {
+            let timestamp = db_query_from_file!("revoked_tokens/select row")
+                .bind(client_id.encode())
+                .map(|row: sqlx::postgres::PgRow| row.get("revoked_at"))
+                .fetch_optional(&self.pool)
+                .await?;
+
+            Ok(timestamp)
+        }
+        .boxed()
+    }
+
+    fn revoke_tokens(&self, client_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>> {
+        async move {
+            db_query_from_file!("revoked_tokens/replace row")
+                .bind(client_id.encode())
+                .bind(timestamp)
+                .execute(&self.pool)
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn client_banned_at(&self, client_id: Id) -> BoxFuture<'_, Result<Option<i64>>> {
+        async move {
+            let timestamp = db_query_from_file!("banned_clients/select row")
+                .bind(client_id.encode())
+                .map(|row: sqlx::postgres::PgRow| row.get("banned_at"))
+                .fetch_optional(&self.pool)
+                .await?;
+
+            Ok(timestamp)
+        }
+        .boxed()
+    }
+
+    fn ban_client(&self, client_id: Id, timestamp: i64) -> BoxFuture<'_, Result<()>> {
+        async move {
+            db_query_from_file!("banned_clients/create row")
+                .bind(client_id.encode())
+                .bind(timestamp)
+                .execute(&self.pool)
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn unban_client(&self, client_id: Id) -> BoxFuture<'_, Result<()>> {
+        async move {
+            db_query_from_file!("banned_clients/delete row").bind(client_id.encode()).execute(&self.pool).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Binds all the components of a player entity to the given database query (excluding the entity ID & client ID).
+fn bind_entity_data<'a>(
+    query: sqlx::query::Query<'a, sqlx::Postgres, sqlx::postgres::PgArguments>, entity: &Entity
+) -> sqlx::query::Query<'a, sqlx::Postgres, sqlx::postgres::PgArguments> {
+    query
+        .bind(entity.pos.x)
+        .bind(entity.pos.y)
+        .bind(encode_variant(entity.hair_style))
+        .bind(encode_variant(entity.clothing_colour))
+        .bind(encode_variant(entity.skin_colour))
+        .bind(encode_variant(entity.hair_colour))
+        .bind(bincode::serialize(&entity.gem_collection).unwrap_or_default())
+        .bind(bincode::serialize(&entity.item_inventory).unwrap_or_default())
+        .bind(entity.bombs_placed_count)
+}
+
+/// Encode an enum variant as a 16-bit integer.
+fn encode_variant<T: IntoEnumIterator + PartialEq>(val: T) -> i16 {
+    T::iter().position(|x| x == val).unwrap() as i16
+}
+
+/// Decodes a 16-bit integer into a variant of a given enum type. If the given integer does not corespond to a variant
+/// of the given enum type, then a random variant is returned and a warning message is printed.
+fn decode_variant<T: IntoEnumIterator>(val: i16) -> T {
+    T::iter().nth(val as usize).unwrap_or_else(|| {
+        log::warn!("Failed to decode 32-bit integer {} into enum variant of type {}", val, std::any::type_name::<T>());
+        random_variant()
+    })
+}
@@ -0,0 +1,257 @@
+use std::{collections::HashMap, sync::Arc};
+
+use parking_lot::Mutex;
+use shared::{
+    effects::{StatusEffect, StatusEffects},
+    gems::{self, Gem},
+    items,
+    maps::{
+        entities::{ClothingColour, Direction, Entity, FacialExpression, HairColour, HairStyle, SkinColour},
+        Chunk, ChunkCoords, LegacyChunk, Map, OffsetCoords, Tile, TileCoords, CHUNK_TILE_COUNT
+    }
+};
+
+use super::*;
+use crate::maps::{chunks, entities, ServerMap};
+
+fn make_test_entity() -> Entity {
+    Entity {
+        pos: TileCoords { x: 12, y: -7 },
+        direction: Direction::Down,
+        facial_expression: FacialExpression::Neutral,
+        hair_style: HairStyle::Quiff,
+        clothing_colour: ClothingColour::Grey,
+        skin_colour: SkinColour::Black,
+        hair_colour: HairColour::Black,
+        gem_collection: gems::Collection::default(),
+        item_inventory: items::Inventory::default(),
+        bombs_placed_count: 0,
+        status_effects: StatusEffects::default()
+    }
+}
+
+/// Exercise every method of the given storage backend, which is expected to initially be empty.
+async fn check_storage_backend(storage: &dyn Storage) {
+    // Map seed:
+
+    assert_eq!(storage.load_map_seed().await.unwrap(), None);
+    storage.save_map_seed(1234).await.unwrap();
+    assert_eq!(storage.load_map_seed().await.unwrap(), Some(1234));
+
+    // Loading the map should not replace the existing seed:
+    assert_eq!(ServerMap::load_or_new(storage, Some(5678)).await.unwrap().seed(), 1234);
+    assert_eq!(storage.load_map_seed().await.unwrap(), Some(1234));
+
+    // Chunks:
+
+    let coords = ChunkCoords { x: -1, y: 5 };
+    assert!(storage.load_chunk(coords).await.unwrap().is_none());
+
+    let mut chunk = Chunk::default();
+    chunk.set_tile_at_offset(OffsetCoords { x: 2, y: 3 }, Tile::Rock);
+    storage.save_chunk(coords, &chunk).await.unwrap();
+
+    chunk.set_tile_at_offset(OffsetCoords { x: 2, y: 3 }, Tile::RockSmashed);
+    chunk.set_ground_gem_at(TileCoords { x: -10, y: 85 }, Gem::Diamond);
+    storage.save_chunk(coords, &chunk).await.unwrap();
+
+    let loaded_chunk = storage.load_chunk(coords).await.unwrap().unwrap();
+    assert_eq!(loaded_chunk.tile_at_offset(OffsetCoords { x: 2, y: 3 }), Tile::RockSmashed);
+    assert_eq!(
+        loaded_chunk.get_ground_gems().collect::<Vec<_>>(),
+        vec![(&TileCoords { x: -10, y: 85 }, &Gem::Diamond)]
+    );
+
+    // Players:
+
+    let client_id = crate::id::generate_random();
+    let entity_id = crate::id::generate_with_timestamp();
+    let mut entity = make_test_entity();
+
+    assert!(storage.load_player(client_id).await.unwrap().is_none());
+    assert!(matches!(storage.update_player(entity_id, &entity).await, Err(Error::PlayerNotFound(_))));
+
+    storage.create_player(client_id, entity_id, &entity).await.unwrap();
+
+    entity.pos = TileCoords { x: 13, y: -7 };
+    entity.direction = Direction::Right;
+    entity.gem_collection.increase_quantity(Gem::Ruby, 3);
+    entity.status_effects.apply(StatusEffect::Slowed);
+    storage.update_player(entity_id, &entity).await.unwrap();
+
+    assert_eq!(storage.player_gem_collections().await.unwrap().len(), 1);
+    assert_eq!(storage.player_gem_collections().await.unwrap()[0].1.get_quantity(Gem::Ruby), 3);
+
+    let (loaded_entity_id, loaded_entity) = entities::player_from_storage(client_id, storage).await.unwrap().unwrap();
+    assert_eq!(loaded_entity_id, entity_id);
+    assert_eq!(loaded_entity.pos, TileCoords { x: 13, y: -7 });
+    assert_eq!(loaded_entity.gem_collection.get_quantity(Gem::Ruby), 3);
+
+    // Transient state should not be restored:
+    assert_eq!(loaded_entity.direction, Direction::Down);
+    assert!(!loaded_entity.status_effects.is_active(StatusEffect::Slowed));
+
+    // Last seen times:
+
+    let now = current_timestamp();
+    assert!(storage.players_not_seen_since(now - 60).await.unwrap().is_empty());
+
+    storage.record_player_seen(entity_id, now - 120).await.unwrap();
+    assert_eq!(storage.players_not_seen_since(now - 60).await.unwrap(), vec![(entity_id, now - 120)]);
+
+    let unknown_id = crate::id::generate_with_timestamp();
+    assert!(matches!(storage.record_player_seen(unknown_id, now).await, Err(Error::PlayerNotFound(_))));
+
+    // Removal of players:
+
+    // Not removed as last seen after the given time:
+    assert!(!storage.remove_player_not_seen_since(entity_id, now - 180).await.unwrap());
+    assert!(storage.load_player(client_id).await.unwrap().is_some());
+
+    assert!(storage.remove_player_not_seen_since(entity_id, now - 60).await.unwrap());
+    assert!(storage.load_player(client_id).await.unwrap().is_none());
+    assert!(storage.players_not_seen_since(now).await.unwrap().is_empty());
+    assert!(matches!(storage.update_player(entity_id, &entity).await, Err(Error::PlayerNotFound(_))));
+    assert!(storage.player_gem_collections().await.unwrap().is_empty());
+
+    // Coordinates of stored chunks:
+    assert_eq!(storage.stored_chunk_coords().await.unwrap(), vec![coords]);
+
+    // Revocation of session tokens (which does not require the client to have a player entity):
+
+    assert_eq!(storage.tokens_revoked_at(client_id).await.unwrap(), None);
+    storage.revoke_tokens(client_id, now - 10).await.unwrap();
+    storage.revoke_tokens(client_id, now).await.unwrap();
+    assert_eq!(storage.tokens_revoked_at(client_id).await.unwrap(), Some(now));
+    assert_eq!(storage.tokens_revoked_at(unknown_id).await.unwrap(), None);
+
+    // Bans (the time of the original ban is kept should a client be banned again):
+
+    assert_eq!(storage.client_banned_at(client_id).await.unwrap(), None);
+    storage.ban_client(client_id, now - 10).await.unwrap();
+    storage.ban_client(client_id, now).await.unwrap();
+    assert_eq!(storage.client_banned_at(client_id).await.unwrap(), Some(now - 10));
+
+    storage.unban_client(client_id).await.unwrap();
+    storage.unban_client(client_id).await.unwrap();
+    assert_eq!(storage.client_banned_at(client_id).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn memory_storage() {
+    check_storage_backend(&MemoryStorage::default()).await;
+}
+
+#[tokio::test]
+async fn file_storage() {
+    let directory = std::env::temp_dir().join(format!("gemgame-test-{:016x}", rand::random::<u64>()));
+
+    let storage = FileStorage::open(directory.clone(), None).await.unwrap();
+    check_storage_backend(&storage).await;
+
+    // A new instance using the same directory should see the same data:
+    let reopened_storage = FileStorage::open(directory.clone(), None).await.unwrap();
+    assert_eq!(reopened_storage.load_map_seed().await.unwrap(), Some(1234));
+
+    // A named world should be stored separately:
+    let world_storage = FileStorage::open(directory.clone(), Some("other")).await.unwrap();
+    check_storage_backend(&world_storage).await;
+
+    tokio::fs::remove_dir_all(directory).await.unwrap();
+}
+
+#[tokio::test]
+async fn file_storage_legacy_chunks() {
+    let directory = std::env::temp_dir().join(format!("gemgame-test-{:016x}", rand::random::<u64>()));
+    tokio::fs::create_dir_all(directory.join("chunks")).await.unwrap();
+
+    // Chunk files in the legacy layout:
+    let legacy_data = legacy_chunk_data();
+    tokio::fs::write(directory.join("chunks").join("3_-4"), &legacy_data).await.unwrap();
+    tokio::fs::write(directory.join("chunks").join("0_0"), &legacy_data).await.unwrap();
+
+    // A chunk file already converted by a previous attempt that was interrupted before the conversion was recorded as
+    // complete:
+    let mut converted_chunk = Chunk::default();
+    converted_chunk.set_tile_at_offset(OffsetCoords { x: 1, y: 1 }, Tile::Stones);
+    tokio::fs::write(directory.join("chunks").join("5_5"), bincode::serialize(&converted_chunk).unwrap())
+        .await
+        .unwrap();
+
+    let storage = FileStorage::open(directory.clone(), None).await.unwrap();
+    let chunk = storage.load_chunk(ChunkCoords { x: 3, y: -4 }).await.unwrap().unwrap();
+
+    assert_eq!(chunk.tile_at_offset(OffsetCoords { x: 3, y: 2 }), Tile::RockRuby);
+    assert_eq!(chunk.tile_at_offset(OffsetCoords { x: 4, y: 2 }), Tile::Water);
+    assert_eq!(chunk.get_undetonated_bomb_positions().collect::<Vec<_>>(), vec![&TileCoords { x: 50, y: -60 }]);
+
+    let chunk = storage.load_chunk(ChunkCoords { x: 5, y: 5 }).await.unwrap().unwrap();
+    assert_eq!(chunk.tile_at_offset(OffsetCoords { x: 1, y: 1 }), Tile::Stones);
+
+    // The converted file should be smaller than it was and be loaded as is when reopened:
+    let converted_data = tokio::fs::read(directory.join("chunks").join("3_-4")).await.unwrap();
+    assert!(converted_data.len() < legacy_data.len());
+
+    let reopened_storage = FileStorage::open(directory.clone(), None).await.unwrap();
+    assert!(reopened_storage.load_chunk(ChunkCoords { x: 3, y: -4 }).await.unwrap().is_some());
+
+    tokio::fs::remove_dir_all(directory).await.unwrap();
+}
+
+/// Ensure that a chunk serialised in the layout used before tiles were encoded (i.e. every tile in full followed by the
+/// undetonated bombs) can be decoded.
+#[test]
+fn legacy_chunk_layout() {
+    let chunk = Chunk::from(bincode::deserialize::<LegacyChunk>(&legacy_chunk_data()).unwrap());
+
+    assert_eq!(chunk.tile_at_offset(OffsetCoords { x: 3, y: 2 }), Tile::RockRuby);
+    assert_eq!(chunk.get_undetonated_bomb_positions().count(), 1);
+    assert_eq!(chunk.get_traps().count(), 0);
+    assert_eq!(chunk.get_ground_gems().count(), 0);
+}
+
+/// Chunk data in the legacy layout (see [`LegacyChunk`]) - every tile in full (all water other than a single ruby rock)
+/// followed by the positions of the undetonated bombs placed by each entity.
+fn legacy_chunk_data() -> Vec<u8> {
+    let mut data = Vec::new();
+
+    for index in 0..CHUNK_TILE_COUNT {
+        let tile = if index == 35 { Tile::RockRuby } else { Tile::Water };
+        data.extend(bincode::serialize(&tile).unwrap());
+    }
+
+    let mut undetonated_bombs = HashMap::new();
+    undetonated_bombs.insert(crate::id::generate_random(), vec![TileCoords { x: 50, y: -60 }]);
+    data.extend(bincode::serialize(&undetonated_bombs).unwrap());
+
+    data
+}
+
+/// Ensure that a stored chunk that cannot be read is not replaced by a newly generated chunk (which would later be
+/// saved over the stored chunk).
+#[tokio::test]
+async fn unreadable_chunk_not_regenerated() {
+    let directory = std::env::temp_dir().join(format!("gemgame-test-{:016x}", rand::random::<u64>()));
+    let storage = FileStorage::open(directory.clone(), None).await.unwrap();
+
+    let coords = ChunkCoords { x: 2, y: 7 };
+    tokio::fs::write(directory.join("chunks").join("2_7"), b"not a chunk").await.unwrap();
+
+    let map = Arc::new(Mutex::new(ServerMap::new_with_default_generator(0)));
+    assert!(chunks::get_or_load_or_generate_chunk(&storage, &map, coords).await.is_err());
+    assert!(map.lock().loaded_chunk_at(coords).is_none());
+
+    tokio::fs::remove_dir_all(directory).await.unwrap();
+}
+
+#[tokio::test]
+async fn new_map_seed() {
+    let storage = MemoryStorage::default();
+    assert_eq!(ServerMap::load_or_new(&storage, Some(-42)).await.unwrap().seed(), -42);
+    assert_eq!(storage.load_map_seed().await.unwrap(), Some(-42));
+
+    // A random seed should be chosen and stored when none is specified:
+    let storage = MemoryStorage::default();
+    let seed = ServerMap::load_or_new(&storage, None).await.unwrap().seed();
+    assert_eq!(storage.load_map_seed().await.unwrap(), Some(seed));
+}
@@ -0,0 +1,245 @@
+//! Session tokens issued to clients so that they may resume playing as their pre-existing characters. A token names the
+//! client ID it was issued to along with when it was issued and when it expires, and is signed using HMAC-SHA256 with a
+//! secret known only to the server so that clients cannot forge tokens for other client IDs.
+//!
+//! A new token is issued each time a client connects (replacing the client's previous token) so a token remains valid
+//! for as long as its client keeps playing. All tokens issued to a client can be revoked (see
+//! [`crate::storage::Storage::revoke_tokens`]).
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path
+};
+
+use hmac::{Hmac, Mac, NewMac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use shared::Id;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The number of bytes in a HMAC-SHA256 tag.
+const TAG_LENGTH: usize = 32;
+
+/// The number of random bytes in a newly generated secret.
+const SECRET_LENGTH: usize = 32;
+
+/// The contents of a session token (excluding its signature).
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Claims {
+    pub client_id: Id,
+    /// The time (as a Unix timestamp in seconds) at which the token was issued.
+    pub issued_at: i64,
+    /// The time (as a Unix timestamp in seconds) after which the token is no longer accepted.
+    pub expires_at: i64
+}
+
+/// Issues and verifies session tokens.
+pub struct TokenIssuer {
+    /// The secret used to sign new tokens followed by any previous secrets that tokens are still accepted from.
+    secrets: Vec<Vec<u8>>,
+    /// The number of seconds for which a newly issued token remains valid.
+    lifetime: i64
+}
+
+impl TokenIssuer {
+    /// Create an issuer that signs tokens with the first of the given secrets (but accepts tokens signed with any of
+    /// them) and issues tokens that remain valid for the given number of seconds.
+    pub fn new(secrets: Vec<Vec<u8>>, lifetime: i64) -> Self {
+        assert!(!secrets.is_empty(), "At least one secret is required");
+        TokenIssuer { secrets, lifetime }
+    }
+
+    /// Load the secrets from the file at the given path, generating a new secret and writing it to that file should
+    /// the file not yet exist. Each line of the file is a Base64-encoded secret. The first is used to sign new tokens
+    /// while any others are only used to verify existing tokens - to rotate the secret, add a new line to the top of
+    /// the file and then remove the old line once the tokens signed with it are no longer needed.
+    pub fn load_or_generate(path: &Path, lifetime: i64) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let contents = generate_secret();
+                write_secrets_file(path, &contents)?;
+
+                log::info!("Generated new session token secret in file: {}", path.display());
+
+                contents
+            }
+            Err(e) => return Err(e.into())
+        };
+
+        let secrets = contents
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(index, line)| base64::decode(line).map_err(|_| Error::InvalidSecret(index + 1)))
+            .collect::<Result<Vec<_>>>()?;
+
+        if secrets.is_empty() {
+            Err(Error::NoSecrets)
+        }
+        else {
+            Ok(TokenIssuer::new(secrets, lifetime))
+        }
+    }
+
+    /// The number of secrets that tokens are accepted from.
+    pub fn secret_count(&self) -> usize {
+        self.secrets.len()
+    }
+
+    /// Create a token for the given client ID that is issued at the given time (as a Unix timestamp in seconds).
+    pub fn issue(&self, client_id: Id, now: i64) -> String {
+        let claims = Claims { client_id, issued_at: now, expires_at: now + self.lifetime };
+
+        let mut data = bincode::serialize(&claims).unwrap();
+        let tag = sign(&self.secrets[0], &data);
+        data.extend_from_slice(&tag);
+
+        base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Check the signature and expiry of the given token at the given time (as a Unix timestamp in seconds), returning
+    /// its claims should it be valid. Note that this does not check whether the token has been revoked.
+    pub fn verify(&self, token: &str, now: i64) -> std::result::Result<Claims, InvalidToken> {
+        let data = base64::decode_config(token, base64::URL_SAFE_NO_PAD).map_err(|_| InvalidToken::Malformed)?;
+
+        if data.len() <= TAG_LENGTH {
+            return Err(InvalidToken::Malformed);
+        }
+        let (claims_data, tag) = data.split_at(data.len() - TAG_LENGTH);
+
+        // Tags are compared in constant time so as not to reveal how much of a forged tag is correct:
+        let signed = self.secrets.iter().any(|secret| {
+            let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+            mac.update(claims_data);
+            mac.verify(tag).is_ok()
+        });
+
+        if !signed {
+            return Err(InvalidToken::BadSignature);
+        }
+
+        let claims: Claims = bincode::deserialize(claims_data).map_err(|_| InvalidToken::Malformed)?;
+
+        if now > claims.expires_at {
+            Err(InvalidToken::Expired)
+        }
+        else {
+            Ok(claims)
+        }
+    }
+}
+
+/// Produce the HMAC-SHA256 tag of the given data.
+fn sign(secret: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap(); // HMAC accepts keys of any length.
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Produce a line containing a new random Base64-encoded secret.
+fn generate_secret() -> String {
+    let mut secret = [0; SECRET_LENGTH];
+    rand::thread_rng().fill(&mut secret);
+    format!("{}\n", base64::encode(secret))
+}
+
+/// Create a new secrets file with the given contents that (on Unix) only the owner may read.
+fn write_secrets_file(path: &Path, contents: &str) -> io::Result<()> {
+    let mut open_options = fs::OpenOptions::new();
+    open_options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut open_options, 0o600);
+
+    open_options.open(path)?.write_all(contents.as_bytes())
+}
+
+/// Reasons that a session token may not be accepted.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, thiserror::Error)]
+pub enum InvalidToken {
+    #[error("Session token is malformed")]
+    Malformed,
+    #[error("Session token was not signed by this server")]
+    BadSignature,
+    #[error("Session token has expired")]
+    Expired
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to access session token secrets file - {0}")]
+    Io(#[from] io::Error),
+    #[error("Line {0} of session token secrets file is not a valid Base64-encoded secret")]
+    InvalidSecret(usize),
+    #[error("Session token secrets file contains no secrets")]
+    NoSecrets
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIFETIME: i64 = 60 * 60;
+
+    #[test]
+    fn issue_and_verify() {
+        let issuer = TokenIssuer::new(vec![b"secret".to_vec()], LIFETIME);
+        let client_id = crate::id::generate_random();
+
+        let token = issuer.issue(client_id, 1000);
+        let claims = issuer.verify(&token, 1000 + LIFETIME).unwrap();
+        assert_eq!(claims, Claims { client_id, issued_at: 1000, expires_at: 1000 + LIFETIME });
+
+        assert_eq!(issuer.verify(&token, 1001 + LIFETIME), Err(InvalidToken::Expired));
+        assert_eq!(issuer.verify("not a token", 1000), Err(InvalidToken::Malformed));
+        assert_eq!(issuer.verify(&token[..10], 1000), Err(InvalidToken::Malformed));
+
+        // Tokens signed with a different secret or that have been tampered with should be refused:
+
+        let other_issuer = TokenIssuer::new(vec![b"other secret".to_vec()], LIFETIME);
+        assert_eq!(other_issuer.verify(&token, 1000), Err(InvalidToken::BadSignature));
+
+        let mut data = base64::decode_config(&token, base64::URL_SAFE_NO_PAD).unwrap();
+        data[0] ^= 1;
+        let tampered_token = base64::encode_config(data, base64::URL_SAFE_NO_PAD);
+        assert_eq!(issuer.verify(&tampered_token, 1000), Err(InvalidToken::BadSignature));
+    }
+
+    #[test]
+    fn secret_rotation() {
+        let old_issuer = TokenIssuer::new(vec![b"old".to_vec()], LIFETIME);
+        let rotated_issuer = TokenIssuer::new(vec![b"new".to_vec(), b"old".to_vec()], LIFETIME);
+        let client_id = crate::id::generate_random();
+
+        // Tokens signed with the previous secret remain valid while new tokens are signed with the new secret:
+        assert!(rotated_issuer.verify(&old_issuer.issue(client_id, 0), 0).is_ok());
+        assert_eq!(old_issuer.verify(&rotated_issuer.issue(client_id, 0), 0), Err(InvalidToken::BadSignature));
+    }
+
+    #[test]
+    fn secrets_file() {
+        let path = std::env::temp_dir().join(format!("gemgame-test-secrets-{:016x}", rand::random::<u64>()));
+
+        // A secret should be generated and then reused when loaded again:
+        let issuer = TokenIssuer::load_or_generate(&path, LIFETIME).unwrap();
+        let token = issuer.issue(crate::id::generate_random(), 0);
+        let reloaded_issuer = TokenIssuer::load_or_generate(&path, LIFETIME).unwrap();
+        assert_eq!(reloaded_issuer.secret_count(), 1);
+        assert!(reloaded_issuer.verify(&token, 0).is_ok());
+
+        fs::write(&path, "\n").unwrap();
+        assert!(matches!(TokenIssuer::load_or_generate(&path, LIFETIME), Err(Error::NoSecrets)));
+
+        fs::write(&path, "c2VjcmV0\n%%%\n").unwrap();
+        assert!(matches!(TokenIssuer::load_or_generate(&path, LIFETIME), Err(Error::InvalidSecret(2))));
+
+        fs::remove_file(path).unwrap();
+    }
+}
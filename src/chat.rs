@@ -0,0 +1,69 @@
+//! Types and limits relating to the in-game text chat that are shared between the client and server.
+
+use serde::{Deserialize, Serialize};
+
+/// The maximum number of characters that a single chat message may contain (after being sanitised with
+/// [`sanitise`]).
+pub const MAX_MESSAGE_LENGTH: usize = 200;
+
+/// Determines which players receive a chat message.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Channel {
+    /// Received by all players whose loaded chunks contain the sender's player entity.
+    Proximity,
+    /// Received by all connected players.
+    Global
+}
+
+/// Reasons that the server may refuse to deliver a chat message.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Rejection {
+    /// The message contained no text (once sanitised).
+    Empty,
+    /// The message contained more than [`MAX_MESSAGE_LENGTH`] characters.
+    TooLong,
+    /// Too many messages were sent in a short period of time.
+    RateLimited
+}
+
+/// Replace any whitespace control characters (e.g. line breaks and tabs) in the given chat message text with spaces so
+/// that words remain separated, remove any other control characters, and trim surrounding whitespace. This is done by
+/// the client before sending a message and again by the server upon receiving one.
+pub fn sanitise(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            c if c.is_control() && c.is_whitespace() => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c)
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Check that the given (sanitised) chat message text is neither empty nor too long.
+pub fn validate(text: &str) -> Result<(), Rejection> {
+    if text.is_empty() {
+        Err(Rejection::Empty)
+    }
+    else if text.chars().count() > MAX_MESSAGE_LENGTH {
+        Err(Rejection::TooLong)
+    }
+    else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitise_and_validate() {
+        assert_eq!(sanitise("  hello\nthere\t "), "hello there");
+        assert_eq!(sanitise("tab\tand\u{7}bell"), "tab andbell");
+        assert_eq!(validate(&sanitise(" \r\n ")), Err(Rejection::Empty));
+        assert_eq!(validate(&"é".repeat(MAX_MESSAGE_LENGTH)), Ok(()));
+        assert_eq!(validate(&"a".repeat(MAX_MESSAGE_LENGTH + 1)), Err(Rejection::TooLong));
+    }
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+/// A temporary effect that can be applied to an entity (e.g. as a result of consuming an item).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StatusEffect {
+    /// Movement speed is increased by 50% (ignoring the effect of running shoes). Applied by consuming an energy
+    /// drink.
+    SpeedBoost,
+    /// Movement speed is halved. Applied by triggering a speed trap.
+    Slowed,
+    /// Unable to move at all. Applied by being caught in the outer area of a bomb's blast.
+    Stunned
+}
+
+impl StatusEffect {
+    /// The amount of time in seconds that this effect lasts for after being applied.
+    pub fn duration(&self) -> f32 {
+        match self {
+            StatusEffect::SpeedBoost => 10.0,
+            StatusEffect::Slowed => 10.0,
+            StatusEffect::Stunned => 3.0
+        }
+    }
+}
+
+impl fmt::Display for StatusEffect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatusEffect::SpeedBoost => write!(f, "speed boost"),
+            StatusEffect::Slowed => write!(f, "slowed"),
+            StatusEffect::Stunned => write!(f, "stunned")
+        }
+    }
+}
+
+/// Keeps track of which status effects are currently active and how much time remains before each of them expire.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StatusEffects {
+    /// Active effects mapped to the time (in seconds) remaining before they expire.
+    remaining_times: HashMap<StatusEffect, f32>
+}
+
+impl StatusEffects {
+    /// Apply the given effect for its full duration. If the effect is already active then its remaining time is simply
+    /// reset (i.e. effects do not stack).
+    pub fn apply(&mut self, effect: StatusEffect) {
+        self.remaining_times.insert(effect, effect.duration());
+    }
+
+    /// Immediately remove the given effect. Returns whether or not that effect was active.
+    pub fn remove(&mut self, effect: StatusEffect) -> bool {
+        self.remaining_times.remove(&effect).is_some()
+    }
+
+    pub fn is_active(&self, effect: StatusEffect) -> bool {
+        self.remaining_times.contains_key(&effect)
+    }
+
+    /// The time in seconds until the next active effect expires (or `None` should there be no active effects).
+    pub fn time_until_next_expiry(&self) -> Option<f32> {
+        self.remaining_times.values().copied().reduce(f32::min)
+    }
+
+    /// Decrease the remaining time of each active effect by the given delta time (in seconds). Any effects that have
+    /// expired as a result are removed and returned.
+    pub fn update(&mut self, delta: f32) -> Vec<StatusEffect> {
+        let mut expired = Vec::new();
+
+        self.remaining_times.retain(|effect, remaining_time| {
+            *remaining_time -= delta;
+
+            let has_expired = *remaining_time <= 0.0;
+            if has_expired {
+                expired.push(*effect);
+            }
+            !has_expired
+        });
+
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_and_expire() {
+        let mut effects = StatusEffects::default();
+        assert_eq!(effects.time_until_next_expiry(), None);
+
+        effects.apply(StatusEffect::SpeedBoost);
+        assert!(effects.is_active(StatusEffect::SpeedBoost));
+
+        assert!(effects.update(StatusEffect::SpeedBoost.duration() - 1.0).is_empty());
+        assert_eq!(effects.time_until_next_expiry(), Some(1.0));
+
+        assert_eq!(effects.update(1.0), vec![StatusEffect::SpeedBoost]);
+        assert!(!effects.is_active(StatusEffect::SpeedBoost));
+    }
+
+    #[test]
+    fn refresh_without_stacking() {
+        let mut effects = StatusEffects::default();
+
+        effects.apply(StatusEffect::SpeedBoost);
+        effects.update(4.0);
+
+        // Reapplying an active effect should reset its remaining time rather than extend it:
+        effects.apply(StatusEffect::SpeedBoost);
+        effects.apply(StatusEffect::SpeedBoost);
+        assert_eq!(effects.time_until_next_expiry(), Some(StatusEffect::SpeedBoost.duration()));
+
+        assert_eq!(effects.update(StatusEffect::SpeedBoost.duration()), vec![StatusEffect::SpeedBoost]);
+    }
+}
@@ -1,15 +1,33 @@
 use std::{collections::HashMap, fmt};
 
 use serde::{Deserialize, Serialize};
+use strum::EnumIter;
 
 /// Represents the types of gems which may be collected by players.
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, EnumIter, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Gem {
     Emerald,
     Ruby,
     Diamond
 }
 
+impl Gem {
+    /// The number of points that a single gem of this type contributes to a player's score. Each type of gem is worth
+    /// ten times the type that precedes it (e.g. 1 diamond is worth 10 rubies or 100 emeralds).
+    pub fn value(self) -> u64 {
+        match self {
+            Gem::Emerald => 1,
+            Gem::Ruby => 10,
+            Gem::Diamond => 100
+        }
+    }
+}
+
+/// The types of gem that may be found on the ground along with how likely each is to be found relative to the others.
+/// Both the gems placed on the ground when generating the map and the gems that traps are disguised as are chosen using
+/// these weights so that gems on the ground cannot be told apart from traps placed by other players.
+pub const GROUND_GEM_WEIGHTS: &[(Gem, u32)] = &[(Gem::Emerald, 20), (Gem::Ruby, 4), (Gem::Diamond, 1)];
+
 /// Represents the potential yield of gems produced from the smashing of a rock tile.
 pub struct Yield {
     pub gem: Gem,
@@ -34,6 +52,11 @@ impl Collection {
     pub fn decrease_quantity(&mut self, gem: Gem, decrease: u32) {
         *self.collection.entry(gem).or_default() -= decrease;
     }
+
+    /// The player's score as determined by the value of all the gems in this collection (see [`Gem::value`]).
+    pub fn score(&self) -> u64 {
+        self.collection.iter().map(|(gem, quantity)| gem.value() * *quantity as u64).sum()
+    }
 }
 
 impl fmt::Display for Collection {
@@ -50,3 +73,19 @@ impl fmt::Display for Collection {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collection_score() {
+        let mut collection = Collection::default();
+        assert_eq!(collection.score(), 0);
+
+        collection.increase_quantity(Gem::Emerald, 7);
+        collection.increase_quantity(Gem::Ruby, 2);
+        collection.increase_quantity(Gem::Diamond, 3);
+        assert_eq!(collection.score(), 327);
+    }
+}
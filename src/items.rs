@@ -23,13 +23,40 @@ impl Item for BoolItem {
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum QuantitativeItem {
-    Bomb
+    Bomb,
+    EnergyDrink,
+    SpeedTrap,
+    TheftTrap
 }
 
 impl Item for QuantitativeItem {
     fn get_price(&self) -> (Gem, u32) {
         match self {
-            QuantitativeItem::Bomb => (Gem::Ruby, 5)
+            QuantitativeItem::Bomb => (Gem::Ruby, 5),
+            QuantitativeItem::EnergyDrink => (Gem::Emerald, 10),
+            QuantitativeItem::SpeedTrap => (Gem::Diamond, 2),
+            QuantitativeItem::TheftTrap => (Gem::Diamond, 5)
+        }
+    }
+}
+
+/// The types of trap that a player can place. Traps appear as gems on the ground to all players except the one who
+/// placed them and can only be triggered once.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Trap {
+    /// Halves the movement speed of the player who triggers it for a period of time (see
+    /// [`crate::effects::StatusEffect::Slowed`]).
+    Speed,
+    /// Takes 25% of the emeralds held by the player who triggers it and gives them to the player who placed it.
+    Theft
+}
+
+impl Trap {
+    /// The item that is taken from a player's inventory when they place this type of trap.
+    pub fn as_item(&self) -> QuantitativeItem {
+        match self {
+            Trap::Speed => QuantitativeItem::SpeedTrap,
+            Trap::Theft => QuantitativeItem::TheftTrap
         }
     }
 }
@@ -1,3 +1,5 @@
+pub mod chat;
+pub mod effects;
 pub mod gems;
 pub mod id;
 pub mod items;
@@ -22,6 +22,12 @@ impl TileCoords {
         }
     }
 
+    /// The number of tiles between these coordinates and the given coordinates, where diagonally adjacent tiles are
+    /// considered to be 1 tile apart (i.e. the Chebyshev distance).
+    pub fn distance_to(&self, other: TileCoords) -> i32 {
+        cmp::max((self.x - other.x).abs(), (self.y - other.y).abs())
+    }
+
     /// Identify the offset from its containing chunk that the specified tile would be found at.
     pub fn as_chunk_offset_coords(&self) -> OffsetCoords {
         let offset_x = self.x % CHUNK_WIDTH;
@@ -99,4 +105,14 @@ mod tests {
             assert_eq!(tile.as_chunk_offset_coords(), *offset);
         }
     }
+
+    #[test]
+    fn tile_coords_distance() {
+        let origin = TileCoords { x: 0, y: 0 };
+
+        assert_eq!(origin.distance_to(origin), 0);
+        assert_eq!(origin.distance_to(TileCoords { x: 1, y: -1 }), 1);
+        assert_eq!(origin.distance_to(TileCoords { x: -3, y: 2 }), 3);
+        assert_eq!(TileCoords { x: -3, y: 2 }.distance_to(origin), 3);
+    }
 }
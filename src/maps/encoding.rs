@@ -0,0 +1,319 @@
+//! Compact encoding of the tiles of a chunk, used both when chunks are sent to clients and when chunks are stored.
+//!
+//! Each distinct tile of a chunk is listed once in a palette after which every tile of the chunk is given as an index
+//! into that palette. The indices are laid out either as runs of the same index or packed into as few bits as the size
+//! of the palette allows (whichever is smaller). Everything following the first byte is then compressed with DEFLATE
+//! should that make it smaller still:
+//!
+//! * 1 byte - The layout of the indices (`RUN_LENGTH` or `BIT_PACKED`), combined with `COMPRESSED` should the rest of
+//!   the data be compressed.
+//! * 1 byte - The number of tiles in the palette.
+//! * 1 byte per palette entry - The index of that entry's [`Tile`] variant.
+//! * Run-length layout - Pairs of bytes each giving the length of a run (minus 1) followed by the palette index
+//!   repeated throughout that run.
+//! * Bit-packed layout - [`CHUNK_TILE_COUNT`] palette indices, each of the fewest bits able to represent every index of
+//!   the palette, least significant bit first. A palette of a single tile therefore requires no bytes at all.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use super::{Tile, CHUNK_TILE_COUNT};
+
+const RUN_LENGTH: u8 = 0;
+const BIT_PACKED: u8 = 1;
+const COMPRESSED: u8 = 0b1000_0000;
+
+/// DEFLATE compression level (from 0 to 10) used when encoding.
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// The size of the largest possible (uncompressed) palette and indices, beyond which decompression is abandoned.
+const MAX_UNCOMPRESSED_SIZE: usize = 1 + u8::MAX as usize + 2 * CHUNK_TILE_COUNT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    Decompression,
+    UnknownLayout(u8),
+    UnknownTile(u8),
+    InvalidPaletteIndex(u8),
+    WrongTileCount
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "encoded tiles are truncated"),
+            DecodeError::Decompression => write!(f, "failed to decompress encoded tiles"),
+            DecodeError::UnknownLayout(layout) => write!(f, "unknown layout {} of encoded tiles", layout),
+            DecodeError::UnknownTile(index) => write!(f, "palette contains unknown tile {}", index),
+            DecodeError::InvalidPaletteIndex(index) => write!(f, "palette index {} is out of range", index),
+            DecodeError::WrongTileCount => write!(f, "encoded tiles do not number exactly {}", CHUNK_TILE_COUNT)
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Encode the given tiles of a chunk.
+pub fn encode(tiles: &[Tile; CHUNK_TILE_COUNT]) -> Vec<u8> {
+    let mut palette: Vec<Tile> = Vec::new();
+
+    let indices: Vec<u8> = tiles
+        .iter()
+        .map(|tile| match palette.iter().position(|palette_tile| palette_tile == tile) {
+            Some(index) => index as u8,
+            None => {
+                palette.push(*tile);
+                (palette.len() - 1) as u8
+            }
+        })
+        .collect();
+
+    let runs = encode_runs(&indices);
+    let packed = pack_bits(&indices, bits_per_index(palette.len()));
+
+    let (layout, indices_data) = if runs.len() < packed.len() { (RUN_LENGTH, runs) } else { (BIT_PACKED, packed) };
+
+    let mut uncompressed = Vec::with_capacity(1 + palette.len() + indices_data.len());
+    uncompressed.push(palette.len() as u8);
+    uncompressed.extend(palette.iter().map(|tile| *tile as u8));
+    uncompressed.extend(indices_data);
+
+    let compressed = miniz_oxide::deflate::compress_to_vec(&uncompressed, COMPRESSION_LEVEL);
+
+    let (header, body) =
+        if compressed.len() < uncompressed.len() { (layout | COMPRESSED, compressed) } else { (layout, uncompressed) };
+
+    let mut encoded = Vec::with_capacity(1 + body.len());
+    encoded.push(header);
+    encoded.extend(body);
+    encoded
+}
+
+/// Decode tiles encoded by [`encode`].
+pub fn decode(data: &[u8]) -> Result<[Tile; CHUNK_TILE_COUNT], DecodeError> {
+    let (header, rest) = data.split_first().ok_or(DecodeError::Truncated)?;
+
+    let decompressed;
+    let body = if header & COMPRESSED != 0 {
+        decompressed = miniz_oxide::inflate::decompress_to_vec_with_limit(rest, MAX_UNCOMPRESSED_SIZE)
+            .map_err(|_| DecodeError::Decompression)?;
+        &decompressed[..]
+    }
+    else {
+        rest
+    };
+
+    let (palette_len, rest) = body.split_first().ok_or(DecodeError::Truncated)?;
+    if rest.len() < *palette_len as usize {
+        return Err(DecodeError::Truncated);
+    }
+    let (palette_data, indices_data) = rest.split_at(*palette_len as usize);
+
+    let palette = palette_data
+        .iter()
+        .map(|index| Tile::iter().nth(*index as usize).ok_or(DecodeError::UnknownTile(*index)))
+        .collect::<Result<Vec<Tile>, _>>()?;
+    let palette_tile = |index: u8| palette.get(index as usize).copied().ok_or(DecodeError::InvalidPaletteIndex(index));
+
+    let mut tiles = [Tile::default(); CHUNK_TILE_COUNT];
+
+    match header & !COMPRESSED {
+        RUN_LENGTH => {
+            if indices_data.len() % 2 != 0 {
+                return Err(DecodeError::Truncated);
+            }
+
+            let mut position = 0;
+
+            for run in indices_data.chunks(2) {
+                let length = run[0] as usize + 1;
+                let tile = palette_tile(run[1])?;
+
+                let run_tiles = tiles.get_mut(position..position + length).ok_or(DecodeError::WrongTileCount)?;
+                run_tiles.iter_mut().for_each(|run_tile| *run_tile = tile);
+                position += length;
+            }
+
+            if position != CHUNK_TILE_COUNT {
+                return Err(DecodeError::WrongTileCount);
+            }
+        }
+
+        BIT_PACKED => {
+            let bits = bits_per_index(palette.len());
+
+            if indices_data.len() != packed_len(bits) {
+                return Err(DecodeError::WrongTileCount);
+            }
+
+            for (i, tile) in tiles.iter_mut().enumerate() {
+                let mut index = 0;
+
+                for bit in 0..bits {
+                    let position = i * bits + bit;
+                    if (indices_data[position / 8] >> (position % 8)) & 1 == 1 {
+                        index |= 1 << bit;
+                    }
+                }
+
+                *tile = palette_tile(index)?;
+            }
+        }
+
+        layout => return Err(DecodeError::UnknownLayout(layout))
+    }
+
+    Ok(tiles)
+}
+
+/// For use with `#[serde(with = "encoding::encoded_tiles")]` so that the tiles of a chunk are (de)serialised in their
+/// encoded form.
+pub(crate) mod encoded_tiles {
+    use serde::{de::Error, Deserializer, Serializer};
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(tiles: &[Tile; CHUNK_TILE_COUNT], serializer: S) -> Result<S::Ok, S::Error> {
+        encode(tiles).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[Tile; CHUNK_TILE_COUNT], D::Error> {
+        decode(&Vec::<u8>::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+/// The number of bits required for each index into a palette of the given size.
+fn bits_per_index(palette_len: usize) -> usize {
+    let mut bits = 0;
+    while (1 << bits) < palette_len {
+        bits += 1;
+    }
+    bits
+}
+
+/// The number of bytes taken by [`CHUNK_TILE_COUNT`] (a multiple of 8) indices each of the given number of bits.
+fn packed_len(bits: usize) -> usize {
+    CHUNK_TILE_COUNT / 8 * bits
+}
+
+fn encode_runs(indices: &[u8]) -> Vec<u8> {
+    let mut runs = Vec::new();
+    let mut position = 0;
+
+    while position < indices.len() {
+        let index = indices[position];
+        let length = indices[position..].iter().take(256).take_while(|other| **other == index).count();
+
+        runs.push((length - 1) as u8);
+        runs.push(index);
+        position += length;
+    }
+
+    runs
+}
+
+fn pack_bits(indices: &[u8], bits: usize) -> Vec<u8> {
+    let mut packed = vec![0; packed_len(bits)];
+
+    for (i, index) in indices.iter().enumerate() {
+        for bit in 0..bits {
+            if (index >> bit) & 1 == 1 {
+                let position = i * bits + bit;
+                packed[position / 8] |= 1 << (position % 8);
+            }
+        }
+    }
+
+    packed
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn any_tile() -> impl Strategy<Value = Tile> {
+        (0..Tile::iter().count()).prop_map(|index| Tile::iter().nth(index).unwrap())
+    }
+
+    /// Tiles made up of runs of the same tile (repeated to fill the chunk) so that chunks with small palettes and long
+    /// runs (like generated terrain) are produced as well as noisy chunks.
+    fn any_tiles() -> impl Strategy<Value = [Tile; CHUNK_TILE_COUNT]> {
+        prop::collection::vec((any_tile(), 1..48usize), 1..CHUNK_TILE_COUNT).prop_map(|runs| {
+            let mut expanded = Vec::new();
+            for (tile, length) in runs {
+                expanded.resize(expanded.len() + length, tile);
+            }
+
+            let mut tiles = [Tile::default(); CHUNK_TILE_COUNT];
+            for (i, tile) in tiles.iter_mut().enumerate() {
+                *tile = expanded[i % expanded.len()];
+            }
+            tiles
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(tiles in any_tiles()) {
+            prop_assert_eq!(&decode(&encode(&tiles)).unwrap()[..], &tiles[..]);
+        }
+
+        #[test]
+        fn round_trip_noise(tiles in prop::collection::vec(any_tile(), CHUNK_TILE_COUNT)) {
+            let mut array = [Tile::default(); CHUNK_TILE_COUNT];
+            array.copy_from_slice(&tiles);
+
+            prop_assert_eq!(&decode(&encode(&array)).unwrap()[..], &tiles[..]);
+        }
+
+        #[test]
+        fn never_larger_than_bit_packed(tiles in any_tiles()) {
+            let palette_len = tiles.iter().fold(Vec::new(), |mut palette, tile| {
+                if !palette.contains(tile) {
+                    palette.push(*tile);
+                }
+                palette
+            }).len();
+
+            prop_assert!(encode(&tiles).len() <= 2 + palette_len + packed_len(bits_per_index(palette_len)));
+        }
+
+        #[test]
+        fn arbitrary_data_does_not_panic(data in prop::collection::vec(any::<u8>(), 0..600)) {
+            let _ = decode(&data);
+        }
+    }
+
+    #[test]
+    fn layouts() {
+        // A single tile requires no indices:
+        let uniform = [Tile::Water; CHUNK_TILE_COUNT];
+        assert_eq!(encode(&uniform), vec![BIT_PACKED, 1, Tile::Water as u8]);
+
+        // A few long runs are smallest run-length encoded:
+        let mut halves = [Tile::Grass; CHUNK_TILE_COUNT];
+        halves[CHUNK_TILE_COUNT / 2..].iter_mut().for_each(|tile| *tile = Tile::Dirt);
+        assert_eq!(encode(&halves), vec![RUN_LENGTH, 2, Tile::Grass as u8, Tile::Dirt as u8, 127, 0, 127, 1]);
+
+        // Alternating tiles are smallest bit-packed and then compressed:
+        let mut alternating = [Tile::Grass; CHUNK_TILE_COUNT];
+        alternating.iter_mut().step_by(2).for_each(|tile| *tile = Tile::Rock);
+        assert_eq!(encode(&alternating)[0], BIT_PACKED | COMPRESSED);
+    }
+
+    #[test]
+    fn invalid_data() {
+        assert_eq!(decode(&[]), Err(DecodeError::Truncated));
+        assert_eq!(decode(&[7, 1, 0]), Err(DecodeError::UnknownLayout(7)));
+        assert_eq!(decode(&[BIT_PACKED, 1, 200]), Err(DecodeError::UnknownTile(200)));
+        assert_eq!(decode(&[RUN_LENGTH, 1, 0, 255, 1]), Err(DecodeError::InvalidPaletteIndex(1)));
+        assert_eq!(decode(&[RUN_LENGTH, 1, 0, 255, 0, 0, 0]), Err(DecodeError::WrongTileCount));
+        assert_eq!(decode(&[RUN_LENGTH, 1, 0, 254, 0]), Err(DecodeError::WrongTileCount));
+        assert_eq!(decode(&[BIT_PACKED | COMPRESSED, 1, 2, 3]), Err(DecodeError::Decompression));
+    }
+}
@@ -1,10 +1,11 @@
 use std::{collections::HashMap, fmt};
 
 use serde::{Deserialize, Serialize};
-use strum::EnumIter;
+use strum::{EnumIter, IntoEnumIterator};
 
 use super::{Tile, TileCoords};
 use crate::{
+    effects::{StatusEffect, StatusEffects},
     gems,
     items::{self, BoolItem},
     Id
@@ -15,6 +16,9 @@ pub type Entities = HashMap<Id, Entity>;
 
 const STANDARD_MOVEMENT_TIME: f32 = 0.13;
 const RUNNING_MOVEMENT_TIME: f32 = STANDARD_MOVEMENT_TIME * 0.75;
+const SPEED_BOOST_MOVEMENT_TIME: f32 = STANDARD_MOVEMENT_TIME / 1.5;
+
+const SLOWED_MOVEMENT_TIME_MODIFIER: f32 = 2.0;
 
 const SMASHABLE_TILE_MOVEMENT_TIME_MODIFIER: f32 = 2.5;
 const GRASSY_TILE_MOVEMENT_TIME_MODIFIER: f32 = 0.8;
@@ -43,19 +47,31 @@ pub struct Entity {
     /// Stores items that this entity has.
     pub item_inventory: items::Inventory,
     /// Number of bombs the entity has placed (excluding detonated bombs).
-    pub bombs_placed_count: i32
+    pub bombs_placed_count: i32,
+    /// Temporary effects (e.g. speed boost from an energy drink) currently applied to this entity.
+    pub status_effects: StatusEffects
 }
 
 impl Entity {
     /// The amount of time in seconds taken for the entity to move to an adjacent tile.
     pub fn movement_time(&self, tile_at_destination: Tile) -> f32 {
-        let base_time = if self.item_inventory.has(BoolItem::RunningShoes) {
+        let base_time = if self.status_effects.is_active(StatusEffect::SpeedBoost) {
+            SPEED_BOOST_MOVEMENT_TIME // Speed boost ignores the effect of running shoes.
+        }
+        else if self.item_inventory.has(BoolItem::RunningShoes) {
             RUNNING_MOVEMENT_TIME
         }
         else {
             STANDARD_MOVEMENT_TIME
         };
 
+        let base_time = if self.status_effects.is_active(StatusEffect::Slowed) {
+            base_time * SLOWED_MOVEMENT_TIME_MODIFIER
+        }
+        else {
+            base_time
+        };
+
         if tile_at_destination.is_smashable() {
             base_time * SMASHABLE_TILE_MOVEMENT_TIME_MODIFIER
         }
@@ -67,11 +83,27 @@ impl Entity {
         }
     }
 
+    /// Whether or not this entity is currently able to move at all (i.e. is not stunned).
+    pub fn can_move(&self) -> bool {
+        !self.status_effects.is_active(StatusEffect::Stunned)
+    }
+
     /// Modify entity position without performing any sort of checks.
     pub fn move_towards_unchecked(&mut self, direction: Direction) {
         let new_pos = direction.apply(self.pos);
         self.pos = new_pos;
     }
+
+    /// The customisable aspects of this entity's look.
+    pub fn appearance(&self) -> Appearance {
+        Appearance { hair_style: self.hair_style, hair_colour: self.hair_colour, skin_colour: self.skin_colour }
+    }
+
+    pub fn set_appearance(&mut self, appearance: Appearance) {
+        self.hair_style = appearance.hair_style;
+        self.hair_colour = appearance.hair_colour;
+        self.skin_colour = appearance.skin_colour;
+    }
 }
 
 impl fmt::Display for Entity {
@@ -89,6 +121,38 @@ impl fmt::Display for Entity {
     }
 }
 
+/// The aspects of an entity's look that its player may change (see [`Entity::appearance`]).
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Appearance {
+    pub hair_style: HairStyle,
+    pub hair_colour: HairColour,
+    pub skin_colour: SkinColour
+}
+
+impl fmt::Display for Appearance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} hair style, {:?} hair colour and {:?} skin colour",
+            self.hair_style, self.hair_colour, self.skin_colour
+        )
+    }
+}
+
+/// Returns the variant following the given one (or preceding it should `forwards` be false), wrapping around at either
+/// end. Used to cycle through the options available when customising an entity's appearance.
+pub fn cycle_variant<T: IntoEnumIterator + PartialEq + Copy>(variant: T, forwards: bool) -> T {
+    let variants: Vec<T> = T::iter().collect();
+    let index = variants.iter().position(|v| *v == variant).unwrap_or(0);
+
+    if forwards {
+        variants[(index + 1) % variants.len()]
+    }
+    else {
+        variants[(index + variants.len() - 1) % variants.len()]
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Direction {
     Up,
@@ -187,3 +251,16 @@ pub enum HairColour {
     Green,
     Blue
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_variants() {
+        assert_eq!(cycle_variant(HairStyle::Quiff, true), HairStyle::Mohawk);
+        assert_eq!(cycle_variant(HairStyle::Fringe, true), HairStyle::Quiff);
+        assert_eq!(cycle_variant(HairStyle::Quiff, false), HairStyle::Fringe);
+        assert_eq!(cycle_variant(SkinColour::Pale, false), SkinColour::Brown);
+    }
+}
@@ -1,15 +1,18 @@
 pub mod coords;
+pub mod encoding;
 pub mod entities;
 
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
 
 pub use coords::*;
 use entities::Entity;
 use serde::{Deserialize, Serialize};
 use serde_big_array::big_array;
+use strum::EnumIter;
 
 use crate::{
     gems::{self, Gem},
+    items::Trap,
     Id
 };
 
@@ -54,6 +57,60 @@ pub trait Map {
         }
     }
 
+    /// Place a trap at the specified tile coordinates assuming it is in a chunk that is already loaded and that there
+    /// is neither a trap nor a gem on the ground already at that position.
+    fn set_trap_at(&mut self, pos: TileCoords, placed_trap: PlacedTrap) -> bool {
+        if self.ground_gem_at(pos).is_some() {
+            return false;
+        }
+
+        if let Some(chunk) = self.loaded_chunk_at_mut(pos.as_chunk_coords()) {
+            if let Entry::Vacant(entry) = chunk.traps.entry(pos) {
+                entry.insert(placed_trap);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Fetch the trap at the given tile coordinates (if any) assuming it is in a chunk that is already loaded.
+    fn trap_at(&self, pos: TileCoords) -> Option<&PlacedTrap> {
+        self.loaded_chunk_at(pos.as_chunk_coords())?.traps.get(&pos)
+    }
+
+    /// Takes (i.e. removes and returns) the trap at the specified tile coordinates (if any).
+    fn take_trap_at(&mut self, pos: TileCoords) -> Option<PlacedTrap> {
+        self.loaded_chunk_at_mut(pos.as_chunk_coords())?.traps.remove(&pos)
+    }
+
+    /// Place a gem on the ground at the specified tile coordinates assuming it is in a chunk that is already loaded and
+    /// that there is no trap at that position (a trap and a gem on the ground sharing a position could otherwise be
+    /// told apart).
+    fn set_ground_gem_at(&mut self, pos: TileCoords, gem: Gem) -> bool {
+        if self.trap_at(pos).is_some() {
+            return false;
+        }
+
+        if let Some(chunk) = self.loaded_chunk_at_mut(pos.as_chunk_coords()) {
+            chunk.set_ground_gem_at(pos, gem);
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// Fetch the gem on the ground at the given tile coordinates (if any) assuming it is in a chunk that is already
+    /// loaded.
+    fn ground_gem_at(&self, pos: TileCoords) -> Option<&Gem> {
+        self.loaded_chunk_at(pos.as_chunk_coords())?.ground_gems.get(&pos)
+    }
+
+    /// Takes (i.e. removes and returns) the gem on the ground at the specified tile coordinates (if any).
+    fn take_ground_gem_at(&mut self, pos: TileCoords) -> Option<Gem> {
+        self.loaded_chunk_at_mut(pos.as_chunk_coords())?.ground_gems.remove(&pos)
+    }
+
     fn is_tile_loaded(&self, coords: TileCoords) -> bool {
         self.loaded_chunk_at(coords.as_chunk_coords()).is_some()
     }
@@ -115,6 +172,21 @@ pub trait Map {
 
         positions
     }
+
+    /// Takes (i.e. removes) the bombs placed by the entity with the given ID at the specified positions. Positions in
+    /// chunks that are not loaded or without a bomb placed by that entity are ignored. Returns the positions at which
+    /// bombs were actually removed.
+    fn take_bombs_placed_by_at(&mut self, placed_by: Id, positions: &[TileCoords]) -> Vec<TileCoords> {
+        positions
+            .iter()
+            .copied()
+            .filter(|pos| {
+                self.loaded_chunk_at_mut(pos.as_chunk_coords())
+                    .map(|chunk| chunk.take_bomb_placed_by_at(placed_by, *pos))
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
 }
 
 /// Type alias for a hash map that maps chunk coordinates to chunks.
@@ -123,12 +195,16 @@ pub type Chunks = HashMap<ChunkCoords, Chunk>;
 /// Area of tiles on a map. As maps are infinite, chunks are generated, loaded, and unloaded dynamically as necessary.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Chunk {
-    /// The tiles that this chunk is comprised of.
-    #[serde(with = "BigArray")]
+    /// The tiles that this chunk is comprised of (serialised in the compact form described in [`encoding`]).
+    #[serde(with = "encoding::encoded_tiles")]
     tiles: [Tile; CHUNK_TILE_COUNT],
     /// Bombs placed in this chunk - sets of bomb positions are mapped to by the ID of the entity that placed those
     /// bombs.
-    undetonated_bombs: HashMap<Id, Vec<TileCoords>>
+    undetonated_bombs: HashMap<Id, Vec<TileCoords>>,
+    /// Traps placed in this chunk mapped to by their positions.
+    traps: HashMap<TileCoords, PlacedTrap>,
+    /// Gems lying on the ground in this chunk mapped to by their positions.
+    ground_gems: HashMap<TileCoords, Gem>
 }
 
 impl Chunk {
@@ -147,15 +223,96 @@ impl Chunk {
     pub fn take_bombs_placed_by(&mut self, placed_by: Id) -> Vec<TileCoords> {
         self.undetonated_bombs.remove(&placed_by).unwrap_or_default()
     }
+
+    /// Remove the bomb placed by the entity with the given ID at the specified position. Returns whether or not there
+    /// was such a bomb to remove.
+    pub fn take_bomb_placed_by_at(&mut self, placed_by: Id, pos: TileCoords) -> bool {
+        if let Some(positions) = self.undetonated_bombs.get_mut(&placed_by) {
+            if let Some(index) = positions.iter().position(|bomb_pos| *bomb_pos == pos) {
+                positions.swap_remove(index);
+
+                if positions.is_empty() {
+                    self.undetonated_bombs.remove(&placed_by);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn get_traps(&self) -> impl Iterator<Item = (&TileCoords, &PlacedTrap)> {
+        self.traps.iter()
+    }
+
+    pub fn get_ground_gems(&self) -> impl Iterator<Item = (&TileCoords, &Gem)> {
+        self.ground_gems.iter()
+    }
+
+    /// Place a gem on the ground at the given position (which should be within this chunk).
+    pub fn set_ground_gem_at(&mut self, pos: TileCoords, gem: Gem) {
+        self.ground_gems.insert(pos, gem);
+    }
+
+    /// Produces a copy of this chunk as it should appear to the player with the given entity ID. Traps placed by any
+    /// other player are disguised as gems on the ground so that the viewing player cannot tell them apart.
+    pub fn as_seen_by(&self, viewer_entity_id: Id) -> Chunk {
+        let mut chunk = self.clone();
+
+        chunk.traps.retain(|_, placed_trap| placed_trap.placed_by == viewer_entity_id);
+        chunk.ground_gems.extend(
+            self.traps
+                .iter()
+                .filter(|(_, placed_trap)| placed_trap.placed_by != viewer_entity_id)
+                .map(|(pos, placed_trap)| (*pos, placed_trap.disguise))
+        );
+
+        chunk
+    }
 }
 
 impl Default for Chunk {
     fn default() -> Self {
-        Chunk { tiles: [Tile::default(); CHUNK_TILE_COUNT], undetonated_bombs: HashMap::new() }
+        Chunk {
+            tiles: [Tile::default(); CHUNK_TILE_COUNT],
+            undetonated_bombs: HashMap::new(),
+            traps: HashMap::new(),
+            ground_gems: HashMap::new()
+        }
+    }
+}
+
+/// The layout in which chunks were serialised before their tiles were encoded (see [`encoding`]), with each tile in
+/// full. Chunks in this layout predate traps and gems on the ground so have neither. Only kept so that chunks stored in
+/// this layout can still be loaded.
+#[derive(Deserialize)]
+pub struct LegacyChunk {
+    #[serde(with = "BigArray")]
+    tiles: [Tile; CHUNK_TILE_COUNT],
+    undetonated_bombs: HashMap<Id, Vec<TileCoords>>
+}
+
+impl From<LegacyChunk> for Chunk {
+    fn from(legacy: LegacyChunk) -> Self {
+        Chunk {
+            tiles: legacy.tiles,
+            undetonated_bombs: legacy.undetonated_bombs,
+            traps: HashMap::new(),
+            ground_gems: HashMap::new()
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// A trap that has been placed on the map by a player.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PlacedTrap {
+    pub trap: Trap,
+    /// The ID of the entity that placed this trap.
+    pub placed_by: Id,
+    /// The type of gem that this trap appears as to players other than the one that placed it.
+    pub disguise: Gem
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, EnumIter)]
 pub enum Tile {
     Grass,
     FlowerPatch,
@@ -215,6 +372,20 @@ impl Tile {
         matches!(self, Tile::Rock | Tile::RockEmerald | Tile::RockRuby | Tile::RockDiamond)
     }
 
+    /// Returns the tile that this tile should become when caught in the blast of a bomb (or `None` should this tile
+    /// not be destroyed by bomb blasts). Rocks are smashed while shrubs and stones are cleared to grass.
+    pub fn destroyed_by_blast(&self) -> Option<Tile> {
+        if self.is_smashable() {
+            Some(Tile::RockSmashed)
+        }
+        else if matches!(self, Tile::Shrub | Tile::Stones) {
+            Some(Tile::Grass)
+        }
+        else {
+            None
+        }
+    }
+
     pub fn is_grassy(&self) -> bool {
         matches!(self, Tile::Grass | Tile::FlowerPatch | Tile::FlowerBlue | Tile::FlowersYellowOrange)
     }
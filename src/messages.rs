@@ -3,9 +3,10 @@
 use std::fmt;
 
 use serde::{Deserialize, Serialize};
+use strum::{EnumVariantNames, IntoStaticStr};
 
 use crate::{
-    gems, items,
+    chat, effects, gems, items,
     maps::{
         self,
         entities::{self, Entity}
@@ -13,16 +14,47 @@ use crate::{
     Id
 };
 
-/// Message sent from the client to the server over the WebSocket protocol.
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// Version of the protocol (i.e. the messages below and how they are encoded) spoken by this build. Must be incremented
+/// whenever a change is made that is not understood by builds speaking an earlier version. This is independent of
+/// [`crate::VERSION`] so that builds differing only in changes that do not affect the protocol (e.g. patch releases)
+/// are able to interoperate.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// The earliest protocol version spoken by clients that the server accepts. Should only be less than
+/// [`PROTOCOL_VERSION`] while the server still understands (and only sends messages understood by) clients speaking
+/// those earlier versions.
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = 3;
+
+/// Check whether a client speaking the given protocol version is supported by this build of the server.
+pub fn check_protocol_version(client_protocol_version: u32) -> Result<(), ProtocolRejection> {
+    if client_protocol_version < MIN_SUPPORTED_PROTOCOL_VERSION {
+        Err(ProtocolRejection::ClientTooOld)
+    }
+    else if client_protocol_version > PROTOCOL_VERSION {
+        Err(ProtocolRejection::ClientTooNew)
+    }
+    else {
+        Ok(())
+    }
+}
+
+/// Message sent from the client to the server over the WebSocket protocol. Converts into the name of its variant (the
+/// names of all variants are listed by `ToServer::VARIANTS`).
+#[derive(Serialize, Deserialize, IntoStaticStr, EnumVariantNames, Debug, PartialEq)]
 pub enum ToServer {
     /// This must be the first message sent by a client to the server after establishing a WebSocket connection.
+    ///
+    /// This must remain the first variant and its `protocol_version` field must remain its first field so that any
+    /// version of the server is able to determine the protocol version spoken by any version of the client.
     Hello {
-        /// If this player has played before then a client ID value will be sent so that they may continue playing as
+        /// The protocol version spoken by the client (see [`PROTOCOL_VERSION`]). Checked by the server before anything
+        /// else.
+        protocol_version: u32,
+        /// If this player has played before then their credentials will be sent so that they may continue playing as
         /// their pre-existing character. If this player has never played before (or have cleared their browser
         /// cookies) then this field should be `None` (but note that a 'hello' message must still be the first
         /// message sent by the client).
-        client_id_option: Option<Id>
+        credentials_option: Option<Credentials>
     },
 
     /// Inform the server that the player has moved their player entity. The server will respond with a
@@ -50,15 +82,59 @@ pub enum ToServer {
     /// Inform the server that the player wishes the purchase the specified quantity of the given item (of type
     /// [`items::QuantitativeItem`]). The server will ignore the message if the player does have enough gems to
     /// complete the purchase.
-    PurchaseItemQuantity { item: items::QuantitativeItem, quantity: u32 }
+    PurchaseItemQuantity { item: items::QuantitativeItem, quantity: u32 },
+
+    /// Consume one of the player's energy drinks in order to apply the [`effects::StatusEffect::SpeedBoost`] effect
+    /// to their player entity. The server will respond with a [`FromServer::StatusEffectStarted`] message provided
+    /// that the player actually has an energy drink to consume.
+    ConsumeEnergyDrink,
+
+    /// Attempt to place a trap of the given type at the player entity's position. The trap will appear to other
+    /// players as a gem of the specified type on the ground. The client is expected to ensure that the player
+    /// actually has a trap of that type to place before sending this message.
+    PlaceTrap { trap: items::Trap, disguise: gems::Gem },
+
+    /// Send a chat message to other players on the given channel. The text should be sanitised (see
+    /// [`chat::sanitise`]) and no longer than [`chat::MAX_MESSAGE_LENGTH`] characters. The server responds with either
+    /// a [`FromServer::ChatMessage`] message echoing the (possibly filtered) text or a
+    /// [`FromServer::ChatMessageRejected`] message.
+    SendChatMessage { channel: chat::Channel, text: String },
+
+    /// Change the facial expression of the player's entity. The expression reverts to neutral after a period of time
+    /// (or immediately should the given expression be neutral). The server responds with a
+    /// [`FromServer::FacialExpressionChanged`] message.
+    SetFacialExpression(entities::FacialExpression),
+
+    /// Briefly show the given facial expression on the player's entity (i.e. a short-lived emote) before it reverts
+    /// to neutral. The server responds with a [`FromServer::FacialExpressionChanged`] message.
+    PlayEmote(entities::FacialExpression),
+
+    /// Change the hair style, hair colour and skin colour of the player's entity. The server responds with a
+    /// [`FromServer::AppearanceChanged`] message containing the player entity's appearance (which will be unchanged
+    /// should the request have been refused).
+    ChangeAppearance(entities::Appearance),
+
+    /// Begin (should the value be true) or stop receiving the leaderboard. While watching, the server sends a
+    /// [`FromServer::Leaderboard`] message straight away and again whenever the leaderboard or the player's rank
+    /// changes.
+    WatchLeaderboard(bool)
 }
 
 impl fmt::Display for ToServer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ToServer::Hello { client_id_option } => match client_id_option {
-                Some(id) => write!(f, "hello as existing client {}", id),
-                None => write!(f, "hello as new client")
+            ToServer::Hello { protocol_version, credentials_option } => match credentials_option {
+                Some(Credentials::SessionToken(_)) => {
+                    write!(f, "hello as existing client with session token (protocol version {})", protocol_version)
+                }
+                Some(Credentials::LegacyClientId(id)) => {
+                    write!(
+                        f,
+                        "hello as existing client {} (legacy client ID, protocol version {})",
+                        id, protocol_version
+                    )
+                }
+                None => write!(f, "hello as new client (protocol version {})", protocol_version)
             },
             ToServer::MoveMyEntity { request_number, direction } => {
                 write!(f, "move my player entity {} (request #{})", direction, request_number)
@@ -66,28 +142,55 @@ impl fmt::Display for ToServer {
             ToServer::PlaceBomb => write!(f, "place bomb"),
             ToServer::DetonateBombs => write!(f, "detonate bombs"),
             ToServer::PurchaseSingleItem(item) => write!(f, "purchase {:?}", item),
-            ToServer::PurchaseItemQuantity { item, quantity } => write!(f, "purchase {} of {:?}", quantity, item)
+            ToServer::PurchaseItemQuantity { item, quantity } => write!(f, "purchase {} of {:?}", quantity, item),
+            ToServer::ConsumeEnergyDrink => write!(f, "consume energy drink"),
+            ToServer::PlaceTrap { trap, disguise } => write!(f, "place {:?} trap disguised as {:?}", trap, disguise),
+            ToServer::SendChatMessage { channel, text } => write!(f, "send {:?} chat message '{}'", channel, text),
+            ToServer::SetFacialExpression(expression) => write!(f, "set facial expression to {}", expression),
+            ToServer::PlayEmote(expression) => write!(f, "play emote {}", expression),
+            ToServer::ChangeAppearance(appearance) => write!(f, "change appearance to {}", appearance),
+            ToServer::WatchLeaderboard(watch) => {
+                write!(f, "{} watching leaderboard", if *watch { "start" } else { "stop" })
+            }
         }
     }
 }
 
-/// Message sent from the server to the client over the WebSocket protocol.
-#[derive(Serialize, Deserialize)]
+/// Message sent from the server to the client over the WebSocket protocol. Converts into the name of its variant.
+#[derive(Serialize, Deserialize, IntoStaticStr)]
 pub enum FromServer {
     /// Response to a [`ToServer::Hello`] message. This should be the first message sent from the server to each
     /// client.
+    ///
+    /// This and [`FromServer::ProtocolRejected`] must remain the first two variants (and their fields unchanged) so
+    /// that any version of the client is able to understand whether or not it was accepted by any version of the
+    /// server.
     Welcome {
-        /// The version of the game that the server is running. If this does not match the client's version then the
-        /// client should close the connection.
+        /// The version of the game that the server is running (for informational purposes only - compatibility is
+        /// determined by protocol versions).
         version: String,
         /// The ID assigned to the client.
         your_client_id: Id,
+        /// A newly issued session token that the client should present in its next [`ToServer::Hello`] message (in
+        /// place of any token it presented previously).
+        your_session_token: String,
         /// The entity ID and player entity that the client controls.
         your_entity_with_id: (Id, Entity)
     },
 
+    /// Sent instead of a [`FromServer::Welcome`] message should the client speak a protocol version not supported by
+    /// the server. The connection is closed after this message is sent.
+    ProtocolRejected {
+        reason: ProtocolRejection,
+        /// The earliest protocol version supported by the server.
+        min_supported_version: u32,
+        /// The latest protocol version supported by the server.
+        max_supported_version: u32
+    },
+
     /// Provide chunk data to a client so it may store it locally. Chunks are provided automatically based on the
-    /// position of a client's player entity.
+    /// position of a client's player entity. The chunk's tiles are sent in the compact form described in
+    /// [`maps::encoding`].
     ProvideChunk(maps::ChunkCoords, maps::Chunk),
 
     /// Indicate to a client that they should unload the chunk at the specified coordinates. This message is sent when
@@ -122,24 +225,105 @@ pub enum FromServer {
     /// Inform the client that some entity (not their own) placed a bomb down within the client's loaded chunks.
     BombPlaced { placed_by_entity_id: Id, position: maps::TileCoords },
 
-    /// Inform the client that all the bombs placed by the entity with the given ID in and surrounded the specified
-    /// chunk have now detonated.
-    BombsDetonated { placed_by_entity_id: Id, in_and_around_chunk_coords: maps::ChunkCoords },
+    /// Inform the client that the bombs placed by the entity with the given ID at the specified positions have now
+    /// detonated. Only the positions within the client's loaded chunks are included.
+    BombsDetonated { placed_by_entity_id: Id, positions: Vec<maps::TileCoords> },
+
+    /// Informs the client of the type and quantity of gems they received after their entity smashed a rock (or after
+    /// a theft trap that they placed was triggered).
+    YouCollectedGems { gem_type: gems::Gem, quantity_increase: u32 },
+
+    /// Informs the client of the type and quantity of gems they lost (e.g. as a result of triggering a theft trap or
+    /// dying).
+    YouLostGems { gem_type: gems::Gem, quantity_decrease: u32 },
 
-    /// Informs the client of the type and quantity of gems they received after their entity smashed a rock.
-    YouCollectedGems { gem_type: gems::Gem, quantity_increase: u32 }
+    /// Inform the client that a gem has appeared on the ground within the client's loaded chunks. Traps placed by
+    /// other players are provided to the client using this message so that they cannot be told apart from real gems.
+    GroundGemPlaced { position: maps::TileCoords, gem: gems::Gem },
+
+    /// Inform the client that the trap at the given position (within the client's loaded chunks) was triggered by the
+    /// entity with the specified ID. The client should remove any trap or gem on the ground at that position.
+    TrapTriggered { position: maps::TileCoords, trap: items::Trap, triggered_by_entity_id: Id },
+
+    /// Inform the client that the gem on the ground at the given position (within the client's loaded chunks) was
+    /// collected by the entity with the specified ID (which may be the client's own player entity). The client should
+    /// remove the gem from the ground.
+    GroundGemCollected { position: maps::TileCoords, collected_by_entity_id: Id },
+
+    /// Inform the client that a status effect has been applied to the entity with the given ID (which may be the
+    /// client's own player entity). This message is sent for all entities within the client's loaded chunks.
+    StatusEffectStarted { entity_id: Id, effect: effects::StatusEffect },
+
+    /// Inform the client that a status effect applied to the entity with the given ID (which may be the client's own
+    /// player entity) has expired.
+    StatusEffectEnded { entity_id: Id, effect: effects::StatusEffect },
+
+    /// Inform the client that their player entity was killed by a bomb blast and has respawned at the given position.
+    /// Any gems lost are provided to the client using [`FromServer::YouLostGems`] messages sent beforehand.
+    YouDied { respawn_position: maps::TileCoords },
+
+    /// Inform the client that the entity with the given ID was killed at the specified position (within the client's
+    /// loaded chunks). The client should remove that entity - should it respawn within the client's loaded chunks
+    /// then a [`FromServer::ProvideEntity`] message will follow.
+    EntityDied { entity_id: Id, position: maps::TileCoords },
+
+    /// Inform the client that the server is shutting down for the given reason and will close the connection in the
+    /// specified number of seconds.
+    ServerShuttingDown { reason: String, seconds: u32 },
+
+    /// Inform the client that the connection is about to be closed for the given reason (sent either instead of a
+    /// [`FromServer::Welcome`] message or at any point afterwards).
+    SessionEnded(SessionEndReason),
+
+    /// Deliver a chat message sent by the player with the given entity ID (which may be the client's own player
+    /// entity). Proximity chat messages are only delivered to clients whose loaded chunks contain the sender's player
+    /// entity while global chat messages are delivered to all clients.
+    ChatMessage { channel: chat::Channel, sender_entity_id: Id, text: String },
+
+    /// Inform the client that the chat message they most recently sent was not delivered for the given reason.
+    ChatMessageRejected(chat::Rejection),
+
+    /// Inform the client that the facial expression of the entity with the given ID (which may be the client's own
+    /// player entity) has changed. This message is sent for all entities within the client's loaded chunks.
+    FacialExpressionChanged { entity_id: Id, expression: entities::FacialExpression },
+
+    /// Inform the client of the appearance of the entity with the given ID (which may be the client's own player
+    /// entity) following a change. This message is sent for all entities within the client's loaded chunks.
+    AppearanceChanged { entity_id: Id, appearance: entities::Appearance },
+
+    /// Provide the entity IDs and scores of the players with the highest scores (highest first) along with the rank
+    /// and score of the client's own player. Only sent while the client is watching the leaderboard (see
+    /// [`ToServer::WatchLeaderboard`]).
+    Leaderboard { top_scores: Vec<(Id, u64)>, your_rank: u32, your_score: u64 },
+
+    /// Inform the client that their player entity was moved to the given position by the server's operators. The
+    /// chunks surrounding that position follow.
+    YouTeleported { position: maps::TileCoords },
+
+    /// Provide the client with the contents of their player entity's item inventory after it was changed by the
+    /// server's operators.
+    YourInventoryChanged(items::Inventory),
+
+    /// Inform the client that messages it sent were discarded as it is sending messages faster than allowed. The
+    /// client will be disconnected should it continue to do so.
+    RateLimitWarning
 }
 
 impl fmt::Display for FromServer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            FromServer::Welcome { version, your_client_id, your_entity_with_id: (entity_id, entity) } => {
+            FromServer::Welcome { version, your_client_id, your_entity_with_id: (entity_id, entity), .. } => {
                 write!(
                     f,
                     "welcome client {} to server running version '{}' and provide entity {} - {}",
                     your_client_id, version, entity, entity_id
                 )
             }
+            FromServer::ProtocolRejected { reason, min_supported_version, max_supported_version } => write!(
+                f,
+                "protocol rejected as {:?} (supported versions are {} to {})",
+                reason, min_supported_version, max_supported_version
+            ),
             FromServer::ProvideChunk(coords, _chunk) => write!(f, "provide chunk at {}", coords),
             FromServer::ShouldUnloadChunk(coords) => write!(f, "should unload chunk at {}", coords),
             FromServer::ChangeTile(coords, tile) => write!(f, "change tile at {} to {:?}", coords, tile),
@@ -154,16 +338,135 @@ impl fmt::Display for FromServer {
             FromServer::BombPlaced { placed_by_entity_id, position } => {
                 write!(f, "bomb placed at {} by entity {}", position, placed_by_entity_id)
             }
-            FromServer::BombsDetonated { placed_by_entity_id, in_and_around_chunk_coords } => {
+            FromServer::BombsDetonated { placed_by_entity_id, positions } => {
+                write!(f, "{} bombs placed by entity {} detonated", positions.len(), placed_by_entity_id)
+            }
+            FromServer::YouCollectedGems { gem_type, quantity_increase } => {
+                write!(f, "you collected {} gems of type {:?}", quantity_increase, gem_type)
+            }
+            FromServer::YouLostGems { gem_type, quantity_decrease } => {
+                write!(f, "you lost {} gems of type {:?}", quantity_decrease, gem_type)
+            }
+            FromServer::GroundGemPlaced { position, gem } => {
+                write!(f, "gem {:?} placed on the ground at {}", gem, position)
+            }
+            FromServer::TrapTriggered { position, trap, triggered_by_entity_id } => {
+                write!(f, "{:?} trap at {} triggered by entity {}", trap, position, triggered_by_entity_id)
+            }
+            FromServer::GroundGemCollected { position, collected_by_entity_id } => {
+                write!(f, "gem on the ground at {} collected by entity {}", position, collected_by_entity_id)
+            }
+            FromServer::StatusEffectStarted { entity_id, effect } => {
+                write!(f, "status effect {} started for entity {}", effect, entity_id)
+            }
+            FromServer::StatusEffectEnded { entity_id, effect } => {
+                write!(f, "status effect {} ended for entity {}", effect, entity_id)
+            }
+            FromServer::YouDied { respawn_position } => write!(f, "you died and respawned at {}", respawn_position),
+            FromServer::EntityDied { entity_id, position } => write!(f, "entity {} died at {}", entity_id, position),
+            FromServer::ServerShuttingDown { reason, seconds } => {
+                write!(f, "server shutting down in {} seconds due to: {}", seconds, reason)
+            }
+            FromServer::SessionEnded(reason) => write!(f, "session ended ({:?})", reason),
+            FromServer::ChatMessage { channel, sender_entity_id, text } => {
+                write!(f, "{:?} chat message from entity {} - '{}'", channel, sender_entity_id, text)
+            }
+            FromServer::ChatMessageRejected(rejection) => write!(f, "chat message rejected ({:?})", rejection),
+            FromServer::FacialExpressionChanged { entity_id, expression } => {
+                write!(f, "facial expression of entity {} changed to {}", entity_id, expression)
+            }
+            FromServer::AppearanceChanged { entity_id, appearance } => {
+                write!(f, "appearance of entity {} changed to {}", entity_id, appearance)
+            }
+            FromServer::Leaderboard { top_scores, your_rank, your_score } => {
                 write!(
                     f,
-                    "bombs placed entity {} in and around chunk at {} detonated",
-                    placed_by_entity_id, in_and_around_chunk_coords
+                    "leaderboard of {} players (ranked {} with score {})",
+                    top_scores.len(),
+                    your_rank,
+                    your_score
                 )
             }
-            FromServer::YouCollectedGems { gem_type, quantity_increase } => {
-                write!(f, "you collected {} gems of type {:?}", quantity_increase, gem_type)
+            FromServer::YouTeleported { position } => write!(f, "you were teleported to {}", position),
+            FromServer::YourInventoryChanged(_) => write!(f, "your item inventory changed"),
+            FromServer::RateLimitWarning => write!(f, "you are sending messages too quickly")
+        }
+    }
+}
+
+/// Proof of identity presented by a returning client in its [`ToServer::Hello`] message.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub enum Credentials {
+    /// A session token issued by the server in a previous [`FromServer::Welcome`] message.
+    SessionToken(String),
+    /// A client ID as stored by versions of the client that predate session tokens. Only accepted by the server for a
+    /// limited period so that existing players can continue playing as their pre-existing characters.
+    LegacyClientId(Id)
+}
+
+/// Reasons that the server may refuse the protocol version spoken by a client (see [`FromServer::ProtocolRejected`]).
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ProtocolRejection {
+    /// The client speaks an earlier protocol version than the server supports so the client should be updated.
+    ClientTooOld,
+    /// The client speaks a later protocol version than the server supports (e.g. the server has not yet been updated).
+    ClientTooNew
+}
+
+impl fmt::Display for ProtocolRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolRejection::ClientTooOld => write!(f, "This version of the game is no longer supported"),
+            ProtocolRejection::ClientTooNew => {
+                write!(f, "The server has not yet been updated to this version of the game")
+            }
+        }
+    }
+}
+
+/// Reasons that the server may end a client's session (other than shutting down).
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SessionEndReason {
+    /// The same client connected again (e.g. in another browser tab) and so took over from this connection.
+    ConnectedElsewhere,
+    /// The client is already connected (e.g. in another browser tab) so this connection was refused.
+    AlreadyConnected,
+    /// The credentials presented by the client were not accepted (e.g. its session token expired or was revoked) so
+    /// this connection was refused. The client should discard its stored credentials.
+    InvalidCredentials,
+    /// The client was disconnected by the server's operators.
+    Kicked,
+    /// The client has been banned by the server's operators.
+    Banned,
+    /// The client continued to send messages faster than allowed after being warned (see
+    /// [`FromServer::RateLimitWarning`]).
+    TooManyMessages
+}
+
+impl fmt::Display for SessionEndReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionEndReason::ConnectedElsewhere => write!(f, "You have connected to the game elsewhere"),
+            SessionEndReason::AlreadyConnected => write!(f, "You are already connected to the game elsewhere"),
+            SessionEndReason::InvalidCredentials => {
+                write!(f, "Your session has expired or been revoked - reconnect to start as a new player")
             }
+            SessionEndReason::Kicked => write!(f, "You have been disconnected by a moderator"),
+            SessionEndReason::Banned => write!(f, "You have been banned from the game"),
+            SessionEndReason::TooManyMessages => write!(f, "You were disconnected for sending too many messages")
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protocol_version_check() {
+        assert_eq!(check_protocol_version(PROTOCOL_VERSION), Ok(()));
+        assert_eq!(check_protocol_version(MIN_SUPPORTED_PROTOCOL_VERSION), Ok(()));
+        assert_eq!(check_protocol_version(MIN_SUPPORTED_PROTOCOL_VERSION - 1), Err(ProtocolRejection::ClientTooOld));
+        assert_eq!(check_protocol_version(PROTOCOL_VERSION + 1), Err(ProtocolRejection::ClientTooNew));
+    }
+}